@@ -72,6 +72,10 @@ impl AnalysisSet {
     pub fn add_default<T: Analysis + Default + 'static>(&mut self) {
         self.add(Box::<T>::default())
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.analyses.is_empty()
+    }
 }
 
 impl Analysis for AnalysisSet {
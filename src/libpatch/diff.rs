@@ -0,0 +1,272 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module computes the difference between two versions of a file as a
+//! list of `Hunk`s, so it can be written out as a patch.
+//!
+//! It uses the Myers' O(ND) algorithm, which produces the shortest edit
+//! script, same as diff does.
+
+use std::cmp::{max, min};
+
+use crate::patch::{Hunk, HunksVec};
+
+
+/// Default amount of context lines around each change, same as `diff -u`.
+pub const DEFAULT_CONTEXT: usize = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Find the shortest edit script that turns `old` into `new`.
+fn edit_script<Line: PartialEq>(old: &[Line], new: &[Line]) -> Vec<Edit> {
+    // Common prefix and suffix are cheap to find and they are typically most
+    // of the file, so take them out of the expensive part.
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let old_middle = &old[prefix..(old.len() - suffix)];
+    let new_middle = &new[prefix..(new.len() - suffix)];
+
+    let mut edits = Vec::with_capacity(max(old.len(), new.len()));
+    edits.resize(prefix, Edit::Equal);
+    edits.extend(middle_edit_script(old_middle, new_middle));
+    edits.resize(edits.len() + suffix, Edit::Equal);
+    edits
+}
+
+/// The Myers' algorithm itself.
+fn middle_edit_script<Line: PartialEq>(old: &[Line], new: &[Line]) -> Vec<Edit> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max_d = n + m;
+
+    // `v[k + max_d]` is the furthest x reached on diagonal k. `trace[d]` holds
+    // the part of `v` for diagonals -d..=d as it was before step d.
+    let mut v = vec![0isize; 2 * max_d as usize + 2];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let idx = |k: isize| (k + max_d) as usize;
+
+    'search: for d in 0..=max_d {
+        trace.push(v[idx(-d)..=idx(d)].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk back through the trace and collect the edits.
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { get(prev_k) };
+        let prev_y = if d == 0 { 0 } else { prev_x - prev_k };
+
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+
+        if d > 0 {
+            edits.push(if x == prev_x { Edit::Insert } else { Edit::Delete });
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    edits
+}
+
+/// Compute the hunks that turn `old` into `new`, with `context` lines of
+/// context around every change. Changes closer than twice the context are
+/// merged into a single hunk. Returns no hunks if the contents are equal.
+pub fn diff<'a, Line: PartialEq + Copy>(old: &[Line], new: &[Line], context: usize)
+    -> HunksVec<'a, Line>
+{
+    let edits = edit_script(old, new);
+
+    // Positions in `old` and `new` before each edit
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for edit in &edits {
+        positions.push((old_pos, new_pos));
+        match edit {
+            Edit::Equal => { old_pos += 1; new_pos += 1; }
+            Edit::Delete => { old_pos += 1; }
+            Edit::Insert => { new_pos += 1; }
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    let changes: Vec<usize> = edits.iter().enumerate()
+        .filter(|(_, edit)| **edit != Edit::Equal)
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut hunks = HunksVec::new();
+    let mut i = 0;
+    while i < changes.len() {
+        // Find the last change that belongs to this hunk
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] - 1 <= 2 * context {
+            j += 1;
+        }
+
+        let start = changes[i].saturating_sub(context);
+        let end = min(changes[j] + 1 + context, edits.len());
+
+        let (old_start, new_start) = positions[start];
+        let mut hunk = Hunk::new(old_start, new_start, b"");
+        let (mut old_i, mut new_i) = (old_start, new_start);
+        for edit in &edits[start..end] {
+            match edit {
+                Edit::Equal => {
+                    hunk.remove.content.push(old[old_i]);
+                    hunk.add.content.push(new[new_i]);
+                    old_i += 1;
+                    new_i += 1;
+                }
+                Edit::Delete => {
+                    hunk.remove.content.push(old[old_i]);
+                    old_i += 1;
+                }
+                Edit::Insert => {
+                    hunk.add.content.push(new[new_i]);
+                    new_i += 1;
+                }
+            }
+        }
+        hunk.prefix_context = changes[i] - start;
+        hunk.suffix_context = end - changes[j] - 1;
+
+        hunks.push(hunk);
+        i = j + 1;
+    }
+
+    hunks
+}
+
+#[cfg(test)]
+fn lines(text: &'static str) -> Vec<&'static [u8]> {
+    crate::util::split_lines_with_endings(text.as_bytes()).collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test_diff_equal() {
+    let old = lines("a\nb\nc\n");
+    assert!(diff(&old, &old, DEFAULT_CONTEXT).is_empty());
+    assert!(diff::<&[u8]>(&[], &[], DEFAULT_CONTEXT).is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn test_diff_single_change() {
+    let old = lines("1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+    let new = lines("1\n2\n3\n4\nfive\n6\n7\n8\n9\n");
+
+    let hunks = diff(&old, &new, DEFAULT_CONTEXT);
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(hunks[0].remove.target_line, 1);
+    assert_eq!(hunks[0].add.target_line, 1);
+    assert_eq!(hunks[0].remove.content, lines("2\n3\n4\n5\n6\n7\n8\n"));
+    assert_eq!(hunks[0].add.content, lines("2\n3\n4\nfive\n6\n7\n8\n"));
+    assert_eq!(hunks[0].prefix_context, 3);
+    assert_eq!(hunks[0].suffix_context, 3);
+}
+
+#[cfg(test)]
+#[test]
+fn test_diff_hunks_merging() {
+    let old = lines("a\n1\n2\n3\n4\n5\n6\nb\n1\n2\n3\n4\n5\n6\n7\nc\n");
+    let new = lines("A\n1\n2\n3\n4\n5\n6\nB\n1\n2\n3\n4\n5\n6\n7\nC\n");
+
+    // Six lines between "a" and "b" fit into twice the context...
+    let hunks = diff(&old, &new, DEFAULT_CONTEXT);
+    assert_eq!(hunks.len(), 2);
+    assert_eq!(hunks[0].prefix_context, 0);
+    assert_eq!(hunks[0].remove.content.len(), 11);
+
+    // ... but seven lines between "b" and "c" do not.
+    assert_eq!(hunks[1].remove.target_line, 12);
+    assert_eq!(hunks[1].remove.content, lines("5\n6\n7\nc\n"));
+    assert_eq!(hunks[1].add.content, lines("5\n6\n7\nC\n"));
+    assert_eq!(hunks[1].suffix_context, 0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_diff_create_and_delete() {
+    let content = lines("a\nb\n");
+
+    let hunks = diff(&[], &content, DEFAULT_CONTEXT);
+    assert_eq!(hunks.len(), 1);
+    assert!(hunks[0].remove.content.is_empty());
+    assert_eq!(hunks[0].add.content, content);
+
+    let hunks = diff(&content, &[], DEFAULT_CONTEXT);
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(hunks[0].remove.content, content);
+    assert!(hunks[0].add.content.is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn test_diff_is_minimal() {
+    let old = lines("a\nb\nc\na\nb\nb\na\n");
+    let new = lines("c\nb\na\nb\na\nc\n");
+
+    // The classic example from the Myers' paper has edit distance 5.
+    let edits = edit_script(&old, &new);
+    assert_eq!(edits.iter().filter(|edit| **edit != Edit::Equal).count(), 5);
+}
+
+#[cfg(test)]
+#[test]
+fn test_diff_applies() {
+    use crate::analysis::{AnalysisSet, fn_analysis_note_noop};
+    use crate::modified_file::ModifiedFile;
+    use crate::patch::{BatchThreshold, FilePatchKind, PatchDirection, TextFilePatchBuilder};
+
+    let old_text = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n17\n18\n19\n20\n";
+    let new_text = "0\n1\n2\n4\n5\n6\n7\n8\n9\n10\nten\n11\n12\n13\n14\n15\n16\n17\n19\n20";
+
+    let file_patch = TextFilePatchBuilder::default()
+        .kind(FilePatchKind::Modify)
+        .hunks(diff(&lines(old_text), &lines(new_text), DEFAULT_CONTEXT))
+        .build()
+        .unwrap();
+
+    let mut file = ModifiedFile::new(old_text.as_bytes(), true, None);
+    let report = file_patch.apply(&mut file, PatchDirection::Forward, 0, None, false, false, false, None, BatchThreshold::default(), &AnalysisSet::default(), &fn_analysis_note_noop);
+    assert!(report.ok());
+    assert_eq!(file.content, lines(new_text));
+}
@@ -5,6 +5,7 @@
 extern crate test;
 
 pub mod analysis;
+pub mod diff;
 pub mod modified_file;
 pub mod patch;
 mod util;
@@ -1,7 +1,9 @@
 // Licensed under the MIT license. See LICENSE.md
 
+use std::fmt;
 use std::fs::Permissions;
 use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
 
 use crate::util::split_lines_with_endings;
 
@@ -26,13 +28,71 @@ pub struct ModifiedFile<'a> {
     /// the old permissions matched, so neither do we, therefore we don't have
     /// to query the permissions of the original file.
     pub permissions: Option<Permissions>,
+
+    /// The owner (uid and gid) of the original file, if it is known. Patches
+    /// do not change it.
+    pub owner: Option<(u32, u32)>,
+
+    /// The rest of the file that was not loaded into `content` yet. It is
+    /// `None` if the whole file is loaded. The `content` always ends with a
+    /// complete line, so the tail can be appended to it at any time.
+    pub tail: Option<FileTail<'a>>,
+
+    /// The file starts with a UTF-8 byte order mark, which was taken out of
+    /// the first line in `content` by `strip_bom`. `write_to` puts it back.
+    pub bom: bool,
 }
 
+/// The UTF-8 byte order mark, which some editors put at the start of files
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
 const AVG_LINE_LENGTH: usize = 30; // Heuristics, for initial estimation of line count.
 
+/// Minimal and maximal amount of bytes read from `FileTail` at once.
+const MIN_TAIL_CHUNK_SIZE: usize = 1 << 20;
+const MAX_TAIL_CHUNK_SIZE: usize = 64 << 20;
+
+/// Provides the content of a file that is loaded lazily.
+pub trait TailSource<'a>: Send + Sync {
+    /// Read chunk of the file starting at `offset`. The chunk is at least
+    /// `min_size` bytes long and it ends with a complete line, unless the end
+    /// of the file was reached. Returns empty slice at the end of the file.
+    fn read_chunk(&self, offset: u64, min_size: usize) -> Result<&'a [u8], io::Error>;
+
+    /// Copy everything from `offset` to the end of the file into `writer`.
+    fn copy_rest(&self, offset: u64, writer: &mut dyn Write) -> Result<(), io::Error>;
+}
+
+/// The part of a file that was not loaded yet. See `ModifiedFile::tail`.
+#[derive(Clone)]
+pub struct FileTail<'a> {
+    source: Arc<dyn TailSource<'a> + 'a>,
+    offset: u64,
+}
+
+impl<'a> FileTail<'a> {
+    /// Create tail that starts at `offset` in the `source`.
+    pub fn new(source: Arc<dyn TailSource<'a> + 'a>, offset: u64) -> Self {
+        Self { source, offset }
+    }
+}
+
+impl fmt::Debug for FileTail<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileTail").field("offset", &self.offset).finish_non_exhaustive()
+    }
+}
+
 impl<'arena> ModifiedFile<'arena> {
     /// Create new `ModifiedFile` with lines from given `bytes`.
     pub fn new(bytes: &'arena [u8], existed: bool, permissions: Option<Permissions>) -> Self {
+        Self::new_partial(bytes, None, existed, permissions)
+    }
+
+    /// Create new `ModifiedFile` with lines from given `bytes`, followed by
+    /// the `tail` that will be loaded when needed. The `bytes` must end with
+    /// a complete line.
+    pub fn new_partial(bytes: &'arena [u8], tail: Option<FileTail<'arena>>, existed: bool, permissions: Option<Permissions>) -> Self {
         let mut content = Vec::with_capacity(bytes.len() / AVG_LINE_LENGTH);
 
         content.extend(
@@ -44,6 +104,9 @@ impl<'arena> ModifiedFile<'arena> {
             deleted: false,
             existed,
             permissions,
+            owner: None,
+            tail,
+            bom: false,
         }
     }
 
@@ -54,9 +117,59 @@ impl<'arena> ModifiedFile<'arena> {
             deleted: true,
             existed: false,
             permissions: None,
+            owner: None,
+            tail: None,
+            bom: false,
+        }
+    }
+
+    /// Is the whole file loaded in `content`?
+    pub fn is_fully_loaded(&self) -> bool {
+        self.tail.is_none()
+    }
+
+    /// Load lines from the tail until there are at least `count` lines in
+    /// `content`, or the whole file is loaded.
+    pub fn ensure_lines(&mut self, count: usize) -> Result<(), io::Error> {
+        while self.content.len() < count {
+            let Some(tail) = &mut self.tail else {
+                break;
+            };
+
+            let min_size = (count - self.content.len()).saturating_mul(AVG_LINE_LENGTH)
+                .clamp(MIN_TAIL_CHUNK_SIZE, MAX_TAIL_CHUNK_SIZE);
+            let chunk = tail.source.read_chunk(tail.offset, min_size)?;
+            if chunk.is_empty() {
+                self.tail = None;
+                break;
+            }
+
+            tail.offset += chunk.len() as u64;
+            self.content.extend(split_lines_with_endings(chunk));
+        }
+
+        Ok(())
+    }
+
+    /// Take the UTF-8 byte order mark out of the first line, so it matches
+    /// the lines of patches made without it. It is kept in `bom` and written
+    /// back by `write_to`. Returns false if the file does not start with it.
+    pub fn strip_bom(&mut self) -> bool {
+        match self.content.first().and_then(|line| line.strip_prefix(UTF8_BOM)) {
+            Some(rest) if !self.bom => {
+                self.content[0] = rest;
+                self.bom = true;
+                true
+            }
+            _ => false,
         }
     }
 
+    /// Load the whole tail.
+    pub fn load_all(&mut self) -> Result<(), io::Error> {
+        self.ensure_lines(usize::MAX)
+    }
+
     /// Intended to be used when renaming files.
     /// This ModifiedFile must stay as a record that the original was deleted,
     /// but the content is taken away.
@@ -72,6 +185,9 @@ impl<'arena> ModifiedFile<'arena> {
             deleted: false,
             existed: false,
             permissions: self.permissions.take(),
+            owner: self.owner,
+            tail: self.tail.take(),
+            bom: std::mem::take(&mut self.bom),
         }
     }
 
@@ -79,16 +195,19 @@ impl<'arena> ModifiedFile<'arena> {
     /// The content of this modified file is replaced by the `other` one, but
     /// only if this one was empty. Otherwise false is returned.
     pub fn move_in(&mut self, other: &mut ModifiedFile<'arena>) -> bool {
-        if !self.content.is_empty() && !self.deleted {
+        if (!self.content.is_empty() || self.tail.is_some()) && !self.deleted {
             return false;
         }
 
         std::mem::swap(&mut self.content, &mut other.content);
+        std::mem::swap(&mut self.tail, &mut other.tail);
+        self.bom = std::mem::take(&mut other.bom);
         other.deleted = true;
         self.deleted = false;
         // self.existed remains at it was
 
         self.permissions = other.permissions.take();
+        self.owner = self.owner.or(other.owner);
 
         true
     }
@@ -100,10 +219,17 @@ impl<'arena> ModifiedFile<'arena> {
 
         let mut writer = BufWriter::new(writer);
 
+        if self.bom {
+            writer.write_all(UTF8_BOM)?;
+        }
         for line in &self.content {
             writer.write_all(line)?;
         }
 
+        if let Some(tail) = &self.tail {
+            tail.source.copy_rest(tail.offset, &mut writer)?;
+        }
+
         Ok(())
     }
 }
@@ -14,9 +14,9 @@
 //! ones are:
 //!
 //! * `Line = &[u8]`: The line is a slice of some external buffer. This is how
-//!                   patches come from parser.
-//!                   Type aliases `TextPatch`, `TextFilePatch` and `TextHunk`
-//!                   can be used as shortcut.
+//!   patches come from parser.
+//!   Type aliases `TextPatch`, `TextFilePatch` and `TextHunk` can be used as
+//!   shortcut.
 //!
 //! Other line representations would be possible, for example one where
 //! `Line = String` for a patch that owns its data.
@@ -29,11 +29,14 @@
 use std::borrow::Cow;
 use std::cmp::{max, min};
 use std::fs;
+use std::ops::Range;
 use std::vec::Vec;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use derive_builder::Builder;
 use itertools::Itertools;
+use rayon::prelude::*;
 
 use crate::analysis::{Analysis, AnalysisSet, Note};
 use crate::modified_file::ModifiedFile;
@@ -43,6 +46,45 @@ pub mod unified;
 
 type ContentVec<Line> = Vec<Line>;
 
+/// How many candidate lines are tried between checks of the deadline when
+/// searching for a place where a hunk matches.
+const DEADLINE_CHECK_INTERVAL: usize = 1024;
+
+/// When a file patch is applied in batch: its hunks are searched for in
+/// parallel and all changes are committed in one pass over the file. For
+/// few hunks or a small file it does not pay off, the threads cost more than
+/// they save.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchThreshold {
+    /// The file patch must have at least this many hunks
+    pub min_hunks: usize,
+
+    /// The loaded content of the file must have at least this many bytes
+    pub min_file_size: usize,
+}
+
+impl BatchThreshold {
+    pub const DEFAULT_MIN_HUNKS: usize = 64;
+    pub const DEFAULT_MIN_FILE_SIZE: usize = 64 * 1024;
+
+    /// Returns true if a file patch with `hunks` hunks is applied in batch
+    /// on the `modified_file`.
+    pub fn applies_to(&self, hunks: usize, modified_file: &ModifiedFile) -> bool {
+        // The size is only summed up for patches with enough hunks.
+        hunks >= self.min_hunks &&
+            modified_file.content.iter().map(|line| line.len()).sum::<usize>() >= self.min_file_size
+    }
+}
+
+impl Default for BatchThreshold {
+    fn default() -> Self {
+        Self {
+            min_hunks: Self::DEFAULT_MIN_HUNKS,
+            min_file_size: Self::DEFAULT_MIN_FILE_SIZE,
+        }
+    }
+}
+
 /// This is part of hunk representing the lines to be added or removed together
 /// with the target line.
 #[derive(Clone, Debug)]
@@ -256,11 +298,13 @@ pub enum HunkApplyFailureReason {
     CreatingFileThatExists,
     DeletingFileThatDoesNotMatch,
     MisorderedHunks,
+    TimedOut,
+    AmbiguousMatch,
 }
 
 /// The result of applying a `Hunk`.
 #[derive(Debug)]
-pub enum HunkApplyReport {
+pub enum HunkApplyReport<'a> {
     /// It was applied on given line, with given offset.
     Applied {
         /// Line on which the hunk was applied (in the original file).
@@ -271,14 +315,37 @@ pub enum HunkApplyReport {
 	/// difference may not be representable as an isize.
         offset: isize,
 
-        /// Fuzz with which this specific hunk was applied
+        /// Fuzz with which this specific hunk was applied, the least one
+        /// with which it matched
         fuzz: usize,
     },
 
+    /// It did not match, but its content was written anyway, because
+    /// forced application was requested.
+    Forced {
+        /// Line on which the hunk was written (in the original file).
+        line: usize,
+
+        /// The original lines that were overwritten by the hunk. They are
+        /// needed to roll the hunk back.
+        replaced: Vec<&'a [u8]>,
+    },
+
     /// It failed to apply.
     Failed(HunkApplyFailureReason),
 }
 
+/// Where a hunk was applied, to apply it there again with
+/// `FilePatch::apply_at`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HunkMatch {
+    /// Line on which the hunk was applied (in the original file).
+    pub line: usize,
+
+    /// Fuzz with which the hunk was applied
+    pub fuzz: usize,
+}
+
 /// Try to apply given `HunkView` onto the `ModifiedFile`.
 /// This function does not really modify the modified_file, only returns report
 /// describing if application is possible and where would it go.
@@ -292,13 +359,23 @@ pub enum HunkApplyReport {
 /// `movable`: can the hunk be applied elsewhere (i.e. with a non-zero offset)?
 ///
 /// `min_modify_line`: first line that can be modified by a new hunk, i.e. first that is not modified by a previous hunk.
+///
+/// `max_offset`: if the hunk is movable, it is searched for at most this many lines away from the `target_line`.
+///
+/// `strict_ambiguity`: if the hunk matches equally far before and after the `target_line`, fail instead of picking the one after.
+///
+/// `deadline`: if the search for matching lines is still running at this time, it is given up.
+#[allow(clippy::too_many_arguments)]
 fn try_apply_hunk(
     hunk_view: &TextHunkView,
     modified_file: &ModifiedFile,
     target_line: usize,
     movable: bool,
-    min_modify_line: usize)
-    -> HunkApplyReport
+    min_modify_line: usize,
+    max_offset: Option<usize>,
+    strict_ambiguity: bool,
+    deadline: Option<Instant>)
+    -> HunkApplyReport<'static>
 {
     // If the file doesn't exist, fail immediatelly
     if modified_file.deleted {
@@ -320,7 +397,11 @@ fn try_apply_hunk(
     // backwards for a set of lines matching the context given in the hunk."
     let (min_line, max_line) =
 	if movable {
-	    (0, max_target)
+	    match max_offset {
+		Some(max_offset) => (target_line.saturating_sub(max_offset),
+				     min(target_line.saturating_add(max_offset), max_target)),
+		None => (0, max_target),
+	    }
 	} else {
 	    (target_line, target_line)
 	};
@@ -331,11 +412,35 @@ fn try_apply_hunk(
     // The intended target line (zero offset) is part of `backward_targets`,
     // so that a positive offset in `forward_targets` (e.g. +5) is tried
     // before the corresponding negative offsets (e.g. -5).
-    let Some(target_line) = backward_targets.interleave(forward_targets)
-	.find(|&line| &modified_file.content[line..(line + remove_content.len())] == remove_content)
-	else {
-            return HunkApplyReport::Failed(HunkApplyFailureReason::NoMatchingLines);
-        };
+    let matches_at = |line: usize| &modified_file.content[line..(line + remove_content.len())] == remove_content;
+    let mut timed_out = false;
+    let found = backward_targets.interleave(forward_targets)
+        .enumerate()
+        .find(|&(i, line)| {
+            if i % DEADLINE_CHECK_INTERVAL == DEADLINE_CHECK_INTERVAL - 1 &&
+               deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                timed_out = true;
+                return true;
+            }
+            matches_at(line)
+        });
+    if timed_out {
+        return HunkApplyReport::Failed(HunkApplyFailureReason::TimedOut);
+    }
+    let Some((_, found_line)) = found else {
+        return HunkApplyReport::Failed(HunkApplyFailureReason::NoMatchingLines);
+    };
+
+    // A match after the `target_line` is picked over one at the same distance
+    // before it. That is what patch does, but it is a guess.
+    if strict_ambiguity && found_line > target_line {
+        let mirrored_line = target_line.checked_sub(found_line - target_line);
+        if mirrored_line.is_some_and(|line| line >= min_line && matches_at(line)) {
+            return HunkApplyReport::Failed(HunkApplyFailureReason::AmbiguousMatch);
+        }
+    }
+    let target_line = found_line;
 
     // Check that we are not modifying frozen content
     if target_line.saturating_add(hunk_view.prefix_context()) < min_modify_line {
@@ -358,43 +463,177 @@ fn try_apply_hunk(
     }
 }
 
-impl HunkApplyReport {
+/// Return the line where the `hunk_view` would be applied with zero offset,
+/// given the offset of the previous hunk, and whether it can be applied
+/// elsewhere.
+fn hunk_target_line(hunk_view: &TextHunkView, modified_file: &ModifiedFile, last_hunk_offset: isize) -> (usize, bool) {
+    match hunk_view.position() {
+	HunkPosition::Start =>
+	    (0, false),
+
+	// man patch:
+	// "As a first guess, [patch] takes the line number
+	// mentioned for the hunk, plus or minus any offset
+	// used in applying the previous hunk.."
+	HunkPosition::Middle =>
+	    (hunk_view.remove_target_line().saturating_add_signed(last_hunk_offset), true),
+
+	// If the hunk is longer than the file, `try_apply_hunk` rejects it anyway.
+	HunkPosition::End =>
+	    (modified_file.content.len().saturating_sub(hunk_view.remove_content().len()), false),
+    }
+}
+
+/// Take over the result of a speculative `try_apply_hunk` of the hunk at
+/// fuzz 0 that was done with no previous hunks applied, if it is the same as
+/// the result of trying it now at `target_line` after the previous hunks.
+///
+/// The search itself only depends on the target line, the previous hunks
+/// only decide whether the match is misordered.
+fn reuse_speculation(speculation: &(usize, HunkApplyReport<'static>),
+                     hunk_view: &TextHunkView,
+                     target_line: usize,
+                     min_modify_line: usize)
+                     -> Option<HunkApplyReport<'static>>
+{
+    let (speculated_line, ref report) = *speculation;
+    if speculated_line != target_line {
+        return None;
+    }
+    match *report {
+        HunkApplyReport::Applied { line, .. } if line.saturating_add(hunk_view.prefix_context()) < min_modify_line =>
+            Some(HunkApplyReport::Failed(HunkApplyFailureReason::MisorderedHunks)),
+
+        HunkApplyReport::Applied { line, offset, fuzz } =>
+            Some(HunkApplyReport::Applied { line, offset, fuzz }),
+
+        // The time may not have run out yet, try again.
+        HunkApplyReport::Failed(HunkApplyFailureReason::TimedOut) =>
+            None,
+
+        HunkApplyReport::Failed(reason) =>
+            Some(HunkApplyReport::Failed(reason)),
+
+        HunkApplyReport::Forced { .. } =>
+            None,
+    }
+}
+
+/// Do the lines changed by two hunks overlap? Hunks that change lines at the
+/// same place, e.g. insert them, overlap too, their order would be ambiguous.
+fn ranges_overlap(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start == b.start || (a.start < b.end && b.start < a.end)
+}
+
+/// Decide where to write a hunk that did not match anywhere, so it can be
+/// force-applied. The hunk is placed where it was expected (taking the offset
+/// of the previous hunk into account), but never before `min_modify_line`.
+fn force_apply_hunk<'a>(
+    hunk_view: &TextHunkView,
+    modified_file: &ModifiedFile<'a>,
+    last_hunk_offset: isize,
+    min_modify_line: usize)
+    -> HunkApplyReport<'a>
+{
+    let file_len = modified_file.content.len();
+    let remove_len = hunk_view.remove_content().len();
+
+    let target_line = match hunk_view.position() {
+        HunkPosition::Start => 0,
+        HunkPosition::Middle => hunk_view.remove_target_line().saturating_add_signed(last_hunk_offset),
+        HunkPosition::End => file_len.saturating_sub(remove_len),
+    };
+    let line = min(max(target_line, min_modify_line), file_len);
+    let end = min(line + remove_len, file_len);
+
+    HunkApplyReport::Forced {
+        line,
+        replaced: modified_file.content[line..end].to_vec(),
+    }
+}
+
+impl<'a> HunkApplyReport<'a> {
+    // Find out what applying a hunk according to the report changes: the range
+    // of lines in the modified_file that is replaced, the view of the hunk and
+    // the range of its added lines that replace them.
+    fn replacement<'hunk>(&self,
+                          hunk: &'hunk TextHunk<'a>,
+                          direction: PatchDirection)
+                          -> Option<(Range<usize>, TextHunkView<'a, 'hunk>, Range<usize>)>
+    {
+	match *self {
+	    HunkApplyReport::Applied { line, fuzz, .. } => {
+		let hunk_view = hunk.view(direction, fuzz);
+		let prefix_len = hunk_view.prefix_context();
+		let suffix_len = hunk_view.suffix_context();
+		let range = (line + prefix_len)..(line + hunk_view.remove_content().len() - suffix_len);
+		let add_range = prefix_len..(hunk_view.add_content().len() - suffix_len);
+		Some((range, hunk_view, add_range))
+	    }
+
+	    HunkApplyReport::Forced { line, ref replaced } => {
+		// The context did not match, so it is replaced too.
+		let hunk_view = hunk.view(direction, 0);
+		let add_range = 0..hunk_view.add_content().len();
+		Some((line..(line + replaced.len()), hunk_view, add_range))
+	    }
+
+	    HunkApplyReport::Failed(..) => None,
+	}
+    }
+
     // Apply a hunk to a modified_file according to the report
-    // (i.e. commit the changes). Return the new line number
-    // difference between the original file and the patched file.
-    //
-    // TODO: Do some more efficient than multiple `Vec::splice`s? The problem with multiple
-    //       splices is that they move the tail of the file multiple times. Alternative is to
-    //       generate new Vec and copy every Line at most once, but that seems to be even
-    //       slower. Ideally we would need some in-place modification that moves every Line
-    //       at most once. But I don't think it is possible in general case.
-    pub fn commit<'a>(&self,
-		      modified_file: &mut ModifiedFile<'a>,
-		      hunk: &TextHunk<'a>,
-		      direction: PatchDirection)
+    // (i.e. commit the changes).
+    pub fn commit(&self,
+		  modified_file: &mut ModifiedFile<'a>,
+		  hunk: &TextHunk<'a>,
+		  direction: PatchDirection)
     {
-	if let HunkApplyReport::Applied { line, fuzz, .. } = *self {
-	    let hunk_view = hunk.view(direction, fuzz);
-	    let prefix_len = hunk_view.prefix_context();
-	    let suffix_len = hunk_view.suffix_context();
-	    let range = (line + prefix_len)..(line + hunk_view.remove_content().len() - suffix_len);
+	if let Some((range, hunk_view, add_range)) = self.replacement(hunk, direction) {
 	    // Note: cloned just makes `&[u8]` out of `&&[u8]`, no real cloning here.
-	    modified_file.content.splice(range, hunk_view.add_content()[prefix_len..(hunk_view.add_content().len() - suffix_len)].iter().cloned());
+	    modified_file.content.splice(range, hunk_view.add_content()[add_range].iter().cloned());
 	}
     }
 }
 
+/// The change of a hunk: its index, the range of lines in the modified_file
+/// that is replaced, the view of the hunk and the range of its added lines
+/// that replace them.
+type Replacement<'a, 'hunk> = (usize, Range<usize>, TextHunkView<'a, 'hunk>, Range<usize>);
+
+/// Commit the `replacements` to the modified_file in a single pass that
+/// copies every Line once.
+///
+/// The replaced ranges must be sorted and must not overlap, see
+/// `FilePatchApplyReport::replacements`.
+fn commit_hunks<'a>(modified_file: &mut ModifiedFile<'a>,
+                    replacements: &[Replacement<'a, '_>])
+{
+    let old_content = std::mem::take(&mut modified_file.content);
+    let mut content = Vec::with_capacity(old_content.len());
+    let mut copied_until = 0;
+
+    for (_, range, hunk_view, add_range) in replacements {
+	content.extend_from_slice(&old_content[copied_until..range.start]);
+	content.extend_from_slice(&hunk_view.add_content()[add_range.clone()]);
+	copied_until = range.end;
+    }
+    content.extend_from_slice(&old_content[copied_until..]);
+
+    modified_file.content = content;
+}
+
 /// The result of applying a `FilePatch`
 #[derive(Debug)]
-pub struct FilePatchApplyReport {
+pub struct FilePatchApplyReport<'a> {
     any_failed: bool,
-    hunk_reports: Vec<HunkApplyReport>,
+    hunk_reports: Vec<HunkApplyReport<'a>>,
     direction: PatchDirection,
     max_fuzz: usize,
     previous_permissions: Option<fs::Permissions>,
 }
 
-impl FilePatchApplyReport {
+impl<'a> FilePatchApplyReport<'a> {
     /// Create a report for given amount of hunks
     fn new_with_capacity(direction: PatchDirection, max_fuzz: usize, capacity: usize) -> Self {
         Self {
@@ -437,7 +676,7 @@ impl FilePatchApplyReport {
     }
 
     /// Add a hunk report
-    fn push_hunk_report(&mut self, hunk_report: HunkApplyReport) {
+    fn push_hunk_report(&mut self, hunk_report: HunkApplyReport<'a>) {
         if let HunkApplyReport::Failed(..) = hunk_report {
             self.any_failed = true;
         }
@@ -450,8 +689,86 @@ impl FilePatchApplyReport {
     /// Did the applying failed? Any hunk failed?
     pub fn failed(&self) -> bool { self.any_failed }
 
+    /// Was any hunk written without matching? (See `HunkApplyReport::Forced`.)
+    pub fn forced(&self) -> bool {
+        self.hunk_reports.iter().any(|report| matches!(report, HunkApplyReport::Forced { .. }))
+    }
+
+    /// Did any hunk run out of time? (See `HunkApplyFailureReason::TimedOut`.)
+    pub fn timed_out(&self) -> bool {
+        self.hunk_reports.iter().any(|report| matches!(report, HunkApplyReport::Failed(HunkApplyFailureReason::TimedOut)))
+    }
+
+    /// Would this report be the same if it was made on the whole file, not
+    /// just its first `loaded_lines` lines? That is the case if every hunk
+    /// matched without fuzz and all positions that would be tried before the
+    /// matching one were loaded.
+    ///
+    /// The `file_patch` must be the one that produced this report.
+    pub fn is_independent_of_tail(&self, file_patch: &TextFilePatch, loaded_lines: usize) -> bool {
+        let mut last_hunk_offset = 0isize;
+        file_patch.hunks.iter().zip(self.hunk_reports.iter()).all(|(hunk, hunk_report)| {
+            let HunkApplyReport::Applied { line, offset, fuzz: 0 } = *hunk_report else {
+                // Failed or forced hunks might have matched somewhere in the
+                // tail, fuzzy ones might have matched there with less fuzz.
+                return false;
+            };
+
+            let hunk_view = hunk.view(self.direction, 0);
+            let independent = match hunk_view.position() {
+                HunkPosition::Start => true,
+                HunkPosition::End => false,
+                HunkPosition::Middle => {
+                    // The search starts at the target line moved by the offset
+                    // of the previous hunk and goes both ways, so every line up
+                    // to as far after it as the match is from it was tried.
+                    let target_line = hunk_view.remove_target_line().saturating_add_signed(last_hunk_offset);
+                    target_line.saturating_add(target_line.abs_diff(line)).saturating_add(hunk_view.remove_content().len()) <= loaded_lines
+                }
+            };
+            last_hunk_offset = offset;
+            independent
+        })
+    }
+
+    /// Where every hunk was applied, or `None` if any hunk failed or was
+    /// forced.
+    pub fn hunk_matches(&self) -> Option<Vec<HunkMatch>> {
+        self.hunk_reports.iter().map(|hunk_report| match *hunk_report {
+            HunkApplyReport::Applied { line, fuzz, .. } => Some(HunkMatch { line, fuzz }),
+            _ => None,
+        }).collect()
+    }
+
+    /// What the applied and forced hunks changed, sorted by the position in
+    /// the file: the index of the hunk, the range of lines it replaced in the
+    /// file as it was before the patch and the number of lines that replaced
+    /// them.
+    ///
+    /// The `file_patch` must be the one that produced this report.
+    pub fn replaced_lines(&self, file_patch: &TextFilePatch<'a>) -> Vec<(usize, Range<usize>, usize)> {
+        self.replacements(&file_patch.hunks).into_iter()
+            .map(|(hunk_index, range, _, add_range)| (hunk_index, range, add_range.len()))
+            .collect()
+    }
+
+    /// The changes of the applied and forced `hunks`, sorted by the position
+    /// in the file. That is the order of the hunks, unless they were applied
+    /// with `unordered_hunks`.
+    fn replacements<'hunk>(&self, hunks: &'hunk [TextHunk<'a>]) -> Vec<Replacement<'a, 'hunk>> {
+        let mut replacements: Vec<_> = hunks.iter().zip(self.hunk_reports.iter()).enumerate()
+            .filter_map(|(hunk_index, (hunk, hunk_report))| {
+                let (range, hunk_view, add_range) = hunk_report.replacement(hunk, self.direction)?;
+                Some((hunk_index, range, hunk_view, add_range))
+            })
+            .collect();
+        // Stable, so hunks that insert lines at the same place stay in order.
+        replacements.sort_by_key(|(_, range, _, _)| (range.start, range.end));
+        replacements
+    }
+
     /// Get the reports for the individual hunks.
-    pub fn hunk_reports(&self) -> &[HunkApplyReport] { &self.hunk_reports }
+    pub fn hunk_reports(&self) -> &[HunkApplyReport<'a>] { &self.hunk_reports }
 
     /// Direction that was used to apply this patch
     pub fn direction(&self) -> PatchDirection {
@@ -483,6 +800,11 @@ pub struct FilePatch<'a, Line> {
     #[builder(default)]
     new_filename: Option<Cow<'a, Path>>,
 
+    /// The filename from the "Index:" line of old CVS and SVN diffs, if
+    /// there was any.
+    #[builder(default)]
+    index_filename: Option<Cow<'a, Path>>,
+
     #[builder(default)]
     is_rename: bool,
 
@@ -511,6 +833,17 @@ impl<'a, Line> FilePatch<'a, Line> {
 
     pub fn old_filename(&self) -> Option<&Cow<'a, Path>> { self.old_filename.as_ref() }
     pub fn new_filename(&self) -> Option<&Cow<'a, Path>> { self.new_filename.as_ref() }
+    pub fn index_filename(&self) -> Option<&Cow<'a, Path>> { self.index_filename.as_ref() }
+
+    /// Patch the file named in the "Index:" line instead of the old and new
+    /// filename, e.g. if those are not found. Does nothing if there was no
+    /// "Index:" line.
+    pub fn use_index_filename(&mut self) {
+        if let Some(index_filename) = &self.index_filename {
+            self.old_filename = Some(index_filename.clone());
+            self.new_filename = Some(index_filename.clone());
+        }
+    }
 
     #[allow(dead_code)]
     pub fn is_rename(&self) -> bool { self.is_rename }
@@ -525,7 +858,7 @@ impl<'a, Line> FilePatch<'a, Line> {
 
     pub fn hunks(&self) -> &[Hunk<'a, Line>] { &self.hunks }
 
-    /// Strip the leading path from the filename and new_filename
+    /// Strip the leading path from the filename, new_filename and index_filename
     pub fn strip(&mut self, strip: usize) {
         fn strip_path(path: &mut Cow<Path>, strip: usize) {
             match path {
@@ -551,6 +884,46 @@ impl<'a, Line> FilePatch<'a, Line> {
         if let Some(new_filename) = &mut self.new_filename {
             strip_path(new_filename, strip);
         }
+        if let Some(index_filename) = &mut self.index_filename {
+            strip_path(index_filename, strip);
+        }
+    }
+
+    /// Prepend `old_prefix` to the old filename and `new_prefix` to the new
+    /// filename. This is the opposite of `strip`. If only one of them is
+    /// known (the file is created or deleted), it is used for both, like in
+    /// the "diff --git" line.
+    pub fn prefix(&mut self, old_prefix: &Path, new_prefix: &Path) {
+        let old_filename = self.old_filename.as_ref().or(self.new_filename.as_ref())
+            .map(|filename| Cow::Owned(old_prefix.join(filename)));
+        let new_filename = self.new_filename.as_ref().or(self.old_filename.as_ref())
+            .map(|filename| Cow::Owned(new_prefix.join(filename)));
+        self.old_filename = old_filename;
+        self.new_filename = new_filename;
+    }
+
+    /// Remove the `prefix` from the filenames that start with it, so they are
+    /// relative to it. Filenames that would become empty are kept.
+    pub fn strip_prefix(&mut self, prefix: &Path) {
+        for filename in self.old_filename.iter_mut().chain(self.new_filename.iter_mut()).chain(self.index_filename.iter_mut()) {
+            let relative = filename.strip_prefix(prefix).ok()
+                .filter(|relative| !relative.as_os_str().is_empty())
+                .map(Path::to_path_buf);
+            if let Some(relative) = relative {
+                *filename = Cow::Owned(relative);
+            }
+        }
+    }
+
+    /// Replace the old and new filename with the ones that `replace` returns
+    /// for them. The filenames it returns `None` for are kept.
+    pub fn replace_filenames<E, F: FnMut(&Path) -> Result<Option<PathBuf>, E>>(&mut self, mut replace: F) -> Result<(), E> {
+        for filename in self.old_filename.iter_mut().chain(self.new_filename.iter_mut()) {
+            if let Some(replaced) = replace(filename)? {
+                *filename = Cow::Owned(replaced);
+            }
+        }
+        Ok(())
     }
 
     /// Return the maximum fuzz that can be applied to this file patch. Applying
@@ -564,19 +937,151 @@ pub type TextFilePatch<'a> = FilePatch<'a, &'a [u8]>;
 pub type TextFilePatchBuilder<'a> = FilePatchBuilder<'a, &'a [u8]>;
 
 impl<'a> TextFilePatch<'a> {
+    /// How many lines from the start of the file should be loaded to apply
+    /// this patch, if its hunks apply close to where they expect. Returns
+    /// `None` if the whole file is needed. Whether the loaded lines were
+    /// really enough can be checked with `FilePatchApplyReport::is_independent_of_tail`.
+    pub fn lines_needed(&self, direction: PatchDirection) -> Option<usize> {
+        if self.kind != FilePatchKind::Modify {
+            // Creating or deleting compares the whole content
+            return None;
+        }
+
+        let mut needed = 0usize;
+        for hunk in &self.hunks {
+            let hunk_view = hunk.view(direction, 0);
+            if hunk_view.position() == HunkPosition::End {
+                return None;
+            }
+            needed = max(needed, hunk_view.remove_target_line().saturating_add(hunk_view.remove_content().len()));
+        }
+
+        // Leave room for the hunks to move forward as much as they can move back
+        Some(needed.saturating_mul(2))
+    }
+
+    /// A copy of this patch with the line endings of the `modified_file`, for
+    /// hand-edited patches that mix "\n" and "\r\n" between hunks. Every hunk
+    /// whose lines end differently than the lines of the file where it is
+    /// expected gets the ending of those lines on both sides, so the matched
+    /// lines are equal and the added lines end like their surroundings. The
+    /// new lines are put into the `store`. Returns `None` if no hunk differs.
+    pub fn with_line_endings_of(&self,
+                                modified_file: &ModifiedFile,
+                                direction: PatchDirection,
+                                store: &mut dyn FnMut(Vec<u8>) -> &'a [u8])
+                                -> Option<Self>
+    {
+        fn line_ending(line: &[u8]) -> Option<&'static [u8]> {
+            if line.ends_with(b"\r\n") {
+                Some(b"\r\n")
+            } else if line.ends_with(b"\n") {
+                Some(b"\n")
+            } else {
+                None
+            }
+        }
+
+        if self.kind != FilePatchKind::Modify || modified_file.content.is_empty() {
+            return None;
+        }
+
+        let mut converted = self.clone();
+        let mut changed = false;
+        for hunk in &mut converted.hunks {
+            // The ending of the first file line that has one, from where the
+            // hunk is expected on.
+            let hunk_view = hunk.view(direction, 0);
+            let start = min(hunk_view.remove_target_line(), modified_file.content.len() - 1);
+            let Some(file_ending) = modified_file.content[start..].iter()
+                .chain(modified_file.content[..start].iter().rev())
+                .find_map(|line| line_ending(line)) else {
+                continue;
+            };
+
+            let differs = hunk_view.remove_content().iter()
+                .any(|line| line_ending(line).is_some_and(|ending| ending != file_ending));
+            if !differs {
+                continue;
+            }
+
+            let mut data = Vec::new();
+            let mut lengths = Vec::new();
+            for line in hunk.remove.content.iter().chain(&hunk.add.content) {
+                let length = match line_ending(line) {
+                    Some(ending) => line.len() - ending.len(),
+                    None => line.len(),
+                };
+                data.extend_from_slice(&line[..length]);
+                if length != line.len() {
+                    data.extend_from_slice(file_ending);
+                }
+                lengths.push(data.len());
+            }
+
+            let mut data = store(data);
+            let mut end = 0;
+            let lines = hunk.remove.content.iter_mut().chain(hunk.add.content.iter_mut());
+            for (line, length) in lines.zip(lengths) {
+                let (new_line, rest) = data.split_at(length - end);
+                *line = new_line;
+                data = rest;
+                end = length;
+            }
+            changed = true;
+        }
+
+        changed.then_some(converted)
+    }
+
     /// Apply (or revert - based on `direction`) this patch to the `modified_file` using the given `max_fuzz`.
+    ///
+    /// Like in patch, every hunk is tried without fuzz first and then with
+    /// fuzz 1, 2 and so on up to `max_fuzz`, so its report has the least fuzz
+    /// it needed. A hunk applied exactly has fuzz 0.
+    ///
+    /// Hunks are searched for at most `max_offset` lines away from their
+    /// expected position, or in the whole file if it is `None`.
+    ///
+    /// If `strict_ambiguity` is true, hunks that match equally far before and
+    /// after their expected position fail with
+    /// `HunkApplyFailureReason::AmbiguousMatch`, otherwise the match after it
+    /// is used.
+    ///
+    /// If `unordered_hunks` is true, a hunk may also apply before the previous
+    /// hunks, as long as the lines it changes do not overlap theirs. Otherwise
+    /// it fails with `HunkApplyFailureReason::MisorderedHunks`, like in patch.
+    ///
+    /// If `force` is true, hunks that do not match anywhere are written at
+    /// their expected position anyway, replacing whatever content is there.
+    ///
+    /// If the `deadline` passes before all hunks are matched, the remaining
+    /// hunks fail with `HunkApplyFailureReason::TimedOut`.
+    ///
+    /// File patches above the `batch_threshold` are applied in batch, see
+    /// `apply_modify`.
+    #[allow(clippy::too_many_arguments)]
     pub fn apply(&self,
                  modified_file: &mut ModifiedFile<'a>,
                  direction: PatchDirection,
                  max_fuzz: usize,
+                 max_offset: Option<usize>,
+                 strict_ambiguity: bool,
+                 unordered_hunks: bool,
+                 force: bool,
+                 deadline: Option<Instant>,
+                 batch_threshold: BatchThreshold,
                  analyses: &AnalysisSet,
                  fn_analysis_note: &dyn Fn(&dyn Note, &TextFilePatch))
-                 -> FilePatchApplyReport
+                 -> FilePatchApplyReport<'a>
     {
         // Call the appropriate specialized function
         let mut report = match (self.kind, direction) {
-            (FilePatchKind::Modify, _) =>
-                self.apply_modify(modified_file, direction, max_fuzz, analyses, fn_analysis_note),
+            (FilePatchKind::Modify, _) => {
+                let batch = batch_threshold.applies_to(self.hunks.len(), modified_file);
+                self.apply_modify(modified_file, direction, max_fuzz, max_offset, strict_ambiguity, unordered_hunks, force,
+                                  deadline, batch, analyses, fn_analysis_note)
+            }
 
             (FilePatchKind::Create, PatchDirection::Forward) |
             (FilePatchKind::Delete, PatchDirection::Revert) =>
@@ -587,7 +1092,83 @@ impl<'a> TextFilePatch<'a> {
                 self.apply_delete(modified_file, direction, max_fuzz),
         };
 
-        // Determine the new file mode and record the previous one
+        self.change_permissions(modified_file, direction, &mut report);
+        report
+    }
+
+    /// Apply (or revert - based on `direction`) this `FilePatchKind::Modify`
+    /// patch to the `modified_file` with every hunk at the line and fuzz from
+    /// `hunk_matches`, e.g. where they matched when the patch was applied to
+    /// the same file before. Nothing is searched for.
+    ///
+    /// The positions are checked as if the hunks were found there by `apply`
+    /// with the given `max_fuzz` and `max_offset`. If any hunk does not match
+    /// at its position, `None` is returned and the file is not touched.
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_at(&self,
+                    modified_file: &mut ModifiedFile<'a>,
+                    direction: PatchDirection,
+                    max_fuzz: usize,
+                    max_offset: Option<usize>,
+                    hunk_matches: &[HunkMatch],
+                    batch_threshold: BatchThreshold,
+                    analyses: &AnalysisSet,
+                    fn_analysis_note: &dyn Fn(&dyn Note, &TextFilePatch))
+                    -> Option<FilePatchApplyReport<'a>>
+    {
+        if self.kind != FilePatchKind::Modify || modified_file.deleted || hunk_matches.len() != self.hunks.len() {
+            return None;
+        }
+
+        let mut report = FilePatchApplyReport::new_with_capacity(direction, max_fuzz, self.hunks.len());
+        let mut last_hunk_offset = 0isize;
+        let mut min_modify_line = 0;
+
+        for (hunk, &HunkMatch { line, fuzz }) in self.hunks.iter().zip(hunk_matches) {
+            if fuzz > min(max_fuzz, hunk.max_useable_fuzz()) {
+                return None;
+            }
+
+            let hunk_view = &hunk.view(direction, fuzz);
+            let remove_content = hunk_view.remove_content();
+            let (target_line, movable) = hunk_target_line(hunk_view, modified_file, last_hunk_offset);
+            let distance = line.abs_diff(target_line);
+            if (!movable && distance != 0) || max_offset.is_some_and(|max_offset| distance > max_offset) {
+                return None;
+            }
+            if line.saturating_add(hunk_view.prefix_context()) < min_modify_line {
+                return None;
+            }
+            if modified_file.content.get(line..line.saturating_add(remove_content.len())) != Some(remove_content) {
+                return None;
+            }
+
+            // Same as in `try_apply_hunk`
+            let orig_line = hunk_view.remove_target_line();
+            let offset = if line >= orig_line {
+                0isize.saturating_add_unsigned(line - orig_line)
+            } else {
+                0isize.saturating_sub_unsigned(orig_line - line)
+            };
+
+            last_hunk_offset = offset;
+            min_modify_line = line + remove_content.len() - hunk_view.suffix_context();
+            report.push_hunk_report(HunkApplyReport::Applied { line, offset, fuzz });
+        }
+
+        let batch = batch_threshold.applies_to(self.hunks.len(), modified_file);
+        self.commit_report(modified_file, direction, &report, batch, analyses, fn_analysis_note);
+        self.change_permissions(modified_file, direction, &mut report);
+        Some(report)
+    }
+
+    /// Set the file mode the patch changes the `modified_file` to and record
+    /// the previous one in the `report`.
+    fn change_permissions(&self,
+                          modified_file: &mut ModifiedFile<'a>,
+                          direction: PatchDirection,
+                          report: &mut FilePatchApplyReport<'a>)
+    {
         let change_permissions_to = match direction {
             PatchDirection::Forward => &self.new_permissions,
             PatchDirection::Revert => &self.old_permissions,
@@ -599,8 +1180,6 @@ impl<'a> TextFilePatch<'a> {
             None =>
                 modified_file.permissions.clone(),
         };
-
-        report
     }
 
     /// Apply this `FilePatchKind::Create` patch on the file.
@@ -608,7 +1187,7 @@ impl<'a> TextFilePatch<'a> {
                     modified_file: &mut ModifiedFile<'a>,
                     direction: PatchDirection,
                     max_fuzz: usize)
-                    -> FilePatchApplyReport
+                    -> FilePatchApplyReport<'a>
     {
         assert!(self.hunks.len() == 1);
 
@@ -634,7 +1213,7 @@ impl<'a> TextFilePatch<'a> {
                     modified_file: &mut ModifiedFile,
                     direction: PatchDirection,
                     max_fuzz: usize)
-                    -> FilePatchApplyReport
+                    -> FilePatchApplyReport<'a>
     {
         assert!(self.hunks.len() == 1);
 
@@ -662,16 +1241,42 @@ impl<'a> TextFilePatch<'a> {
     }
 
     /// Apply this `FilePatchKind::Modify` patch on the file.
-    fn apply_modify(&self,
+    ///
+    /// If `batch` is true, the hunks are first searched for in parallel,
+    /// speculating that no hunk before them moved, and all changes are
+    /// committed in one pass. The result is the same either way, `apply` uses
+    /// it for file patches with many hunks in big files.
+    #[allow(clippy::too_many_arguments)] // Same as `apply`.
+    pub(crate) fn apply_modify(&self,
                     modified_file: &mut ModifiedFile<'a>,
                     direction: PatchDirection,
                     max_fuzz: usize,
+                    max_offset: Option<usize>,
+                    strict_ambiguity: bool,
+                    unordered_hunks: bool,
+                    force: bool,
+                    deadline: Option<Instant>,
+                    batch: bool,
                     analyses: &AnalysisSet,
                     fn_analysis_note: &dyn Fn(&dyn Note, &TextFilePatch))
-                    -> FilePatchApplyReport
+                    -> FilePatchApplyReport<'a>
     {
         let mut report = FilePatchApplyReport::new_with_capacity(direction, max_fuzz, self.hunks.len());
 
+        // The results of trying every hunk at fuzz 0 on the unmodified file,
+        // with the line where it was tried.
+        let speculations: Vec<(usize, HunkApplyReport<'static>)> = if batch && !modified_file.deleted {
+            let modified_file = &*modified_file;
+            self.hunks.par_iter().map(|hunk| {
+                let hunk_view = &hunk.view(direction, 0);
+                let (target_line, movable) = hunk_target_line(hunk_view, modified_file, 0);
+                (target_line, try_apply_hunk(hunk_view, modified_file, target_line, movable,
+                                             0, max_offset, strict_ambiguity, deadline))
+            }).collect()
+        } else {
+            Vec::new()
+        };
+
         let mut last_hunk_offset = 0isize;
 
         // This adds artificial limitation on ordering of hunks to replicate the behavior of patch.
@@ -680,10 +1285,22 @@ impl<'a> TextFilePatch<'a> {
         // don't accept more patches than patch. After all, if we accept hunks in arbitrary order,
         // it is not well defined if they should match against the file before or after
         // modifications from the previous hunks.
+        //
+        // With `unordered_hunks`, hunks are matched against the file before the
+        // modifications and only must not change the same lines, so the lines
+        // they change are remembered instead. `min_modify_line` is then only
+        // used to place forced hunks after all others.
         let mut min_modify_line = 0;
+        let mut changed_ranges: Vec<Range<usize>> = Vec::new();
+
+        for (hunk_index, hunk) in self.hunks.iter().enumerate() {
+            // Once we run out of time, do not even try the remaining hunks.
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                report.push_hunk_report(HunkApplyReport::Failed(HunkApplyFailureReason::TimedOut));
+                continue;
+            }
 
-        for hunk in self.hunks.iter() {
-            let mut hunk_report: Option<HunkApplyReport> = None;
+            let mut hunk_report: Option<HunkApplyReport<'a>> = None;
 
             // Consider fuzz 0 up to given maximum fuzz, but no more than what is useable for this hunk
             for current_fuzz in 0..=min(max_fuzz, hunk.max_useable_fuzz()) {
@@ -691,47 +1308,91 @@ impl<'a> TextFilePatch<'a> {
                 let hunk_view = &hunk.view(direction, current_fuzz);
 
 		let remove_content = hunk_view.remove_content();
-		let (target_line, movable) = match hunk_view.position() {
-		    HunkPosition::Start =>
-			(0, false),
-
-		    // man patch:
-		    // "As a first guess, [patch] takes the line number
-		    // mentioned for the hunk, plus or minus any offset
-		    // used in applying the previous hunk.."
-		    HunkPosition::Middle =>
-			(hunk_view.remove_target_line().saturating_add_signed(last_hunk_offset), true),
-
-		    HunkPosition::End =>
-			(modified_file.content.len() - remove_content.len(), false),
-		};
+		let (target_line, movable) = hunk_target_line(hunk_view, modified_file, last_hunk_offset);
 
-                hunk_report = Some(try_apply_hunk(hunk_view, modified_file,
-						  target_line, movable,
-						  min_modify_line));
+                let min_match_line = if unordered_hunks { 0 } else { min_modify_line };
+                let speculation = speculations.get(hunk_index).filter(|_| current_fuzz == 0);
+                hunk_report = Some(speculation
+                    .and_then(|speculation| reuse_speculation(speculation, hunk_view, target_line, min_match_line))
+                    .unwrap_or_else(|| try_apply_hunk(hunk_view, modified_file,
+                                                      target_line, movable,
+                                                      min_match_line, max_offset, strict_ambiguity, deadline)));
 
                 // If it succeeded, we are done with this hunk, remember the last_hunk_offset
                 // and min_modify_line, so we can use them for the next hunk and do not try
                 // any more fuzz levels.
                 if let Some(HunkApplyReport::Applied { line, offset, .. }) = hunk_report {
+                    let changed_range = (line + hunk_view.prefix_context())..(line + remove_content.len() - hunk_view.suffix_context());
+                    if unordered_hunks {
+                        if changed_ranges.iter().any(|other| ranges_overlap(other, &changed_range)) {
+                            hunk_report = Some(HunkApplyReport::Failed(HunkApplyFailureReason::MisorderedHunks));
+                            continue;
+                        }
+                        changed_ranges.push(changed_range.clone());
+                    }
                     last_hunk_offset = offset;
-                    min_modify_line = line + remove_content.len() - hunk_view.suffix_context();
+                    min_modify_line = max(min_modify_line, changed_range.end);
+                    break;
+                }
+
+                // Higher fuzz would only take more time. And it would not
+                // make an ambiguous match any less ambiguous.
+                if let Some(HunkApplyReport::Failed(HunkApplyFailureReason::TimedOut |
+                                                    HunkApplyFailureReason::AmbiguousMatch)) = hunk_report {
                     break;
                 }
             }
+
+            // If it did not match at all, write it anyway if we are asked to.
+            if force {
+                if let Some(HunkApplyReport::Failed(HunkApplyFailureReason::NoMatchingLines)) |
+                       Some(HunkApplyReport::Failed(HunkApplyFailureReason::MisorderedHunks)) = hunk_report
+                {
+                    let forced_report = force_apply_hunk(&hunk.view(direction, 0), modified_file,
+                                                         last_hunk_offset, min_modify_line);
+                    if let HunkApplyReport::Forced { line, ref replaced } = forced_report {
+                        min_modify_line = line + replaced.len();
+                        changed_ranges.push(line..min_modify_line);
+                    }
+                    hunk_report = Some(forced_report);
+                }
+            }
+
             report.push_hunk_report(hunk_report.expect("No fuzz has been considered!"));
         }
 
-        analyses.before_modifications(modified_file, self, direction, &report, fn_analysis_note);
+        self.commit_report(modified_file, direction, &report, batch, analyses, fn_analysis_note);
+        report
+    }
 
-        // Now commit all changes to the file in reverse order to preserve line numbers.
-        for (hunk, hunk_report) in self.hunks.iter().zip(report.hunk_reports.iter()).rev() {
-            hunk_report.commit(modified_file, hunk, direction);
+    /// Commit the changes of the hunks to the `modified_file` according to
+    /// the `report`, running the analyses before and after.
+    ///
+    /// Committing the hunks one by one moves the tail of the file with every
+    /// `Vec::splice`. With `batch`, `commit_hunks` instead builds new content
+    /// and copies every Line once. For a few hunks that is slower.
+    fn commit_report(&self,
+                     modified_file: &mut ModifiedFile<'a>,
+                     direction: PatchDirection,
+                     report: &FilePatchApplyReport<'a>,
+                     batch: bool,
+                     analyses: &AnalysisSet,
+                     fn_analysis_note: &dyn Fn(&dyn Note, &TextFilePatch))
+    {
+        analyses.before_modifications(modified_file, self, direction, report, fn_analysis_note);
+
+        let replacements = report.replacements(&self.hunks);
+        if batch {
+            commit_hunks(modified_file, &replacements);
+        } else {
+            // Now commit all changes to the file in reverse order to preserve line numbers.
+            for (_, range, hunk_view, add_range) in replacements.into_iter().rev() {
+                // Note: cloned just makes `&[u8]` out of `&&[u8]`, no real cloning here.
+                modified_file.content.splice(range, hunk_view.add_content()[add_range].iter().cloned());
+            }
         }
 
-        analyses.after_modifications(modified_file, self, direction, &report, fn_analysis_note);
-
-        report
+        analyses.after_modifications(modified_file, self, direction, report, fn_analysis_note);
     }
 
     /// Rollback the application (or the revertion - based on direction) of this patch from the `modified_file`
@@ -739,7 +1400,7 @@ impl<'a> TextFilePatch<'a> {
     pub fn rollback(&self,
                     modified_file: &mut ModifiedFile<'a>,
                     direction: PatchDirection,
-                    apply_report: &FilePatchApplyReport)
+                    apply_report: &FilePatchApplyReport<'a>)
     {
         assert!(self.hunks.len() == apply_report.hunk_reports().len());
 
@@ -787,7 +1448,7 @@ Apply report:
     fn rollback_delete(&self,
                     modified_file: &mut ModifiedFile<'a>,
                     direction: PatchDirection,
-                    apply_report: &FilePatchApplyReport)
+                    apply_report: &FilePatchApplyReport<'a>)
                     -> bool
     {
 	apply_report.failed() ||
@@ -798,7 +1459,7 @@ Apply report:
     fn rollback_create(&self,
                     modified_file: &mut ModifiedFile,
                     direction: PatchDirection,
-                    apply_report: &FilePatchApplyReport)
+                    apply_report: &FilePatchApplyReport<'a>)
                     -> bool
     {
 	apply_report.failed() ||
@@ -809,17 +1470,34 @@ Apply report:
     fn rollback_modify(&self,
                     modified_file: &mut ModifiedFile<'a>,
                     direction: PatchDirection,
-                    apply_report: &FilePatchApplyReport)
+                    apply_report: &FilePatchApplyReport<'a>)
                     -> bool
     {
 	let mut ok = true;
 
-        for (hunk, apply_hunk_report) in self.hunks.iter().zip(apply_report.hunk_reports.iter()) {
+        // In the order of the changes in the file, the line numbers of the
+        // ones after are still those of the original file then.
+        for (hunk_index, ..) in apply_report.replacements(&self.hunks) {
+            let hunk = &self.hunks[hunk_index];
+            let apply_hunk_report = &apply_report.hunk_reports[hunk_index];
             let (fuzz, rollback_line) =
 		match *apply_hunk_report {
                     // If the hunk applied, pick the specific fuzz level
                     HunkApplyReport::Applied { fuzz, line, .. } => (fuzz, line),
 
+                    // If the hunk was forced, just put back the original lines.
+                    HunkApplyReport::Forced { line, ref replaced } => {
+                        // Note: `direction` is already the opposite here, so the lines
+                        // written by the hunk are its "remove" part.
+                        let written = hunk.view(direction, 0).remove_content().len();
+                        if line + written > modified_file.content.len() {
+                            ok = false;
+                        } else {
+                            modified_file.content.splice(line..(line + written), replaced.iter().cloned());
+                        }
+                        continue;
+                    }
+
                     // If the hunk failed to apply, skip it now.
                     HunkApplyReport::Failed(..) => continue,
 		};
@@ -828,7 +1506,7 @@ Apply report:
             let hunk_view = HunkView::with_no_suffix(hunk, direction, fuzz);
 
             let hunk_report = try_apply_hunk(&hunk_view, modified_file,
-					     rollback_line, false, 0);
+					     rollback_line, false, 0, None, false, None);
 	    match hunk_report {
 		HunkApplyReport::Failed(..) => ok = false,
 		_ => hunk_report.commit(modified_file, hunk, direction),
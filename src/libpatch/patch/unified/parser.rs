@@ -8,8 +8,10 @@ use std::vec::Vec;
 
 use thiserror::Error;
 
+use crate::modified_file::UTF8_BOM;
 use crate::patch::*;
 use crate::patch::unified::*;
+use crate::util::{line_terminator, split_lines_with_endings};
 
 
 #[derive(Debug, PartialEq)]
@@ -98,6 +100,25 @@ pub enum ParseError {
     BadHash(String),
 }
 
+impl<'a> ErrorBuilder<'a> {
+    /// The part of the input that the error is about, if any.
+    fn input(&self) -> Option<&'a [u8]> {
+        match *self {
+            NoMatch | UnexpectedEndOfFile => None,
+            UnsupportedMetadata(input) |
+            MissingFilenameForHunk(input) |
+            UnexpectedEndOfLine(input) |
+            BadHunkHeader(input) |
+            BadLineInHunk(input) |
+            NumberTooBig(input) |
+            BadNumber(input) |
+            BadMode(input) |
+            BadSequence(input) |
+            BadHash(input) => Some(input),
+        }
+    }
+}
+
 impl From<ErrorBuilder<'_>> for ParseError {
     fn from(err: ErrorBuilder) -> Self {
         match err {
@@ -140,6 +161,16 @@ impl From<ErrorBuilder<'_>> for ParseError {
     }
 }
 
+/// A `ParseError` together with the line of the patch where it was found.
+#[derive(Debug, Error, PartialEq)]
+#[error("Line {line}: {error}")]
+pub struct LocatedParseError {
+    /// 1-based, counted from the start of the patch
+    pub line: usize,
+
+    pub error: ParseError,
+}
+
 /// Tests if `c` is an ASCII space or TAB.
 fn is_space(c: u8) -> bool {
     c == b' ' ||
@@ -176,7 +207,7 @@ fn parse_oct3(input: &[u8]) -> Option<u8> {
     {
         Some(((input[0] - b'0') << 6) |
              ((input[1] - b'0') << 3) |
-             ((input[2] - b'0')))
+             (input[2] - b'0'))
     } else { None }
 }
 
@@ -232,6 +263,9 @@ enum MetadataLine<'a> {
     MinusFilename(Filename<'a>),
     PlusFilename(Filename<'a>),
 
+    /// "Index: filename" of CVS and SVN diffs
+    IndexFilename(Filename<'a>),
+
     // ...?
 }
 
@@ -338,6 +372,9 @@ struct InputParser<'a> {
     input: &'a [u8],
     pos: usize,
     warnings: Vec<String>,
+
+    /// The byte that ends the lines, see `line_terminator`.
+    newline: u8,
 }
 
 impl<'a> InputParser<'a> {
@@ -346,6 +383,7 @@ impl<'a> InputParser<'a> {
             input,
             pos: 0,
 	    warnings: vec![],
+            newline: line_terminator(input),
         }
     }
 
@@ -374,7 +412,7 @@ impl<'a> InputParser<'a> {
 
     pub fn newline(&mut self) -> Result<&'a [u8], ErrorBuilder<'a>> {
         match self.peek_byte() {
-            Some(&b'\n') => Ok(self.take_n(1)),
+            Some(&c) if c == self.newline => Ok(self.take_n(1)),
             Some(_) => Err(NoMatch),
             None => Err(UnexpectedEndOfFile),
         }
@@ -383,7 +421,7 @@ impl<'a> InputParser<'a> {
     /// Takes a line from input. The newline byte is skipped but is not part
     /// of the parsed line.
     pub fn take_line_skip(&mut self) -> Result<&'a [u8], ErrorBuilder<'a>> {
-        match memchr::memchr(b'\n', self.remain()) {
+        match memchr::memchr(self.newline, self.remain()) {
             Some(index) => Ok(&self.take_n(index+1)[..index]),
             None => Err(UnexpectedEndOfFile),
         }
@@ -391,7 +429,7 @@ impl<'a> InputParser<'a> {
 
     /// Takes a line from input including the newline byte.
     pub fn take_line_incl(&mut self) -> Result<&'a [u8], ErrorBuilder<'a>> {
-        match memchr::memchr(b'\n', self.remain()) {
+        match memchr::memchr(self.newline, self.remain()) {
             Some(index) => Ok(self.take_n(index+1)),
             None => Err(UnexpectedEndOfFile),
         }
@@ -400,7 +438,7 @@ impl<'a> InputParser<'a> {
     /// Takes a line from input including the newline byte if present.
     /// It is OK if there is no newline.
     pub fn take_line_or_eof(&mut self) -> &'a [u8] {
-        self.take_n(match memchr::memchr(b'\n', self.remain()) {
+        self.take_n(match memchr::memchr(self.newline, self.remain()) {
             Some(index) => index+1,
             None => self.remain().len(),
         })
@@ -474,7 +512,7 @@ impl<'a> InputParser<'a> {
                     res.push(c);
                 }
                 b'\"' => return Ok(res),
-                b'\n' => return Err(UnexpectedEndOfLine(errloc)),
+                c if c == self.newline => return Err(UnexpectedEndOfLine(errloc)),
                 other => res.push(other),
             }
         }
@@ -576,6 +614,7 @@ impl<'a> InputParser<'a> {
         }
     }
 
+    #[allow(clippy::collapsible_match)] // The prefix checks consume input, keep them out of match guards.
     pub fn take_metadata_line(&mut self) -> Result<MetadataLine<'a>, ErrorBuilder<'a>> {
 	use MetadataLine::*;
 
@@ -603,6 +642,13 @@ impl<'a> InputParser<'a> {
                     return Ok(PlusFilename(filename));
                 }
             }
+            b'I' => {
+                if self.strip_prefix(b"Index: ").is_some() {
+                    let filename = self.take_filename()?;
+                    self.take_line_incl()?;
+                    return Ok(IndexFilename(filename));
+                }
+            }
             _ => {}
         }
         Err(NoMatch)
@@ -625,6 +671,7 @@ impl<'a> InputParser<'a> {
         }
     }
 
+    #[allow(clippy::collapsible_match)] // See above.
     pub fn take_git_metadata_line(&mut self) -> Result<GitMetadataLine<'a>, ErrorBuilder<'a>> {
 	use GitMetadataLine::*;
 
@@ -751,8 +798,8 @@ impl<'a> InputParser<'a> {
         self.strip_prefix(b" @")
             .ok_or(BadHunkHeader(errloc))?;
 
-        // Parse function if it is separated by " @@ "
-        let function = if self.strip_prefix(b"@ ").is_some() {
+        // Parse function if it is separated by " @@ " (or TAB)
+        let function = if self.strip_prefix(b"@ ").or_else(|| self.strip_prefix(b"@\t")).is_some() {
             self.take_line_skip()?
         } else {
             self.take_line_incl().map(|_| &b""[..])?
@@ -783,7 +830,12 @@ impl<'a> InputParser<'a> {
             // XXX: patch allows context lines starting with TAB character. That TAB is then part of the line.
             Some(b'\t') => (Context, self.rewind(startpos).take_line_incl()?),
             // XXX: patch allows completely empty line as an empty context line.
-            Some(b'\n') => (Context, &self.input[startpos..self.pos]),
+            Some(&c) if c == self.newline => (Context, &self.input[startpos..self.pos]),
+            // The same in a patch with "\r\n" line endings
+            Some(b'\r') if self.newline == b'\n' && self.peek_byte() == Some(&b'\n') => {
+                self.take_byte();
+                (Context, &self.input[startpos..self.pos])
+            }
             Some(_) =>
                 return Err(BadLineInHunk(&self.input[startpos..])),
             None =>
@@ -807,9 +859,12 @@ impl<'a> InputParser<'a> {
             .map_err(|err| if err == NoMatch { err }
                      else { BadHunkHeader(errloc) })?;
 
-        let mut hunk = Hunk::new(
-            header.remove_line.saturating_sub(1),
-            header.add_line.saturating_sub(1),
+        // An empty side is numbered by the line before it (e.g. "-20,0" is
+        // after line 20), which is where it goes when counting from 0.
+        let target_line = |line: usize, count: usize| if count == 0 { line } else { line.saturating_sub(1) };
+        let mut hunk: TextHunk = Hunk::new(
+            target_line(header.remove_line, header.remove_count),
+            target_line(header.add_line, header.add_count),
             header.function
         );
 
@@ -820,7 +875,20 @@ impl<'a> InputParser<'a> {
 
         while header.add_count > 0 || header.remove_count > 0 {
             let errloc = self.remain();
-            let (line_type, line) = self.take_hunk_line()?;
+
+            // XXX: patch assumes that up to two empty context lines at the end
+            // of the patch were chopped off, e.g. by an editor.
+            let chopped = errloc.is_empty() && header.add_count == header.remove_count && header.add_count < 3;
+            let (line_type, line) = if chopped {
+                let empty_line: &[u8] = match hunk.remove.content.last() {
+                    Some(line) if line.ends_with(b"\r\n") => b"\r\n",
+                    _ if self.newline == b'\r' => b"\r",
+                    _ => b"\n",
+                };
+                (HunkLineType::Context, empty_line)
+            } else {
+                self.take_hunk_line()?
+            };
 
             match line_type {
                 HunkLineType::Add => {
@@ -864,6 +932,16 @@ impl<'a> InputParser<'a> {
             }
         }
 
+        // A single line at the start without newline is the whole file. If the
+        // file has old Mac line endings, diff did not split it, but we do.
+        for side in [&mut hunk.remove, &mut hunk.add] {
+            if let [line] = side.content[..] {
+                if side.target_line == 0 && line_terminator(line) == b'\r' {
+                    side.content = split_lines_with_endings(line).collect();
+                }
+            }
+        }
+
         Ok(hunk)
     }
 
@@ -922,9 +1000,9 @@ impl<'a> InputParser<'a> {
                     } else {
 			let endpos = self.pos;
                         if let Ok(HunkHeader { .. }) = self.rewind(linestart).take_hunk_header() {
-                            self.warnings.push(format!("Possibly ignored hunk: {}", String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line))));
+                            self.warnings.push(format!("Possibly ignored hunk: {}", String::from_utf8_lossy(line.strip_suffix(&[self.newline]).unwrap_or(line))));
                         }
-                        if memchr::memchr(b'\n', line).is_none() {
+                        if memchr::memchr(self.newline, line).is_none() {
                             self.warnings.push("Patch unexpectedly ends in the middle of a line.".to_string());
                         }
 			self.rewind(endpos);
@@ -973,6 +1051,9 @@ impl<'a> InputParser<'a> {
                 Metadata(MinusFilename(filename)) => {
                     metadata.old_filename = Some(filename);
                 }
+                Metadata(IndexFilename(filename)) => {
+                    metadata.index_filename = Some(filename);
+                }
 
                 GitMetadata(Index(old_hash, new_hash, _)) => {
                     metadata.old_hash = Some(old_hash);
@@ -1167,6 +1248,7 @@ fn test_parse_metadata_line() {
 
     assert_parsed!(parse_metadata_line, b"--- aaa\n", MinusFilename(Filename::Real(Cow::Owned(PathBuf::from("aaa")))));
     assert_parsed!(parse_metadata_line, b"+++ aaa\n", PlusFilename(Filename::Real(Cow::Owned(PathBuf::from("aaa")))));
+    assert_parsed!(parse_metadata_line, b"Index: aaa\n", IndexFilename(Filename::Real(Cow::Owned(PathBuf::from("aaa")))));
 
     // Filename with date
     assert_parsed!(parse_metadata_line, b"--- a/bla/ble.c	2013-09-23 18:41:09.000000000 -0400\n", MinusFilename(Filename::Real(Cow::Owned(PathBuf::from("a/bla/ble.c")))));
@@ -1388,6 +1470,7 @@ fn test_parse_hunk_header() {
         function: s!(b"function name"),
     };
     assert_parsed!(parse_hunk_header, b"@@ -1,2 +3,4 @@ function name\n", h3);
+    assert_parsed!(parse_hunk_header, b"@@ -1,2 +3,4 @@\tfunction name\n", h3);
 
     let h4 = HunkHeader {
         add_line: 3,
@@ -1416,7 +1499,8 @@ fn test_parse_hunk_header() {
 #[cfg(test)]
 #[test]
 fn test_parse_hunk_line() {
-    fn parse_hunk_line(input: &[u8]) -> Result<(&[u8], (HunkLineType, &[u8])), ErrorBuilder<'_>> {
+    type HunkLineResult<'a> = Result<(&'a [u8], (HunkLineType, &'a [u8])), ErrorBuilder<'a>>;
+    fn parse_hunk_line(input: &[u8]) -> HunkLineResult<'_> {
         let mut parser = InputParser::new(input);
         parser.take_hunk_line().map(|result| (parser.remain(), result))
     }
@@ -1528,6 +1612,41 @@ xxxxx
                         ParseError::BadHunkHeader("@@ - invalid".to_string()));
 }
 
+#[cfg(test)]
+#[test]
+fn test_parse_hunk_zero_context() {
+    use crate::patch::unified::writer::UnifiedPatchHunkHeaderWriter;
+
+    fn parse_hunk(input: &[u8]) -> TextHunk<'_> {
+        InputParser::new(input).take_hunk().unwrap()
+    }
+
+    fn header(hunk: &TextHunk) -> String {
+        let mut output = Vec::new();
+        hunk.write_header_to(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    // Added after line 20, like `diff -U0` writes it
+    let h = parse_hunk(b"@@ -20,0 +21,2 @@\n+aaa\n+bbb\n");
+    assert_eq!(h.remove.target_line, 20);
+    assert_eq!(h.add.target_line, 20);
+    assert_eq!((h.prefix_context, h.suffix_context), (0, 0));
+    assert_eq!(header(&h), "@@ -20,0 +21,2 @@");
+
+    // Removed lines 8 and 9, after line 7 of the new file
+    let h = parse_hunk(b"@@ -8,2 +7,0 @@\n-aaa\n-bbb\n");
+    assert_eq!(h.remove.target_line, 7);
+    assert_eq!(h.add.target_line, 7);
+    assert_eq!(header(&h), "@@ -8,2 +7,0 @@");
+
+    // Created file
+    let h = parse_hunk(b"@@ -0,0 +1 @@\n+aaa\n");
+    assert_eq!(h.remove.target_line, 0);
+    assert_eq!(h.add.target_line, 0);
+    assert_eq!(header(&h), "@@ -0,0 +1,1 @@");
+}
+
 #[cfg(test)]
 #[test]
 fn test_parse_hunks() {
@@ -1564,6 +1683,7 @@ Other content.
 struct FilePatchMetadata<'a> {
     old_filename: Option<Filename<'a>>,
     new_filename: Option<Filename<'a>>,
+    index_filename: Option<Filename<'a>>,
     rename_from: bool,
     rename_to: bool,
     old_permissions: Option<Permissions>,
@@ -1574,7 +1694,7 @@ struct FilePatchMetadata<'a> {
 
 impl<'a> FilePatchMetadata<'a> {
     pub fn have_filename(&self) -> bool {
-        self.old_filename.is_some() || self.new_filename.is_some()
+        self.old_filename.is_some() || self.new_filename.is_some() || self.index_filename.is_some()
     }
 
     pub fn recognize_kind(&self, hunks: &[TextHunk]) -> FilePatchKind {
@@ -1608,39 +1728,47 @@ impl<'a> FilePatchMetadata<'a> {
         // Set the kind
         let builder = builder.kind(self.recognize_kind(&hunks));
 
-        // Set the filenames
-        let has_old_filename = matches!(self.old_filename, Some(Filename::Real(_)));
-        let has_new_filename = matches!(self.new_filename, Some(Filename::Real(_)));
+        // Move out the filenames
+        let old_filename = match self.old_filename {
+            Some(Filename::Real(old_filename)) => Some(old_filename),
+            _ => None,
+        };
+        let new_filename = match self.new_filename {
+            Some(Filename::Real(new_filename)) => Some(new_filename),
+            _ => None,
+        };
+        let index_filename = match self.index_filename {
+            Some(Filename::Real(index_filename)) => Some(index_filename),
+            _ => None,
+        };
+
+        // Old diffs may name the file only in the "Index:" line, it is used
+        // if neither the "---" nor the "+++" line names a file.
+        let (old_filename, new_filename) = match (old_filename, new_filename, &index_filename) {
+            (None, None, Some(index_filename)) => (Some(index_filename.clone()), Some(index_filename.clone())),
+            (old_filename, new_filename, _) => (old_filename, new_filename),
+        };
 
         let builder = if self.rename_from && self.rename_to {
             // If it is renaming patch, we must have both filenames
-            if !has_old_filename || !has_new_filename {
+            if old_filename.is_none() || new_filename.is_none() {
                 return None;
             }
 
             builder.is_rename(true)
         } else {
             // If it is non-renaming patch, we must have at least one filename
-            if !has_old_filename && !has_new_filename {
+            if old_filename.is_none() && new_filename.is_none() {
                 return None;
             }
 
             builder
         };
 
-        // Move out the filenames
-        let old_filename = match self.old_filename {
-            Some(Filename::Real(old_filename)) => Some(old_filename),
-            _ => None,
-        };
-        let new_filename = match self.new_filename {
-            Some(Filename::Real(new_filename)) => Some(new_filename),
-            _ => None,
-        };
-
         let builder = builder
             .old_filename(old_filename)
             .new_filename(new_filename)
+            .index_filename(index_filename)
 
             // Set the permissions
             .old_permissions(self.old_permissions)
@@ -2055,6 +2183,45 @@ garbage3
             panic!("Got unexpected success when parsing patch with missing filenames!");
         }
     }
+
+
+    // Filepatch with the filename only in the "Index:" line
+    let filepatch_txt = br#"Index: dir/filename1
+===================================================================
+@@ -200,3 +210,3 @@ place2
+ aaa
+-bbb
++ccc
+ ddd
+"#;
+
+    let (_header, file_patch, warnings) = parse_filepatch(filepatch_txt, false).unwrap();
+    assert!(warnings.is_empty());
+    assert_eq!(file_patch.kind(), FilePatchKind::Modify);
+    assert_eq!(file_patch.old_filename(), Some(&Cow::Owned(PathBuf::from("dir/filename1"))));
+    assert_eq!(file_patch.new_filename(), Some(&Cow::Owned(PathBuf::from("dir/filename1"))));
+    assert_eq!(file_patch.index_filename(), Some(&Cow::Owned(PathBuf::from("dir/filename1"))));
+    assert_eq!(file_patch.hunks.len(), 1);
+
+
+    // CVS filepatch, the "---" and "+++" lines win over the "Index:" line
+    let filepatch_txt = br#"Index: dir/filename1
+===================================================================
+RCS file: /cvsroot/dir/filename1,v
+--- filename1	12 Mar 2003 10:00:00 -0000	1.2
++++ filename1	14 Mar 2003 09:30:00 -0000
+@@ -200,3 +210,3 @@ place2
+ aaa
+-bbb
++ccc
+ ddd
+"#;
+
+    let (_header, file_patch, warnings) = parse_filepatch(filepatch_txt, false).unwrap();
+    assert!(warnings.is_empty());
+    assert_eq!(file_patch.old_filename(), Some(&Cow::Owned(PathBuf::from("filename1"))));
+    assert_eq!(file_patch.new_filename(), Some(&Cow::Owned(PathBuf::from("filename1"))));
+    assert_eq!(file_patch.index_filename(), Some(&Cow::Owned(PathBuf::from("dir/filename1"))));
 }
 
 #[cfg(unix)]
@@ -2140,7 +2307,16 @@ new mode 100755
     assert_eq!(file_patch.hunks.len(), 0);
 }
 
+/// Parse the patch in the `bytes` and strip `strip` components from its
+/// filenames. A UTF-8 byte order mark at the start, as some editors save it,
+/// is skipped.
 pub fn parse_patch(bytes: &[u8], strip: usize) -> Result<TextPatch<'_>, ParseError> {
+    parse_patch_located(bytes, strip).map_err(|err| err.error)
+}
+
+/// Like `parse_patch`, but the error tells the line where it was found.
+pub fn parse_patch_located(bytes: &[u8], strip: usize) -> Result<TextPatch<'_>, LocatedParseError> {
+    let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
     let mut parser = InputParser::new(bytes);
 
     let mut wants_header = true;
@@ -2158,7 +2334,18 @@ pub fn parse_patch(bytes: &[u8], strip: usize) -> Result<TextPatch<'_>, ParseErr
 
             // Actual error
             Err(err) => {
-                return Err(ParseError::from(err));
+                // The line of the input it is about, or where the parser
+                // stopped, e.g. at the end of a truncated hunk.
+                let offset = err.input()
+                    .and_then(|input| (input.as_ptr() as usize).checked_sub(bytes.as_ptr() as usize))
+                    .filter(|&offset| offset <= bytes.len())
+                    .unwrap_or(parser.pos.min(bytes.len()));
+                let before = &bytes[..offset];
+                let mut line = memchr::memchr_iter(parser.newline, before).count() + 1;
+                if offset == bytes.len() && before.last() == Some(&parser.newline) {
+                    line -= 1;
+                }
+                return Err(LocatedParseError { line, error: ParseError::from(err) });
             }
         };
 
@@ -2364,8 +2551,7 @@ index 0123456789ab..cdefedcba987 100644
 garbage with no EOL"#;
 
     let patch = parse_patch(filepatch_txt, 0).unwrap();
-    assert!(patch.warnings.iter().fold(false, |found, item|
-				       found || item.contains("unexpectedly ends")));
+    assert!(patch.warnings.iter().any(|item| item.contains("unexpectedly ends")));
 
     // An extra line with a single space between hunks terminates the patch.
     // See issue #25
@@ -2386,8 +2572,53 @@ garbage with no EOL"#;
 "#;
 
     let patch = parse_patch(filepatch_txt, 0).unwrap();
-    assert!(patch.warnings.iter().fold(false, |found, item|
-				       found || item.contains("ignored hunk")));
+    assert!(patch.warnings.iter().any(|item| item.contains("ignored hunk")));
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_patch_located() {
+    // Malformed hunk header in the second file patch
+    let patch_txt = br#"garbage1
+--- filename1
++++ filename1
+@@ -200,3 +210,3 @@ place1
+ mmm
+-nnn
++ooo
+ ppp
+--- filename2
++++ filename2
+@@ -200,x +210,3 @@ place2
+ aaa
+"#;
+
+    assert_eq!(parse_patch_located(patch_txt, 0).unwrap_err(),
+               LocatedParseError { line: 11, error: ParseError::BadHunkHeader("@@ -200,x +210,3 @@ place2".to_string()) });
+
+    // Bad line in the middle of a hunk
+    let patch_txt = br#"--- filename1
++++ filename1
+@@ -1,3 +1,3 @@
+ aaa
+-bbb
+xxx
+ ccc
+"#;
+
+    assert_eq!(parse_patch_located(patch_txt, 0).unwrap_err(),
+               LocatedParseError { line: 6, error: ParseError::BadLineInHunk("xxx".to_string()) });
+
+    // Truncated hunk, it ends with the last line
+    let patch_txt = br#"--- filename1
++++ filename1
+@@ -1,3 +1,3 @@
+ aaa
+-bbb
+"#;
+
+    assert_eq!(parse_patch_located(patch_txt, 0).unwrap_err(),
+               LocatedParseError { line: 5, error: ParseError::UnexpectedEndOfFile });
 }
 
 #[cfg(test)]
@@ -27,14 +27,15 @@ impl<Line> UnifiedPatchHunkHeaderWriter for Hunk<'_, Line> {
         let add_count = self.add.content.len();
         let remove_count = self.remove.content.len();
 
+        // An empty side is numbered by the line before it
         let add_line = if add_count == 0 {
-            0
+            self.add.target_line
         } else {
             self.add.target_line + 1
         };
 
         let remove_line = if remove_count == 0 {
-            0
+            self.remove.target_line
         } else {
             self.remove.target_line + 1
         };
@@ -120,10 +121,15 @@ fn write_file_patch_header_to<'a, W: Write>(filepatch: &FilePatch<'a, &'a [u8]>,
     // The "diff --git" line always seem to have real filenames, never "/dev/null"
     writeln!(writer, "diff --git {} {}", old_filename.display(), new_filename.display())?;
 
-    // Print rename metadata
+    // Print rename metadata. Git writes the names without the "a/" and "b/"
+    // prefixes there.
     if filepatch.is_rename() {
-        writeln!(writer, "rename from {}", old_filename.display())?;
-        writeln!(writer, "rename to {}", new_filename.display())?;
+        let (rename_from, rename_to) = match (old_filename.strip_prefix("a"), new_filename.strip_prefix("b")) {
+            (Ok(rename_from), Ok(rename_to)) => (rename_from, rename_to),
+            _ => (old_filename.as_ref(), new_filename.as_ref()),
+        };
+        writeln!(writer, "rename from {}", rename_from.display())?;
+        writeln!(writer, "rename to {}", rename_to.display())?;
     }
 
     // Print permissions metadata
@@ -134,14 +140,14 @@ fn write_file_patch_header_to<'a, W: Write>(filepatch: &FilePatch<'a, &'a [u8]>,
 
             if let Some(permissions) = filepatch.old_permissions() {
                 if filepatch.kind() == FilePatchKind::Delete {
-                    writeln!(writer, "delete file mode {:o}", permissions.mode())?;
+                    writeln!(writer, "deleted file mode {:o}", permissions.mode())?;
                 } else {
                     writeln!(writer, "old mode {:o}", permissions.mode())?;
                 }
             }
 
             if let Some(permissions) = filepatch.new_permissions() {
-                if filepatch.kind() == FilePatchKind::Delete {
+                if filepatch.kind() == FilePatchKind::Create {
                     writeln!(writer, "new file mode {:o}", permissions.mode())?;
                 } else {
                     writeln!(writer, "new mode {:o}", permissions.mode())?;
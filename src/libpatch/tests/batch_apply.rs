@@ -0,0 +1,158 @@
+use std::vec::Vec;
+
+use anyhow::Result;
+
+use crate::analysis::{AnalysisSet, fn_analysis_note_noop};
+use crate::modified_file::ModifiedFile;
+use crate::patch::{BatchThreshold, PatchDirection};
+use crate::patch::unified::parser::parse_patch;
+
+/// A file with `lines` numbered lines
+#[cfg(test)]
+fn numbered_lines(lines: usize) -> Vec<u8> {
+    (0..lines).map(|i| format!("line {}\n", i)).collect::<String>().into_bytes()
+}
+
+/// A patch for `numbered_lines(lines)` that changes every `step`-th line and
+/// adds one after it.
+#[cfg(test)]
+fn many_hunks_patch(lines: usize, step: usize) -> Vec<u8> {
+    let mut patch = String::from("--- a/file.txt\n+++ b/file.txt\n");
+    for changed in (step..(lines - step)).step_by(step) {
+        patch += &format!("@@ -{},7 +{},8 @@\n", changed - 2, changed - 2);
+        for i in (changed - 3)..changed {
+            patch += &format!(" line {}\n", i);
+        }
+        patch += &format!("-line {}\n+changed {}\n+added {}\n", changed, changed, changed);
+        for i in (changed + 1)..(changed + 4) {
+            patch += &format!(" line {}\n", i);
+        }
+    }
+    patch.into_bytes()
+}
+
+/// Apply the only file patch of `patch_data` on the `file` with or without
+/// batching. Returns the debug output of the report and the patched file.
+#[cfg(test)]
+fn apply_modify(patch_data: &[u8], file: &[u8], fuzz: usize, force: bool, batch: bool) -> Result<(String, Vec<u8>)> {
+    let patch = parse_patch(patch_data, 0)?;
+    let file_patch = &patch.file_patches[0];
+    let mut modified_file = ModifiedFile::new(file, true, None);
+
+    let report = file_patch.apply_modify(&mut modified_file, PatchDirection::Forward, fuzz, None, false, false, force, None,
+                                         batch, &AnalysisSet::default(), &fn_analysis_note_noop);
+
+    let mut output = Vec::new();
+    modified_file.write_to(&mut output)?;
+
+    // Rolling back must work the same too
+    file_patch.rollback(&mut modified_file, PatchDirection::Forward, &report);
+    let mut rollback = Vec::new();
+    modified_file.write_to(&mut rollback)?;
+    assert!(rollback == file, "Content after rollback does not match the original input!");
+
+    Ok((format!("{:?}", report), output))
+}
+
+#[cfg(test)]
+#[test]
+fn batch_matches_sequential() -> Result<()> {
+    let lines = 200_000;
+    let patch_data = many_hunks_patch(lines, 400);
+    let file = numbered_lines(lines);
+
+    // Lines inserted in the middle move the following hunks
+    let mut shifted = file.clone();
+    let middle = String::from_utf8(file.clone())?.match_indices('\n').nth(lines / 2).unwrap().0 + 1;
+    shifted.splice(middle..middle, b"inserted 1\ninserted 2\n".iter().cloned());
+
+    // Changed context needs fuzz or force
+    let stale = String::from_utf8(file.clone())?.replace("line 1201\n", "other 1201\n").into_bytes();
+
+    for (file, fuzz, force) in [(&file, 0, false), (&shifted, 0, false), (&stale, 0, false), (&stale, 1, false), (&stale, 0, true)] {
+        let (sequential_report, sequential_output) = apply_modify(&patch_data, file, fuzz, force, false)?;
+        let (batch_report, batch_output) = apply_modify(&patch_data, file, fuzz, force, true)?;
+
+        assert_eq!(batch_report, sequential_report);
+        assert!(batch_output == sequential_output, "The batch application wrote a different output!");
+        assert!(batch_output != *file);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn batch_threshold_skips_small_files() -> Result<()> {
+    let threshold = BatchThreshold::default();
+
+    // 68 hunks, but only about 6 KiB
+    let small_file = numbered_lines(700);
+    let small_patch_data = many_hunks_patch(700, 10);
+    let small_patch = parse_patch(&small_patch_data, 0)?;
+    let hunks = small_patch.file_patches[0].hunks().len();
+    assert!(hunks >= BatchThreshold::DEFAULT_MIN_HUNKS);
+    assert!(!threshold.applies_to(hunks, &ModifiedFile::new(&small_file, true, None)));
+
+    let big_file = numbered_lines(200_000);
+    assert!(threshold.applies_to(hunks, &ModifiedFile::new(&big_file, true, None)));
+    assert!(!threshold.applies_to(BatchThreshold::DEFAULT_MIN_HUNKS - 1, &ModifiedFile::new(&big_file, true, None)));
+
+    // Both limits can be lowered.
+    let threshold = BatchThreshold { min_hunks: 2, min_file_size: 0 };
+    assert!(threshold.applies_to(2, &ModifiedFile::new(&small_file, true, None)));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "bencher")]
+mod benchmarks {
+    use super::*;
+    use test::{Bencher, black_box};
+
+    fn bench_apply(b: &mut Bencher, batch: bool) {
+        let lines = 200_000;
+        let patch_data = many_hunks_patch(lines, 400);
+        let file = numbered_lines(lines);
+
+        b.iter(|| {
+            black_box(apply_modify(&patch_data, &file, 0, false, batch).unwrap());
+        });
+    }
+
+    #[bench]
+    fn bench_apply_many_hunks_sequential(b: &mut Bencher) {
+        bench_apply(b, false);
+    }
+
+    #[bench]
+    fn bench_apply_many_hunks_batch(b: &mut Bencher) {
+        bench_apply(b, true);
+    }
+
+    /// Many hunks in a small file, applied like `FilePatch::apply` does with
+    /// the default `BatchThreshold`, i.e. without batch.
+    #[bench]
+    fn bench_apply_small_file_default(b: &mut Bencher) {
+        let patch_data = many_hunks_patch(700, 10);
+        let file = numbered_lines(700);
+        let patch = parse_patch(&patch_data, 0).unwrap();
+        let batch = BatchThreshold::default().applies_to(patch.file_patches[0].hunks().len(), &ModifiedFile::new(&file, true, None));
+
+        b.iter(|| {
+            black_box(apply_modify(&patch_data, &file, 0, false, batch).unwrap());
+        });
+    }
+
+    /// The same in batch, as it was done before the file size was checked.
+    #[bench]
+    fn bench_apply_small_file_batch(b: &mut Bencher) {
+        let patch_data = many_hunks_patch(700, 10);
+        let file = numbered_lines(700);
+
+        b.iter(|| {
+            black_box(apply_modify(&patch_data, &file, 0, false, true).unwrap());
+        });
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::Result;
+
+use crate::analysis::{AnalysisSet, fn_analysis_note_noop};
+use crate::modified_file::ModifiedFile;
+use crate::patch::{BatchThreshold, HunkApplyReport, PatchDirection};
+use crate::patch::unified::parser::parse_patch;
+
+const PATCH: &[u8] = b"\
+--- a/file.txt
++++ b/file.txt
+@@ -3,7 +3,7 @@
+ line 2
+ line 3
+ line 4
+-line 5
++changed 5
+ line 6
+ line 7
+ line 8
+@@ -18,7 +18,7 @@
+ line 17
+ line 18
+ line 19
+-line 20
++changed 20
+ line 21
+ line 22
+ line 23
+";
+
+/// Apply the `PATCH` with `max_fuzz` on numbered lines, with the first
+/// context line of the first hunk changed. Returns the fuzz of every hunk, or
+/// `None` for the hunks that failed.
+#[cfg(test)]
+fn hunk_fuzzes(max_fuzz: usize) -> Result<Vec<Option<usize>>> {
+    let file = (0..30).map(|i| format!("line {}\n", i)).collect::<String>()
+        .replace("line 2\n", "other 2\n")
+        .into_bytes();
+    let patch = parse_patch(PATCH, 1)?;
+    let mut modified_file = ModifiedFile::new(&file, true, None);
+    let report = patch.file_patches[0].apply(&mut modified_file, PatchDirection::Forward, max_fuzz, None, false, false, false,
+                                             None, BatchThreshold::default(), &AnalysisSet::default(), &fn_analysis_note_noop);
+
+    Ok(report.hunk_reports().iter().map(|hunk_report| match hunk_report {
+        HunkApplyReport::Applied { fuzz, .. } => Some(*fuzz),
+        _ => None,
+    }).collect())
+}
+
+#[cfg(test)]
+#[test]
+fn hunks_report_least_fuzz() -> Result<()> {
+    // The first hunk needs fuzz 1 even when more is allowed, the second one
+    // matches exactly.
+    assert_eq!(hunk_fuzzes(0)?, [None, Some(0)]);
+    assert_eq!(hunk_fuzzes(1)?, [Some(1), Some(0)]);
+    assert_eq!(hunk_fuzzes(2)?, [Some(1), Some(0)]);
+    Ok(())
+}
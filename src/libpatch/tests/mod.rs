@@ -1,2 +1,4 @@
+mod batch_apply;
+mod minimal_fuzz;
 mod testdata_parsing;
 mod testdata_patching;
@@ -22,9 +22,9 @@ fn compare_output<'a>(path: &Path, patch: TextPatch<'a>) -> Result<()> {
         let stderr = std::io::stderr();
         let mut stderr = stderr.lock();
         writeln!(stderr, "*** EXPECTED ***")?;
-        stderr.write(&expected_output)?;
+        stderr.write_all(&expected_output)?;
         writeln!(stderr, "*** WROTE ***")?;
-        stderr.write(&output)?;
+        stderr.write_all(&output)?;
 
         // Try to save what we thought should be there, but ignore errors
         if let Ok(mut file) = fs::File::create(path.with_extension("patch-bad")) {
@@ -58,8 +58,10 @@ fn compare_error(path: &Path, error: ParseError) -> Result<()> {
 #[cfg(test)]
 #[test]
 fn all_files() -> Result<()> {
-    for entry in fs::read_dir("testdata/parsing")? {
-        let entry = entry?;
+    // In the same order everywhere, so the output is reproducible
+    let mut entries = fs::read_dir("testdata/parsing")?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
         let path = entry.path();
         match path.extension() {
             Some(extension) if extension == "patch" => {},
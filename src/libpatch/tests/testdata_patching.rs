@@ -6,16 +6,18 @@ use anyhow::{bail, Context, Result};
 
 use crate::analysis::{AnalysisSet, fn_analysis_note_noop};
 use crate::modified_file::ModifiedFile;
-use crate::patch::PatchDirection;
+use crate::patch::{BatchThreshold, PatchDirection};
 use crate::patch::unified::parser::parse_patch;
 
 
 #[cfg(test)]
 #[test]
 fn all_files() -> Result<()> {
-    for entry in fs::read_dir("testdata/patching")? {
+    // In the same order everywhere, so the output is reproducible
+    let mut entries = fs::read_dir("testdata/patching")?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
         // Skip everything that doesn't end with ".patch"
-        let entry = entry?;
         let path = entry.path();
         match path.extension() {
             Some(extension) if extension == "patch" => {},
@@ -31,12 +33,16 @@ fn all_files() -> Result<()> {
 
         // Parse our special headers
         let mut fuzz = 0;
+        let mut force = false;
+        let mut strict_ambiguity = false;
+        let mut unordered_hunks = false;
         for header_line in patch.header.split(|&c| c == b'\n') {
             let header_line = String::from_utf8_lossy(header_line);
             match &header_line.splitn(2, ": ").collect::<Vec<_>>()[..] {
-                ["fuzz", fuzz_str] => {
-                    fuzz = fuzz_str.parse()?;
-                }
+                ["fuzz", fuzz_str] => fuzz = fuzz_str.parse()?,
+                ["force", force_str] => force = *force_str == "yes",
+                ["strict-ambiguity", strict_str] => strict_ambiguity = *strict_str == "yes",
+                ["unordered-hunks", unordered_str] => unordered_hunks = *unordered_str == "yes",
                 _ => {}
             }
         }
@@ -58,7 +64,7 @@ fn all_files() -> Result<()> {
         let mut modified_file = ModifiedFile::new(&file, true, None);
 
         // Patch it
-        let report = file_patch.apply(&mut modified_file, PatchDirection::Forward, fuzz, &AnalysisSet::default(), &fn_analysis_note_noop);
+        let report = file_patch.apply(&mut modified_file, PatchDirection::Forward, fuzz, None, strict_ambiguity, unordered_hunks, force, None, BatchThreshold::default(), &AnalysisSet::default(), &fn_analysis_note_noop);
 
         // Check if it failed when shouldn't or succeeded when it was expected to fail
         let error_file = path.with_extension("error");
@@ -74,6 +80,9 @@ fn all_files() -> Result<()> {
         if !should_fail && report.failed() {
             panic!("The patch unexpectedly failed to apply! Report: {:#?}", report);
         }
+        if report.forced() != force {
+            panic!("The patch was expected to be forced: {}, but it was: {}! Report: {:#?}", force, report.forced(), report);
+        }
 
         // Write the output to a buffer
         let mut output = Vec::<u8>::new();
@@ -94,9 +103,9 @@ fn all_files() -> Result<()> {
             let stderr = std::io::stderr();
             let mut stderr = stderr.lock();
             writeln!(stderr, "*** EXPECTED ***")?;
-            stderr.write(&expected_output)?;
+            stderr.write_all(&expected_output)?;
             writeln!(stderr, "*** WROTE ***")?;
-            stderr.write(&output)?;
+            stderr.write_all(&output)?;
 
             // Try to save what we thought should be there, but ignore errors
             if let Ok(mut file) = fs::File::create(path.with_extension("out-bad")) {
@@ -114,9 +123,9 @@ fn all_files() -> Result<()> {
 	    let stderr = std::io::stderr();
 	    let mut stderr = stderr.lock();
 	    writeln!(stderr, "*** ORIGINAL ***")?;
-	    stderr.write(&file)?;
+	    stderr.write_all(&file)?;
 	    writeln!(stderr, "*** ROLLBACK ***")?;
-	    stderr.write(&rollback)?;
+	    stderr.write_all(&rollback)?;
 	    panic!("Content after rollback does not match the original input!");
 	}
     }
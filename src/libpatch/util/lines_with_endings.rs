@@ -1,4 +1,4 @@
-use memchr::{memchr_iter, Memchr};
+use memchr::{memchr, memchr_iter, Memchr};
 
 
 struct LinesWithEndings<'a> {
@@ -12,7 +12,7 @@ impl<'a> LinesWithEndings<'a> {
         LinesWithEndings {
             input,
             previous_offset: 0,
-            iter: memchr_iter(b'\n', input),
+            iter: memchr_iter(line_terminator(input), input),
         }
     }
 }
@@ -40,9 +40,21 @@ impl<'a> Iterator for LinesWithEndings<'a> {
     }
 }
 
+/// Returns the byte that ends the lines in the `input`: `\r` if it has old
+/// Mac line endings (some `\r` and no `\n` at all), otherwise `\n`. "\r\n"
+/// line endings need no special care, they end with `\n` too.
+pub fn line_terminator(input: &[u8]) -> u8 {
+    if memchr(b'\n', input).is_none() && memchr(b'\r', input).is_some() {
+        b'\r'
+    } else {
+        b'\n'
+    }
+}
+
 /// This splits the byte slice into subslices by newline character and keeps
 /// the newline character in the subslice. The only possible expection is the
-/// last subslice in case there was no newline character at the end.
+/// last subslice in case there was no newline character at the end. The
+/// newline character is the one from `line_terminator`.
 pub fn split_lines_with_endings(input: &[u8]) -> impl Iterator<Item = &[u8]> {
     LinesWithEndings::new(input)
 }
@@ -58,7 +70,7 @@ mod tests {
         assert!(l.next() == Some(b"aaa\n"));
         assert!(l.next() == Some(b"bbb\n"));
         assert!(l.next() == Some(b"ccc\n"));
-        assert!(l.next() == None);
+        assert!(l.next().is_none());
     }
 
     #[test]
@@ -66,7 +78,7 @@ mod tests {
         let mut l = split_lines_with_endings(b"aaa\n");
 
         assert!(l.next() == Some(b"aaa\n"));
-        assert!(l.next() == None);
+        assert!(l.next().is_none());
     }
 
     #[test]
@@ -74,7 +86,7 @@ mod tests {
         let mut l = split_lines_with_endings(b"\n");
 
         assert!(l.next() == Some(b"\n"));
-        assert!(l.next() == None);
+        assert!(l.next().is_none());
     }
 
     #[test]
@@ -84,7 +96,7 @@ mod tests {
         assert!(l.next() == Some(b"aaa\n"));
         assert!(l.next() == Some(b"bbb\n"));
         assert!(l.next() == Some(b"ccc"));
-        assert!(l.next() == None);
+        assert!(l.next().is_none());
     }
 
     #[test]
@@ -92,14 +104,33 @@ mod tests {
         let mut l = split_lines_with_endings(b"aaa");
 
         assert!(l.next() == Some(b"aaa"));
-        assert!(l.next() == None);
+        assert!(l.next().is_none());
     }
 
     #[test]
     fn empty_no_trailing_newline() {
         let mut l = split_lines_with_endings(b"");
 
-        assert!(l.next() == None);
+        assert!(l.next().is_none());
+    }
+
+    #[test]
+    fn old_mac_line_endings() {
+        let mut l = split_lines_with_endings(b"aaa\rbbb\rccc");
+
+        assert!(l.next() == Some(b"aaa\r"));
+        assert!(l.next() == Some(b"bbb\r"));
+        assert!(l.next() == Some(b"ccc"));
+        assert!(l.next().is_none());
+    }
+
+    #[test]
+    fn stray_carriage_return() {
+        let mut l = split_lines_with_endings(b"aaa\rbbb\r\nccc\n");
+
+        assert!(l.next() == Some(b"aaa\rbbb\r\n"));
+        assert!(l.next() == Some(b"ccc\n"));
+        assert!(l.next().is_none());
     }
 }
 
@@ -0,0 +1,101 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module checks ".pc/applied-patches" against the series and rebuilds
+//! it from the backups of the patches (`--repair`).
+//!
+//! The list must be the start of the series. A list that was cut off, e.g.
+//! by a full disk, or that names patches which were removed from the series
+//! is reported with the line that is wrong, instead of pushing patches on
+//! the wrong state of the tree.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::apply::{BackupStore, SeriesPatch, backup_names};
+use crate::pop::write_applied_patches;
+
+/// Appended to every problem found in ".pc/applied-patches"
+const REPAIR_HINT: &str = "Run it again with \"--repair\" to rebuild the list from the backups in \".pc\".";
+
+/// Find out how many of the `series_patches` are applied. Fails if
+/// ".pc/applied-patches" is not the start of the series.
+pub fn count_applied_patches(base_dir: &Path, series_patches: &[SeriesPatch]) -> Result<usize> {
+    let data = match fs::read(base_dir.join(".pc/applied-patches")) {
+        Ok(data) => data,
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(error).context("Reading \".pc/applied-patches\""),
+    };
+    let data = String::from_utf8_lossy(&data);
+    let complete = data.is_empty() || data.ends_with('\n');
+
+    let lines: Vec<_> = data.lines().enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    for (index, &(line_index, name)) in lines.iter().enumerate() {
+        let name = Path::new(name);
+        let line = line_index + 1;
+        let Some(series_patch) = series_patches.get(index) else {
+            bail!("Line {} of \".pc/applied-patches\" says that patch {} is applied, but the series has only {} patches.\n{}",
+                  line, name.display(), series_patches.len(), REPAIR_HINT);
+        };
+        if series_patch.filename == name {
+            continue;
+        }
+
+        let last = index + 1 == lines.len();
+        let cut_off = series_patch.filename.to_string_lossy().starts_with(&*name.to_string_lossy());
+        if last && !complete && cut_off {
+            bail!("The last line of \".pc/applied-patches\" is cut off (\"{}\" instead of \"{}\"), the file may be truncated.\n{}",
+                  name.display(), series_patch.filename.display(), REPAIR_HINT);
+        } else if series_patches.iter().any(|series_patch| series_patch.filename == name) {
+            bail!("Line {} of \".pc/applied-patches\" names patch {}, but patch {} is at that place in the series.\n{}",
+                  line, name.display(), series_patch.filename.display(), REPAIR_HINT);
+        } else {
+            bail!("Line {} of \".pc/applied-patches\" names patch {}, which is not in the series.\n{}",
+                  line, name.display(), REPAIR_HINT);
+        }
+    }
+
+    Ok(lines.len())
+}
+
+/// The result of `repair_applied_patches`
+#[derive(Debug, Default, PartialEq)]
+pub struct Repair {
+    /// The patches now in ".pc/applied-patches"
+    pub applied_patches: Vec<PathBuf>,
+
+    /// Applied patches without a backup, they can not be popped
+    pub missing_backups: Vec<PathBuf>,
+}
+
+/// Rebuild ".pc/applied-patches" from the backups in the `backup_store`.
+/// The patches are pushed in the order of the series, so every patch up to
+/// the last one with a backup is applied, even if it has none itself.
+pub fn repair_applied_patches(base_dir: &Path, series_patches: &[SeriesPatch], backup_store: &dyn BackupStore) -> Result<Repair> {
+    let backup_names = backup_names(series_patches.iter().map(|series_patch| series_patch.filename.as_path()));
+    let has_backup = series_patches.iter().zip(&backup_names)
+        .map(|(series_patch, backup_name)| {
+            backup_store.list(backup_name)
+                .map(|files| files.is_some())
+                .with_context(|| format!("Reading backup files of patch {}", series_patch.filename.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let applied_count = has_backup.iter().rposition(|&has_backup| has_backup).map_or(0, |index| index + 1);
+
+    let mut repair = Repair::default();
+    for (series_patch, &has_backup) in series_patches[..applied_count].iter().zip(&has_backup) {
+        if !has_backup {
+            repair.missing_backups.push(series_patch.filename.clone());
+        }
+        repair.applied_patches.push(series_patch.filename.clone());
+    }
+
+    fs::create_dir_all(base_dir.join(".pc"))
+        .context("Creating \".pc\"")?;
+    write_applied_patches(base_dir, &repair.applied_patches)?;
+    Ok(repair)
+}
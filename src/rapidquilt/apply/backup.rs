@@ -0,0 +1,194 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Storage of the quilt backup files, which keep the original content of the
+//! files changed by every patch, so the patch can be popped.
+//!
+//! By default the backups are files in ".pc/<patch>/<filename>" in the
+//! working directory, like quilt keeps them. Other storage can be plugged in
+//! through `ApplyConfig::backup_store`.
+//!
+//! A patch that is in the series more than once has a backup for every time
+//! it is applied, the later ones have the number of the application appended
+//! to the name of the patch, e.g. ".pc/<patch>~2/<filename>".
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, Permissions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use libpatch::modified_file::ModifiedFile;
+
+use super::common::create_file;
+
+/// The `st_mode` file type bits of symlinks, as in `ModifiedFile::permissions`
+#[cfg(unix)]
+const SYMLINK_MODE: u32 = 0o120000;
+#[cfg(unix)]
+const FILE_TYPE_MASK: u32 = 0o170000;
+
+#[cfg(unix)]
+fn is_symlink(permissions: &Option<Permissions>) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.as_ref().is_some_and(|permissions| permissions.mode() & FILE_TYPE_MASK == SYMLINK_MODE)
+}
+
+#[cfg(not(unix))]
+fn is_symlink(_permissions: &Option<Permissions>) -> bool {
+    false
+}
+
+/// The original content of a file, as returned by `BackupStore::restore`.
+#[derive(Clone, Debug)]
+pub struct BackupFile {
+    /// The content of the file, or the target if it is a symlink
+    pub content: Vec<u8>,
+
+    /// The permissions of the original file, if they were known. Symlinks are
+    /// recognized by the file type bits in them, like in `ModifiedFile`.
+    pub permissions: Option<Permissions>,
+}
+
+impl BackupFile {
+    pub fn is_symlink(&self) -> bool {
+        is_symlink(&self.permissions)
+    }
+}
+
+/// The names under which the backups of the patches with the
+/// `patch_filenames` are kept, in the order they are applied: the names of
+/// the patches, with "~<n>" appended for the n-th application of a patch.
+pub fn backup_names<'a, I: IntoIterator<Item = &'a Path>>(patch_filenames: I) -> Vec<PathBuf> {
+    let mut applications = HashMap::new();
+    patch_filenames.into_iter()
+        .map(|patch_filename| {
+            let application = applications.entry(patch_filename).or_insert(0);
+            *application += 1;
+            if *application == 1 {
+                return patch_filename.to_path_buf();
+            }
+            let mut name = patch_filename.as_os_str().to_os_string();
+            name.push(format!("~{}", application));
+            PathBuf::from(name)
+        })
+        .collect()
+}
+
+/// Storage of the backup files, see the module documentation. All paths are
+/// relative to the working directory.
+pub trait BackupStore: fmt::Debug + Sync {
+    /// Save the `original_file` as the backup of the file with `filename`
+    /// for the patch with `patch_filename`. A file that did not exist is
+    /// saved empty.
+    fn save(&self, patch_filename: &Path, filename: &Path, original_file: &ModifiedFile) -> Result<(), io::Error>;
+
+    /// List the files backed up for the patch. Returns `None` if there is no
+    /// backup of it at all.
+    fn list(&self, patch_filename: &Path) -> Result<Option<Vec<PathBuf>>, io::Error>;
+
+    /// Load the backup of the file. Returns `None` if the file did not exist
+    /// before the patch.
+    fn restore(&self, patch_filename: &Path, filename: &Path) -> Result<Option<BackupFile>, io::Error>;
+
+    /// Remove all backup files of the patch.
+    fn remove(&self, patch_filename: &Path) -> Result<(), io::Error>;
+}
+
+/// The quilt backup files in ".pc" of the `base_dir`
+#[derive(Debug)]
+pub struct FileBackupStore<'a> {
+    base_dir: &'a Path,
+}
+
+impl<'a> FileBackupStore<'a> {
+    pub fn new(base_dir: &'a Path) -> Self {
+        Self { base_dir }
+    }
+
+    fn backup_dir(&self, patch_filename: &Path) -> PathBuf {
+        self.base_dir.join(".pc").join(patch_filename)
+    }
+}
+
+/// Collect all files in the `backup_dir`, relative to it, sorted by name
+/// in every directory.
+fn collect_files(backup_dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    let mut entries = fs::read_dir(backup_dir.join(relative))?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            collect_files(backup_dir, &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl BackupStore for FileBackupStore<'_> {
+    fn save(&self, patch_filename: &Path, filename: &Path, original_file: &ModifiedFile) -> Result<(), io::Error> {
+        let path = self.backup_dir(patch_filename).join(filename); // Note that this may add multiple directories plus filename
+
+        // SAFETY: We know that there is a parent; we built the path ourselves using `.pc/<patch_filename>/<filename>`.
+        fs::create_dir_all(path.parent().expect("Backup path must have a parent"))?;
+
+        // A patch that changes the type of a file deletes and creates it, so
+        // it is backed up twice. The earlier state replaces the backup of
+        // the later one, which must not be written through if it is a symlink.
+        match fs::remove_file(&path) {
+            Ok(()) => {},
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => return Err(err),
+        }
+
+        #[cfg(unix)]
+        {
+            // A deleted symlink is backed up empty, like any missing file.
+            if !original_file.deleted && is_symlink(&original_file.permissions) {
+                let mut target = Vec::new();
+                original_file.write_to(&mut target)?;
+                let target_str = std::str::from_utf8(&target).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                return std::os::unix::fs::symlink(target_str, &path);
+            }
+        }
+
+        let mut f = create_file(&path, &original_file.permissions)?;
+        original_file.write_to(&mut f)
+    }
+
+    fn list(&self, patch_filename: &Path) -> Result<Option<Vec<PathBuf>>, io::Error> {
+        let backup_dir = self.backup_dir(patch_filename);
+        if !backup_dir.is_dir() {
+            return Ok(None);
+        }
+        let mut files = Vec::new();
+        collect_files(&backup_dir, Path::new(""), &mut files)?;
+        Ok(Some(files))
+    }
+
+    fn restore(&self, patch_filename: &Path, filename: &Path) -> Result<Option<BackupFile>, io::Error> {
+        let path = self.backup_dir(patch_filename).join(filename);
+        let metadata = fs::symlink_metadata(&path)?;
+        let permissions = Some(metadata.permissions());
+
+        #[cfg(unix)]
+        {
+            if metadata.file_type().is_symlink() {
+                use std::os::unix::ffi::OsStrExt;
+                let content = fs::read_link(&path)?.as_os_str().as_bytes().to_vec();
+                return Ok(Some(BackupFile { content, permissions }));
+            }
+        }
+
+        // Quilt marks files that did not exist with empty backups
+        if metadata.len() == 0 {
+            return Ok(None);
+        }
+        Ok(Some(BackupFile { content: fs::read(&path)?, permissions }))
+    }
+
+    fn remove(&self, patch_filename: &Path) -> Result<(), io::Error> {
+        fs::remove_dir_all(self.backup_dir(patch_filename))
+    }
+}
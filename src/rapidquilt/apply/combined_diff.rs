@@ -0,0 +1,102 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module computes the net effect of the applied patches as a single
+//! patch against the original files (see `ApplyConfig::emit_diff`).
+//!
+//! Every file is compared as a whole, so a renamed file shows as deleted and
+//! created again.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use libpatch::diff::{diff, DEFAULT_CONTEXT};
+use libpatch::modified_file::ModifiedFile;
+use libpatch::patch::{FilePatchKind, TextFilePatchBuilder};
+use libpatch::patch::unified::writer::UnifiedPatchWriter;
+
+use crate::apply::*;
+use crate::apply::common::*;
+use crate::arena::Arena;
+
+/// Write the difference between the original `old_file` and the patched
+/// `new_file` as a patch for `filename`. Writes nothing if they are the
+/// same.
+///
+/// If the `old_filename` is different, the patch renames it to `filename`.
+pub fn write_file_diff(
+    old_filename: &Path,
+    filename: &Path,
+    old_file: Option<&ModifiedFile>,
+    new_file: Option<&ModifiedFile>,
+    output: &mut Vec<u8>)
+    -> Result<()>
+{
+    let kind = match (old_file, new_file) {
+        (None, None) => return Ok(()),
+        (None, Some(_)) => FilePatchKind::Create,
+        (Some(_), None) => FilePatchKind::Delete,
+        (Some(_), Some(_)) => FilePatchKind::Modify,
+    };
+
+    let old_permissions = old_file.and_then(|file| file.permissions.as_ref());
+    let new_permissions = new_file.and_then(|file| file.permissions.as_ref());
+    let (old_permissions, new_permissions) = match kind {
+        FilePatchKind::Modify if old_permissions == new_permissions => (None, None),
+        _ => (old_permissions.cloned(), new_permissions.cloned()),
+    };
+
+    let old_content = old_file.map(|file| &file.content[..]).unwrap_or(&[]);
+    let new_content = new_file.map(|file| &file.content[..]).unwrap_or(&[]);
+    let hunks = diff(old_content, new_content, DEFAULT_CONTEXT);
+    let is_rename = old_filename != filename;
+    if hunks.is_empty() && kind == FilePatchKind::Modify && new_permissions.is_none() && !is_rename {
+        return Ok(());
+    }
+
+    let file_patch = TextFilePatchBuilder::default()
+        .kind(kind)
+        .old_filename(Some(Cow::Owned(Path::new("a").join(old_filename))))
+        .new_filename(Some(Cow::Owned(Path::new("b").join(filename))))
+        .is_rename(is_rename)
+        .old_permissions(old_permissions)
+        .new_permissions(new_permissions)
+        .hunks(hunks)
+        .build()?;
+
+    file_patch.write_to(output)?;
+    Ok(())
+}
+
+/// Compare every file in `modified_files` with the original loaded from the
+/// `arena` and return the differences of those that changed, sorted by
+/// filename.
+pub fn collect_file_diffs(config: &ApplyConfig, arena: &dyn Arena, modified_files: &ModifiedFiles)
+    -> Result<Vec<FileDiff>>
+{
+    let mut file_diffs = Vec::new();
+
+    for (filename, file) in modified_files.iter() {
+        let old_file = load_existing_file(arena, &config.base_dir.join(filename))
+            .with_context(|| ApplyError::LoadFileToPatch { filename: filename.to_path_buf() })?;
+
+        // The patched file may be loaded only partially, get all of it.
+        let mut new_data = Vec::new();
+        let new_file = if file.deleted {
+            None
+        } else {
+            file.write_to(&mut new_data)?;
+            Some(ModifiedFile::new(&new_data, true, file.permissions.clone()))
+        };
+
+        let mut diff = Vec::new();
+        write_file_diff(filename, filename, old_file.as_ref(), new_file.as_ref(), &mut diff)?;
+        if !diff.is_empty() {
+            file_diffs.push(FileDiff { filename: filename.to_path_buf(), diff });
+        }
+    }
+
+    file_diffs.sort_by(|a, b| a.filename.cmp(&b.filename));
+    Ok(file_diffs)
+}
@@ -4,25 +4,172 @@
 //! `sequential` modules.
 
 use std::borrow::Cow;
-use std::collections::{HashMap, HashSet, hash_map::Entry};
+use std::collections::{BTreeSet, HashMap, HashSet, hash_map::Entry};
+use std::ffi::{OsStr, OsString};
 use std::fs::{self, File};
 use std::hash::BuildHasherDefault;
-use std::io::{self, BufWriter};
+use std::io::{self, BufWriter, Write};
 use std::hash::BuildHasher;
 use std::ops::{Deref, DerefMut};
-use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Error, Result};
+use itertools::Itertools;
 use seahash::SeaHasher;
+use sha1::{Digest, Sha1};
 
-use libpatch::analysis::{AnalysisSet, Note};
+use libpatch::analysis::{AnalysisSet, Note, fn_analysis_note_noop};
 use libpatch::modified_file::ModifiedFile;
-use libpatch::patch::{FilePatchApplyReport, PatchDirection, TextFilePatch, TextPatch};
+use libpatch::patch::{FilePatchApplyReport, FilePatchKind, HunkApplyReport, PatchDirection, TextFilePatch, TextPatch};
+use libpatch::patch::unified::parser::parse_patch;
 use libpatch::patch::unified::writer::UnifiedPatchRejWriter;
 
 use crate::apply::*;
+use crate::apply::parallel::FilenameDistributor;
 use crate::arena::Arena;
+use crate::audit::{AuditLog, AuditOperation, AuditRecord, hash_file_on_disk, hash_modified_file};
+use crate::match_cache::match_key;
+use crate::patch_source::{FilesystemSource, PatchSource};
+
+/// Hunks applied further than this many lines away from the line given in the
+/// patch are reported, the match may be a wrong one.
+const LARGE_OFFSET: usize = 1000;
+
+/// Compute the time by which the patch that starts applying now must be
+/// done, if there is a `patch_timeout` configured.
+pub fn patch_deadline(config: &ApplyConfig) -> Option<Instant> {
+    config.patch_timeout.map(|timeout| Instant::now() + timeout)
+}
+
+/// The strip levels tried by `parse_series_patch`
+const AUTO_STRIP_LEVELS: [usize; 3] = [0, 1, 2];
+
+/// Returns true if the file with the `filename` exists in the `base_dir`.
+fn file_exists(config: &ApplyConfig, filename: &Path) -> bool {
+    config.base_dir.join(filename).symlink_metadata().is_ok()
+}
+
+/// The file that the `filename` points to, if it is a symlink, relative to
+/// the `base_dir` if it is in it. The `PathGuard` checks those that are not.
+fn symlink_target_file(config: &ApplyConfig, filename: &Path) -> Result<Option<PathBuf>> {
+    let path = config.base_dir.join(filename);
+    if !path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        return Ok(None);
+    }
+
+    let target = fs::canonicalize(&path)
+        .with_context(|| format!("Following symlink {:?}", filename))?;
+    let root = if config.base_dir.as_os_str().is_empty() { Path::new(".") } else { config.base_dir };
+    let canonical_root = fs::canonicalize(root)
+        .with_context(|| format!("Resolving working directory {:?}", root))?;
+    match target.strip_prefix(&canonical_root) {
+        Ok(relative) => Ok(Some(relative.to_path_buf())),
+        Err(_) => Ok(Some(target)),
+    }
+}
+
+/// Returns true if every file that the `patch` modifies or deletes exists in
+/// the `base_dir`.
+fn patched_files_exist(config: &ApplyConfig, patch: &TextPatch) -> bool {
+    patch.file_patches.iter()
+        .filter(|file_patch| file_patch.kind() != FilePatchKind::Create)
+        .all(|file_patch| file_patch.old_filename().into_iter().chain(file_patch.new_filename())
+             .any(|filename| file_exists(config, filename)))
+}
+
+/// Parse a patch from its `data` at the `strip` level. With
+/// `ApplyConfig::relative`, absolute paths leading into the `base_dir` are
+/// made relative to it.
+///
+/// A file that is neither found under its old nor its new filename is
+/// patched under the filename from its "Index:" line, if that one exists. Old
+/// CVS diffs name only the basename in the "---" and "+++" lines.
+///
+/// With `ApplyConfig::prefix_map`, the leading directories of the paths are
+/// rewritten, after they were made relative.
+///
+/// With `ApplyConfig::follow_symlinks`, modified symlinks are replaced by the
+/// files they point to.
+pub fn parse_tree_patch<'a>(config: &ApplyConfig, data: &'a [u8], strip: usize) -> Result<TextPatch<'a>> {
+    let mut patch = parse_patch(data, strip)?;
+    if config.relative {
+        let root = if config.base_dir.as_os_str().is_empty() { Path::new(".") } else { config.base_dir };
+        let canonical_root = fs::canonicalize(root)
+            .with_context(|| format!("Resolving working directory {:?}", root))?;
+        for file_patch in &mut patch.file_patches {
+            if root.is_absolute() {
+                file_patch.strip_prefix(root);
+            }
+            file_patch.strip_prefix(&canonical_root);
+        }
+    }
+    if let Some(prefix_map) = config.prefix_map {
+        for file_patch in &mut patch.file_patches {
+            file_patch.replace_filenames(|filename| Ok::<_, Error>(prefix_map.map(filename)))?;
+        }
+    }
+    for file_patch in &mut patch.file_patches {
+        let use_index = file_patch.kind() != FilePatchKind::Create && !file_patch.is_rename() &&
+            file_patch.index_filename().is_some_and(|filename| file_exists(config, filename)) &&
+            !file_patch.old_filename().into_iter().chain(file_patch.new_filename())
+                .any(|filename| file_exists(config, filename));
+        if use_index {
+            file_patch.use_index_filename();
+        }
+
+        if config.follow_symlinks && file_patch.kind() == FilePatchKind::Modify && !file_patch.is_rename() {
+            file_patch.replace_filenames(|filename| symlink_target_file(config, filename))?;
+        }
+    }
+    Ok(patch)
+}
+
+/// Load the `series_patch` from the `ApplyConfig::patch_source`, or from the
+/// `patches_path` if there is none.
+pub fn load_series_patch<'arena>(config: &ApplyConfig, arena: &'arena dyn Arena, series_patch: &SeriesPatch)
+    -> Result<&'arena [u8], io::Error>
+{
+    match config.patch_source {
+        Some(patch_source) => patch_source.load_patch(arena, &series_patch.filename),
+        None => FilesystemSource::new(config.patches_path).load_patch(arena, &series_patch.filename),
+    }
+}
+
+/// Parse the `series_patch` from its `data`. With `ApplyConfig::auto_strip`,
+/// other strip levels are tried if the files are not found at the level from
+/// the series. Returns the patch and the strip level that was used.
+pub fn parse_series_patch<'a>(config: &ApplyConfig, series_patch: &SeriesPatch, data: &'a [u8])
+    -> Result<(TextPatch<'a>, usize)>
+{
+    let patch = parse_tree_patch(config, data, series_patch.strip)?;
+    if !config.auto_strip || patched_files_exist(config, &patch) {
+        return Ok((patch, series_patch.strip));
+    }
+
+    for strip in AUTO_STRIP_LEVELS.iter().copied().filter(|&strip| strip != series_patch.strip) {
+        // Some levels may strip more than the paths have.
+        if let Ok(other_patch) = parse_tree_patch(config, data, strip) {
+            if patched_files_exist(config, &other_patch) {
+                return Ok((other_patch, strip));
+            }
+        }
+    }
 
+    // Nothing better, let it fail normally.
+    Ok((patch, series_patch.strip))
+}
+
+/// Report that the `series_patch` is applied with a different `strip` level
+/// than the series says. (See `parse_series_patch`.)
+pub fn print_auto_strip(config: &ApplyConfig, series_patch: &SeriesPatch, strip: usize) {
+    if config.verbosity >= Verbosity::Normal && strip != series_patch.strip {
+        println!("Patch {} applied with -p{} instead of -p{}, its files were not found otherwise",
+                 series_patch.filename.display(), strip, series_patch.strip);
+    }
+}
 
 pub fn print_parser_warnings(
     config: &ApplyConfig,
@@ -38,13 +185,526 @@ pub fn print_parser_warnings(
     }
 }
 
-/// Build a ".rej" filename for given path.
-pub fn make_rej_filename<P: AsRef<Path>>(path: P) -> PathBuf {
-    let path = path.as_ref();
+/// Checks that the files touched by patches stay within the `base_dir`, so
+/// malicious patches can not write outside of it. Neither ".." in the paths
+/// nor symlinked directories may lead out. (See `ApplyConfig::unsafe_paths`.)
+#[derive(Debug)]
+pub struct PathGuard<'config> {
+    base_dir: &'config Path,
+
+    /// Canonical form of the `base_dir`
+    root: PathBuf,
+
+    /// Directories that were already found to be safe
+    safe_dirs: Mutex<HashSet<PathBuf, BuildHasherDefault<SeaHasher>>>,
+
+    /// The symlinks that the patches create and the directories that the
+    /// files touched by them are in, see `check_series_symlinks`
+    series_paths: Mutex<SeriesPaths>,
+}
+
+/// Paths seen by the `PathGuard`, each with the patch that touches it.
+#[derive(Debug, Default)]
+struct SeriesPaths {
+    symlinks: HashMap<PathBuf, PathBuf, BuildHasherDefault<SeaHasher>>,
+    dirs: HashMap<PathBuf, PathBuf, BuildHasherDefault<SeaHasher>>,
+}
+
+/// The `path` with "." left out and ".." taking back the previous component.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => { normalized.pop(); }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Is the file that the `file_patch` leaves behind a symlink?
+#[cfg(unix)]
+fn creates_symlink(file_patch: &TextFilePatch, reverse: bool) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    let permissions = if reverse { file_patch.old_permissions() } else { file_patch.new_permissions() };
+    permissions.is_some_and(|permissions| permissions.mode() & 0o170000 == 0o120000)
+}
 
-    match path.extension() {
-        Some(extension) => path.with_extension(extension.to_string_lossy().into_owned() + ".rej"),
-        None => path.with_extension("rej")
+#[cfg(not(unix))]
+fn creates_symlink(_file_patch: &TextFilePatch, _reverse: bool) -> bool {
+    false
+}
+
+impl<'config> PathGuard<'config> {
+    /// Create the guard for the `config`, unless it allows unsafe paths.
+    pub fn for_config(config: &'config ApplyConfig) -> Result<Option<Self>> {
+        if config.unsafe_paths {
+            return Ok(None);
+        }
+
+        let root = if config.base_dir.as_os_str().is_empty() { Path::new(".") } else { config.base_dir };
+        let root = fs::canonicalize(root)
+            .with_context(|| format!("Resolving working directory {:?}", root))?;
+
+        Ok(Some(Self {
+            base_dir: config.base_dir,
+            root,
+            safe_dirs: Mutex::new(HashSet::default()),
+            series_paths: Mutex::new(SeriesPaths::default()),
+        }))
+    }
+
+    /// Returns true if the `filename`, relative to the `base_dir`, is within it.
+    pub fn is_safe(&self, filename: &Path) -> Result<bool, io::Error> {
+        let mut depth = 0usize;
+        for component in filename.components() {
+            match component {
+                Component::Normal(_) => depth += 1,
+                Component::CurDir => {}
+                Component::ParentDir if depth > 0 => depth -= 1,
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => return Ok(false),
+            }
+        }
+
+        // The file itself may be a symlink leading out.
+        let path = self.base_dir.join(filename);
+        if path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            match fs::canonicalize(&path) {
+                Ok(resolved) if !resolved.starts_with(&self.root) => return Ok(false),
+                Ok(_) => {},
+                // A dangling symlink is replaced, not written through.
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+                Err(err) => return Err(err),
+            }
+        }
+
+        let parent = match filename.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => return Ok(true),
+        };
+
+        // NOTE(unwrap): If the lock is poisoned, another thread panicked. We may as well.
+        if self.safe_dirs.lock().unwrap().contains(parent) {
+            return Ok(true);
+        }
+
+        // Resolve the closest directory that exists. Missing directories will
+        // be created by us, so they can not lead anywhere else, unless the
+        // series creates a symlink there. That is `check_series_symlinks`.
+        let mut dir = parent;
+        let resolved = loop {
+            match fs::canonicalize(self.base_dir.join(dir)) {
+                Ok(resolved) => break resolved,
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => match dir.parent() {
+                    Some(dir_parent) if !dir_parent.as_os_str().is_empty() => dir = dir_parent,
+                    _ => return Ok(true),
+                },
+                Err(err) => return Err(err),
+            }
+        };
+
+        let safe = resolved.starts_with(&self.root);
+        if safe {
+            self.safe_dirs.lock().unwrap().insert(parent.to_path_buf());
+        }
+        Ok(safe)
+    }
+
+    /// Check that no file is in a directory that a patch of the series turns
+    /// into a symlink, which could lead anywhere once it is saved. The paths
+    /// are remembered, so it does not matter which patch is checked first.
+    fn check_series_symlinks(&self, patch_filename: &Path, patch: &TextPatch, reverse: bool) -> Result<()> {
+        let unsafe_path = |patch_filename: &Path, filename: &Path| -> Error {
+            ApplyError::UnsafePath {
+                patch_filename: patch_filename.to_path_buf(),
+                filename: filename.to_path_buf(),
+            }.into()
+        };
+
+        // NOTE(unwrap): If the lock is poisoned, another thread panicked. We may as well.
+        let mut series_paths = self.series_paths.lock().unwrap();
+        for file_patch in &patch.file_patches {
+            let symlink = match reverse {
+                false => file_patch.new_filename().or(file_patch.old_filename()),
+                true => file_patch.old_filename().or(file_patch.new_filename()),
+            };
+            if let Some(symlink) = symlink.filter(|_| creates_symlink(file_patch, reverse)) {
+                let symlink = normalize_path(symlink);
+                if let Some(other_patch_filename) = series_paths.dirs.get(&symlink) {
+                    return Err(unsafe_path(other_patch_filename, &symlink));
+                }
+                series_paths.symlinks.insert(symlink, patch_filename.to_path_buf());
+            }
+
+            for filename in file_patch.old_filename().into_iter().chain(file_patch.new_filename()) {
+                for dir in filename.ancestors().skip(1).map(normalize_path) {
+                    if dir.as_os_str().is_empty() {
+                        continue;
+                    }
+                    if series_paths.symlinks.contains_key(&dir) {
+                        return Err(unsafe_path(patch_filename, filename));
+                    }
+                    series_paths.dirs.entry(dir).or_insert_with(|| patch_filename.to_path_buf());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check all files touched by the `patch` from the `series_patch`.
+    pub fn check_patch(&self, series_patch: &SeriesPatch, patch: &TextPatch) -> Result<()> {
+        let patch_filename = &series_patch.filename;
+        for file_patch in &patch.file_patches {
+            for filename in file_patch.old_filename().into_iter().chain(file_patch.new_filename()) {
+                let safe = self.is_safe(filename)
+                    .with_context(|| format!("Checking path {:?}", filename))?;
+                if !safe {
+                    return Err(ApplyError::UnsafePath {
+                        patch_filename: patch_filename.to_path_buf(),
+                        filename: filename.to_path_buf(),
+                    }.into());
+                }
+            }
+        }
+        self.check_series_symlinks(patch_filename, patch, series_patch.reverse)
+    }
+}
+
+/// The `name` with the case of its letters swapped, `None` if it has no
+/// letters with case.
+fn swap_case(name: &OsStr) -> Option<OsString> {
+    let name = name.to_str()?;
+    let swapped: String = name.chars()
+        .flat_map(|c| if c.is_lowercase() { c.to_uppercase().collect::<Vec<_>>() } else { c.to_lowercase().collect() })
+        .collect();
+    (swapped != name).then(|| swapped.into())
+}
+
+/// Whether the `path` and the `other_path` are the same file.
+fn is_same_file(path: &Path, other_path: &Path) -> io::Result<bool> {
+    let other_metadata = match fs::symlink_metadata(other_path) {
+        Ok(metadata) => metadata,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = fs::symlink_metadata(path)?;
+        Ok(metadata.dev() == other_metadata.dev() && metadata.ino() == other_metadata.ino())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, other_metadata);
+        Ok(true)
+    }
+}
+
+/// Find out if the filesystem of the `dir` (or of its closest existing
+/// parent) ignores the case of filenames, like it usually does on macOS and
+/// Windows. Nothing is written, an entry of the directory is looked up with
+/// the case of its letters swapped. If no entry has letters, it is taken as
+/// case-sensitive.
+pub fn is_case_insensitive(dir: &Path) -> io::Result<bool> {
+    let mut dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    while !dir.exists() {
+        match dir.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => dir = parent,
+            _ => dir = Path::new("."),
+        }
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Some(swapped) = swap_case(&entry.file_name()) {
+            return is_same_file(&entry.path(), &dir.join(swapped));
+        }
+    }
+    Ok(false)
+}
+
+/// Checks that no two files touched by the patches differ only in the case of
+/// their names, when the output directory is on a case-insensitive
+/// filesystem. They would be the same file there, and one patch would
+/// silently overwrite the changes of the other. The patches must be checked in
+/// series order, so the first collision is reported.
+#[derive(Debug, Default)]
+pub struct CaseGuard {
+    /// The touched files and the patches that touched them first, by their
+    /// lowercase names
+    seen: HashMap<String, (PathBuf, PathBuf), BuildHasherDefault<SeaHasher>>,
+}
+
+impl CaseGuard {
+    /// Create the guard for the `config`, if its output directory is on a
+    /// case-insensitive filesystem.
+    pub fn for_config(config: &ApplyConfig) -> Result<Option<Self>> {
+        let output_dir = config.output_dir();
+        let case_insensitive = is_case_insensitive(output_dir)
+            .with_context(|| format!("Checking the case sensitivity of directory {:?}", output_dir))?;
+        Ok(case_insensitive.then(CaseGuard::default))
+    }
+
+    /// Check all files touched by the `patch` against each other and the
+    /// files touched by the patches checked before.
+    pub fn check_patch(&mut self, patch_filename: &Path, patch: &TextPatch) -> Result<()> {
+        for file_patch in &patch.file_patches {
+            for filename in file_patch.old_filename().into_iter().chain(file_patch.new_filename()) {
+                let key = filename.to_string_lossy().to_lowercase();
+                match self.seen.entry(key) {
+                    Entry::Occupied(entry) => {
+                        let (other_filename, other_patch_filename) = entry.get();
+                        if other_filename != filename.as_ref() {
+                            return Err(ApplyError::CaseCollision {
+                                patch_filename: patch_filename.to_path_buf(),
+                                filename: filename.to_path_buf(),
+                                other_patch_filename: other_patch_filename.clone(),
+                                other_filename: other_filename.clone(),
+                            }.into());
+                        }
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert((filename.to_path_buf(), patch_filename.to_path_buf()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that the `patch` touches only files accepted by the
+/// `ApplyConfig::allowed_files`.
+pub fn check_allowed_files(config: &ApplyConfig, patch_filename: &Path, patch: &TextPatch) -> Result<()> {
+    let Some(allowed_files) = config.allowed_files else {
+        return Ok(());
+    };
+
+    for file_patch in &patch.file_patches {
+        for filename in file_patch.old_filename().into_iter().chain(file_patch.new_filename()) {
+            if !allowed_files.is_included(filename) {
+                return Err(ApplyError::DisallowedFile {
+                    patch_filename: patch_filename.to_path_buf(),
+                    filename: filename.to_path_buf(),
+                }.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collect the files touched by every patch in the `config`, in series
+/// order. Both the old and the new filename count.
+pub fn touched_files_by_patch(config: &ApplyConfig, arena: &dyn Arena) -> Result<Vec<BTreeSet<PathBuf>>> {
+    let path_guard = PathGuard::for_config(config)?;
+
+    config.series_patches.iter().map(|series_patch| {
+        let patch = load_series_patch(config, arena, series_patch)
+            .map_err(Error::from)
+            .and_then(|data| parse_tree_patch(config, data, series_patch.strip))
+            .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+        if let Some(path_guard) = &path_guard {
+            path_guard.check_patch(series_patch, &patch)?;
+        }
+        check_allowed_files(config, &series_patch.filename, &patch)?;
+
+        let mut files = BTreeSet::new();
+        for file_patch in &patch.file_patches {
+            files.extend(file_patch.old_filename().map(|filename| filename.to_path_buf()));
+            files.extend(file_patch.new_filename().map(|filename| filename.to_path_buf()));
+        }
+        Ok(files)
+    }).collect()
+}
+
+/// Remove the `FilePatch`es for files not accepted by the
+/// `ApplyConfig::file_filter` from the `patch` with given `index`. Returns
+/// the removed files.
+pub fn filter_file_patches(
+    config: &ApplyConfig,
+    index: usize,
+    patch: &mut TextPatch)
+    -> Vec<PatchedFile>
+{
+    let file_filter = match config.file_filter {
+        Some(file_filter) => file_filter,
+        None => return Vec::new(),
+    };
+
+    let mut filtered_files = Vec::new();
+    patch.file_patches.retain(|file_patch| {
+        let included = file_patch.old_filename().into_iter()
+            .chain(file_patch.new_filename())
+            .any(|filename| file_filter.is_included(filename));
+        if !included {
+            // SAFETY: The parser guarantees that there is at least one of the filenames.
+            let filename = file_patch.old_filename().or_else(|| file_patch.new_filename()).expect("FilePatch must have a filename");
+            filtered_files.push(PatchedFile {
+                index,
+                patch_filename: config.series_patches[index].filename.clone(),
+                filename: filename.to_path_buf(),
+            });
+        }
+        included
+    });
+    filtered_files
+}
+
+/// Count the `FilePatch`es of the patch with `index` that failed, and how
+/// many of them failed only because `ApplyConfig::strict` applied them again
+/// exactly.
+pub fn count_failed_file_patches(applied_patches: &[PatchStatus], index: usize) -> (usize, usize) {
+    applied_patches.iter()
+        .filter(|patch_status| patch_status.index == index && patch_status.report.failed())
+        .fold((0, 0), |(failed, inexact), patch_status| (failed + 1, inexact + patch_status.inexact as usize))
+}
+
+/// Collect the files patched by patches before `final_patch` for which
+/// `filter` returns true.
+pub fn collect_patched_files<F: Fn(&PatchStatus) -> bool>(
+    applied_patches: &[PatchStatus],
+    final_patch: usize,
+    filter: F)
+    -> Vec<PatchedFile>
+{
+    applied_patches.iter()
+        .filter(|patch_status| patch_status.index < final_patch && filter(patch_status))
+        .map(|patch_status| PatchedFile {
+            index: patch_status.index,
+            patch_filename: patch_status.patch_filename.to_path_buf(),
+            filename: patch_status.final_filename.to_path_buf(),
+        })
+        .collect()
+}
+
+/// Collect the content of the `modified_files`, sorted by filename. Files
+/// that neither existed before nor after the patches are left out.
+pub fn collect_changed_files(modified_files: &ModifiedFiles) -> Result<Vec<ChangedFile>, io::Error> {
+    let mut changed_files = Vec::new();
+    for (filename, file) in modified_files.iter() {
+        if file.deleted && !file.existed {
+            continue;
+        }
+
+        // The patched file may be loaded only partially, get all of it.
+        let content = if file.deleted {
+            None
+        } else {
+            let mut content = Vec::new();
+            file.write_to(&mut content)?;
+            Some(content)
+        };
+        changed_files.push(ChangedFile {
+            filename: filename.to_path_buf(),
+            content,
+            permissions: file.permissions.clone(),
+        });
+    }
+
+    changed_files.sort_by(|a, b| a.filename.cmp(&b.filename));
+    Ok(changed_files)
+}
+
+/// Count the hunks, the ones that needed fuzz, and changed bytes of the
+/// patches before `final_patch`, from the `applied_patches`. Files left unchanged because they were
+/// already patched are not counted.
+pub fn count_patch_sizes(config: &ApplyConfig, applied_patches: &[PatchStatus], final_patch: usize) -> Vec<PatchSize> {
+    let mut patch_sizes: Vec<_> = config.series_patches[..final_patch].iter()
+        .map(|series_patch| PatchSize { patch_filename: series_patch.filename.clone(), ..PatchSize::default() })
+        .collect();
+
+    let line_bytes = |lines: &[&[u8]]| lines.iter().map(|line| line.len()).sum::<usize>();
+    for patch_status in applied_patches {
+        if patch_status.index >= final_patch || patch_status.already_applied {
+            continue;
+        }
+
+        let patch_size = &mut patch_sizes[patch_status.index];
+        for (hunk, hunk_report) in patch_status.file_patch.hunks().iter().zip(patch_status.report.hunk_reports()) {
+            let remove = &hunk.remove.content[hunk.prefix_context..hunk.remove.content.len() - hunk.suffix_context];
+            let add = &hunk.add.content[hunk.prefix_context..hunk.add.content.len() - hunk.suffix_context];
+            let (added, removed) = if config.series_patches[patch_status.index].reverse {
+                (remove, add)
+            } else {
+                (add, remove)
+            };
+
+            patch_size.hunks += 1;
+            if let HunkApplyReport::Applied { fuzz, .. } = hunk_report {
+                patch_size.fuzzy_hunks += usize::from(*fuzz > 0);
+            }
+            patch_size.added_bytes += line_bytes(added);
+            patch_size.removed_bytes += line_bytes(removed);
+        }
+    }
+    patch_sizes
+}
+
+/// Sum up the times of the patches before `final_patch` from the
+/// `applied_patches`, and find the longest chain of them that had to be
+/// applied one after another, because they change the same files.
+pub fn count_patch_timings(config: &ApplyConfig, applied_patches: &[PatchStatus], final_patch: usize)
+    -> (Vec<PatchTiming>, Option<CriticalPath>)
+{
+    let mut patch_timings: Vec<_> = config.series_patches[..final_patch].iter()
+        .map(|series_patch| PatchTiming { patch_filename: series_patch.filename.clone(), ..PatchTiming::default() })
+        .collect();
+
+    // The files renamed into each other are one chain, like when they are
+    // assigned to threads.
+    let applied_patches: Vec<_> = applied_patches.iter()
+        .filter(|patch_status| patch_status.index < final_patch)
+        .collect();
+    let mut filename_distributor = FilenameDistributor::<&Path>::new(1);
+    for patch_status in &applied_patches {
+        let renamed_to = Some(&*patch_status.final_filename).filter(|filename| *filename != &*patch_status.target_filename);
+        filename_distributor.add(&patch_status.target_filename, renamed_to);
+    }
+    let filename_to_component = filename_distributor.components();
+
+    // The time, the first file and the last patch of every chain, and how
+    // many patches it has.
+    let mut chains: HashMap<usize, (CriticalPath, usize)> = HashMap::new();
+    for patch_status in &applied_patches {
+        patch_timings[patch_status.index].duration += patch_status.duration;
+
+        let component = filename_to_component[&*patch_status.target_filename];
+        let (chain, last_index) = chains.entry(component).or_insert_with(|| {
+            (CriticalPath { filename: patch_status.target_filename.to_path_buf(), ..CriticalPath::default() }, usize::MAX)
+        });
+        chain.duration += patch_status.duration;
+        if *last_index != patch_status.index {
+            chain.patches += 1;
+            *last_index = patch_status.index;
+        }
+    }
+
+    // The first one of the slowest, so it is the same every time.
+    let critical_path = chains.into_values()
+        .map(|(chain, _)| chain)
+        .max_by(|a, b| a.duration.cmp(&b.duration).then_with(|| b.filename.cmp(&a.filename)));
+    (patch_timings, critical_path)
+}
+
+/// Build a ".rej" filename for given path, with the `suffix` appended.
+pub fn make_rej_filename<P: AsRef<Path>>(path: P, suffix: &str) -> PathBuf {
+    let mut rej_filename = path.as_ref().as_os_str().to_owned();
+    rej_filename.push(suffix);
+    PathBuf::from(rej_filename)
+}
+
+/// The ".rej" filename for the file with `filename`, in the reject directory
+/// if there is one. Otherwise it is relative to the output directory.
+fn config_rej_filename(config: &ApplyConfig, filename: &Path) -> PathBuf {
+    let rej_filename = make_rej_filename(filename, config.reject_suffix);
+    match config.reject_dir {
+        // Absolute paths (see `ApplyConfig::unsafe_paths`) go into it too.
+        Some(reject_dir) => reject_dir.join(rej_filename.components()
+                                            .filter(|component| !matches!(component, Component::Prefix(_) | Component::RootDir))
+                                            .collect::<PathBuf>()),
+        None => rej_filename,
     }
 }
 
@@ -109,8 +769,78 @@ where P: AsRef<Path>,
     Ok(())
 }
 
+/// Load the file as it is on disk, for comparison. Returns `None` if the file
+/// does not exist.
+pub fn load_existing_file<'arena>(arena: &'arena dyn Arena, path: &Path) -> Result<Option<ModifiedFile<'arena>>, io::Error> {
+    match arena.load_metadata(path) {
+        Ok(metadata) => {
+            let data = if metadata.is_symlink() {
+                arena.load_symlink_target(path)?
+            } else {
+                arena.load_file(path)?
+            };
+            Ok(Some(ModifiedFile::new(data, true, Some(metadata.permissions()))))
+        }
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Whether the `file` is saved as a symlink, its content is the target.
+#[cfg(unix)]
+fn is_symlink(file: &ModifiedFile) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    file.permissions.as_ref().is_some_and(|permissions| permissions.mode() & 0o170000 == 0o120000)
+}
+
+#[cfg(not(unix))]
+fn is_symlink(_file: &ModifiedFile) -> bool {
+    false
+}
+
+/// The number of hard links of the file at `path`, 1 if it does not exist.
+#[cfg(unix)]
+fn hard_link_count(path: &Path) -> Result<u64, io::Error> {
+    use std::os::unix::fs::MetadataExt;
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => Ok(metadata.nlink()),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(1),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+fn hard_link_count(_path: &Path) -> Result<u64, io::Error> {
+    Ok(1)
+}
+
+/// Write the `file` into the existing file at `file_path`, so its other hard
+/// links see the new content too (see `ApplyConfig::preserve_hard_links`).
+fn write_through_hard_links(config: &ApplyConfig, file_path: &Path, file: &ModifiedFile) -> Result<(), io::Error> {
+    // The content may still come from the file, e.g. its lazily loaded tail.
+    let mut content = Vec::new();
+    file.write_to(&mut content)?;
+
+    let mut output = File::options().write(true).truncate(true).open(file_path)?;
+    if let Some(ref permissions) = file.permissions {
+        output.set_permissions(permissions.clone())?;
+    }
+    output.write_all(&content)?;
+    if let Some(touch) = config.touch {
+        output.set_modified(touch)?;
+    }
+    Ok(())
+}
+
 /// Save the `file` to disk. It also takes care of creating/deleting the file
 /// and containing directories.
+///
+/// A regular file is written into a temporary file in the same directory
+/// and renamed over the original, so it is never seen half written. The
+/// temporary file is not created in `$TMPDIR`, the rename only works within
+/// one filesystem. That also breaks hard links of the original, unless
+/// `ApplyConfig::preserve_hard_links` says to write through them.
+#[allow(clippy::ptr_arg)] // We need to know whether `filename` is borrowed from the arena.
 pub fn save_modified_file<'arena, H: BuildHasher>(
     config: &ApplyConfig,
     filename: &Cow<'arena, Path>,
@@ -122,11 +852,17 @@ pub fn save_modified_file<'arena, H: BuildHasher>(
         println!("Saving modified file: {:?}: existed: {:?} deleted: {:?} len: {}", filename.as_ref(), file.existed, file.deleted, file.content.len());
     }
 
-    let file_path = config.base_dir.join(filename);
-    if file.existed {
-        // If the file file existed, delete it. Whether we want to overwrite it
-        // or really delete it - the file may be a hard link and we must replace
-        // it with a new one, not edit the shared content.
+    // Recorded first, while the original is still there.
+    if let Some(manifest) = config.manifest {
+        manifest.record(&config.base_dir.join(filename), filename, file)?;
+    }
+
+    let file_path = config.output_dir().join(filename);
+    if file.existed && (file.deleted || is_symlink(file)) {
+        // If the file existed, delete it. Whether we want to replace it with
+        // a symlink or really delete it - the file may be a hard link and we
+        // must replace it with a new one, not edit the shared content. The
+        // rename of a regular file replaces it too.
         match fs::remove_file(&file_path) {
             Ok(_) => {
                 // All is good.
@@ -156,43 +892,181 @@ pub fn save_modified_file<'arena, H: BuildHasher>(
             if let Some(parent) = parent {
                 directories_for_cleaning.insert(parent);
             }
+
+            // The original is still in the lower layer, hide it.
+            if config.overlay_whiteouts {
+                if let (Some(parent), Some(name)) = (file_path.parent(), file_path.file_name()) {
+                    fs::create_dir_all(parent)?;
+                    let mut whiteout_name = OsString::from(".wh.");
+                    whiteout_name.push(name);
+                    File::create(parent.join(whiteout_name))?;
+                }
+            }
         }
     } else {
         // If the file is not tracked as deleted, re-create it with the next content.
-        if !file.existed {
+        if !file.existed || config.out_dir.is_some() {
             // If the file is new, the directory may be new as well. Let's
-            // create it now.
-                        if let Some(parent) = file_path.parent() {
-                            fs::create_dir_all(parent)?;
-                        }
-                    }
-            
-                    #[cfg(unix)]
-                    {
-                        use std::os::unix::fs::PermissionsExt;
-                        if let Some(ref permissions) = file.permissions {
-                            if permissions.mode() & 0o170000 == 0o120000 {
-                                let mut target = Vec::new();
-                                file.write_to(&mut target)?;
-                                let target_str = std::str::from_utf8(&target).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                                return std::os::unix::fs::symlink(target_str, file_path);
-                            }
-                        }
+            // create it now. The output directory starts empty, so there it
+            // is needed for every file.
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        #[cfg(unix)]
+        if is_symlink(file) {
+            let mut target = Vec::new();
+            file.write_to(&mut target)?;
+            let target_str = std::str::from_utf8(&target).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            std::os::unix::fs::symlink(target_str, &file_path)?;
+            restore_owner(config, &file_path, file);
+            return Ok(());
+        }
+
+        if file.existed && config.out_dir.is_none() && (config.preserve_hard_links || config.verbosity >= Verbosity::Verbose) {
+            let links = hard_link_count(&file_path)?;
+            if links > 1 {
+                if config.verbosity >= Verbosity::Verbose {
+                    if config.preserve_hard_links {
+                        println!("Writing {} through its {} hard links", filename.display(), links);
+                    } else {
+                        println!("Breaking the hard link of {} to {} other files", filename.display(), links - 1);
                     }
-            
-                    let mut output = File::create(file_path)?;
+                }
+                if config.preserve_hard_links {
+                    return write_through_hard_links(config, &file_path, file);
+                }
+            }
+        }
+
+        let directory = match file_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".rapidquilt-");
+        // A new file gets the default permissions, not the private ones of
+        // a temporary file.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            builder.permissions(fs::Permissions::from_mode(0o666));
+        }
+        let mut output = builder.tempfile_in(directory)?;
 
         // If any patch set non-default permission, set them now
         if let Some(ref permissions) = file.permissions {
-            output.set_permissions(permissions.clone())?;
+            output.as_file().set_permissions(permissions.clone())?;
         }
 
         file.write_to(&mut output)?;
+
+        if let Some(touch) = config.touch {
+            output.as_file().set_modified(touch)?;
+        }
+
+        // The temporary file is deleted if this fails.
+        output.persist(&file_path).map_err(|err| err.error)?;
+
+        #[cfg(unix)]
+        restore_owner(config, &file_path, file);
     }
 
     Ok(())
 }
 
+/// Save the `file` to disk like `save_modified_file` and record the change
+/// in the `audit_log`.
+#[allow(clippy::ptr_arg)] // Passed on to `save_modified_file`.
+fn save_audited_file<'arena, H: BuildHasher>(
+    config: &ApplyConfig,
+    audit_log: &AuditLog,
+    filename: &Cow<'arena, Path>,
+    file: &ModifiedFile,
+    patch_filename: Option<&Path>,
+    directories_for_cleaning: &mut HashSet<Cow<'arena, Path>, H>)
+    -> Result<()>
+{
+    let operation = match (file.existed, file.deleted) {
+        (false, false) => AuditOperation::Create,
+        (true, false) => AuditOperation::Modify,
+        (true, true) => AuditOperation::Delete,
+        (false, true) => {
+            // It never existed and it doesn't exist now, there is nothing to save.
+            return Ok(());
+        }
+    };
+
+    let old_hash = hash_file_on_disk(&config.base_dir.join(filename))
+        .with_context(|| ApplyError::SaveModifiedFile { filename: filename.to_path_buf() })?;
+
+    save_modified_file(config, filename, file, directories_for_cleaning)
+        .with_context(|| ApplyError::SaveModifiedFile { filename: filename.to_path_buf() })?;
+
+    let new_hash = if file.deleted {
+        None
+    } else {
+        Some(hash_modified_file(file)?)
+    };
+
+    audit_log.record(&AuditRecord {
+        path: filename,
+        operation,
+        old_hash,
+        new_hash,
+        patch: patch_filename,
+    }).context(ApplyError::WriteAuditLog)
+}
+
+/// Run the `ApplyConfig::post_hook` command after the patch `patch_filename`
+/// was applied and the `filenames` saved. Returns the exit status of the
+/// command.
+pub fn run_post_hook(config: &ApplyConfig, post_hook: &str, patch_filename: &Path, filenames: &[Cow<Path>])
+    -> Result<ExitStatus>
+{
+    let files = filenames.iter().map(|filename| filename.to_string_lossy()).join("\n");
+
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(post_hook)
+        .env("RAPIDQUILT_PATCH", patch_filename)
+        .env("RAPIDQUILT_FILES", files);
+    if !config.output_dir().as_os_str().is_empty() {
+        command.current_dir(config.output_dir());
+    }
+
+    command.status()
+        .with_context(|| format!("Failed to run post-hook for patch {:?}", patch_filename))
+}
+
+/// Give the re-created file back to the owner of the original file, if it is
+/// requested by `ApplyConfig::preserve_ownership`. Failure is only a warning,
+/// unprivileged users can not give files away.
+#[cfg(unix)]
+fn restore_owner(config: &ApplyConfig, file_path: &Path, file: &ModifiedFile) {
+    use std::os::unix::fs::MetadataExt;
+
+    if !config.preserve_ownership {
+        return;
+    }
+
+    let Some((uid, gid)) = file.owner else {
+        return;
+    };
+
+    // Nothing to do if we created the file with the right owner already.
+    if let Ok(metadata) = fs::symlink_metadata(file_path) {
+        if (metadata.uid(), metadata.gid()) == (uid, gid) {
+            return;
+        }
+    }
+
+    if let Err(err) = std::os::unix::fs::lchown(file_path, Some(uid), Some(gid)) {
+        eprintln!("{} Could not restore the owner ({}:{}) of {}: {}",
+                  prefix_warning(), uid, gid, file_path.display(), err);
+    }
+}
+
 #[derive(Debug)]
 pub struct ModifiedFiles<'arena, 'config> {
     config: &'config ApplyConfig<'config>,
@@ -221,14 +1095,62 @@ impl<'arena, 'config> ModifiedFiles<'arena, 'config> {
 
     /// Save all `modified_files` to disk. It also takes care of
     /// creating/deleting the files and containing directories.
+    ///
+    /// `applied_patches` are used to find which patch changed each file for
+    /// the audit log.
     pub fn save<H: BuildHasher>(
         &self,
+        applied_patches: &[PatchStatus],
         directories_for_cleaning: &mut HashSet<Cow<'arena, Path>, H>)
         -> Result<()>
     {
+        let audit_log = match self.config.audit_log {
+            Some(audit_log) => audit_log,
+            None => {
+                for (filename, file) in self.iter() {
+                    save_modified_file(self.config, filename, file, directories_for_cleaning)
+                        .with_context(|| ApplyError::SaveModifiedFile { filename: filename.to_path_buf() })?;
+                }
+                return Ok(());
+            }
+        };
+
+        // The last patch that touched each file
+        let mut last_patches = HashMap::<&Path, &Path, BuildHasherDefault<SeaHasher>>::default();
+        for applied_patch in applied_patches {
+            last_patches.insert(&applied_patch.target_filename, applied_patch.patch_filename);
+            last_patches.insert(&applied_patch.final_filename, applied_patch.patch_filename);
+        }
+
         for (filename, file) in self.iter() {
-            save_modified_file(self.config, filename, file, directories_for_cleaning)
-                .with_context(|| ApplyError::SaveModifiedFile { filename: filename.to_path_buf() })?;
+            save_audited_file(self.config, audit_log, filename, file,
+                              last_patches.get(filename.as_ref()).copied(), directories_for_cleaning)?;
+        }
+
+        Ok(())
+    }
+
+    /// Save the files with `filenames` to disk right away, as changed by the
+    /// patch `patch_filename`. (See `ApplyConfig::post_hook`.) Afterwards the
+    /// files count as originally existing if they exist on disk now.
+    pub fn save_files<H: BuildHasher>(
+        &mut self,
+        filenames: &[Cow<'arena, Path>],
+        patch_filename: &Path,
+        directories_for_cleaning: &mut HashSet<Cow<'arena, Path>, H>)
+        -> Result<()>
+    {
+        let config = self.config;
+        for filename in filenames {
+            // SAFETY: The caller passes only names of files it loaded.
+            let file = self.inner.get_mut(filename).expect("File must be loaded");
+            match config.audit_log {
+                Some(audit_log) => save_audited_file(config, audit_log, filename, file, Some(patch_filename),
+                                                     directories_for_cleaning)?,
+                None => save_modified_file(config, filename, file, directories_for_cleaning)
+                    .with_context(|| ApplyError::SaveModifiedFile { filename: filename.to_path_buf() })?,
+            }
+            file.existed = !file.deleted;
         }
 
         Ok(())
@@ -249,16 +1171,19 @@ impl<'arena, 'config> ModifiedFiles<'arena, 'config> {
 
             Entry::Vacant(entry) => {
                 let real_path = config.base_dir.join(filename);
-                match fs::symlink_metadata(&real_path) {
+                match arena.load_metadata(&real_path) {
                     Ok(metadata) => {
-                        // Note: We use metadata.file_type().is_symlink() instead of metadata.is_symlink()
-                        // to maintain compatibility with Rust versions older than 1.58.
-                        let data = if metadata.file_type().is_symlink() {
-                            arena.load_symlink_target(&real_path)?
+                        let (data, tail) = if metadata.is_symlink() {
+                            (arena.load_symlink_target(&real_path)?, None)
+                        } else if let Some(head_size) = config.lazy_load.filter(|&size| metadata.size > size as u64) {
+                            // Big file, load only its start for now
+                            arena.load_file_head(&real_path, head_size)?
                         } else {
-                            arena.load_file(&real_path)?
+                            (arena.load_file(&real_path)?, None)
                         };
-                        entry.insert(ModifiedFile::new(data, true, Some(metadata.permissions())))
+                        let mut file = ModifiedFile::new_partial(data, tail, true, Some(metadata.permissions()));
+                        file.owner = metadata.owner();
+                        entry.insert(file)
                     }
 
                     // If the file doesn't exist, make empty one.
@@ -273,6 +1198,15 @@ impl<'arena, 'config> ModifiedFiles<'arena, 'config> {
         Ok(item)
     }
 
+    /// Undoes single applied `FilePatch` that is not going to be applied
+    /// after all. Unlike `rollback`, it leaves alone the files that were
+    /// already patched, the `FilePatch` did not change them.
+    pub fn undo(&mut self, applied_patch: &PatchStatus<'arena, '_>) {
+        if !applied_patch.already_applied {
+            self.rollback(applied_patch);
+        }
+    }
+
     /// Rolls back single applied `FilePatch`
     pub fn rollback(
         &mut self,
@@ -283,7 +1217,7 @@ impl<'arena, 'config> ModifiedFiles<'arena, 'config> {
         // during the forward application of this patch.
         let file = self.get_mut(&applied_patch.final_filename).expect("File must be loaded during application");
 
-        applied_patch.file_patch.rollback(file, PatchDirection::Forward, &applied_patch.report);
+        applied_patch.file_patch.rollback(file, applied_patch.direction, &applied_patch.report);
 
 
         if applied_patch.file_patch.is_rename() {
@@ -324,7 +1258,58 @@ pub fn create_file(filename: &Path, permissions: &Option<fs::Permissions>)
     Ok(f)
 }
 
-/// Write the `original_file` as a quilt backup file.
+/// Compute the git blob hash of the `file`, which must be fully loaded.
+fn git_blob_hash(file: &ModifiedFile) -> Result<String, io::Error> {
+    let size: usize = file.content.iter().map(|line| line.len()).sum();
+    let mut hasher = Sha1::new_with_prefix(format!("blob {}\0", size));
+    file.write_to(&mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Result of `check_index`
+enum IndexCheck {
+    /// The patch has no "index" line
+    NoIndex,
+    Match,
+    /// The file does not match, the hashes are abbreviated to the length
+    /// used in the patch.
+    Mismatch { actual: String, expected: String },
+}
+
+/// Compare the `file` with the blob hash in the "index" line of the git
+/// `file_patch`, which may be abbreviated. All zeros stand for a file that
+/// does not exist.
+fn check_index(file_patch: &TextFilePatch, file: &mut ModifiedFile, direction: PatchDirection)
+    -> Result<IndexCheck, io::Error>
+{
+    let expected = match direction {
+        PatchDirection::Forward => file_patch.old_hash(),
+        PatchDirection::Revert => file_patch.new_hash(),
+    };
+    let Some(expected) = expected else {
+        return Ok(IndexCheck::NoIndex);
+    };
+    let expected = String::from_utf8_lossy(expected).to_ascii_lowercase();
+    let expected_missing = expected.bytes().all(|c| c == b'0');
+
+    if file.deleted {
+        if expected_missing {
+            return Ok(IndexCheck::Match);
+        }
+        return Ok(IndexCheck::Mismatch { actual: "no file".to_string(), expected });
+    }
+
+    file.load_all()?;
+    let mut actual = git_blob_hash(file)?;
+    if !expected_missing && actual.starts_with(&expected) {
+        return Ok(IndexCheck::Match);
+    }
+    actual.truncate(expected.len());
+    Ok(IndexCheck::Mismatch { actual, expected })
+}
+
+/// Write the `original_file` as a quilt backup file into the
+/// `ApplyConfig::backup_store`.
 pub fn save_backup_file(config: &ApplyConfig,
                         patch_filename: &Path,
                         filename: &Path,
@@ -339,30 +1324,51 @@ pub fn save_backup_file(config: &ApplyConfig,
         println!("Saving backup file {:?}", path);
     }
 
-     let real_path = config.base_dir.join(&path);
-    // SAFETY: We know that there is a parent; we built the path ourselves using `.pc/<patch_filename>/<filename>`.
-    let path_parent = real_path.parent().expect("Backup path must have a parent");
-    fs::create_dir_all(path_parent)?;
+    let result = match config.backup_store {
+        Some(backup_store) => backup_store.save(patch_filename, filename, original_file),
+        None => FileBackupStore::new(config.base_dir).save(patch_filename, filename, original_file),
+    };
+    result.with_context(|| ApplyError::SaveQuiltBackupFile { filename: path })
+}
 
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        if let Some(ref permissions) = original_file.permissions {
-            if permissions.mode() & 0o170000 == 0o120000 {
-                let mut target = Vec::new();
-                original_file.write_to(&mut target)?;
-                let target_str = std::str::from_utf8(&target).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                return std::os::unix::fs::symlink(target_str, &real_path)
-                    .with_context(|| ApplyError::SaveQuiltBackupFile { filename: path });
-            }
-        }
+/// Check if the `file` is already patched by the `file_patch`, which just
+/// failed to apply on it with `failed_report`. That is the case if the patch
+/// can be reverted and applied again, ending up with the same content.
+///
+/// If so, the `file` is left unchanged and a report for applying the patch on
+/// the reverted file is returned, so the backup file can be made by rolling
+/// the patch back. If the patch fails after all, the `file` must be left
+/// alone, see `ModifiedFiles::undo`. Otherwise the `file` is left as it was
+/// after the failed application.
+fn test_already_applied<'arena>(
+    config: &ApplyConfig,
+    file_patch: &TextFilePatch<'arena>,
+    file: &mut ModifiedFile<'arena>,
+    direction: PatchDirection,
+    deadline: Option<Instant>,
+    failed_report: &FilePatchApplyReport<'arena>)
+    -> Option<FilePatchApplyReport<'arena>>
+{
+    // Take back the hunks that did apply
+    let mut original = file.clone();
+    file_patch.rollback(&mut original, direction, failed_report);
+
+    let mut reverted = original.clone();
+    let revert_report = file_patch.apply(&mut reverted, direction.opposite(), config.fuzz, config.max_offset, config.strict_ambiguity, config.unordered_hunks, false, deadline, config.batch_threshold,
+                                         &AnalysisSet::default(), &fn_analysis_note_noop);
+    if revert_report.failed() {
+        return None;
     }
 
-    let mut f = create_file(&real_path, &original_file.permissions)?;
-    original_file.write_to(&mut f)
-        .with_context(|| ApplyError::SaveQuiltBackupFile { filename: path })?;
+    let mut reapplied = reverted;
+    let apply_report = file_patch.apply(&mut reapplied, direction, config.fuzz, config.max_offset, config.strict_ambiguity, config.unordered_hunks, false, deadline, config.batch_threshold,
+                                        &AnalysisSet::default(), &fn_analysis_note_noop);
+    if apply_report.failed() || reapplied.content != original.content || reapplied.deleted != original.deleted {
+        return None;
+    }
 
-    Ok(())
+    *file = original;
+    Some(apply_report)
 }
 
 #[derive(Debug)]
@@ -375,6 +1381,10 @@ pub struct PatchStatus<'arena, 'config> {
     /// The applied `FilePatch`
     pub file_patch: TextFilePatch<'arena>,
 
+    /// The direction it was applied in, `PatchDirection::Revert` for patches
+    /// with "-R" in the series.
+    pub direction: PatchDirection,
+
     /// Which filename was actually patched. Because patch has to choose between
     /// `old_filename` and `new_filename` (in some cases even more) based on
     /// which files exist on the disk.
@@ -387,10 +1397,26 @@ pub struct PatchStatus<'arena, 'config> {
     pub final_filename: Cow<'arena, Path>,
 
     /// Report from `FilePatch::apply`
-    pub report: FilePatchApplyReport,
+    pub report: FilePatchApplyReport<'arena>,
 
     /// The filename of the patch file, e.g. "blabla.patch"
     pub patch_filename: &'config Path,
+
+    /// The file was already in the patched state (see `--reverse-if-applied`).
+    /// The `report` then describes applying the patch on the reverted file.
+    pub already_applied: bool,
+
+    /// The patch left the file byte-identical, with the same permissions.
+    /// Only checked with `ApplyConfig::report_unchanged`.
+    pub unchanged: bool,
+
+    /// The hunks matched only with fuzz or at an offset, so they were applied
+    /// again exactly, because of `ApplyConfig::strict`.
+    pub inexact: bool,
+
+    /// How long it took to load the file and apply the `FilePatch`. Only
+    /// measured with `ApplyConfig::timings`.
+    pub duration: Duration,
 }
 
 /// Decides which filename to use, old or new, depending on which
@@ -458,12 +1484,98 @@ pub fn choose_filename_to_patch<'arena, 'filename>(
     }
 }
 
+/// Save the ".rej" file with the hunks of the `applied_patch` that failed.
+/// Returns its path, or `None` if its directory does not exist.
+pub fn save_rej_file(config: &ApplyConfig, applied_patch: &PatchStatus) -> Result<Option<PathBuf>> {
+    let rej_filename = config_rej_filename(config, &applied_patch.target_filename);
+
+    let rej_path = match config.reject_dir {
+        Some(_) => rej_filename.clone(),
+        None => config.output_dir().join(&rej_filename),
+    };
+
+    // The reject goes where it would go in the source tree, so
+    // mirror its directory in the output directory. The reject directory
+    // mirrors the tree even where it does not exist.
+    if config.reject_dir.is_some() {
+        if let Some(parent) = rej_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| ApplyError::SaveRejectFile { filename: rej_filename.clone() })?;
+        }
+    } else if config.out_dir.is_some() {
+        if let Some(parent) = rej_filename.parent().filter(|parent| config.base_dir.join(parent).is_dir()) {
+            fs::create_dir_all(config.output_dir().join(parent))
+                .with_context(|| ApplyError::SaveRejectFile { filename: rej_filename.clone() })?;
+        }
+    }
+
+    let rej_old_hash = match config.audit_log {
+        Some(_) => hash_file_on_disk(&rej_path)
+            .with_context(|| ApplyError::SaveRejectFile { filename: rej_filename.clone() })?,
+        None => None,
+    };
+
+    let file = match File::create(&rej_path) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut writer = BufWriter::new(file);
+
+    applied_patch.file_patch.write_rej_to(&mut writer, &applied_patch.report).
+        with_context(|| ApplyError::SaveRejectFile { filename: rej_filename.clone() })?;
+
+    if let Some(audit_log) = config.audit_log {
+        writer.flush()
+            .with_context(|| ApplyError::SaveRejectFile { filename: rej_filename.clone() })?;
+
+        let new_hash = hash_file_on_disk(&rej_path)
+            .with_context(|| ApplyError::SaveRejectFile { filename: rej_filename.clone() })?;
+
+        audit_log.record(&AuditRecord {
+            path: &rej_filename,
+            operation: if rej_old_hash.is_some() { AuditOperation::Modify } else { AuditOperation::Create },
+            old_hash: rej_old_hash,
+            new_hash,
+            patch: Some(applied_patch.patch_filename),
+        }).context(ApplyError::WriteAuditLog)?;
+    }
+
+    Ok(Some(rej_path))
+}
+
+/// Messages printed while applying the patches and saving the results. They
+/// are either printed right away, or kept to be printed in the end in series
+/// order. (See `ApplyConfig::deterministic`.)
+#[derive(Debug, Default)]
+pub struct OutputBuffer {
+    pub buffered: bool,
+    pub messages: Vec<BufferedMessage>,
+}
+
+impl OutputBuffer {
+    /// Print the `text` about the patch with `index`. It must include the
+    /// newline.
+    pub fn print(&mut self, index: usize, stream: OutputStream, text: String) {
+        if self.buffered {
+            self.messages.push(BufferedMessage { index, stream, text });
+        } else {
+            match stream {
+                OutputStream::Stdout => print!("{}", text),
+                OutputStream::Stderr => eprint!("{}", text),
+            }
+        }
+    }
+}
+
 /// Contains the states of applied patches
 #[derive(Debug)]
 pub struct AppliedState<'arena, 'config> {
     pub config: &'config ApplyConfig<'config>,
     pub applied_patches: Vec::<PatchStatus<'arena, 'config>>,
     pub modified_files: ModifiedFiles<'arena, 'config>,
+    pub output: OutputBuffer,
 }
 
 impl<'arena, 'config> AppliedState<'arena, 'config> {
@@ -473,6 +1585,24 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
             config,
             applied_patches: Vec::with_capacity(capacity),
             modified_files: ModifiedFiles::new(config),
+            output: OutputBuffer::default(),
+        }
+    }
+
+    /// Forget the applied patches and the modified files, and release the
+    /// files that were loaded to patch them in the `arena` (see
+    /// `Arena::release_file`). Call this only when done with both.
+    pub fn release_files(&mut self, arena: &dyn Arena) {
+        let paths: Vec<PathBuf> = self.modified_files.keys()
+            .map(|filename| self.config.base_dir.join(filename))
+            .collect();
+        self.applied_patches.clear();
+        self.modified_files.clear();
+
+        for path in paths {
+            // SAFETY: Only the modified files and the reports of the applied
+            // patches refer to their content, both are gone now.
+            unsafe { arena.release_file(&path); }
         }
     }
 
@@ -481,6 +1611,7 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
     /// `config`: The configuration of the task.
     /// `index`: Index of the **patch** in the configuration.
     /// `file_patch`: The `FilePatch` that should be applied.
+    /// `deadline`: When the patch runs out of time. (See `patch_deadline`.)
     /// `applied_patches`: Vector of `PatchStatus`es with reports of previously applied patches. Report for this one will be appended in.
     /// `modified_files`: HashMap of modified files so far. The currently patched file will be taken from or added to here.
     /// `arena`: For loading files.
@@ -490,6 +1621,84 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
         &mut self,
         index: usize,
         file_patch: TextFilePatch<'arena>,
+        deadline: Option<Instant>,
+        arena: &'arena dyn Arena,
+        analyses: &AnalysisSet,
+        fn_analysis_note: &dyn Fn(&dyn Note, &TextFilePatch))
+        -> Result<bool>
+    {
+        let config = self.config;
+        self.apply_one_file_patch_with(index, file_patch, config.force, config.reverse_if_applied,
+                                       deadline, arena, analyses, fn_analysis_note)
+    }
+
+    /// With `ApplyConfig::rename_index`, find the file to patch instead of the
+    /// `target_filename` if that one is missing. Only a single file with the
+    /// lines that the `file_patch` expects is used, preferably with the same
+    /// name.
+    fn find_renamed_file(
+        &mut self,
+        index: usize,
+        file_patch: &TextFilePatch<'arena>,
+        direction: PatchDirection,
+        target_filename: &Path)
+        -> Option<PathBuf>
+    {
+        let config = self.config;
+        let rename_index = config.rename_index?;
+        if self.modified_files.contains_key(target_filename) || file_exists(config, target_filename) {
+            return None;
+        }
+
+        let mut candidates: Vec<_> = rename_index.find(file_patch, direction, config.fuzz).into_iter()
+            .filter(|filename| !self.modified_files.get(*filename).is_some_and(|file| file.deleted))
+            .filter(|filename| config.allowed_files.is_none_or(|allowed_files| allowed_files.is_included(filename)))
+            .collect();
+        if candidates.len() > 1 {
+            let same_name: Vec<_> = candidates.iter()
+                .copied()
+                .filter(|filename| filename.file_name() == target_filename.file_name())
+                .collect();
+            if !same_name.is_empty() {
+                candidates = same_name;
+            }
+        }
+
+        let patch_filename = &config.series_patches[index].filename;
+        match candidates[..] {
+            [] => None,
+            [renamed_filename] => {
+                if config.verbosity >= Verbosity::Normal {
+                    self.output.print(index, OutputStream::Stdout, format!(
+                        "Patch {}: {} is missing, patching {} instead, it has the lines that the patch expects (renamed?).\n",
+                        patch_filename.display(),
+                        target_filename.display(),
+                        renamed_filename.display()));
+                }
+                Some(renamed_filename.to_path_buf())
+            }
+            _ => {
+                self.output.print(index, OutputStream::Stderr, format!(
+                    "{} Patch {}: {} is missing and several files have the lines that the patch expects: {}\n",
+                    prefix_warning(),
+                    patch_filename.display(),
+                    target_filename.display(),
+                    candidates.iter().map(|filename| filename.display()).join(", ")));
+                None
+            }
+        }
+    }
+
+    /// Same as `apply_one_file_patch`, but with the given `force` and
+    /// `reverse_if_applied` instead of those from the configuration.
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_one_file_patch_with(
+        &mut self,
+        index: usize,
+        mut file_patch: TextFilePatch<'arena>,
+        force: bool,
+        reverse_if_applied: bool,
+        deadline: Option<Instant>,
         arena: &'arena dyn Arena,
         analyses: &AnalysisSet,
         fn_analysis_note: &dyn Fn(&dyn Note, &TextFilePatch))
@@ -497,23 +1706,37 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
     {
         let config = self.config;
         let patch = &config.series_patches[index];
+        let start = config.timings.then(Instant::now);
+
+        let direction = if patch.reverse {
+            PatchDirection::Revert
+        } else {
+            PatchDirection::Forward
+        };
 
         // Get the file to patch
-        let target_filename = choose_filename_to_patch(config, file_patch.old_filename(), file_patch.new_filename(), &self.modified_files).clone();
+        let mut target_filename = choose_filename_to_patch(config, file_patch.old_filename(), file_patch.new_filename(), &self.modified_files).clone();
+        if let Some(renamed_filename) = self.find_renamed_file(index, &file_patch, direction, &target_filename) {
+            target_filename = Cow::Owned(renamed_filename);
+        }
         let file = self.modified_files.get_or_load(&target_filename, arena)
             .with_context(|| ApplyError::LoadFileToPatch { filename: target_filename.to_path_buf() })?;
 
         // If the patch renames the file. do it now...
         let (file, final_filename) = if file_patch.is_rename() {
-            // SAFETY: Renaming patches are guaranteed by the parser to have a new_filename.
-            let new_filename = file_patch.new_filename().expect("Renaming patch must have a new filename");
+            // SAFETY: Renaming patches are guaranteed by the parser to have both filenames.
+            let new_filename = match direction {
+                PatchDirection::Forward => file_patch.new_filename(),
+                PatchDirection::Revert => file_patch.old_filename(),
+            }.expect("Renaming patch must have both filenames");
 
             if *new_filename == target_filename {
                 // TODO: Proper reporting!
-                println!("Patch {} would rename file {} to {}, but it already has the name.",
-                         patch.filename.display(),
-                         target_filename.display(),
-                         new_filename.display());
+                self.output.print(index, OutputStream::Stdout, format!(
+                    "Patch {} would rename file {} to {}, but it already has the name.\n",
+                    patch.filename.display(),
+                    target_filename.display(),
+                    new_filename.display()));
             }
 
             // Move out its content, but keep it among modified_files - we need a record on what
@@ -528,10 +1751,11 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
                 // We can not do that, because we would have no way to rollback.
 
                 // TODO: Proper reporting!
-                println!("Patch {} is renaming file {} to {}, which overwrites existing file!",
-                         patch.filename.display(),
-                         target_filename.display(),
-                         new_filename.display());
+                self.output.print(index, OutputStream::Stdout, format!(
+                    "Patch {} is renaming file {} to {}, which overwrites existing file!\n",
+                    patch.filename.display(),
+                    target_filename.display(),
+                    new_filename.display()));
 
                 // Put the content back to the old file.
                 let file = self.modified_files.get_or_load(&target_filename, arena)
@@ -553,24 +1777,196 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
             (file, target_filename.clone())
         };
 
-        let direction = if patch.reverse {
-            PatchDirection::Revert
-        } else {
-            PatchDirection::Forward
-        };
+        // Check that the file is what the patch was made for.
+        if config.verify_index {
+            let index_check = check_index(&file_patch, file, direction)
+                .with_context(|| ApplyError::LoadFileToPatch { filename: final_filename.to_path_buf() })?;
+            match index_check {
+                IndexCheck::Mismatch { actual, expected } => {
+                    self.output.print(index, OutputStream::Stderr, format!(
+                        "{} Patch {}: {} does not match the index line of the patch ({} instead of {}), the tree may not be at the expected base.\n",
+                        prefix_warning(),
+                        patch.filename.display(),
+                        final_filename.display(),
+                        actual,
+                        expected));
+                }
+                IndexCheck::Match if config.verbosity >= Verbosity::Verbose => {
+                    self.output.print(index, OutputStream::Stdout, format!(
+                        "Patch {}: {} matches the index line of the patch.\n",
+                        patch.filename.display(),
+                        final_filename.display()));
+                }
+                _ => {}
+            }
+        }
+
+        // If the file is loaded lazily, load as much as the patch needs. The
+        // analyses, the match cache and --report-unchanged look at the whole
+        // file.
+        if !file.is_fully_loaded() {
+            match file_patch.lines_needed(direction) {
+                Some(lines) if analyses.is_empty() && config.match_cache.is_none() && !config.report_unchanged => file.ensure_lines(lines),
+                _ => file.load_all(),
+            }.with_context(|| ApplyError::LoadFileToPatch { filename: final_filename.to_path_buf() })?;
+        }
+        let loaded_lines = file.content.len();
+
+        // The file before the patch, to tell if it changed anything
+        let original = config.report_unchanged
+            .then(|| (file.content.clone(), file.deleted, file.permissions.clone()));
+
+        // If the patch was applied to the same file before, apply it at the
+        // same places.
+        let cache_key = config.match_cache
+            .filter(|_| file_patch.kind() == FilePatchKind::Modify && !file_patch.hunks().is_empty())
+            .map(|match_cache| (match_cache, match_key(&file_patch, direction, config.fuzz, config.max_offset, config.strict_ambiguity, file)));
+        let cached_report = cache_key.and_then(|(match_cache, key)| {
+            let hunk_matches = match_cache.get(key)?;
+            let report = file_patch.apply_at(file, direction, config.fuzz, config.max_offset, &hunk_matches, config.batch_threshold, analyses, fn_analysis_note)?;
+            match_cache.record_hit();
+            Some(report)
+        });
+        let from_cache = cached_report.is_some();
 
         // Apply the `FilePatch` on it.
-        let report = file_patch.apply(file, direction, config.fuzz, analyses, fn_analysis_note);
+        let mut report = match cached_report {
+            Some(report) => report,
+            None => file_patch.apply(file, direction, config.fuzz, config.max_offset, config.strict_ambiguity, config.unordered_hunks, force, deadline, config.batch_threshold, analyses, fn_analysis_note),
+        };
+
+        // If the result could be different with the whole file, try again with the whole file.
+        if !file.is_fully_loaded() && !report.is_independent_of_tail(&file_patch, loaded_lines) {
+            file_patch.rollback(file, direction, &report);
+            file.load_all()
+                .with_context(|| ApplyError::LoadFileToPatch { filename: final_filename.to_path_buf() })?;
+            report = file_patch.apply(file, direction, config.fuzz, config.max_offset, config.strict_ambiguity, config.unordered_hunks, force, deadline, config.batch_threshold, analyses, fn_analysis_note);
+        }
+
+        if let (Some((match_cache, key)), false) = (cache_key, from_cache) {
+            if let Some(hunk_matches) = report.hunk_matches() {
+                match_cache.insert(key, hunk_matches);
+            }
+        }
+
+        // The UTF-8 byte order mark at the start of a file is missing in
+        // patches made against the file without it, so their first hunk
+        // does not match. Try again without the mark, it is written back.
+        if report.failed() && !file.bom {
+            let mut stripped = file.clone();
+            file_patch.rollback(&mut stripped, direction, &report);
+            if stripped.strip_bom() {
+                let stripped_report = file_patch.apply(&mut stripped, direction, config.fuzz, config.max_offset, config.strict_ambiguity, config.unordered_hunks, force, deadline, config.batch_threshold, analyses, fn_analysis_note);
+                if stripped_report.ok() {
+                    *file = stripped;
+                    report = stripped_report;
+                }
+            }
+        }
+
+        // Hand-edited patches may mix "\n" and "\r\n" between hunks, so
+        // the hunks that end their lines differently than the file do not
+        // match. Try again with the line endings of the file.
+        if report.failed() {
+            let mut store = |data: Vec<u8>| arena.store_data(data.into_boxed_slice());
+            if let Some(converted_patch) = file_patch.with_line_endings_of(file, direction, &mut store) {
+                let mut converted = file.clone();
+                file_patch.rollback(&mut converted, direction, &report);
+                let converted_report = converted_patch.apply(&mut converted, direction, config.fuzz, config.max_offset, config.strict_ambiguity, config.unordered_hunks, force, deadline, config.batch_threshold, analyses, fn_analysis_note);
+                if converted_report.ok() {
+                    *file = converted;
+                    report = converted_report;
+                    file_patch = converted_patch;
+                }
+            }
+        }
+
+        // With --strict, the hunks must apply exactly where they say. Apply
+        // again without fuzz and offset, so the report says which failed.
+        let mut inexact = false;
+        if config.strict && report.ok() && !report.forced() {
+            let inexact_hunks: Vec<_> = report.hunk_reports().iter().enumerate()
+                .filter_map(|(i, hunk_report)| match *hunk_report {
+                    HunkApplyReport::Applied { offset, fuzz, .. } if offset != 0 || fuzz != 0 => Some((i, offset, fuzz)),
+                    _ => None,
+                })
+                .collect();
+
+            inexact = !inexact_hunks.is_empty();
+            if inexact {
+                for (i, offset, fuzz) in inexact_hunks {
+                    self.output.print(index, OutputStream::Stderr, format!(
+                        "Patch {}: hunk #{} of {} applies only with offset {} and fuzz {}, which is not allowed with --strict.\n",
+                        patch.filename.display(),
+                        i + 1,
+                        final_filename.display(),
+                        offset,
+                        fuzz));
+                }
+
+                file_patch.rollback(file, direction, &report);
+                report = file_patch.apply(file, direction, 0, Some(0), config.strict_ambiguity, config.unordered_hunks, false, deadline, config.batch_threshold, analyses, fn_analysis_note);
+            }
+        }
+
+        let mut already_applied = false;
+
+        // Maybe the file is already patched?
+        if report.failed() && reverse_if_applied && !file_patch.is_rename() {
+            if let Some(applied_report) = test_already_applied(config, &file_patch, file, direction, deadline, &report) {
+                report = applied_report;
+                already_applied = true;
+            }
+        }
+
+        // POSIX patch never removes files, it leaves them empty.
+        if config.posix && report.ok() && file.deleted {
+            file.deleted = false;
+        }
 
         let report_ok = report.ok();
 
+        let unchanged = report_ok && !already_applied && !file_patch.is_rename() && original.is_some_and(|(content, deleted, permissions)| {
+            content == file.content && deleted == file.deleted && permissions == file.permissions
+        });
+
+        if report_ok && !already_applied && config.verbosity >= Verbosity::Normal {
+            for (i, hunk_report) in report.hunk_reports().iter().enumerate() {
+                if let HunkApplyReport::Applied { offset, fuzz, .. } = hunk_report {
+                    // The least fuzz the hunk needed, like patch says it.
+                    if *fuzz > 0 && config.verbosity >= Verbosity::Verbose {
+                        self.output.print(index, OutputStream::Stdout, format!(
+                            "Patch {}: hunk #{} of {} applied with fuzz {}.\n",
+                            patch.filename.display(),
+                            i + 1,
+                            final_filename.display(),
+                            fuzz));
+                    }
+                    if offset.unsigned_abs() > LARGE_OFFSET {
+                        self.output.print(index, OutputStream::Stderr, format!(
+                            "{} Patch {}: hunk #{} of {} applied with a large offset of {} lines.\n",
+                            prefix_warning(),
+                            patch.filename.display(),
+                            i + 1,
+                            final_filename.display(),
+                            offset));
+                    }
+                }
+            }
+        }
+
         self.applied_patches.push(PatchStatus {
             index,
             file_patch,
+            direction,
             target_filename,
             final_filename,
             report,
             patch_filename: &patch.filename,
+            already_applied,
+            unchanged,
+            inexact,
+            duration: start.map_or(Duration::ZERO, |start| start.elapsed()),
         });
 
         Ok(report_ok)
@@ -590,42 +1986,30 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
                 break;
             }
 
-            self.modified_files.rollback(applied_patch);
+            self.modified_files.undo(applied_patch);
 
-            if applied_patch.report.failed() {
-                let rej_filename = make_rej_filename(&applied_patch.target_filename);
+            if applied_patch.report.failed() && config.save_rej_files {
+                let rej_filename = config_rej_filename(config, &applied_patch.target_filename);
 
-                let file = match File::create(config.base_dir.join(&rej_filename)) {
-                    Ok(file) => {
-                        file
-                    }
-
-                    Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
-                        // This proably means the target directory doesn't exist.
-                        // In that case quilt doesn't create the reject file, so we do the same.
-                        // We still have to keep going, as other patches might be rejected.
-                        self.applied_patches.pop();
-
-                        if config.verbosity >= Verbosity::Normal {
-                            println!("Bypassing reject {:?} as directory doesn't exist", rej_filename);
-                        }
+                if save_rej_file(config, applied_patch)?.is_none() {
+                    // This proably means the target directory doesn't exist.
+                    // In that case quilt doesn't create the reject file, so we do the same.
+                    // We still have to keep going, as other patches might be rejected.
+                    let index = applied_patch.index;
+                    self.applied_patches.pop();
 
-                        continue
+                    if config.verbosity >= Verbosity::Normal {
+                        self.output.print(index, OutputStream::Stdout, format!(
+                            "Bypassing reject {:?} as directory doesn't exist\n", rej_filename));
                     }
 
-                    Err(err) => {
-                        return Err(err.into());
-                    }
-                };
+                    continue
+                }
 
                 if config.verbosity >= Verbosity::Normal {
-                    println!("Saving rejects to {:?}", rej_filename);
+                    self.output.print(applied_patch.index, OutputStream::Stdout, format!(
+                        "Saving rejects to {:?}\n", rej_filename));
                 }
-
-                let mut writer = BufWriter::new(file);
-
-                applied_patch.file_patch.write_rej_to(&mut writer, &applied_patch.report).
-                    with_context(|| ApplyError::SaveRejectFile { filename: rej_filename.clone() })?;
             }
 
             self.applied_patches.pop();
@@ -634,6 +2018,35 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
         Ok(())
     }
 
+    /// Names of the files patched by the patch with `index`, in the state
+    /// before and after the patch.
+    pub fn patched_filenames(&self, index: usize) -> Vec<Cow<'arena, Path>> {
+        let start = self.applied_patches.iter().rposition(|applied_patch| applied_patch.index != index)
+            .map_or(0, |position| position + 1);
+
+        let mut filenames = Vec::new();
+        for applied_patch in &self.applied_patches[start..] {
+            for filename in [&applied_patch.target_filename, &applied_patch.final_filename] {
+                if !filenames.contains(filename) {
+                    filenames.push(filename.clone());
+                }
+            }
+        }
+        filenames
+    }
+
+    /// Rolls back all `FilePatch`es belonging to the last patch with `index`,
+    /// without saving anything.
+    pub fn rollback_patch(&mut self, index: usize) {
+        while let Some(applied_patch) = self.applied_patches.last() {
+            if applied_patch.index < index {
+                break;
+            }
+            self.modified_files.undo(applied_patch);
+            self.applied_patches.pop();
+        }
+    }
+
     /// Rolls back all `FilePatch`es up to the one belonging to patch with
     /// `down_to_index` index and generates quilt backup files for all of them.
     pub fn rollback_and_save_backup_files(
@@ -649,7 +2062,8 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
 
             let file = self.modified_files.rollback(applied_patch);
 
-            save_backup_file(config, applied_patch.patch_filename, &applied_patch.target_filename, file)?;
+            let backup_name = config.backup_name(applied_patch.index);
+            save_backup_file(config, backup_name, &applied_patch.target_filename, file)?;
 
             if applied_patch.file_patch.is_rename() {
                 // If it was a rename, we also have to backup the new file (it will be empty file).
@@ -658,7 +2072,7 @@ impl<'arena, 'config> AppliedState<'arena, 'config> {
                 // SAFETY: The file must be present in `self.modified_files` because it was loaded/created
                 // during the forward application of this patch.
                 let new_file = self.modified_files.get(new_filename).expect("File must be loaded during application");
-                save_backup_file(config, applied_patch.patch_filename, new_filename, new_file)?;
+                save_backup_file(config, backup_name, new_filename, new_file)?;
             }
         }
 
@@ -20,14 +20,14 @@ use libpatch::analysis::{AnalysisSet, Note, NoteSeverity, fn_analysis_note_noop}
 use libpatch::modified_file::ModifiedFile;
 
 use libpatch::patch::{
+    BatchThreshold,
     TextFilePatch,
     TextHunk,
     HunkApplyFailureReason,
     HunkApplyReport,
     HunkPosition,
-    PatchDirection
 };
-use libpatch::patch::unified::writer::UnifiedPatchHunkHeaderWriter;
+use libpatch::patch::unified::writer::{UnifiedPatchHunkHeaderWriter, UnifiedPatchRejWriter};
 use libpatch::patch::FilePatchApplyReport;
 
 use crate::apply::common::*;
@@ -35,9 +35,9 @@ use crate::apply::{Verbosity, prefix_hint, prefix_warning};
 
 
 /// Try if the patch would apply with some fuzz. It doesn't do any permanent changes.
-pub fn test_apply_with_fuzzes<H: BuildHasher>(
-    patch_status: &PatchStatus,
-    modified_files: &HashMap<Cow<Path>, ModifiedFile, H>)
+pub fn test_apply_with_fuzzes<'arena, H: BuildHasher>(
+    patch_status: &PatchStatus<'arena, '_>,
+    modified_files: &HashMap<Cow<'arena, Path>, ModifiedFile<'arena>, H>)
     -> Option<usize>
 {
     let file = modified_files.get(&patch_status.final_filename).unwrap(); // NOTE(unwrap): It must be there, otherwise we got bad modified_files, which would be bug.
@@ -46,7 +46,7 @@ pub fn test_apply_with_fuzzes<H: BuildHasher>(
     let mut file = file.clone();
 
     // Rollback the failed application
-    patch_status.file_patch.rollback(&mut file, PatchDirection::Forward, &patch_status.report);
+    patch_status.file_patch.rollback(&mut file, patch_status.direction, &patch_status.report);
 
     let current_fuzz = patch_status.report.max_fuzz();
     let max_fuzz = patch_status.file_patch.max_useable_fuzz();
@@ -59,7 +59,7 @@ pub fn test_apply_with_fuzzes<H: BuildHasher>(
         // Make another copy for test application
         let mut file = file.clone();
 
-        let report = patch_status.file_patch.apply(&mut file, PatchDirection::Forward, fuzz, &AnalysisSet::default(), &fn_analysis_note_noop);
+        let report = patch_status.file_patch.apply(&mut file, patch_status.direction, fuzz, None, false, false, false, None, BatchThreshold::default(), &AnalysisSet::default(), &fn_analysis_note_noop);
 
         if report.ok() {
             return Some(fuzz);
@@ -69,10 +69,10 @@ pub fn test_apply_with_fuzzes<H: BuildHasher>(
     None
 }
 
-pub fn test_apply_after_reverting_other<H: BuildHasher>(
-    failed_patch_status: &PatchStatus,
-    suspect_patch_status: &PatchStatus,
-    modified_files: &HashMap<Cow<Path>, ModifiedFile, H>)
+pub fn test_apply_after_reverting_other<'arena, H: BuildHasher>(
+    failed_patch_status: &PatchStatus<'arena, '_>,
+    suspect_patch_status: &PatchStatus<'arena, '_>,
+    modified_files: &HashMap<Cow<'arena, Path>, ModifiedFile<'arena>, H>)
     -> bool
 {
     let file = modified_files.get(&failed_patch_status.final_filename).unwrap(); // NOTE(unwrap): It must be there, otherwise we got bad modified_files, which would be bug.
@@ -81,29 +81,57 @@ pub fn test_apply_after_reverting_other<H: BuildHasher>(
     let mut file = file.clone();
 
     // Rollback the failed application
-    failed_patch_status.file_patch.rollback(&mut file, PatchDirection::Forward, &failed_patch_status.report);
+    failed_patch_status.file_patch.rollback(&mut file, failed_patch_status.direction, &failed_patch_status.report);
 
     // Revert the suspect
-    let revert_report = suspect_patch_status.file_patch.apply(&mut file, PatchDirection::Revert, suspect_patch_status.report.max_fuzz(), &AnalysisSet::default(), &fn_analysis_note_noop);
+    let revert_report = suspect_patch_status.file_patch.apply(&mut file, suspect_patch_status.direction.opposite(), suspect_patch_status.report.max_fuzz(), None, false, false, false, None, BatchThreshold::default(), &AnalysisSet::default(), &fn_analysis_note_noop);
     if revert_report.failed() {
         // If we couldn't even revert the suspect, we can't test anything
         return false;
     }
 
     // Try to apply our failed patch again
-    let apply_report = failed_patch_status.file_patch.apply(&mut file, PatchDirection::Forward, failed_patch_status.report.max_fuzz(), &AnalysisSet::default(), &fn_analysis_note_noop);
+    let apply_report = failed_patch_status.file_patch.apply(&mut file, failed_patch_status.direction, failed_patch_status.report.max_fuzz(), None, false, false, false, None, BatchThreshold::default(), &AnalysisSet::default(), &fn_analysis_note_noop);
 
     // Report whether it would apply ok now
     apply_report.ok()
 }
 
+/// Render the hunks of the `broken_patch_index` that failed to apply into
+/// `writer`, in the same form as they are saved into ".rej" files.
+pub fn write_inline_rejects<W: Write>(
+    broken_patch_index: usize,
+    applied_patches: &[PatchStatus],
+    writer: &mut W)
+    -> Result<()>
+{
+    for patch_status in applied_patches.iter().rev() {
+        if patch_status.index != broken_patch_index {
+            break;
+        }
+
+        if patch_status.report.ok() {
+            continue;
+        }
+
+        writeln!(writer, "{} {}:", "Rejected hunks for".yellow(), patch_status.target_filename.display())?;
+
+        let mut buf = Vec::<u8>::new();
+        patch_status.file_patch.write_rej_to(&mut buf, &patch_status.report)?;
+        write!(writer, "{}", String::from_utf8_lossy(&buf))?;
+    }
+
+    Ok(())
+}
+
 /// Render a report into `writer` about why the `broken_patch_index` failed to
 /// apply.
 pub fn analyze_patch_failure<'arena, H: BuildHasher, W: Write>(
     verbosity: Verbosity,
+    function_context: bool,
     broken_patch_index: usize,
     applied_patches: &Vec<PatchStatus<'arena, '_>>,
-    modified_files: &HashMap<Cow<'arena, Path>, ModifiedFile, H>,
+    modified_files: &HashMap<Cow<'arena, Path>, ModifiedFile<'arena>, H>,
     writer: &mut W)
     -> Result<()>
 {
@@ -133,6 +161,10 @@ pub fn analyze_patch_failure<'arena, H: BuildHasher, W: Write>(
                         }
                     }
 
+                    HunkApplyReport::Forced { .. } => {
+                        write!(writer, "{}", "FORCED ".bright_red().bold())?;
+                    }
+
                     HunkApplyReport::Failed(reason) => {
                         write!(writer, "{}", "FAILED ".bright_red().bold())?;
 
@@ -153,6 +185,12 @@ pub fn analyze_patch_failure<'arena, H: BuildHasher, W: Write>(
 
                             HunkApplyFailureReason::MisorderedHunks =>
                                 Some("Misordered hunks! The hunk would modify content before (or overlapping) some previous hunk."),
+
+                            HunkApplyFailureReason::TimedOut =>
+                                Some("Timed out, the patch took longer than allowed by --patch-timeout."),
+
+                            HunkApplyFailureReason::AmbiguousMatch =>
+                                Some("Ambiguous match! The hunk matches equally far before and after its line (see --strict-ambiguity)."),
                         };
 
                         if let Some(reason_str) = reason_str {
@@ -165,6 +203,15 @@ pub fn analyze_patch_failure<'arena, H: BuildHasher, W: Write>(
                 patch_status.file_patch.hunks()[i].write_header_to(&mut buf)?;
                 writeln!(writer, "\t{}", String::from_utf8_lossy(&buf).bright_blue())?;
 
+                let hunk = &patch_status.file_patch.hunks()[i];
+                if function_context && matches!(hunk_report, HunkApplyReport::Failed(_)) && !hunk.function.is_empty() {
+                    write_function_context(&patch_status.report,
+                                           hunk,
+                                           &modified_files[&patch_status.target_filename],
+                                           writer,
+                                           "      ")?;
+                }
+
                 if let HunkApplyReport::Failed(HunkApplyFailureReason::NoMatchingLines) = hunk_report {
                     if verbosity >= Verbosity::Normal {
                         print_difference_to_closest_match(&patch_status.report,
@@ -176,7 +223,9 @@ pub fn analyze_patch_failure<'arena, H: BuildHasher, W: Write>(
                 }
             }
 
-            if verbosity >= Verbosity::Normal {
+            // The experiments below apply the patch again several times, that
+            // would take too long if it timed out.
+            if verbosity >= Verbosity::Normal && !patch_status.report.timed_out() {
                 // Find which other patches touched this file
                 let mut other_patches = Vec::<(&Path, bool)>::new();
                 for other_patch_status in applied_patches.iter() {
@@ -235,6 +284,53 @@ pub fn analyze_patch_failure<'arena, H: BuildHasher, W: Write>(
     Ok(())
 }
 
+/// Find the line with the `hunk`'s function (the text after "@@ ... @@" in
+/// its header) in the file. Git truncates the function line, so the file
+/// line only has to start with it. The closest one before the place where
+/// the hunk was expected wins, otherwise the first one after it. Returns
+/// 0-based line number.
+fn find_function_line(report: &FilePatchApplyReport, hunk: &TextHunk, modified_file: &ModifiedFile)
+    -> Option<usize>
+{
+    let end = hunk.function.iter().rposition(|&c| !is_space(c))?;
+    let function = &hunk.function[..=end];
+
+    let target_line = hunk.view(report.direction(), 0).remove_target_line();
+    let function_lines = modified_file.content.iter().enumerate()
+        .filter(|(_, line)| line.starts_with(function))
+        .map(|(line_number, _)| line_number);
+
+    let mut best = None;
+    for line_number in function_lines {
+        if line_number >= target_line {
+            return best.or(Some(line_number));
+        }
+        best = Some(line_number);
+    }
+    best
+}
+
+/// Write where the function from the header of the failed `hunk` is in the
+/// file, to help find where the hunk should go.
+pub fn write_function_context<W: Write>(
+    report: &FilePatchApplyReport,
+    hunk: &TextHunk,
+    modified_file: &ModifiedFile,
+    writer: &mut W,
+    prefix: &str)
+    -> Result<()>
+{
+    let function = String::from_utf8_lossy(hunk.function);
+    let function = function.trim_end();
+    match find_function_line(report, hunk, modified_file) {
+        Some(line_number) =>
+            writeln!(writer, "{}{} {} found at line {}", prefix, "Function".yellow(), function.bright_blue(), line_number + 1)?,
+        None =>
+            writeln!(writer, "{}{} {} not found in the file", prefix, "Function".yellow(), function.bright_blue())?,
+    }
+    Ok(())
+}
+
 /// Tests if `c` is a space, TAB or newline
 fn is_space(c: u8) -> bool {
     c == b' ' ||
@@ -313,11 +409,7 @@ impl<'a> FirstStepMatches<'a> {
 		let match_index = self.match_index;
 		self.match_index += 1;
 
-		let line_diff = if file_line < self.target_line {
-		    self.target_line - file_line
-		} else {
-		    file_line - self.target_line
-		};
+		let line_diff = self.target_line.abs_diff(file_line);
 		let cost =
 		    // skipped lines at hunk beginning
 		    SCORE_SCALE * self.line_index +
@@ -574,9 +666,11 @@ pub fn print_difference_to_closest_match<W: Write>(
 
 /// This function prints note from libpatch'es analysis
 pub fn print_analysis_note(patch_filename: &Path, note: &dyn Note, file_patch: &TextFilePatch) -> Result<()> {
-    let stderr = io::stderr();
-    let mut out = stderr.lock();
+    write_analysis_note(&mut io::stderr().lock(), patch_filename, note, file_patch)
+}
 
+/// Write the note like `print_analysis_note`, but into the `out`.
+pub fn write_analysis_note<W: IoWrite>(out: &mut W, patch_filename: &Path, note: &dyn Note, file_patch: &TextFilePatch) -> Result<()> {
     writeln!(out, "{} {}", "Patch".yellow(), patch_filename.display())?;
     writeln!(out, "  {} {}", "File".yellow(), file_patch.old_filename().unwrap_or_else(|| file_patch.new_filename().unwrap()).display())?;
 
@@ -594,7 +688,7 @@ pub fn print_analysis_note(patch_filename: &Path, note: &dyn Note, file_patch: &
         NoteSeverity::Warning => write!(out, "{} ", prefix_warning())?,
     }
 
-    note.write(&mut out)?;
+    note.write(out)?;
     writeln!(out)?;
     writeln!(out)?;
 
@@ -0,0 +1,170 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `push --interactive`, asking the user what to do
+//! with every file that fails to patch (see `ApplyConfig::interactive`).
+//!
+//! The failed file is always the last one in `AppliedState::applied_patches`,
+//! so it can be rolled back and patched again in a different way.
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process::Command;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use colored::*;
+
+use libpatch::analysis::{AnalysisSet, Note};
+use libpatch::patch::{HunkApplyReport, TextFilePatch};
+
+use crate::apply::*;
+use crate::apply::common::*;
+use crate::arena::Arena;
+
+/// What to do with a file that failed to patch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Leave the file as it was, the rest of the patch is applied.
+    Skip,
+
+    /// Write the hunks that did not match anyway (like `--force`).
+    Force,
+
+    /// The file is already patched, leave it unchanged (like
+    /// `--reverse-if-applied`).
+    Reverse,
+
+    /// Open the ".rej" file in an editor and ask again.
+    Edit,
+
+    /// Give up, the patch fails.
+    Quit,
+}
+
+/// Ask the user which `Resolution` to use. Unknown answers are asked again,
+/// the end of `input` means `Resolution::Quit`.
+pub fn ask_resolution<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<Resolution> {
+    loop {
+        write!(output, "[s]kip file, [f]orce hunks, [r]everse (already applied), [e]dit .rej, [q]uit? ")?;
+        output.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            writeln!(output)?;
+            return Ok(Resolution::Quit);
+        }
+
+        match answer.trim().to_lowercase().as_str() {
+            "s" | "skip" => return Ok(Resolution::Skip),
+            "f" | "force" => return Ok(Resolution::Force),
+            "r" | "reverse" => return Ok(Resolution::Reverse),
+            "e" | "edit" => return Ok(Resolution::Edit),
+            "q" | "quit" => return Ok(Resolution::Quit),
+            answer => writeln!(output, "Unknown answer \"{}\".", answer)?,
+        }
+    }
+}
+
+/// Describe the failure of the `applied_patch` for the user.
+fn write_failure<W: Write>(output: &mut W, applied_patch: &PatchStatus) -> io::Result<()> {
+    let failed_hunks: Vec<_> = applied_patch.report.hunk_reports().iter().enumerate()
+        .filter(|(_, hunk_report)| matches!(hunk_report, HunkApplyReport::Failed(..)))
+        .map(|(i, _)| format!("#{}", i + 1))
+        .collect();
+
+    writeln!(output, "{} {} {} {}: hunks {} of {} failed",
+             "File".yellow(), applied_patch.target_filename.display(),
+             "FAILED in".bright_red().bold(), applied_patch.patch_filename.display(),
+             failed_hunks.join(", "), applied_patch.report.hunk_reports().len())
+}
+
+/// Open the `path` in the editor from `$VISUAL` or `$EDITOR`, or in `vi`.
+fn run_editor(path: &std::path::Path) -> Result<()> {
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+
+    Command::new("sh")
+        .arg("-c").arg(format!("{} \"$1\"", editor))
+        .arg("sh").arg(path)
+        .status()
+        .with_context(|| format!("Failed to run editor \"{}\"", editor))?;
+    Ok(())
+}
+
+/// Ask the user what to do with the file patch of the patch with `index`
+/// that just failed, and do it. Files that are skipped are added to
+/// `skipped_files`.
+///
+/// Returns whether the failure was resolved, i.e. the patch can go on.
+#[allow(clippy::too_many_arguments)]
+pub fn resolve_failed_file_patch<'arena, R: BufRead, W: Write>(
+    state: &mut AppliedState<'arena, '_>,
+    index: usize,
+    deadline: Option<Instant>,
+    arena: &'arena dyn Arena,
+    analyses: &AnalysisSet,
+    fn_analysis_note: &dyn Fn(&dyn Note, &TextFilePatch),
+    skipped_files: &mut Vec<PatchedFile>,
+    input: &mut R,
+    output: &mut W)
+    -> Result<bool>
+{
+    let config = state.config;
+
+    loop {
+        // NOTE(unwrap): The failed file patch was just pushed there.
+        let applied_patch = state.applied_patches.last().unwrap();
+        assert_eq!(applied_patch.index, index);
+        write_failure(output, applied_patch)?;
+
+        let resolution = ask_resolution(input, output)?;
+        match resolution {
+            Resolution::Quit => return Ok(false),
+
+            Resolution::Edit => {
+                if config.dry_run {
+                    writeln!(output, "There is no .rej file with --dry-run.")?;
+                    continue;
+                }
+                match save_rej_file(config, applied_patch)? {
+                    Some(rej_path) => run_editor(&rej_path)?,
+                    None => writeln!(output, "The directory for the .rej file does not exist.")?,
+                }
+            }
+
+            Resolution::Skip => {
+                // NOTE(unwrap): Checked above.
+                let applied_patch = state.applied_patches.pop().unwrap();
+                state.modified_files.rollback(&applied_patch);
+                skipped_files.push(PatchedFile {
+                    index,
+                    patch_filename: applied_patch.patch_filename.to_path_buf(),
+                    filename: applied_patch.target_filename.to_path_buf(),
+                });
+                return Ok(true);
+            }
+
+            Resolution::Force | Resolution::Reverse => {
+                // NOTE(unwrap): Checked above.
+                let applied_patch = state.applied_patches.pop().unwrap();
+                state.modified_files.rollback(&applied_patch);
+
+                let applied_count = state.applied_patches.len();
+                let force = resolution == Resolution::Force;
+                let ok = state.apply_one_file_patch_with(index, applied_patch.file_patch, force, !force,
+                                                         deadline, arena, analyses, fn_analysis_note)?;
+                if ok {
+                    return Ok(true);
+                }
+                if state.applied_patches.len() == applied_count {
+                    // It was not even tried, there is nothing to ask about.
+                    return Ok(false);
+                }
+
+                match resolution {
+                    Resolution::Force => writeln!(output, "The hunks can not be forced.")?,
+                    _ => writeln!(output, "The patch can not be reverted, the file is not patched yet.")?,
+                }
+            }
+        }
+    }
+}
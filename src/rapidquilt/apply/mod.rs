@@ -1,18 +1,56 @@
 // Licensed under the MIT license. See LICENSE.md
 
 use std::fmt;
+use std::fs;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 use thiserror::Error;
 use colored::*;
+use itertools::Itertools;
+
+use libpatch::patch::BatchThreshold;
+
+use crate::audit::AuditLog;
+use crate::file_filter::FileFilter;
+use crate::json;
+use crate::manifest::Manifest;
+use crate::match_cache::MatchCache;
+use crate::patch_source::PatchSource;
+use crate::prefix_map::PrefixMap;
+use crate::rename_index::RenameIndex;
 
 pub mod sequential;
 pub mod parallel;
+pub mod diagnostics;
+pub mod interactive;
+mod backup;
+mod combined_diff;
 mod common;
-mod diagnostics;
+mod provenance;
+mod reconstruct;
+mod resume;
+mod roundtrip;
+mod snapshot;
+mod squash;
+mod tune;
+mod verify;
 
+pub use self::backup::{BackupFile, BackupStore, FileBackupStore, backup_names};
+pub use self::common::touched_files_by_patch;
+#[cfg(test)]
+pub use self::common::{CaseGuard, is_case_insensitive};
 pub use self::sequential::apply_patches;
 pub use self::parallel::apply_patches as apply_patches_parallel;
+pub use self::provenance::write_provenance;
+pub use self::reconstruct::reconstruct_backups;
+pub use self::resume::adopt_fixed_patch;
+pub use self::roundtrip::check_roundtrip;
+pub use self::snapshot::{diff_snapshot, take_snapshot};
+pub use self::squash::squash_patches;
+pub use self::tune::tune_threads;
+pub use self::verify::{ReferenceDir, ReferenceTree, verify_tree};
 
 
 
@@ -57,20 +95,363 @@ pub struct SeriesPatch {
 #[derive(Debug)]
 pub struct ApplyConfig<'a> {
     pub base_dir: &'a Path,
+    /// Write the patched files into this directory instead of back to the
+    /// `base_dir`, which is then only read.
+    pub out_dir: Option<&'a Path>,
+    /// Mark the original files deleted by the patches with OCI whiteout
+    /// files (".wh.<name>") in the `out_dir`, so it can be used as the upper
+    /// layer over the `base_dir`.
+    pub overlay_whiteouts: bool,
     pub series_patches: &'a [SeriesPatch],
     pub patches_path: &'a Path,
+    /// Load the patches from this source instead of the `patches_path`,
+    /// e.g. from a remote host.
+    pub patch_source: Option<&'a dyn PatchSource>,
+    /// Only files accepted by this filter are patched, the rest of every
+    /// patch is skipped.
+    pub file_filter: Option<&'a FileFilter>,
+    /// Only files accepted by this filter may be touched by the patches. A
+    /// patch that names any other file fails with `ApplyError::DisallowedFile`
+    /// before anything is loaded, e.g. to sandbox untrusted patches.
+    pub allowed_files: Option<&'a FileFilter>,
+    /// Allow patching files outside of the `base_dir`, through ".." or
+    /// symlinked directories.
+    pub unsafe_paths: bool,
+    /// Make the absolute paths in the patches that lead into the `base_dir`
+    /// relative to it. The files, their ".rej" files and all messages are
+    /// then named relative to the working directory, like with other paths.
+    pub relative: bool,
+    /// Rewrite the leading directories of the paths in the patches, after
+    /// stripping them. Files are looked up, filtered and named by the new
+    /// paths.
+    pub prefix_map: Option<&'a PrefixMap>,
+    /// Patch the files that symlinks point to, instead of the symlinks
+    /// themselves. Only for files that are modified, not for files that are
+    /// created, deleted or renamed.
+    pub follow_symlinks: bool,
+    /// If files modified by a patch do not exist at its strip level, try
+    /// the levels 0 to 2 and use the first where they do.
+    pub auto_strip: bool,
     pub fuzz: usize,
+    /// Hunks are searched for at most this many lines away from their
+    /// expected position. `None` searches the whole file.
+    pub max_offset: Option<usize>,
+    /// A hunk that matches equally far before and after its expected
+    /// position fails, instead of being applied after it.
+    pub strict_ambiguity: bool,
+    /// A hunk may apply before the previous hunks of its file patch, if it
+    /// does not change the same lines.
+    pub unordered_hunks: bool,
+    /// Hunks that apply only with fuzz or offset fail.
+    pub strict: bool,
+    /// File patches with many hunks in big files are applied in batch, with
+    /// the hunks searched for in parallel.
+    pub batch_threshold: BatchThreshold,
+    /// Behave like `patch --posix`: files deleted by patches are left as
+    /// empty files.
+    pub posix: bool,
+    /// Warn about files that do not match the blob hash from the "index"
+    /// line of their git patch, so the tree is probably not at the base the
+    /// patch was made for.
+    pub verify_index: bool,
+    pub force: bool,
+    pub reverse_if_applied: bool,
+    /// Ask on the terminal what to do with every file that fails to patch.
+    /// Only used by the sequential `apply_patches`.
+    pub interactive: bool,
+    pub patch_timeout: Option<Duration>,
+    /// Files bigger than this amount of bytes are loaded only partially,
+    /// starting with this amount of bytes. The rest is loaded only if needed.
+    pub lazy_load: Option<usize>,
+    pub show_rejects_inline: bool,
+    pub save_rej_files: bool,
+    /// Where the ".rej" files are saved, at the same paths relative to it as
+    /// the patched files. `None` saves them next to the patched files.
+    pub reject_dir: Option<&'a Path>,
+    /// Appended to the name of a patched file to name its ".rej" file.
+    pub reject_suffix: &'a str,
+    /// When a hunk fails, look up the function from its header ("@@ ... @@
+    /// function") in the file and report where it is.
+    pub function_context: bool,
+    pub preserve_ownership: bool,
+    /// Write a patched file with other hard links into it, so all the links
+    /// change. Otherwise it is replaced by a new file and the other links keep
+    /// the original content.
+    pub preserve_hard_links: bool,
+    /// Set the modification time of every patched file to this time, e.g. a
+    /// fixed one for reproducible builds. Otherwise it is the time of saving.
+    pub touch: Option<SystemTime>,
+    /// Shell command to run after every applied patch. The patched files are
+    /// saved before it runs and the patch fails if the command fails.
+    pub post_hook: Option<&'a str>,
+    /// Do not print from the worker threads, return all messages in
+    /// `ApplyResult::messages` in series order instead.
+    pub deterministic: bool,
     pub do_backups: ApplyConfigDoBackups,
+    /// Where the backup files are saved. `None` saves them to ".pc" in the
+    /// `base_dir`, like quilt.
+    pub backup_store: Option<&'a dyn BackupStore>,
+    /// The names of the backups of the `series_patches`, see `backup_names`.
+    /// `None` uses the names of the patches, for series where every patch is
+    /// only once.
+    pub backup_names: Option<&'a [PathBuf]>,
     pub backup_count: ApplyConfigBackupCount,
     pub dry_run: bool,
+    /// Compare the patched files with the originals and return the
+    /// differences in `ApplyResult::file_diffs`. Only used with `dry_run`.
+    pub emit_diff: bool,
+    /// Return the content of every file touched by the patches in
+    /// `ApplyResult::changed_files`. Only used with `dry_run`.
+    pub emit_files: bool,
+    /// Print the statistics of the arena and return the size of every
+    /// applied patch in `ApplyResult::patch_sizes`.
     pub stats: bool,
+    /// Measure how long every file patch takes and return the time of every
+    /// applied patch in `ApplyResult::patch_timings` and the longest chain of
+    /// them in `ApplyResult::critical_path`.
+    pub timings: bool,
+    /// Return the files that a patch left byte-identical, e.g. because its
+    /// hunks add the same lines they remove, in
+    /// `ApplyResult::unchanged_files`.
+    pub report_unchanged: bool,
     pub verbosity: Verbosity,
+    pub audit_log: Option<&'a AuditLog>,
+    /// Record every created, modified or deleted file here.
+    pub manifest: Option<&'a Manifest>,
+    /// Apply the file patches at the places where they matched the same
+    /// files before, and remember the places of the others.
+    pub match_cache: Option<&'a MatchCache>,
+    /// Patch a file with the lines the hunks expect, found in this index,
+    /// instead of a missing file, e.g. one that was renamed since.
+    pub rename_index: Option<&'a RenameIndex>,
+}
+
+/// The default of `ApplyConfig::reject_suffix`, like patch and quilt use.
+pub const DEFAULT_REJECT_SUFFIX: &str = ".rej";
+
+impl<'a> ApplyConfig<'a> {
+    /// The configuration to apply the `series_patches` from the
+    /// `patches_path` to the `base_dir`, with every other option at its
+    /// default. Backups are not saved, the rejects are.
+    pub fn new(base_dir: &'a Path, patches_path: &'a Path, series_patches: &'a [SeriesPatch], verbosity: Verbosity)
+        -> Self
+    {
+        ApplyConfig {
+            base_dir,
+            out_dir: None,
+            overlay_whiteouts: false,
+            series_patches,
+            patches_path,
+            patch_source: None,
+            file_filter: None,
+            allowed_files: None,
+            unsafe_paths: false,
+            relative: false,
+            prefix_map: None,
+            follow_symlinks: false,
+            auto_strip: false,
+            fuzz: 0,
+            max_offset: None,
+            strict_ambiguity: false,
+            unordered_hunks: false,
+            strict: false,
+            batch_threshold: BatchThreshold::default(),
+            posix: false,
+            verify_index: false,
+            force: false,
+            reverse_if_applied: false,
+            interactive: false,
+            patch_timeout: None,
+            lazy_load: None,
+            show_rejects_inline: false,
+            save_rej_files: true,
+            reject_dir: None,
+            reject_suffix: DEFAULT_REJECT_SUFFIX,
+            function_context: false,
+            preserve_ownership: false,
+            preserve_hard_links: false,
+            touch: None,
+            post_hook: None,
+            deterministic: false,
+            do_backups: ApplyConfigDoBackups::Never,
+            backup_store: None,
+            backup_names: None,
+            backup_count: ApplyConfigBackupCount::All,
+            dry_run: false,
+            emit_diff: false,
+            emit_files: false,
+            stats: false,
+            timings: false,
+            report_unchanged: false,
+            verbosity,
+            audit_log: None,
+            manifest: None,
+            match_cache: None,
+            rename_index: None,
+        }
+    }
+
+    /// The directory where the patched files are written.
+    pub fn output_dir(&self) -> &Path {
+        self.out_dir.unwrap_or(self.base_dir)
+    }
+
+    /// The name of the backup of the patch with the `index`.
+    pub fn backup_name(&self, index: usize) -> &Path {
+        match self.backup_names {
+            Some(backup_names) => &backup_names[index],
+            None => &self.series_patches[index].filename,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Message kept to be printed later (see `ApplyConfig::deterministic`).
+#[derive(Debug)]
+pub struct BufferedMessage {
+    /// The index of the patch the message is about
+    pub index: usize,
+    pub stream: OutputStream,
+    pub text: String,
+}
+
+/// A file patched by a patch in some special way, which needs to be reported
+/// in the end. (E.g. with `--force`.)
+#[derive(Debug)]
+pub struct PatchedFile {
+    /// The index of the patch in the configuration
+    pub index: usize,
+    pub patch_filename: PathBuf,
+    pub filename: PathBuf,
+}
+
+/// How big an applied patch is. (See `ApplyConfig::stats`.)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PatchSize {
+    pub patch_filename: PathBuf,
+    pub hunks: usize,
+
+    /// The hunks that needed fuzz to apply
+    pub fuzzy_hunks: usize,
+
+    /// The bytes of the lines added and removed by the hunks, without the
+    /// context
+    pub added_bytes: usize,
+    pub removed_bytes: usize,
+}
+
+impl PatchSize {
+    /// Add the counts of the `other` size of the same patch, e.g. from
+    /// another thread.
+    pub fn merge(&mut self, other: &PatchSize) {
+        self.hunks += other.hunks;
+        self.fuzzy_hunks += other.fuzzy_hunks;
+        self.added_bytes += other.added_bytes;
+        self.removed_bytes += other.removed_bytes;
+    }
+}
+
+/// How long applying a patch took, the time of all its file patches. (See
+/// `ApplyConfig::timings`.)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PatchTiming {
+    pub patch_filename: PathBuf,
+    pub duration: Duration,
+}
+
+impl PatchTiming {
+    /// Add the time of the `other` timing of the same patch, e.g. from
+    /// another thread.
+    pub fn merge(&mut self, other: &PatchTiming) {
+        self.duration += other.duration;
+    }
+}
+
+/// The file patches that must be applied one after another, because they
+/// change the same file or files renamed into each other. They are applied by
+/// a single thread, so no number of threads applies the patches faster than
+/// these take. (See `ApplyConfig::timings`.)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CriticalPath {
+    /// The time of the file patches
+    pub duration: Duration,
+
+    /// The first of the files they patch
+    pub filename: PathBuf,
+
+    /// The patches they come from
+    pub patches: usize,
+}
+
+/// The net change of a single file by the applied patches, written as a
+/// patch. (See `ApplyConfig::emit_diff`.)
+#[derive(Debug)]
+pub struct FileDiff {
+    pub filename: PathBuf,
+    pub diff: Vec<u8>,
+}
+
+/// A file as the applied patches left it. (See `ApplyConfig::emit_files`.)
+#[derive(Debug)]
+pub struct ChangedFile {
+    pub filename: PathBuf,
+
+    /// `None` if the patches deleted it
+    pub content: Option<Vec<u8>>,
+    pub permissions: Option<fs::Permissions>,
 }
 
 #[derive(Debug)]
 pub struct ApplyResult {
     pub applied_patches: usize,
     pub skipped_patches: usize,
+
+    /// The patch that failed did so only because its hunks matched with fuzz
+    /// or at an offset, which is not allowed by `ApplyConfig::strict`.
+    pub rejected_inexact: bool,
+
+    pub forced_files: Vec<PatchedFile>,
+    pub already_applied_files: Vec<PatchedFile>,
+
+    /// Files that a patch left byte-identical, if requested by
+    /// `ApplyConfig::report_unchanged`.
+    pub unchanged_files: Vec<PatchedFile>,
+
+    /// Files skipped because of `ApplyConfig::file_filter`
+    pub filtered_files: Vec<PatchedFile>,
+
+    /// Files that failed and were skipped in the prompt of
+    /// `ApplyConfig::interactive`.
+    pub skipped_files: Vec<PatchedFile>,
+
+    /// The rejected hunks rendered like ".rej" files, if requested by
+    /// `ApplyConfig::show_rejects_inline`.
+    pub inline_rejects: String,
+
+    /// Messages kept because of `ApplyConfig::deterministic`, in series order.
+    pub messages: Vec<BufferedMessage>,
+
+    /// Differences of the changed files, sorted by filename, if requested
+    /// by `ApplyConfig::emit_diff`.
+    pub file_diffs: Vec<FileDiff>,
+
+    /// The files touched by the patches, sorted by filename, if requested by
+    /// `ApplyConfig::emit_files`.
+    pub changed_files: Vec<ChangedFile>,
+
+    /// Sizes of the applied patches in series order, if requested by
+    /// `ApplyConfig::stats`.
+    pub patch_sizes: Vec<PatchSize>,
+
+    /// Times of the applied patches in series order and the longest chain of
+    /// file patches, if requested by `ApplyConfig::timings`.
+    pub patch_timings: Vec<PatchTiming>,
+    pub critical_path: Option<CriticalPath>,
 }
 
 #[derive(Debug, Error)]
@@ -89,6 +470,189 @@ pub enum ApplyError {
 
     #[error("Failed to save quilt backup file: {filename:?}")]
     SaveQuiltBackupFile { filename: PathBuf },
+
+    #[error("Failed to write to the audit log")]
+    WriteAuditLog,
+
+    #[error("Patch {patch_filename:?} touches file {filename:?} outside of the working directory (use --unsafe-paths to allow it)")]
+    UnsafePath { patch_filename: PathBuf, filename: PathBuf },
+
+    #[error("Patch {patch_filename:?} touches file {filename:?} that is not allowed (see --allow-file and --file-list)")]
+    DisallowedFile { patch_filename: PathBuf, filename: PathBuf },
+
+    #[error("Patch {patch_filename:?} touches file {filename:?}, which is the same file as {other_filename:?} touched by patch {other_patch_filename:?} on the case-insensitive filesystem of the working directory")]
+    CaseCollision { patch_filename: PathBuf, filename: PathBuf, other_patch_filename: PathBuf, other_filename: PathBuf },
+}
+
+/// Write the warning about files that were changed by `--force`.
+pub fn write_forced_files_warning<W: Write>(
+    writer: &mut W,
+    forced_files: &[PatchedFile])
+    -> io::Result<()>
+{
+    if forced_files.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(writer, "{} {} file(s) were patched with --force, their content did not match the patches:",
+             "WARNING".bright_red().bold(), forced_files.len())?;
+    for forced_file in forced_files {
+        writeln!(writer, "  {} {} {} {}",
+                 "File".yellow(), forced_file.filename.display(),
+                 "FORCED by".bright_red().bold(), forced_file.patch_filename.display())?;
+    }
+    writeln!(writer, "Review these files carefully, the result may be broken.")
+}
+
+/// Write the report about patches that were found already applied (see
+/// `--reverse-if-applied`).
+pub fn write_already_applied_report<W: Write>(
+    writer: &mut W,
+    already_applied_files: &[PatchedFile])
+    -> io::Result<()>
+{
+    for (patch_filename, files) in &already_applied_files.iter().chunk_by(|file| &file.patch_filename) {
+        writeln!(writer, "{} {} {}", "Patch".yellow(), patch_filename.display(), "ALREADY APPLIED".bright_cyan().bold())?;
+        for file in files {
+            writeln!(writer, "  {} {} is already in the patched state, it was left unchanged",
+                     "File".yellow(), file.filename.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the report about files that patches left byte-identical (see
+/// `--report-unchanged`).
+pub fn write_unchanged_files_report<W: Write>(
+    writer: &mut W,
+    unchanged_files: &[PatchedFile])
+    -> io::Result<()>
+{
+    for (patch_filename, files) in &unchanged_files.iter().chunk_by(|file| &file.patch_filename) {
+        writeln!(writer, "{} {} {}", "Patch".yellow(), patch_filename.display(), "CHANGES NOTHING IN SOME FILES".bright_cyan().bold())?;
+        for file in files {
+            writeln!(writer, "  {} {} is byte-identical after the patch, its hunks may be redundant",
+                     "File".yellow(), file.filename.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the report about files skipped because of `--include` and
+/// `--exclude`.
+pub fn write_filtered_files_report<W: Write>(
+    writer: &mut W,
+    filtered_files: &[PatchedFile])
+    -> io::Result<()>
+{
+    for (patch_filename, files) in &filtered_files.iter().chunk_by(|file| &file.patch_filename) {
+        writeln!(writer, "{} {} {}", "Patch".yellow(), patch_filename.display(), "PARTIALLY APPLIED".bright_cyan().bold())?;
+        for file in files {
+            writeln!(writer, "  {} {} was skipped by --include/--exclude",
+                     "File".yellow(), file.filename.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the report about files skipped in the interactive prompt (see
+/// `--interactive`).
+pub fn write_skipped_files_report<W: Write>(
+    writer: &mut W,
+    skipped_files: &[PatchedFile])
+    -> io::Result<()>
+{
+    for (patch_filename, files) in &skipped_files.iter().chunk_by(|file| &file.patch_filename) {
+        writeln!(writer, "{} {} {}", "Patch".yellow(), patch_filename.display(), "PARTIALLY APPLIED".bright_cyan().bold())?;
+        for file in files {
+            writeln!(writer, "  {} {} failed and was skipped, it must be fixed by hand",
+                     "File".yellow(), file.filename.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// The note after the count of hunks, if `fuzzy_hunks` of them needed fuzz.
+fn fuzzy_hunks_note(fuzzy_hunks: usize) -> String {
+    if fuzzy_hunks > 0 {
+        format!(" ({} with fuzz)", fuzzy_hunks)
+    } else {
+        String::new()
+    }
+}
+
+/// Write the hunks and changed bytes of every applied patch.
+pub fn write_patch_sizes<W: Write>(writer: &mut W, patch_sizes: &[PatchSize]) -> io::Result<()> {
+    writeln!(writer, "Patch sizes:")?;
+    for patch_size in patch_sizes {
+        writeln!(writer, "  {} {}: {} hunk(s){}, +{} -{} bytes",
+                 "Patch".yellow(), patch_size.patch_filename.display(),
+                 patch_size.hunks, fuzzy_hunks_note(patch_size.fuzzy_hunks),
+                 patch_size.added_bytes, patch_size.removed_bytes)?;
+    }
+    Ok(())
+}
+
+/// Write the hunks and changed bytes of every applied patch as JSON, one
+/// object per patch and line.
+pub fn write_patch_sizes_json<W: Write>(writer: &mut W, patch_sizes: &[PatchSize]) -> io::Result<()> {
+    for patch_size in patch_sizes {
+        writer.write_all(b"{\"patch\":")?;
+        json::write_path(writer, &patch_size.patch_filename)?;
+        writeln!(writer, ",\"hunks\":{},\"fuzzy_hunks\":{},\"added_bytes\":{},\"removed_bytes\":{}}}",
+                 patch_size.hunks, patch_size.fuzzy_hunks, patch_size.added_bytes, patch_size.removed_bytes)?;
+    }
+    Ok(())
+}
+
+/// Write the `top` slowest of the `patch_timings`, the slowest first, and the
+/// `critical_path`.
+pub fn write_timings<W: Write>(writer: &mut W, patch_timings: &[PatchTiming], critical_path: Option<&CriticalPath>,
+                               top: usize) -> io::Result<()> {
+    let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let total: Duration = patch_timings.iter().map(|patch_timing| patch_timing.duration).sum();
+    writeln!(writer, "Slowest patches:")?;
+    for patch_timing in patch_timings.iter().sorted_by(|a, b| b.duration.cmp(&a.duration)).take(top) {
+        writeln!(writer, "  {} {}: {:.3} ms",
+                 "Patch".yellow(), patch_timing.patch_filename.display(), milliseconds(patch_timing.duration))?;
+    }
+    if let Some(critical_path) = critical_path {
+        writeln!(writer, "Critical path: {:.3} ms of {:.3} ms, {} patch(es) of file {}",
+                 milliseconds(critical_path.duration), milliseconds(total),
+                 critical_path.patches, critical_path.filename.display())?;
+    }
+    Ok(())
+}
+
+/// Write the line that sums up a push of `total` patches with the
+/// `apply_result`: how many were applied and how many not, and the hunks and
+/// changed bytes of the applied ones, which need `ApplyConfig::stats`.
+pub fn write_summary<W: Write>(writer: &mut W, total: usize, apply_result: &ApplyResult) -> io::Result<()> {
+    let mut sum = PatchSize::default();
+    for patch_size in &apply_result.patch_sizes {
+        sum.merge(patch_size);
+    }
+    // Pushing stops at the first patch that fails, the rest is not tried.
+    let failed = usize::from(apply_result.skipped_patches > 0);
+    writeln!(writer, "Summary: {} of {} patches applied, {} failed, {} not tried, {} hunk(s){}, +{} -{} bytes",
+             apply_result.applied_patches, total, failed, apply_result.skipped_patches - failed,
+             sum.hunks, fuzzy_hunks_note(sum.fuzzy_hunks), sum.added_bytes, sum.removed_bytes)
+}
+
+/// Print the `messages` to their streams.
+pub fn write_buffered_messages<O: Write, E: Write>(
+    stdout: &mut O,
+    stderr: &mut E,
+    messages: &[BufferedMessage])
+    -> io::Result<()>
+{
+    for message in messages {
+        match message.stream {
+            OutputStream::Stdout => stdout.write_all(message.text.as_bytes())?,
+            OutputStream::Stderr => stderr.write_all(message.text.as_bytes())?,
+        }
+    }
+    Ok(())
 }
 
 fn prefix_warning() -> ColoredString {
@@ -45,34 +45,47 @@
 //! # Step 5 - single-threaded
 //!
 //! Collect results and print reports.
+//!
+//! # Memory
+//!
+//! All patches are resident from step 1 until the end, the `FilePatch`es
+//! borrow their content from the arena. The scheduling in step 2 needs all of
+//! them, because any later rename may tie two files to the same thread. Every
+//! patched file stays in memory until it is saved in step 4, it is needed for
+//! the rollback and the backup files. So the peak memory use is the size of
+//! the patches plus the size of the patched files, whatever the number of
+//! threads. There is no bound on how much is loaded ahead that could lower it.
 
 
 use std;
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::hash::{BuildHasherDefault, Hash};
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Mutex;
+use std::time::Instant;
 
 use colored::*;
 use anyhow::{Context, Error, Result};
 use seahash;
 use rayon;
 use rayon::iter::{
+    IndexedParallelIterator,
     IntoParallelRefIterator,
     ParallelDrainRange,
     ParallelIterator
 };
 
 use crate::apply::*;
+use crate::apply::combined_diff::collect_file_diffs;
 use crate::apply::common::*;
 use crate::apply::diagnostics::*;
 use crate::arena::Arena;
 
 use libpatch::analysis::{AnalysisSet, Note};
-use libpatch::patch::{PatchDirection, TextFilePatch};
-use libpatch::patch::unified::parser::parse_patch;
+use libpatch::patch::TextFilePatch;
 
 /// This is tool that distributes filenames among threads. Currently it doesn't
 /// do any overly smart planning, it just distributes them one by one as they
@@ -131,7 +144,19 @@ impl<T: Hash + Eq> FilenameDistributor<T> {
     }
 
     /// Consume the distributor and produce a map from `filename` to thread index.
-    pub fn build(mut self) -> HashMap<T, usize, BuildHasherDefault<seahash::SeaHasher>> {
+    pub fn build(self) -> HashMap<T, usize, BuildHasherDefault<seahash::SeaHasher>> {
+        let thread_count = self.thread_count;
+        let mut filename_to_thread_id = self.components();
+        for index in filename_to_thread_id.values_mut() {
+            *index %= thread_count;
+        }
+        filename_to_thread_id
+    }
+
+    /// Consume the distributor and produce a map from `filename` to the
+    /// index of its connected component. The filenames with the same index
+    /// are always assigned to the same thread.
+    pub fn components(mut self) -> HashMap<T, usize, BuildHasherDefault<seahash::SeaHasher>> {
         for i in 0..self.connected_components.len() {
             if self.connected_components[i] != i {
                 self.connected_components[i] = self.connected_components[self.connected_components[i]];
@@ -139,7 +164,7 @@ impl<T: Hash + Eq> FilenameDistributor<T> {
         }
 
         for index in self.filename_to_index.values_mut() {
-            *index = self.connected_components[*index] % self.thread_count;
+            *index = self.connected_components[*index];
         }
 
         self.filename_to_index
@@ -163,6 +188,11 @@ fn apply_worker<'arena, 'config>(
     -> Result<AppliedState<'arena, 'config>>
 {
     let mut state = AppliedState::new(config, thread_file_patches.len());
+    state.output.buffered = config.deterministic;
+
+    // The deadline of the patch we are currently applying. Every thread
+    // measures the time it spends on its part of the patch.
+    let mut current_patch: Option<(usize, Option<Instant>)> = None;
 
     // First we go forward and apply patches until we apply all of them or get past the `earliest_broken_patch_index`
     for (index, text_file_patch) in thread_file_patches {
@@ -174,19 +204,42 @@ fn apply_worker<'arena, 'config>(
             break;
         }
 
+        let buffered_notes = RefCell::new(Vec::<u8>::new());
         let fn_analysis_note = |note: &dyn Note, file_patch: &TextFilePatch| {
             // We ignore any error here because currently we don't have a way to propagate it out
             // of this callback. It's not so tragic, error here would most likely be IO error from
             // writing to terminal.
-            let _ = print_analysis_note(&config.series_patches[index].filename, note, file_patch);
+            let patch_filename = &config.series_patches[index].filename;
+            let _ = if config.deterministic {
+                write_analysis_note(&mut *buffered_notes.borrow_mut(), patch_filename, note, file_patch)
+            } else {
+                print_analysis_note(patch_filename, note, file_patch)
+            };
+        };
+
+        let deadline = match current_patch {
+            Some((current_index, deadline)) if current_index == index => deadline,
+            _ => {
+                let deadline = patch_deadline(config);
+                current_patch = Some((index, deadline));
+                deadline
+            }
         };
 
         // Try to apply this one `FilePatch`
-        match state.apply_one_file_patch(index,
-                                         text_file_patch,
-                                         arena,
-                                         analyses,
-                                         &fn_analysis_note) {
+        let result = state.apply_one_file_patch(index,
+                                                text_file_patch,
+                                                deadline,
+                                                arena,
+                                                analyses,
+                                                &fn_analysis_note);
+
+        let buffered_notes = buffered_notes.into_inner();
+        if !buffered_notes.is_empty() {
+            state.output.print(index, OutputStream::Stderr, String::from_utf8_lossy(&buffered_notes).into_owned());
+        }
+
+        match result {
             Ok(false) => {
                 // Patch failed to apply...
 
@@ -219,6 +272,19 @@ struct SharedState {
 #[derive(Default)]
 struct WorkerReport {
     failure_analysis: String,
+    /// The `FilePatch`es of the final patch that failed, and how many of them
+    /// only because of `ApplyConfig::strict`
+    failed_file_patches: (usize, usize),
+    inline_rejects: String,
+    forced_files: Vec<PatchedFile>,
+    already_applied_files: Vec<PatchedFile>,
+    unchanged_files: Vec<PatchedFile>,
+    messages: Vec<BufferedMessage>,
+    file_diffs: Vec<FileDiff>,
+    changed_files: Vec<ChangedFile>,
+    patch_sizes: Vec<PatchSize>,
+    patch_timings: Vec<PatchTiming>,
+    critical_path: Option<CriticalPath>,
 }
 
 /// This function is executed by every thread during the "Step 4" phase - when
@@ -232,6 +298,7 @@ struct WorkerReport {
 /// `analyses`: Set of analyses to run.
 fn save_files_worker(
     config: &ApplyConfig,
+    arena: &dyn Arena,
     shared: &SharedState,
     mut state: AppliedState,
     final_patch: usize)
@@ -243,16 +310,52 @@ fn save_files_worker(
             break;
         }
 
-        // NOTE(unwrap): It must be there, we must have loaded it when applying the patch.
-        let file = state.modified_files.get_mut(applied_patch.final_filename.as_ref()).unwrap();
-        applied_patch.file_patch.rollback(file, PatchDirection::Forward, &applied_patch.report);
+        // The files that were already patched were not changed by it.
+        if !applied_patch.already_applied {
+            // NOTE(unwrap): It must be there, we must have loaded it when applying the patch.
+            let file = state.modified_files.get_mut(applied_patch.final_filename.as_ref()).unwrap();
+            applied_patch.file_patch.rollback(file, applied_patch.direction, &applied_patch.report);
+        }
 
         state.applied_patches.pop();
     };
 
     // Analyze failure, in case there was any
     let mut failure_analysis = String::new();
-    analyze_patch_failure(config.verbosity, final_patch, &state.applied_patches, &state.modified_files, &mut failure_analysis)?;
+    analyze_patch_failure(config.verbosity, config.function_context, final_patch, &state.applied_patches, &state.modified_files, &mut failure_analysis)?;
+    let failed_file_patches = count_failed_file_patches(&state.applied_patches, final_patch);
+
+    let mut inline_rejects = String::new();
+    if config.show_rejects_inline {
+        write_inline_rejects(final_patch, &state.applied_patches, &mut inline_rejects)?;
+    }
+
+    let forced_files = collect_patched_files(&state.applied_patches, final_patch, |status| status.report.forced());
+    let already_applied_files = collect_patched_files(&state.applied_patches, final_patch, |status| status.already_applied);
+    let unchanged_files = collect_patched_files(&state.applied_patches, final_patch, |status| status.unchanged);
+    let patch_sizes = if config.stats {
+        count_patch_sizes(config, &state.applied_patches, final_patch)
+    } else {
+        Vec::new()
+    };
+    let (patch_timings, critical_path) = if config.timings {
+        count_patch_timings(config, &state.applied_patches, final_patch)
+    } else {
+        (Vec::new(), None)
+    };
+
+    let mut file_diffs = Vec::new();
+    let mut changed_files = Vec::new();
+    if (config.emit_diff || config.emit_files) && config.dry_run {
+        // Take back what was applied of the failed patch
+        state.rollback_patch(final_patch);
+        if config.emit_diff {
+            file_diffs = collect_file_diffs(config, arena, &state.modified_files)?;
+        }
+        if config.emit_files {
+            changed_files = collect_changed_files(&state.modified_files)?;
+        }
+    }
 
     // If this is not dry-run, save all the results
     if !config.dry_run {
@@ -266,8 +369,8 @@ fn save_files_worker(
 
         // Save all the files we modified
         let mut directories_for_cleaning = HashSet::with_hasher(BuildHasherDefault::<seahash::SeaHasher>::default());
-        state.modified_files.save(&mut directories_for_cleaning)?;
-        clean_empty_directories(config.base_dir, directories_for_cleaning)?;
+        state.modified_files.save(&state.applied_patches, &mut directories_for_cleaning)?;
+        clean_empty_directories(config.output_dir(), directories_for_cleaning)?;
 
         // Maybe save some backup files
         if config.do_backups == ApplyConfigDoBackups::Always ||
@@ -290,6 +393,17 @@ fn save_files_worker(
 
     Ok(WorkerReport {
         failure_analysis,
+        failed_file_patches,
+        inline_rejects,
+        forced_files,
+        already_applied_files,
+        unchanged_files,
+        messages: state.output.messages,
+        file_diffs,
+        changed_files,
+        patch_sizes,
+        patch_timings,
+        critical_path,
     })
 }
 
@@ -307,21 +421,42 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
         println!("Parsing patches...");
     }
 
+    let path_guard = &PathGuard::for_config(config)?;
+    let mut case_guard = CaseGuard::for_config(config)?;
+
     // Load all patches multi-threaded using rayon's parallel iterator.
     let mut text_patches: Vec<_> = config.series_patches.par_iter().map(|series_patch| -> Result<_> {
         if config.verbosity >= Verbosity::ExtraVerbose {
             // This will fight for stdout lock. But that's expected in ExtraVerbose mode...
             println!("Parsing patch: {:?}", series_patch.filename);
         }
-        let raw_patch_data = arena.load_file(&config.patches_path.join(&series_patch.filename))?;
-        let text_patch = parse_patch(raw_patch_data, series_patch.strip)?;
-        Ok(text_patch)
+        let raw_patch_data = load_series_patch(config, arena, series_patch)?;
+        let (text_patch, strip) = parse_series_patch(config, series_patch, raw_patch_data)?;
+        if let Some(path_guard) = path_guard {
+            path_guard.check_patch(series_patch, &text_patch)?;
+        }
+        check_allowed_files(config, &series_patch.filename, &text_patch)?;
+        Ok((text_patch, strip))
+    }).collect::<Vec<_>>().into_iter().zip(config.series_patches).map(|(result, series_patch)| {
+        // Reported here, to keep the series order.
+        result.map(|(text_patch, strip)| {
+            print_auto_strip(config, series_patch, strip);
+            text_patch
+        })
     }).collect();
 
     if config.verbosity >= Verbosity::Verbose {
         println!("Scheduling files to threads...");
     }
 
+    // Drop the files that should not be patched, before they are scheduled
+    let mut filtered_files = Vec::new();
+    for (index, text_patch) in text_patches.iter_mut().enumerate() {
+        if let Ok(text_patch) = text_patch {
+            filtered_files.extend(filter_file_patches(config, index, text_patch));
+        }
+    }
+
     // Distribute the patches to queues for worker threads
     // Error checking later, here we'll look at the ok ones
     let mut filename_distributor = FilenameDistributor::<Cow<Path>>::new(threads);
@@ -366,6 +501,11 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
 
 	print_parser_warnings(config, &config.series_patches[index].filename, &text_patch);
 
+        // Checked here, so the first collision in the series is reported.
+        if let Some(case_guard) = &mut case_guard {
+            case_guard.check_patch(&config.series_patches[index].filename, &text_patch)?;
+        }
+
         for text_file_patch in text_patch.file_patches.drain(..) {
             // Note that we can dispatch by `old_filename` or `new_filename`, we
             // made sure that both will be assigned to the same `thread_id`.
@@ -388,13 +528,14 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
     let thread_errors: Mutex<Vec<Error>> = Mutex::new(Vec::new());
     let thread_errors_ref = &thread_errors;
 
-    // This will hold per-thread successful applied states.
-    let applied_states: Mutex<Vec<AppliedState>> = Mutex::new(Vec::with_capacity(threads));
+    // This will hold per-thread successful applied states, with the id of
+    // the thread, so the results can be ordered the same way every time.
+    let applied_states: Mutex<Vec<(usize, AppliedState)>> = Mutex::new(Vec::with_capacity(threads));
     let applied_states_ref = &applied_states;
 
     // Run the applying threads
-    text_file_patches_per_thread.par_drain(..).for_each(
-        move |thread_file_patches| {
+    text_file_patches_per_thread.par_drain(..).enumerate().for_each(
+        move |(thread_id, thread_file_patches)| {
             let result = apply_worker(
                 config,
                 arena,
@@ -406,7 +547,7 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
             // while holding it. We may as well.
             match result {
                 Ok(state) =>
-                    applied_states_ref.lock().unwrap().push(state),
+                    applied_states_ref.lock().unwrap().push((thread_id, state)),
                 Err(err) =>
                     thread_errors_ref.lock().unwrap().push(err),
             }
@@ -419,7 +560,7 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
     let final_patch = earliest_broken_patch_index.load(Ordering::Acquire);
 
     // This will hold per-thread successful reports.
-    let thread_reports: Mutex<Vec<WorkerReport>> = Mutex::new(Vec::with_capacity(applied_states.len()));
+    let thread_reports: Mutex<Vec<(usize, WorkerReport)>> = Mutex::new(Vec::with_capacity(applied_states.len()));
     let thread_reports_ref = &thread_reports;
 
     // Initialize state shared by all threads
@@ -430,9 +571,10 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
 
     // Run the saving threads
     applied_states.par_drain(..).for_each(
-        move |state| {
+        move |(thread_id, state)| {
             let result = save_files_worker(
                 config,
+                arena,
                 shared,
                 state,
                 final_patch);
@@ -440,7 +582,7 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
             // NOTE(unwrap): If a lock is poisoned, another thread panicked.
             match result {
                 Ok(report) =>
-                    thread_reports_ref.lock().unwrap().push(report),
+                    thread_reports_ref.lock().unwrap().push((thread_id, report)),
                 Err(err) =>
                     thread_errors_ref.lock().unwrap().push(err),
             }
@@ -455,25 +597,93 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
         return Err(error);
     }
 
+    // NOTE(unwrap): If the lock is poisoned, another thread panicked.
+    let mut thread_reports = thread_reports.into_inner().unwrap();
+    thread_reports.sort_by_key(|(thread_id, _)| *thread_id);
+
+    // The messages of each thread are in series order, merge them. (The sort is stable.)
+    let mut messages: Vec<_> = thread_reports.iter_mut()
+        .flat_map(|(_, report)| report.messages.drain(..))
+        .collect();
+    messages.sort_by_key(|message| message.index);
+
     // Print out failure analysis if we didn't apply everything
     if final_patch != config.series_patches.len() {
-        eprintln!("{} {} {}", "Patch".yellow(), config.series_patches[final_patch].filename.display(), "FAILED".bright_red().bold());
+        let mut output = OutputBuffer { buffered: config.deterministic, messages };
 
-        // NOTE(unwrap): If the lock is poisoned, another thread panicked.
-        let thread_reports = thread_reports.into_inner().unwrap();
+        output.print(final_patch, OutputStream::Stderr, format!(
+            "{} {} {}\n", "Patch".yellow(), config.series_patches[final_patch].filename.display(), "FAILED".bright_red().bold()));
 
-        for result in thread_reports {
-            eprint!("{}", result.failure_analysis);
+        for (_, result) in &thread_reports {
+            output.print(final_patch, OutputStream::Stderr, result.failure_analysis.clone());
         }
+
+        messages = output.messages;
     }
 
-    if config.stats {
-        println!("{}", arena.stats());
+    let mut forced_files = Vec::new();
+    let mut already_applied_files = Vec::new();
+    let mut unchanged_files = Vec::new();
+    let mut inline_rejects = String::new();
+    let mut file_diffs = Vec::new();
+    let mut changed_files = Vec::new();
+    let mut patch_sizes: Vec<PatchSize> = Vec::new();
+    let mut patch_timings: Vec<PatchTiming> = Vec::new();
+    let mut critical_path: Option<CriticalPath> = None;
+    let (mut failed, mut inexact) = (0, 0);
+    for (_, report) in thread_reports {
+        failed += report.failed_file_patches.0;
+        inexact += report.failed_file_patches.1;
+        // Every thread counted its own files of every patch.
+        if patch_sizes.is_empty() {
+            patch_sizes = report.patch_sizes;
+        } else {
+            for (patch_size, thread_patch_size) in patch_sizes.iter_mut().zip(&report.patch_sizes) {
+                patch_size.merge(thread_patch_size);
+            }
+        }
+        if patch_timings.is_empty() {
+            patch_timings = report.patch_timings;
+        } else {
+            for (patch_timing, thread_patch_timing) in patch_timings.iter_mut().zip(&report.patch_timings) {
+                patch_timing.merge(thread_patch_timing);
+            }
+        }
+        // The chains are never split between threads, the longest one of
+        // any thread is the longest one.
+        if report.critical_path.as_ref().map(|path| path.duration) > critical_path.as_ref().map(|path| path.duration) {
+            critical_path = report.critical_path;
+        }
+        file_diffs.extend(report.file_diffs);
+        changed_files.extend(report.changed_files);
+        forced_files.extend(report.forced_files);
+        already_applied_files.extend(report.already_applied_files);
+        unchanged_files.extend(report.unchanged_files);
+        inline_rejects.push_str(&report.inline_rejects);
     }
+    forced_files.sort_by(|a, b| (a.index, &a.filename).cmp(&(b.index, &b.filename)));
+    already_applied_files.sort_by(|a, b| (a.index, &a.filename).cmp(&(b.index, &b.filename)));
+    unchanged_files.sort_by(|a, b| (a.index, &a.filename).cmp(&(b.index, &b.filename)));
+    filtered_files.retain(|file| file.index < final_patch);
+    file_diffs.sort_by(|a, b| a.filename.cmp(&b.filename));
+    changed_files.sort_by(|a, b| a.filename.cmp(&b.filename));
 
     Ok(ApplyResult {
         applied_patches: final_patch,
         skipped_patches: config.series_patches.len() - final_patch,
+        rejected_inexact: failed > 0 && failed == inexact,
+        forced_files,
+        already_applied_files,
+        unchanged_files,
+        filtered_files,
+        skipped_files: Vec::new(),
+        inline_rejects,
+        messages,
+        file_diffs,
+        changed_files,
+        patch_sizes,
+        patch_timings,
+        critical_path,
     })
 }
 
@@ -0,0 +1,135 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `push --provenance FILE`, recording which patch and
+//! hunk last changed every line of the patched files.
+//!
+//! The applied patches are applied once more in memory and the origin of
+//! every line is tracked along with the content of its file: the lines that a
+//! hunk adds come from it, the other lines keep their origin, so a line comes
+//! from the last patch that added or replaced it. Lines that no patch touched
+//! have no origin and are not listed.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use anyhow::{Context, Error, Result};
+use itertools::Itertools;
+
+use libpatch::analysis::{AnalysisSet, fn_analysis_note_noop};
+
+use crate::apply::*;
+use crate::apply::common::*;
+use crate::arena::Arena;
+use crate::json;
+
+/// The index of the patch in the series and of the hunk in its file patch
+/// that a line comes from, `None` if it was in the original file.
+type LineOrigin = Option<(usize, usize)>;
+
+/// The origins of the lines of a file after a patch, from the `origins`
+/// before it and the lines that its hunks replaced, see
+/// `FilePatchApplyReport::replaced_lines`.
+fn replace_origins(origins: &[LineOrigin], replaced_lines: &[(usize, Range<usize>, usize)],
+                   patch_index: usize) -> Vec<LineOrigin> {
+    let mut new_origins = Vec::with_capacity(origins.len());
+    let mut copied_until = 0;
+    for (hunk_index, range, added) in replaced_lines {
+        new_origins.extend_from_slice(&origins[copied_until..range.start]);
+        new_origins.extend(std::iter::repeat_n(Some((patch_index, *hunk_index)), *added));
+        copied_until = range.end;
+    }
+    new_origins.extend_from_slice(&origins[copied_until..]);
+    new_origins
+}
+
+/// Apply the patches in the `config` in memory, up to the first one that
+/// fails, and write where the lines of the patched files come from into the
+/// `writer`. Every line of the output is a JSON object with a range of lines
+/// (1-based, inclusive) of a file, the patch and the hunk (1-based, in the
+/// file patch) that last changed them.
+pub fn write_provenance<W: Write>(config: &ApplyConfig, arena: &dyn Arena, writer: &mut W) -> Result<()> {
+    // The whole files are loaded, so the origins cover every line.
+    let provenance_config = ApplyConfig {
+        lazy_load: None,
+        interactive: false,
+        post_hook: None,
+        do_backups: ApplyConfigDoBackups::Never,
+        backup_count: ApplyConfigBackupCount::All,
+        dry_run: true,
+        emit_diff: false,
+        emit_files: false,
+        audit_log: None,
+        manifest: None,
+        match_cache: None,
+        ..*config
+    };
+
+    let mut state = AppliedState::new(&provenance_config, config.series_patches.len());
+    let mut origins: HashMap<PathBuf, Vec<LineOrigin>> = HashMap::new();
+    for (index, series_patch) in config.series_patches.iter().enumerate() {
+        let (mut patch, _) = load_series_patch(config, arena, series_patch)
+            .map_err(Error::from)
+            .and_then(|data| parse_series_patch(config, series_patch, data))
+            .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+        filter_file_patches(&provenance_config, index, &mut patch);
+
+        // The changes of the file patches, to undo them if the patch fails
+        let mut undo: Vec<(PathBuf, PathBuf, Option<Vec<LineOrigin>>)> = Vec::new();
+        let mut failed = false;
+
+        let deadline = patch_deadline(config);
+        for file_patch in patch.file_patches {
+            let applied = state.apply_one_file_patch(index, file_patch, deadline, arena,
+                                                     &AnalysisSet::default(), &fn_analysis_note_noop)?;
+            failed |= !applied;
+            let Some(applied_patch) = state.applied_patches.last().filter(|applied_patch| applied && !applied_patch.already_applied) else {
+                continue;
+            };
+
+            let replaced_lines = applied_patch.report.replaced_lines(&applied_patch.file_patch);
+            let previous_origins = origins.remove(&*applied_patch.target_filename);
+            let file_origins = match &previous_origins {
+                Some(file_origins) => replace_origins(file_origins, &replaced_lines, index),
+                None => {
+                    // The first patch of this file, all its lines were there before.
+                    let lines = state.modified_files.get(&applied_patch.final_filename)
+                        .map_or(0, |file| file.content.len());
+                    let removed: usize = replaced_lines.iter().map(|(_, range, _)| range.len()).sum();
+                    let added: usize = replaced_lines.iter().map(|(_, _, added)| added).sum();
+                    replace_origins(&vec![None; lines + removed - added], &replaced_lines, index)
+                }
+            };
+            origins.insert(applied_patch.final_filename.to_path_buf(), file_origins);
+            undo.push((applied_patch.target_filename.to_path_buf(), applied_patch.final_filename.to_path_buf(), previous_origins));
+        }
+
+        // The push stops at the patch that fails, it does not change anything.
+        if failed {
+            for (target_filename, final_filename, previous_origins) in undo.into_iter().rev() {
+                origins.remove(&final_filename);
+                if let Some(previous_origins) = previous_origins {
+                    origins.insert(target_filename, previous_origins);
+                }
+            }
+            break;
+        }
+    }
+
+    for (filename, file_origins) in origins.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+        let mut line = 0;
+        for (origin, group) in &file_origins.iter().chunk_by(|origin| **origin) {
+            let count = group.count();
+            if let Some((patch_index, hunk_index)) = origin {
+                writer.write_all(b"{\"path\":")?;
+                json::write_path(writer, filename)?;
+                write!(writer, ",\"start\":{},\"end\":{},\"patch\":", line + 1, line + count)?;
+                json::write_path(writer, &config.series_patches[patch_index].filename)?;
+                writeln!(writer, ",\"hunk\":{}}}", hunk_index + 1)?;
+            }
+            line += count;
+        }
+    }
+    Ok(())
+}
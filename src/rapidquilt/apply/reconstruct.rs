@@ -0,0 +1,122 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `reconstruct-pc --reference <dir>`, rebuilding the
+//! backups in ".pc" of the applied patches from a pristine tree.
+//!
+//! The applied patches are applied in memory on the files of the reference
+//! tree, in the order of the series. The content of every file before a patch
+//! touches it is the backup of that patch, as `push` would have saved it.
+//! The reference tree is only read. The files that the patches produce are
+//! compared with the working tree, a difference means that the reference is
+//! not the tree the patches were pushed on, or that the files were edited
+//! since, and popping would then lose those edits.
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, Context, Error, Result};
+use colored::*;
+use itertools::Itertools;
+
+use libpatch::analysis::{AnalysisSet, fn_analysis_note_noop};
+use libpatch::modified_file::ModifiedFile;
+
+use crate::apply::*;
+use crate::apply::common::*;
+use crate::arena::Arena;
+
+/// Rebuild the backups of all patches in the `config`, which must be the
+/// applied ones, from the pristine tree in the `reference_dir`. Existing
+/// backups of the patches are replaced. The files that do not match the
+/// working tree are reported into the `writer`.
+///
+/// Returns whether all files match.
+pub fn reconstruct_backups<W: Write>(config: &ApplyConfig, arena: &dyn Arena, reference_dir: &Path,
+                                     writer: &mut W) -> Result<bool> {
+    let file_backup_store = FileBackupStore::new(config.base_dir);
+    let backup_store = config.backup_store.unwrap_or(&file_backup_store);
+    let reference_config = ApplyConfig {
+        base_dir: reference_dir,
+        out_dir: None,
+        reverse_if_applied: false,
+        interactive: false,
+        post_hook: None,
+        do_backups: ApplyConfigDoBackups::Never,
+        backup_store: Some(backup_store),
+        backup_count: ApplyConfigBackupCount::All,
+        dry_run: true,
+        rename_index: None,
+        ..*config
+    };
+
+    let mut state = AppliedState::new(&reference_config, config.series_patches.len());
+    for (index, series_patch) in config.series_patches.iter().enumerate() {
+        let (mut patch, _) = load_series_patch(config, arena, series_patch)
+            .map_err(Error::from)
+            .and_then(|data| parse_series_patch(config, series_patch, data))
+            .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+        filter_file_patches(&reference_config, index, &mut patch);
+        if patch.file_patches.is_empty() {
+            writeln!(writer, "{}: Patch {} changes no files, it has no backup and can not be popped.",
+                     "WARNING".bright_yellow(), series_patch.filename.display())?;
+        }
+
+        let deadline = patch_deadline(config);
+        for file_patch in patch.file_patches {
+            state.apply_one_file_patch(index, file_patch, deadline, arena,
+                                       &AnalysisSet::default(), &fn_analysis_note_noop)?;
+        }
+
+        let mismatched_files = state.applied_patches.iter()
+            .filter(|applied_patch| applied_patch.index == index && applied_patch.report.failed())
+            .map(|applied_patch| applied_patch.target_filename.display())
+            .join(", ");
+        if !mismatched_files.is_empty() {
+            bail!("Patch {} does not apply on the reference tree \"{}\". These files do not match it: {}",
+                  series_patch.filename.display(), reference_dir.display(), mismatched_files);
+        }
+    }
+
+    let mut mismatches = Vec::new();
+    for (filename, file) in state.modified_files.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+        let working_file = load_existing_file(arena, &config.base_dir.join(filename))
+            .with_context(|| ApplyError::LoadFileToPatch { filename: filename.to_path_buf() })?;
+
+        // The patched file may be loaded only partially, get all of it.
+        let mut data = Vec::new();
+        let mismatch = match working_file {
+            None if file.deleted => continue,
+            None => "is missing in the working tree",
+            Some(_) if file.deleted => "is deleted by the patches, but it is in the working tree",
+            Some(working_file) => {
+                file.write_to(&mut data)?;
+                if ModifiedFile::new(&data, true, None).content == working_file.content {
+                    continue;
+                }
+                "differs from the working tree"
+            }
+        };
+        mismatches.push(format!("File {} {}", filename.display(), mismatch));
+    }
+
+    for (index, series_patch) in config.series_patches.iter().enumerate() {
+        let backup_name = config.backup_name(index);
+        let has_backup = backup_store.list(backup_name)
+            .with_context(|| format!("Reading backup files of patch {}", series_patch.filename.display()))?
+            .is_some();
+        if has_backup {
+            backup_store.remove(backup_name)
+                .with_context(|| format!("Removing backup files of patch {}", series_patch.filename.display()))?;
+        }
+    }
+    state.rollback_and_save_backup_files(0)?;
+
+    if !mismatches.is_empty() {
+        writeln!(writer, "{}: {} file(s) patched from the reference differ, popping the patches loses these changes:",
+                 "WARNING".bright_yellow(), mismatches.len())?;
+        for mismatch in &mismatches {
+            writeln!(writer, "  {}", mismatch)?;
+        }
+    }
+    Ok(mismatches.is_empty())
+}
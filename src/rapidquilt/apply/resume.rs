@@ -0,0 +1,75 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `push --resume`, continuing after a failed push.
+//!
+//! The patch that failed is expected to be applied by hand in the working
+//! tree now. To check that, it is reverted in memory. If it reverts cleanly,
+//! the reverted files are the original content before the patch, so they are
+//! saved as its quilt backup files. Then the patch can be marked as applied.
+
+use anyhow::{bail, Error, Context, Result};
+use itertools::Itertools;
+
+use libpatch::analysis::{AnalysisSet, fn_analysis_note_noop};
+
+use crate::apply::*;
+use crate::apply::common::*;
+use crate::arena::Arena;
+
+/// Check that the first patch in `config` is applied in the working tree and
+/// create its quilt backup files. Fails if the patch can not be reverted.
+pub fn adopt_fixed_patch(config: &ApplyConfig, arena: &dyn Arena) -> Result<()> {
+    let series_patch = &config.series_patches[0];
+
+    let reversed_series = [SeriesPatch {
+        filename: series_patch.filename.clone(),
+        strip: series_patch.strip,
+        reverse: !series_patch.reverse,
+    }];
+    let reversed_config = ApplyConfig {
+        series_patches: &reversed_series,
+        force: false,
+        reverse_if_applied: false,
+        do_backups: ApplyConfigDoBackups::Never,
+        backup_count: ApplyConfigBackupCount::All,
+        ..*config
+    };
+
+    let patch = load_series_patch(config, arena, series_patch)
+        .map_err(Error::from)
+        .and_then(|data| parse_tree_patch(config, data, series_patch.strip))
+        .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+    if let Some(path_guard) = PathGuard::for_config(config)? {
+        path_guard.check_patch(series_patch, &patch)?;
+    }
+    check_allowed_files(config, &series_patch.filename, &patch)?;
+
+    let mut state = AppliedState::new(&reversed_config, patch.file_patches.len());
+    let deadline = patch_deadline(&reversed_config);
+    for file_patch in patch.file_patches {
+        state.apply_one_file_patch(0, file_patch, deadline, arena, &AnalysisSet::default(), &fn_analysis_note_noop)?;
+    }
+
+    let mismatched_files = state.applied_patches.iter()
+        .filter(|applied_patch| applied_patch.report.failed())
+        .map(|applied_patch| applied_patch.target_filename.display())
+        .join(", ");
+    if !mismatched_files.is_empty() {
+        bail!("Can not resume, patch {} is not applied in the working tree. These files do not match it: {}",
+              series_patch.filename.display(), mismatched_files);
+    }
+
+    if !config.dry_run {
+        // Reverting the patch renamed the files back, so every file touched
+        // by the patch is there under the name it had before the patch.
+        for (filename, file) in state.modified_files.iter() {
+            save_backup_file(config, config.backup_name(0), filename, file)?;
+        }
+    }
+
+    if config.verbosity >= Verbosity::Normal {
+        println!("Patch {} is applied in the working tree, resuming after it.", series_patch.filename.display());
+    }
+
+    Ok(())
+}
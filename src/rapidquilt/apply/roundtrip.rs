@@ -0,0 +1,111 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `push --roundtrip-check`, verifying that the
+//! patches revert cleanly back to the original files.
+//!
+//! The patches are applied in memory and then reverted, last one first, like
+//! `squash` reverts them. The result is compared with the files on disk,
+//! which are never changed. A patch applied with fuzz or at an offset may
+//! revert somewhere else, e.g. at another copy of the same lines, and leave
+//! the file different from the original.
+
+use std::io::Write;
+
+use anyhow::{Context, Error, Result};
+use colored::*;
+use itertools::Itertools;
+
+use libpatch::analysis::{AnalysisSet, fn_analysis_note_noop};
+
+use crate::apply::*;
+use crate::apply::combined_diff::collect_file_diffs;
+use crate::apply::common::*;
+use crate::arena::Arena;
+
+/// Apply the patches in the `config` and revert them again, all in memory,
+/// and compare the result with the original files. Writes why it failed into
+/// the `writer`: a patch that did not apply or revert, or the differences
+/// of the files that did not come back.
+///
+/// Returns whether the files came back exactly as they were.
+pub fn check_roundtrip<W: Write>(config: &ApplyConfig, arena: &dyn Arena, writer: &mut W) -> Result<bool> {
+    // The series forward and then backward, with every patch reversed
+    let roundtrip_series: Vec<_> = config.series_patches.iter()
+        .map(|series_patch| (series_patch, series_patch.reverse))
+        .chain(config.series_patches.iter().rev().map(|series_patch| (series_patch, !series_patch.reverse)))
+        .map(|(series_patch, reverse)| SeriesPatch {
+            filename: series_patch.filename.clone(),
+            strip: series_patch.strip,
+            reverse,
+        })
+        .collect();
+    let roundtrip_config = ApplyConfig {
+        series_patches: &roundtrip_series,
+        reverse_if_applied: false,
+        interactive: false,
+        post_hook: None,
+        do_backups: ApplyConfigDoBackups::Never,
+        backup_count: ApplyConfigBackupCount::All,
+        dry_run: true,
+        ..*config
+    };
+    let path_guard = PathGuard::for_config(config)?;
+
+    // The strip level used for every patch, it may be found by auto-strip
+    let patch_count = config.series_patches.len();
+    let mut strips = Vec::with_capacity(patch_count);
+
+    let mut state = AppliedState::new(&roundtrip_config, roundtrip_series.len());
+    for (index, series_patch) in roundtrip_series.iter().enumerate() {
+        let reverting = index >= patch_count;
+        let mut patch = load_series_patch(config, arena, series_patch)
+            .map_err(Error::from)
+            .and_then(|data| if reverting {
+                parse_tree_patch(config, data, strips[2 * patch_count - 1 - index])
+            } else {
+                parse_series_patch(config, series_patch, data).map(|(patch, strip)| {
+                    strips.push(strip);
+                    patch
+                })
+            })
+            .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+        if !reverting {
+            if let Some(path_guard) = &path_guard {
+                path_guard.check_patch(series_patch, &patch)?;
+            }
+            check_allowed_files(config, &series_patch.filename, &patch)?;
+        }
+        filter_file_patches(&roundtrip_config, index, &mut patch);
+
+        // Forcing the patches back would hide that they do not revert.
+        let force = config.force && !reverting;
+        let deadline = patch_deadline(config);
+        for file_patch in patch.file_patches {
+            state.apply_one_file_patch_with(index, file_patch, force, false, deadline, arena,
+                                            &AnalysisSet::default(), &fn_analysis_note_noop)?;
+        }
+
+        let mismatched_files = state.applied_patches.iter()
+            .filter(|applied_patch| applied_patch.index == index && applied_patch.report.failed())
+            .map(|applied_patch| applied_patch.target_filename.display())
+            .join(", ");
+        if !mismatched_files.is_empty() {
+            let failure = if reverting { "DOES NOT REVERT" } else { "FAILED" };
+            writeln!(writer, "{} {} {}", "Patch".yellow(), series_patch.filename.display(), failure.bright_red().bold())?;
+            writeln!(writer, "  These files do not match it: {}", mismatched_files)?;
+            return Ok(false);
+        }
+    }
+
+    let file_diffs = collect_file_diffs(config, arena, &state.modified_files)?;
+    if file_diffs.is_empty() {
+        return Ok(true);
+    }
+
+    writeln!(writer, "{} {} file(s) differ from the originals after reverting the patches:",
+             "ROUND TRIP FAILED".bright_red().bold(), file_diffs.len())?;
+    for file_diff in &file_diffs {
+        writer.write_all(&file_diff.diff)?;
+    }
+    Ok(false)
+}
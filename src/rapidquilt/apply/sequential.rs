@@ -5,20 +5,23 @@
 //! Patches are read, parsed and applied one by one.
 
 use std::collections::HashSet;
+use std::fmt::Write;
 use std::hash::BuildHasherDefault;
+use std::io;
 
 use colored::*;
 use anyhow::{Context, Error, Result};
 use seahash;
 
 use crate::apply::*;
+use crate::apply::combined_diff::collect_file_diffs;
 use crate::apply::common::*;
 use crate::apply::diagnostics::*;
+use crate::apply::interactive::resolve_failed_file_patch;
 use crate::arena::Arena;
 
 use libpatch::analysis::{AnalysisSet, Note};
 use libpatch::patch::TextFilePatch;
-use libpatch::patch::unified::parser::parse_patch;
 
 pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &AnalysisSet)
     -> Result<ApplyResult> {
@@ -27,6 +30,13 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
     let mut final_patch = 0;
 
     let mut failure_analysis = String::new();
+    let mut inline_rejects = String::new();
+    let mut rejected_inexact = false;
+    let mut filtered_files = Vec::new();
+    let mut skipped_files = Vec::new();
+    let mut directories_for_cleaning = HashSet::with_hasher(BuildHasherDefault::<seahash::SeaHasher>::default());
+    let path_guard = PathGuard::for_config(config)?;
+    let mut case_guard = CaseGuard::for_config(config)?;
 
     if config.verbosity >= Verbosity::Normal {
         println!("Applying {} patches single-threaded...", config.series_patches.len());
@@ -37,15 +47,25 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
             println!("Patch: {:?}", series_patch.filename);
         }
 
-        let patch = arena.load_file(&config.patches_path.join(&series_patch.filename))
+        let (mut patch, strip) = load_series_patch(config, arena, series_patch)
             .map_err(Error::from)
-            .and_then(|data| parse_patch(data, series_patch.strip)
-		      .map_err(Error::from))
+            .and_then(|data| parse_series_patch(config, series_patch, data))
             .with_context(|| ApplyError::PatchLoad { patch_filename: config.series_patches[index].filename.clone() })?;
 
 	print_parser_warnings(config, &series_patch.filename, &patch);
+        print_auto_strip(config, series_patch, strip);
+        if let Some(path_guard) = &path_guard {
+            path_guard.check_patch(series_patch, &patch)?;
+        }
+        if let Some(case_guard) = &mut case_guard {
+            case_guard.check_patch(&series_patch.filename, &patch)?;
+        }
+        check_allowed_files(config, &series_patch.filename, &patch)?;
+        filtered_files.extend(filter_file_patches(config, index, &mut patch));
 
         let mut any_report_failed = false;
+        let mut interactive = config.interactive;
+        let deadline = patch_deadline(config);
 
         for text_file_patch in patch.file_patches {
             let fn_analysis_note = |note: &dyn Note, file_patch: &TextFilePatch| {
@@ -55,19 +75,37 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
                 let _ = print_analysis_note(&series_patch.filename, note, file_patch);
             };
 
+            let applied_count = state.applied_patches.len();
             if !state.apply_one_file_patch(index,
                                            text_file_patch,
+                                           deadline,
                                            arena,
                                            analyses,
                                            &fn_analysis_note)?
             {
+                // After the user gives up, the rest of the patch is only
+                // applied for the report.
+                if interactive && state.applied_patches.len() > applied_count &&
+                   resolve_failed_file_patch(&mut state, index, deadline, arena, analyses, &fn_analysis_note,
+                                             &mut skipped_files, &mut io::stdin().lock(), &mut io::stdout())?
+                {
+                    continue;
+                }
+                interactive = false;
                 any_report_failed = true;
             }
         }
 
         if any_report_failed {
+            let (failed, inexact) = count_failed_file_patches(&state.applied_patches, index);
+            rejected_inexact = failed > 0 && failed == inexact;
+
             // Analyze failure, in case there was any
-            analyze_patch_failure(config.verbosity, index, &state.applied_patches, &state.modified_files, &mut failure_analysis)?;
+            analyze_patch_failure(config.verbosity, config.function_context, index, &state.applied_patches, &state.modified_files, &mut failure_analysis)?;
+
+            if config.show_rejects_inline {
+                write_inline_rejects(index, &state.applied_patches, &mut inline_rejects)?;
+            }
 
             if !config.dry_run {
                 state.rollback_and_save_rej_files(index)?;
@@ -76,17 +114,51 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
             break;
         }
 
+        if let (Some(post_hook), false) = (config.post_hook, config.dry_run) {
+            // The hook must see the patched files on disk
+            let filenames = state.patched_filenames(index);
+            state.modified_files.save_files(&filenames, &series_patch.filename, &mut directories_for_cleaning)?;
+
+            let status = run_post_hook(config, post_hook, &series_patch.filename, &filenames)?;
+            if !status.success() {
+                writeln!(failure_analysis, "  {} {}", "Post-hook failed with".bright_red(), status)?;
+
+                // Put the files back as they were before the patch
+                state.rollback_patch(index);
+                state.modified_files.save_files(&filenames, &series_patch.filename, &mut directories_for_cleaning)?;
+                break;
+            }
+        }
+
         final_patch = index + 1;
     }
 
+    let forced_files = collect_patched_files(&state.applied_patches, final_patch, |status| status.report.forced());
+    let already_applied_files = collect_patched_files(&state.applied_patches, final_patch, |status| status.already_applied);
+    let unchanged_files = collect_patched_files(&state.applied_patches, final_patch, |status| status.unchanged);
+    filtered_files.retain(|file| file.index < final_patch);
+    skipped_files.retain(|file| file.index < final_patch);
+    let patch_sizes = if config.stats {
+        count_patch_sizes(config, &state.applied_patches, final_patch)
+    } else {
+        Vec::new()
+    };
+    let (patch_timings, critical_path) = if config.timings {
+        count_patch_timings(config, &state.applied_patches, final_patch)
+    } else {
+        (Vec::new(), None)
+    };
+
     if !config.dry_run {
         if config.verbosity >= Verbosity::Normal {
             println!("Saving modified files...");
         }
 
-        let mut directories_for_cleaning = HashSet::with_hasher(BuildHasherDefault::<seahash::SeaHasher>::default());
-        state.modified_files.save(&mut directories_for_cleaning)?;
-        clean_empty_directories(config.base_dir, directories_for_cleaning)?;
+        // With a post-hook, every patch was saved right after it was applied.
+        if config.post_hook.is_none() {
+            state.modified_files.save(&state.applied_patches, &mut directories_for_cleaning)?;
+        }
+        clean_empty_directories(config.output_dir(), directories_for_cleaning)?;
 
         if config.do_backups == ApplyConfigDoBackups::Always ||
           (config.do_backups == ApplyConfigDoBackups::OnFail &&
@@ -105,17 +177,43 @@ pub fn apply_patches(config: &ApplyConfig, arena: &dyn Arena, analyses: &Analysi
         }
     }
 
+    let mut file_diffs = Vec::new();
+    let mut changed_files = Vec::new();
+    if (config.emit_diff || config.emit_files) && config.dry_run {
+        // Take back what was applied of the failed patch
+        state.rollback_patch(final_patch);
+        if config.emit_diff {
+            file_diffs = collect_file_diffs(config, arena, &state.modified_files)?;
+        }
+        if config.emit_files {
+            changed_files = collect_changed_files(&state.modified_files)?;
+        }
+    }
+
     if final_patch != config.series_patches.len() {
         eprintln!("{} {} {}", "Patch".yellow(), config.series_patches[final_patch].filename.display(), "FAILED".bright_red().bold());
         eprint!("{}", failure_analysis);
     }
 
-    if config.stats {
-        println!("{}", arena.stats());
-    }
+    // The patched files are saved, their original content is not needed
+    // any more.
+    state.release_files(arena);
 
     Ok(ApplyResult {
         applied_patches: final_patch,
         skipped_patches: config.series_patches.len() - final_patch,
+        rejected_inexact,
+        forced_files,
+        already_applied_files,
+        unchanged_files,
+        filtered_files,
+        skipped_files,
+        inline_rejects,
+        messages: Vec::new(),
+        file_diffs,
+        changed_files,
+        patch_sizes,
+        patch_timings,
+        critical_path,
     })
 }
@@ -0,0 +1,121 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements snapshots of the working tree, like
+//! `quilt snapshot` and `quilt diff --snapshot`.
+//!
+//! The snapshot is stored in the backup store, as if it was a patch called
+//! ".snapshot". Unlike in the backups of patches, files that did not exist
+//! when the snapshot was taken are left out of it, so an empty file can be
+//! told apart from a missing one.
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Error, Result};
+
+use libpatch::diff::{diff, DEFAULT_CONTEXT};
+use libpatch::modified_file::ModifiedFile;
+use libpatch::patch::{FilePatchKind, TextFilePatchBuilder};
+use libpatch::patch::unified::writer::UnifiedPatchWriter;
+
+use crate::apply::*;
+use crate::apply::common::*;
+use crate::arena::Arena;
+
+/// Name of the snapshot in ".pc" directory
+const SNAPSHOT_NAME: &str = ".snapshot";
+
+/// Collect all files touched by the patches in the `config`.
+fn touched_files(config: &ApplyConfig, arena: &dyn Arena) -> Result<BTreeSet<PathBuf>> {
+    Ok(touched_files_by_patch(config, arena)?.into_iter().flatten().collect())
+}
+
+/// Record the current content of all files touched by the patches in the
+/// `config`. Any previous snapshot is replaced.
+pub fn take_snapshot(config: &ApplyConfig, arena: &dyn Arena) -> Result<()> {
+    let snapshot_dir = config.base_dir.join(".pc").join(SNAPSHOT_NAME);
+    match fs::remove_dir_all(&snapshot_dir) {
+        Ok(()) => {},
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => {},
+        Err(error) => return Err(Error::from(error).context("Removing previous snapshot")),
+    }
+    // Make sure the snapshot exists even if no files are touched
+    fs::create_dir_all(&snapshot_dir)?;
+
+    let files = touched_files(config, arena)?;
+
+    let mut modified_files = ModifiedFiles::new(config);
+    for filename in &files {
+        let filename = Cow::Borrowed(filename.as_path());
+        let file = modified_files.get_or_load(&filename, arena)
+            .with_context(|| ApplyError::LoadFileToPatch { filename: filename.to_path_buf() })?;
+        if !file.deleted {
+            save_backup_file(config, Path::new(SNAPSHOT_NAME), &filename, file)?;
+        }
+    }
+
+    if config.verbosity >= Verbosity::Normal {
+        println!("Snapshot of {} files saved.", files.len());
+    }
+
+    Ok(())
+}
+
+/// Write the differences between the snapshot and the current state of the
+/// files as a patch.
+pub fn diff_snapshot<W: Write>(config: &ApplyConfig, arena: &dyn Arena, writer: &mut W) -> Result<()> {
+    let file_backup_store = FileBackupStore::new(config.base_dir);
+    let backup_store = config.backup_store.unwrap_or(&file_backup_store);
+    let snapshot_name = Path::new(SNAPSHOT_NAME);
+    let snapshot_files: BTreeSet<PathBuf> = match backup_store.list(snapshot_name).context("Reading snapshot")? {
+        Some(snapshot_files) => snapshot_files.into_iter().collect(),
+        None => bail!("No snapshot found, create one with the \"snapshot\" command."),
+    };
+
+    // Compare files touched by patches, and also files that were in the
+    // snapshot, but are no longer touched. (E.g. after popping patches.)
+    let mut files = touched_files(config, arena)?;
+    files.extend(snapshot_files.iter().cloned());
+
+    for filename in &files {
+        // Only the files that existed are in the snapshot. The backup store
+        // restores empty files as missing, they are still empty files here.
+        let old_content = match snapshot_files.contains(filename) {
+            true => Some(backup_store.restore(snapshot_name, filename)
+                         .context("Reading snapshot")?
+                         .map(|backup_file| backup_file.content)
+                         .unwrap_or_default()),
+            false => None,
+        };
+        let old_file = old_content.as_ref().map(|content| ModifiedFile::new(content, true, None));
+        let new_file = load_existing_file(arena, &config.base_dir.join(filename))?;
+
+        let kind = match (&old_file, &new_file) {
+            (None, None) => continue,
+            (None, Some(_)) => FilePatchKind::Create,
+            (Some(_), None) => FilePatchKind::Delete,
+            (Some(_), Some(_)) => FilePatchKind::Modify,
+        };
+
+        let old_content = old_file.as_ref().map(|file| &file.content[..]).unwrap_or(&[]);
+        let new_content = new_file.as_ref().map(|file| &file.content[..]).unwrap_or(&[]);
+        let hunks = diff(old_content, new_content, DEFAULT_CONTEXT);
+        if hunks.is_empty() && kind == FilePatchKind::Modify {
+            continue;
+        }
+
+        let file_patch = TextFilePatchBuilder::default()
+            .kind(kind)
+            .old_filename(Some(Cow::Owned(Path::new("a").join(filename))))
+            .new_filename(Some(Cow::Owned(Path::new("b").join(filename))))
+            .hunks(hunks)
+            .build()?;
+
+        file_patch.write_to(writer)?;
+    }
+
+    Ok(())
+}
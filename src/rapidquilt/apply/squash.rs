@@ -0,0 +1,136 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `squash`, writing the applied patches as a single
+//! patch.
+//!
+//! The applied patches are reverted in memory, last one first, which gives
+//! the original content of every file they touch. That is compared with the
+//! current content of the files, so edits of the same lines by several
+//! patches end up in a single hunk.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Error, Result};
+use itertools::Itertools;
+
+use libpatch::analysis::{AnalysisSet, fn_analysis_note_noop};
+use libpatch::modified_file::ModifiedFile;
+
+use crate::apply::*;
+use crate::apply::combined_diff::write_file_diff;
+use crate::apply::common::*;
+use crate::arena::Arena;
+
+/// Write the net change of all patches in the `config`, which must be
+/// applied in the working tree, as a single patch. Renames done by the
+/// patches are kept as renames. Returns the number of changed files.
+pub fn squash_patches<W: Write>(config: &ApplyConfig, arena: &dyn Arena, writer: &mut W) -> Result<usize> {
+    let reversed_series: Vec<_> = config.series_patches.iter().rev()
+        .map(|series_patch| SeriesPatch {
+            filename: series_patch.filename.clone(),
+            strip: series_patch.strip,
+            reverse: !series_patch.reverse,
+        })
+        .collect();
+    let reversed_config = ApplyConfig {
+        series_patches: &reversed_series,
+        force: false,
+        reverse_if_applied: false,
+        do_backups: ApplyConfigDoBackups::Never,
+        backup_count: ApplyConfigBackupCount::All,
+        ..*config
+    };
+    let path_guard = PathGuard::for_config(config)?;
+
+    let mut state = AppliedState::new(&reversed_config, reversed_series.len());
+    for (index, series_patch) in reversed_series.iter().enumerate() {
+        let patch = load_series_patch(config, arena, series_patch)
+            .map_err(Error::from)
+            .and_then(|data| parse_tree_patch(config, data, series_patch.strip))
+            .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+        if let Some(path_guard) = &path_guard {
+            path_guard.check_patch(series_patch, &patch)?;
+        }
+        check_allowed_files(config, &series_patch.filename, &patch)?;
+
+        let deadline = patch_deadline(&reversed_config);
+        for file_patch in patch.file_patches {
+            state.apply_one_file_patch(index, file_patch, deadline, arena, &AnalysisSet::default(), &fn_analysis_note_noop)?;
+        }
+
+        let mismatched_files = state.applied_patches.iter()
+            .filter(|applied_patch| applied_patch.index == index && applied_patch.report.failed())
+            .map(|applied_patch| applied_patch.target_filename.display())
+            .join(", ");
+        if !mismatched_files.is_empty() {
+            bail!("Can not squash, patch {} does not revert cleanly. These files do not match it: {}",
+                  series_patch.filename.display(), mismatched_files);
+        }
+    }
+
+    // Follow the renames in the order the patches were applied, to know the
+    // original name of every renamed file. (While reverting, the "target" is
+    // the name after the patch and the "final" is the name before it.)
+    let mut original_names = HashMap::<PathBuf, PathBuf>::new();
+    for applied_patch in state.applied_patches.iter().rev().filter(|applied_patch| applied_patch.file_patch.is_rename()) {
+        let before = applied_patch.final_filename.to_path_buf();
+        let original_name = original_names.remove(&before).unwrap_or(before);
+        original_names.insert(applied_patch.target_filename.to_path_buf(), original_name);
+    }
+
+    // The original content of every file, `None` if it did not exist.
+    let mut original_data = BTreeMap::new();
+    for (filename, file) in state.modified_files.iter() {
+        let data = if file.deleted {
+            None
+        } else {
+            let mut data = Vec::new();
+            file.write_to(&mut data)?;
+            Some((data, file.permissions.clone()))
+        };
+        original_data.insert(filename.to_path_buf(), data);
+    }
+
+    let mut current_files = BTreeMap::new();
+    for filename in original_data.keys() {
+        let file = load_existing_file(arena, &config.base_dir.join(filename))
+            .with_context(|| ApplyError::LoadFileToPatch { filename: filename.clone() })?;
+        current_files.insert(filename.clone(), file);
+    }
+
+    let original_file = |filename: &Path| -> Option<ModifiedFile> {
+        original_data.get(filename).and_then(Option::as_ref)
+            .map(|(data, permissions)| ModifiedFile::new(data, true, permissions.clone()))
+    };
+
+    // A rename is kept if the original file is gone and the renamed one was
+    // not there before.
+    let renamed_from: BTreeMap<&Path, &Path> = original_names.iter()
+        .filter(|(name, original_name)| {
+            current_files.get(original_name.as_path()).is_some_and(Option::is_none) &&
+            original_file(original_name).is_some() &&
+            original_file(name).is_none() &&
+            current_files.get(name.as_path()).is_some_and(Option::is_some)
+        })
+        .map(|(name, original_name)| (name.as_path(), original_name.as_path()))
+        .collect();
+
+    let mut changed_files = 0;
+    for (filename, current_file) in &current_files {
+        if renamed_from.values().any(|original_name| original_name == filename) {
+            continue;
+        }
+        let old_filename = renamed_from.get(filename.as_path()).copied().unwrap_or(filename);
+
+        let mut diff = Vec::new();
+        write_file_diff(old_filename, filename, original_file(old_filename).as_ref(), current_file.as_ref(), &mut diff)?;
+        if !diff.is_empty() {
+            writer.write_all(&diff)?;
+            changed_files += 1;
+        }
+    }
+
+    Ok(changed_files)
+}
@@ -0,0 +1,110 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `--threads auto-tune`, choosing the number of
+//! threads by measuring how fast the patches apply with some of them.
+//!
+//! The first patches of the series are applied in memory with every candidate
+//! number of threads, each time into a new arena, so the files are loaded
+//! again. More threads help on storage with high latency, like NFS, where the
+//! threads wait for the files, and less on a local SSD, where they compete
+//! for the CPUs and the arena. The files are read once before the
+//! measurements, so the first candidate does not pay alone for reading them
+//! into the page cache. With `--direct-io`, every candidate reads them from
+//! the storage.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::*;
+use crate::arena::Arena;
+
+/// How many patches from the start of the series are applied to measure.
+const CALIBRATION_PATCHES: usize = 200;
+
+/// More threads are chosen only if they are faster by this fraction.
+const MIN_SPEEDUP: f64 = 0.05;
+
+/// The numbers of threads that are tried: the powers of two below
+/// `max_threads`, and `max_threads`.
+fn candidate_thread_counts(max_threads: usize) -> Vec<usize> {
+    let max_threads = max_threads.max(1);
+    let mut counts: Vec<_> = std::iter::successors(Some(1usize), |&count| count.checked_mul(2))
+        .take_while(|&count| count < max_threads)
+        .collect();
+    counts.push(max_threads);
+    counts
+}
+
+/// Apply the `config` in memory with `threads` threads into an arena from
+/// `build_arena` and return how long it took.
+fn measure(config: &ApplyConfig, build_arena: &dyn Fn(usize) -> Result<Box<dyn Arena>>, threads: usize)
+    -> Result<Duration>
+{
+    let arena = build_arena(threads)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    let start = Instant::now();
+    pool.install(|| apply_patches_parallel(config, &*arena, &AnalysisSet::default()))?;
+    Ok(start.elapsed())
+}
+
+/// Choose the number of threads, at most `max_threads`, that applies the
+/// first `CALIBRATION_PATCHES` patches of the `config` the fastest. Every
+/// measurement uses a new arena from `build_arena` for the number of threads.
+/// Nothing is written, and nothing is printed unless the `config` is verbose.
+pub fn tune_threads(config: &ApplyConfig, build_arena: &dyn Fn(usize) -> Result<Box<dyn Arena>>, max_threads: usize)
+    -> Result<usize>
+{
+    let patches = config.series_patches.len().min(CALIBRATION_PATCHES);
+    let calibration_config = ApplyConfig {
+        series_patches: &config.series_patches[..patches],
+        backup_names: config.backup_names.map(|backup_names| &backup_names[..patches]),
+        interactive: false,
+        patch_timeout: None,
+        show_rejects_inline: false,
+        save_rej_files: false,
+        post_hook: None,
+        deterministic: true,
+        do_backups: ApplyConfigDoBackups::Never,
+        backup_count: ApplyConfigBackupCount::All,
+        dry_run: true,
+        emit_diff: false,
+        emit_files: false,
+        stats: false,
+        timings: false,
+        report_unchanged: false,
+        verbosity: Verbosity::Quiet,
+        audit_log: None,
+        manifest: None,
+        match_cache: None,
+        ..*config
+    };
+
+    let candidates = candidate_thread_counts(max_threads);
+    if patches == 0 || candidates.len() == 1 {
+        return Ok(candidates[candidates.len() - 1]);
+    }
+
+    // Read the files once, so the first candidate does not pay for it alone.
+    measure(&calibration_config, build_arena, max_threads)?;
+
+    let mut best: Option<(usize, Duration)> = None;
+    for threads in candidates {
+        let duration = measure(&calibration_config, build_arena, threads)?;
+        if config.verbosity >= Verbosity::Verbose {
+            println!("Calibration: {} patches with {} threads in {:.3} s", patches, threads, duration.as_secs_f64());
+        }
+        best = match best {
+            Some((_, best_duration)) if duration.as_secs_f64() < best_duration.as_secs_f64() * (1.0 - MIN_SPEEDUP) =>
+                Some((threads, duration)),
+            None => Some((threads, duration)),
+            best => best,
+        };
+    }
+    // NOTE(unwrap): There is at least one candidate.
+    Ok(best.unwrap().0)
+}
@@ -0,0 +1,148 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements the `verify` command, comparing the tree that the
+//! patches produce with a reference tree.
+//!
+//! The patches are applied in memory, like `push --roundtrip-check` does, and
+//! every file they touch is compared with the same file in the reference. The
+//! working tree is never changed. With `--ignore-whitespace`, lines that differ
+//! only in whitespace are equal, like with `diff -w`, so a refreshed series
+//! whose reformatting changed only the indentation still matches.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::{Context, Error, Result};
+use colored::*;
+use itertools::Itertools;
+
+use libpatch::analysis::{AnalysisSet, fn_analysis_note_noop};
+use libpatch::modified_file::ModifiedFile;
+
+use crate::apply::*;
+use crate::apply::common::*;
+use crate::arena::Arena;
+
+/// The tree that the patched files are compared with
+pub trait ReferenceTree {
+    /// Load the file with the `filename`, relative to the base directory.
+    /// `None` if the tree does not have it.
+    fn load_file<'arena>(&mut self, arena: &'arena dyn Arena, filename: &Path) -> io::Result<Option<ModifiedFile<'arena>>>;
+}
+
+/// A reference tree in a directory
+pub struct ReferenceDir<'a>(pub &'a Path);
+
+impl ReferenceTree for ReferenceDir<'_> {
+    fn load_file<'arena>(&mut self, arena: &'arena dyn Arena, filename: &Path) -> io::Result<Option<ModifiedFile<'arena>>> {
+        load_existing_file(arena, &self.0.join(filename))
+    }
+}
+
+/// The part of the `line` that is compared: all of it, or without any
+/// whitespace if `ignore_whitespace` is set.
+fn normalized_line(line: &[u8], ignore_whitespace: bool) -> Vec<u8> {
+    if ignore_whitespace {
+        line.iter().copied().filter(|byte| !byte.is_ascii_whitespace()).collect()
+    } else {
+        line.to_vec()
+    }
+}
+
+/// The number of the first line in which the `actual` and `expected` files
+/// differ, `None` if they are equal.
+fn first_difference(actual: &ModifiedFile, expected: &ModifiedFile, ignore_whitespace: bool) -> Option<usize> {
+    let mut actual_lines = actual.content.iter().map(|line| normalized_line(line, ignore_whitespace));
+    let mut expected_lines = expected.content.iter().map(|line| normalized_line(line, ignore_whitespace));
+    let mut line_number = 1;
+    loop {
+        match (actual_lines.next(), expected_lines.next()) {
+            (None, None) => return None,
+            (Some(actual_line), Some(expected_line)) if actual_line == expected_line => {}
+            _ => return Some(line_number),
+        }
+        line_number += 1;
+    }
+}
+
+/// Apply the patches in the `config` in memory and compare every file they
+/// touch with the one in the `reference` tree. Writes the patch that did not
+/// apply or the files that do not match into the `writer`.
+///
+/// Returns whether all files match the reference.
+pub fn verify_tree<W: Write>(config: &ApplyConfig, arena: &dyn Arena, reference: &mut dyn ReferenceTree,
+                             ignore_whitespace: bool, writer: &mut W) -> Result<bool> {
+    let verify_config = ApplyConfig {
+        reverse_if_applied: false,
+        interactive: false,
+        post_hook: None,
+        do_backups: ApplyConfigDoBackups::Never,
+        backup_count: ApplyConfigBackupCount::All,
+        dry_run: true,
+        ..*config
+    };
+    let path_guard = PathGuard::for_config(config)?;
+
+    let mut state = AppliedState::new(&verify_config, config.series_patches.len());
+    for (index, series_patch) in config.series_patches.iter().enumerate() {
+        let (mut patch, _) = load_series_patch(config, arena, series_patch)
+            .map_err(Error::from)
+            .and_then(|data| parse_series_patch(config, series_patch, data))
+            .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+        if let Some(path_guard) = &path_guard {
+            path_guard.check_patch(series_patch, &patch)?;
+        }
+        check_allowed_files(config, &series_patch.filename, &patch)?;
+        filter_file_patches(&verify_config, index, &mut patch);
+
+        let deadline = patch_deadline(config);
+        for file_patch in patch.file_patches {
+            state.apply_one_file_patch(index, file_patch, deadline, arena,
+                                       &AnalysisSet::default(), &fn_analysis_note_noop)?;
+        }
+
+        let mismatched_files = state.applied_patches.iter()
+            .filter(|applied_patch| applied_patch.index == index && applied_patch.report.failed())
+            .map(|applied_patch| applied_patch.target_filename.display())
+            .join(", ");
+        if !mismatched_files.is_empty() {
+            writeln!(writer, "{} {} {}", "Patch".yellow(), series_patch.filename.display(), "FAILED".bright_red().bold())?;
+            writeln!(writer, "  These files do not match it: {}", mismatched_files)?;
+            return Ok(false);
+        }
+    }
+
+    let mut mismatches = Vec::new();
+    for (filename, file) in state.modified_files.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+        let reference_file = reference.load_file(arena, filename)
+            .with_context(|| format!("Loading \"{}\" from the reference", filename.display()))?;
+
+        // The patched file may be loaded only partially, get all of it.
+        let mut data = Vec::new();
+        let mismatch = match reference_file {
+            None if file.deleted => continue,
+            None => "is missing in the reference".to_string(),
+            Some(_) if file.deleted => "is deleted by the patches, but it is in the reference".to_string(),
+            Some(reference_file) => {
+                file.write_to(&mut data)?;
+                let patched_file = ModifiedFile::new(&data, true, file.permissions.clone());
+                match first_difference(&patched_file, &reference_file, ignore_whitespace) {
+                    Some(line_number) => format!("differs from the reference at line {}", line_number),
+                    None => continue,
+                }
+            }
+        };
+        mismatches.push(format!("File {} {}", filename.display(), mismatch));
+    }
+
+    if mismatches.is_empty() {
+        return Ok(true);
+    }
+
+    writeln!(writer, "{} {} file(s) differ from the reference:",
+             "VERIFY FAILED".bright_red().bold(), mismatches.len())?;
+    for mismatch in &mismatches {
+        writeln!(writer, "  {}", mismatch)?;
+    }
+    Ok(false)
+}
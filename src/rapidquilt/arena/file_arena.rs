@@ -2,13 +2,15 @@
 
 use std::marker::PhantomData;
 use std::vec::Vec;
-use std::io;
-use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::fs::{self, File};
 use std::path::Path;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::mem::transmute;
 
-use super::{Arena, Stats};
+use libpatch::modified_file::{FileTail, TailSource};
+
+use super::{check_file_size, Arena, FileMeta, IoRetry, Stats, Syscall, SyscallCounters, SyscallStats};
 
 
 /// Utility that reads files and keeps them loaded in immovable place in memory
@@ -16,24 +18,141 @@ use super::{Arena, Stats};
 /// object of this struct is alive.
 pub struct FileArena<'a> {
     files: Mutex<Vec<Box<[u8]>>>,
+    syscalls: SyscallCounters,
+    max_file_size: Option<u64>,
+    io_retry: IoRetry,
+    direct_io: bool,
     _phantom: PhantomData<&'a [u8]>,
 }
 
+/// The alignment of the buffer, the offset and the size of `O_DIRECT` reads.
+/// It is a multiple of the logical block size of common devices.
+#[cfg(target_os = "linux")]
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// The size of the aligned buffer that files are read through with `O_DIRECT`
+#[cfg(target_os = "linux")]
+const DIRECT_IO_CHUNK_SIZE: usize = 1 << 20;
+
+/// A buffer aligned to `DIRECT_IO_ALIGNMENT`
+#[cfg(target_os = "linux")]
+struct AlignedBuffer {
+    ptr: std::ptr::NonNull<u8>,
+    layout: std::alloc::Layout,
+}
+
+#[cfg(target_os = "linux")]
+impl AlignedBuffer {
+    fn new(size: usize) -> Self {
+        // NOTE(unwrap): The alignment is a power of two and the size is small.
+        let layout = std::alloc::Layout::from_size_align(size, DIRECT_IO_ALIGNMENT).unwrap();
+        // SAFETY: The size is not zero.
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        let Some(ptr) = std::ptr::NonNull::new(ptr) else {
+            std::alloc::handle_alloc_error(layout);
+        };
+        Self { ptr, layout }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: The memory was allocated initialized with this size and
+        // is borrowed along with the buffer.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: Allocated in `new` with the same layout.
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+/// Read the file of `size` bytes at `path` with `O_DIRECT`, bypassing the
+/// page cache. Returns `None` if the filesystem does not support it or does
+/// not return `size` bytes, the file must be read the usual way then.
+#[cfg(target_os = "linux")]
+fn read_direct(path: &Path, size: u64, syscalls: &SyscallCounters) -> Result<Option<Vec<u8>>, io::Error> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let unsupported = |error: &io::Error| error.raw_os_error() == Some(libc::EINVAL);
+
+    syscalls.count(Syscall::Open);
+    let mut file = match File::options().read(true).custom_flags(libc::O_DIRECT).open(path) {
+        Ok(file) => file,
+        Err(ref error) if unsupported(error) => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    // The reads go through the aligned buffer in whole chunks, so every one
+    // starts at an aligned offset. Usually only the last one at the end of
+    // the file is shorter.
+    let mut buffer = AlignedBuffer::new(DIRECT_IO_CHUNK_SIZE);
+    let mut data = Vec::with_capacity(size as usize);
+    loop {
+        syscalls.count(Syscall::Read);
+        let read = match file.read(buffer.as_mut_slice()) {
+            Ok(read) => read,
+            Err(ref error) if unsupported(error) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        data.extend_from_slice(&buffer.as_mut_slice()[..read]);
+        if read == 0 || read % DIRECT_IO_ALIGNMENT != 0 {
+            // The next read would be unaligned.
+            break;
+        }
+    }
+
+    // Some filesystems (e.g. NFS or FUSE) may return less in the middle of
+    // the file, it can not be read further with O_DIRECT then.
+    if data.len() as u64 != size {
+        return Ok(None);
+    }
+    Ok(Some(data))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_direct(_path: &Path, _size: u64, _syscalls: &SyscallCounters) -> Result<Option<Vec<u8>>, io::Error> {
+    Ok(None)
+}
+
 impl FileArena<'_> {
     pub fn new() -> Self {
         Self {
             files: Mutex::new(Vec::new()),
+            syscalls: SyscallCounters::default(),
+            max_file_size: None,
+            io_retry: IoRetry::default(),
+            direct_io: false,
             _phantom: PhantomData,
         }
     }
-}
 
-impl<'a> Arena for FileArena<'a> {
-    /// Load the file and return byte slice of its complete content. The slice
-    /// is valid as long as this object is alive. (Same lifetimes.)
-    fn load_file(&self, path: &Path) -> Result<&[u8], io::Error> {
-        let data = fs::read(path)?.into_boxed_slice();
+    /// Refuse to load files bigger than `max_file_size` bytes.
+    pub fn with_max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
 
+    /// Retry opening and reading files after transient errors.
+    pub fn with_io_retry(mut self, io_retry: IoRetry) -> Self {
+        self.io_retry = io_retry;
+        self
+    }
+
+    /// Read whole files with `O_DIRECT`, so they do not evict other data
+    /// from the page cache. Where it is not supported, e.g. on tmpfs or
+    /// other systems than Linux, they are read the usual way.
+    pub fn with_direct_io(mut self, direct_io: bool) -> Self {
+        self.direct_io = direct_io;
+        self
+    }
+}
+
+impl<'a> FileArena<'a> {
+    /// Keep the `data` for as long as we are alive and return slice of it.
+    fn store(&self, data: Box<[u8]>) -> &'a [u8] {
         let slice = unsafe {
             // We guarantee to the compiler that we will hold the content of the
             // Box for as long as we are alive. We will place the Box into the
@@ -44,11 +163,106 @@ impl<'a> Arena for FileArena<'a> {
 
         self.files.lock().unwrap().push(data); // NOTE(unwrap): If the lock is poisoned, some other thread panicked. We may as well.
 
-        Ok(slice)
+        slice
+    }
+}
+
+/// The source of lazily loaded files. The chunks are stored in the arena, so
+/// they live as long as the other loaded files.
+///
+/// The file stays open, so the rest of the file can be read even after it
+/// was replaced on disk by the patched version.
+struct FileArenaTail<'arena, 'a> {
+    arena: &'arena FileArena<'a>,
+    file: Mutex<File>,
+}
+
+impl<'arena> TailSource<'arena> for FileArenaTail<'arena, '_> {
+    fn read_chunk(&self, offset: u64, min_size: usize) -> Result<&'arena [u8], io::Error> {
+        let mut file = self.file.lock().unwrap(); // NOTE(unwrap): If the lock is poisoned, some other thread panicked. We may as well.
+        let data = self.arena.io_retry.run(|| {
+            file.seek(SeekFrom::Start(offset))?;
+            self.arena.syscalls.count(Syscall::Read);
+
+            let mut reader = BufReader::new(&mut *file);
+            let mut data = Vec::with_capacity(min_size);
+            (&mut reader).take(min_size as u64).read_to_end(&mut data)?;
+
+            // Finish the last line, unless we are at the end already
+            if data.len() == min_size && data.last() != Some(&b'\n') {
+                reader.read_until(b'\n', &mut data)?;
+            }
+            Ok(data)
+        })?;
+
+        Ok(self.arena.store(data.into_boxed_slice()))
+    }
+
+    fn copy_rest(&self, offset: u64, writer: &mut dyn Write) -> Result<(), io::Error> {
+        let mut file = self.file.lock().unwrap(); // NOTE(unwrap): If the lock is poisoned, some other thread panicked. We may as well.
+        file.seek(SeekFrom::Start(offset))?;
+        self.arena.syscalls.count(Syscall::Read);
+        io::copy(&mut *file, writer)?;
+        Ok(())
+    }
+}
+
+impl<'a> Arena for FileArena<'a> {
+    /// Load the file and return byte slice of its complete content. The slice
+    /// is valid as long as this object is alive. (Same lifetimes.)
+    fn load_file(&self, path: &Path) -> Result<&[u8], io::Error> {
+        let data = self.io_retry.run(|| {
+            if self.direct_io {
+                let size = fs::metadata(path)?.len();
+                check_file_size(size, self.max_file_size)?;
+                if let Some(data) = read_direct(path, size, &self.syscalls)? {
+                    return Ok(data);
+                }
+            }
+
+            self.syscalls.count(Syscall::Open);
+            let mut file = File::open(path)?;
+            let size = file.metadata()?.len();
+            check_file_size(size, self.max_file_size)?;
+
+            self.syscalls.count(Syscall::Read);
+            let mut data = Vec::with_capacity(size as usize);
+            file.read_to_end(&mut data)?;
+            Ok(data)
+        })?;
+        Ok(self.store(data.into_boxed_slice()))
+    }
+
+    /// Load the start of the file, the rest is read from the still open file
+    /// when needed.
+    fn load_file_head(&self, path: &Path, min_size: usize) -> Result<(&[u8], Option<FileTail<'_>>), io::Error> {
+        let (file, size) = self.io_retry.run(|| {
+            self.syscalls.count(Syscall::Open);
+            let file = File::open(path)?;
+            let size = file.metadata()?.len();
+            Ok((file, size))
+        })?;
+
+        let source = FileArenaTail {
+            arena: self,
+            file: Mutex::new(file),
+        };
+        let head = source.read_chunk(0, min_size)?;
+
+        let tail = if (head.len() as u64) < size {
+            Some(FileTail::new(Arc::new(source), head.len() as u64))
+        } else {
+            None
+        };
+
+        Ok((head, tail))
     }
 
     fn load_symlink_target(&self, path: &Path) -> Result<&[u8], io::Error> {
-        let target = fs::read_link(path)?;
+        let target = self.io_retry.run(|| {
+            self.syscalls.count(Syscall::Readlink);
+            fs::read_link(path)
+        })?;
 
         #[cfg(unix)]
         let data = {
@@ -61,15 +275,15 @@ impl<'a> Arena for FileArena<'a> {
             target.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Non-UTF8 symlink target"))?.as_bytes().to_vec()
         };
 
-        let data = data.into_boxed_slice();
-
-        let slice = unsafe {
-            transmute::<&[u8], &'a [u8]>(&data)
-        };
+        Ok(self.store(data.into_boxed_slice()))
+    }
 
-        self.files.lock().unwrap().push(data);
+    fn store_data(&self, data: Box<[u8]>) -> &[u8] {
+        self.store(data)
+    }
 
-        Ok(slice)
+    fn load_metadata(&self, path: &Path) -> Result<FileMeta, io::Error> {
+        self.io_retry.run(|| fs::symlink_metadata(path)).map(FileMeta::from)
     }
 
     /// Get statistics
@@ -81,4 +295,8 @@ impl<'a> Arena for FileArena<'a> {
             total_size: files.iter().map(|f| f.len()).sum(),
         }
     }
+
+    fn syscall_stats(&self) -> SyscallStats {
+        self.syscalls.stats()
+    }
 }
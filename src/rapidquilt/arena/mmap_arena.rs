@@ -9,7 +9,7 @@ use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use std::sync::Mutex;
 
-use super::{Arena, Stats, Resource, Mapping};
+use super::{check_file_size, Arena, FileMeta, IoRetry, Stats, Syscall, SyscallCounters, SyscallStats, Resource, Mapping};
 
 
 /// Utility that reads files and keeps them loaded in immovable place in memory
@@ -19,8 +19,14 @@ use super::{Arena, Stats, Resource, Mapping};
 /// This implementation uses mmap, which means that if an external process
 /// changes the file, the content of the memory may change or cause crash if
 /// the file truncated.
+///
+/// The resources are kept in several shards, each with its own lock, so the
+/// threads loading files at the same time rarely wait for each other.
 pub struct MmapArena<'a> {
-    resources: Mutex<Vec<Resource>>,
+    resources: [Mutex<Vec<Resource>>; SHARDS],
+    syscalls: SyscallCounters,
+    max_file_size: Option<u64>,
+    io_retry: IoRetry,
     _phantom: PhantomData<&'a [u8]>,
 }
 
@@ -28,100 +34,167 @@ pub struct MmapArena<'a> {
 // concurently. So no worries...
 unsafe impl Sync for MmapArena<'_> {}
 
+/// Number of shards of the resources
+const SHARDS: usize = 16;
+
 impl MmapArena<'_> {
     pub fn new() -> Self {
         Self {
-            resources: Mutex::new(Vec::new()),
+            resources: std::array::from_fn(|_| Mutex::new(Vec::new())),
+            syscalls: SyscallCounters::default(),
+            max_file_size: None,
+            io_retry: IoRetry::default(),
             _phantom: PhantomData,
         }
     }
+
+    /// Refuse to map files bigger than `max_file_size` bytes.
+    pub fn with_max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Retry opening and mapping files after transient errors.
+    pub fn with_io_retry(mut self, io_retry: IoRetry) -> Self {
+        self.io_retry = io_retry;
+        self
+    }
+
+    /// Keep the `resource` for as long as we are alive.
+    fn push_resource(&self, resource: Resource) {
+        // Every thread of the rayon pool has its own shard, unless there are
+        // more threads than shards. Any other thread uses the first one.
+        let shard = rayon::current_thread_index().unwrap_or(0) % SHARDS;
+        self.resources[shard].lock().unwrap().push(resource); // NOTE(unwrap): If the lock is poisoned, some other thread panicked. We may as well.
+    }
 }
 
 impl<'a> Arena for MmapArena<'a> {
     /// Load the file and return byte slice of its complete content. The slice
     /// is valid as long as this object is alive. (Same lifetimes.)
     fn load_file(&self, path: &Path) -> Result<&[u8], io::Error> {
-        let file = File::open(path)?;
-        let size = file.metadata()?.len() as usize;
-        let fd = file.as_raw_fd();
-
-        let start = unsafe {
-            let start = libc::mmap(ptr::null_mut(),
-                size,
-                libc::PROT_READ,
-                libc::MAP_PRIVATE,
-                fd,
-                0
-            );
+        let (start, size) = self.io_retry.run(|| {
+            self.syscalls.count(Syscall::Open);
+            let file = File::open(path)?;
+            let size = file.metadata()?.len();
+            check_file_size(size, self.max_file_size)?;
+            let size = size as usize;
+            let fd = file.as_raw_fd();
+
+            self.syscalls.count(Syscall::Mmap);
+            let start = unsafe {
+                libc::mmap(ptr::null_mut(),
+                    size,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    fd,
+                    0
+                )
+            };
 
             if start == libc::MAP_FAILED {
                 return Err(io::Error::last_os_error());
             }
 
-            start
-        };
+            Ok((start, size))
+        })?;
 
         let mapping = Mapping {
             start,
             size,
+            path: path.to_path_buf(),
         };
 
         let slice = unsafe {
             std::slice::from_raw_parts::<'a>(start as *const u8, size)
         };
 
-        self.resources.lock().unwrap().push(Resource::Mapping(mapping)); // NOTE(unwrap): If the lock is poisoned, some other thread panicked. We may as well.
+        self.push_resource(Resource::Mapping(mapping));
 
         Ok(slice)
     }
 
     fn load_symlink_target(&self, path: &Path) -> Result<&[u8], io::Error> {
         use std::fs;
-        use std::mem::transmute;
 
-        let target = fs::read_link(path)?;
+        let target = self.io_retry.run(|| {
+            self.syscalls.count(Syscall::Readlink);
+            fs::read_link(path)
+        })?;
         let data = {
             use std::os::unix::ffi::OsStrExt;
             target.as_os_str().as_bytes().to_vec()
         };
 
-        let data = data.into_boxed_slice();
+        Ok(self.store_data(data.into_boxed_slice()))
+    }
+
+    fn store_data(&self, data: Box<[u8]>) -> &[u8] {
+        use std::mem::transmute;
 
         let slice = unsafe {
             transmute::<&[u8], &'a [u8]>(&data)
         };
 
-        self.resources.lock().unwrap().push(Resource::Data(data));
+        self.push_resource(Resource::Data(data));
 
-        Ok(slice)
+        slice
+    }
+
+    fn load_metadata(&self, path: &Path) -> Result<FileMeta, io::Error> {
+        self.io_retry.run(|| std::fs::symlink_metadata(path)).map(FileMeta::from)
+    }
+
+    /// Unmap the file, every time it was loaded.
+    unsafe fn release_file(&self, path: &Path) {
+        for shard in &self.resources {
+            let mut resources = shard.lock().unwrap(); // NOTE(unwrap): If the lock is poisoned, some other thread panicked. We may as well.
+            resources.retain(|r| match r {
+                Resource::Mapping(m) if m.path == path => {
+                    libc::munmap(m.start, m.size);
+                    false
+                }
+                _ => true,
+            });
+        }
     }
 
     /// Get statistics
     fn stats(&self) -> Stats {
-        let resources = self.resources.lock().unwrap(); // NOTE(unwrap): If the lock is poisoned, some other thread panicked. We may as well.
-
+        let mut loaded_files = 0;
         let mut total_size = 0;
-        for r in resources.iter() {
-            total_size += match r {
-                Resource::Mapping(m) => m.size,
-                Resource::Data(d) => d.len(),
-            };
+        for shard in &self.resources {
+            let resources = shard.lock().unwrap(); // NOTE(unwrap): If the lock is poisoned, some other thread panicked. We may as well.
+
+            loaded_files += resources.len();
+            for r in resources.iter() {
+                total_size += match r {
+                    Resource::Mapping(m) => m.size,
+                    Resource::Data(d) => d.len(),
+                };
+            }
         }
 
         Stats {
-            loaded_files: resources.len(),
+            loaded_files,
             total_size,
         }
     }
+
+    fn syscall_stats(&self) -> SyscallStats {
+        self.syscalls.stats()
+    }
 }
 
 impl Drop for MmapArena<'_> {
     fn drop(&mut self) {
-        if let Ok(resources) = self.resources.lock() {
-            for r in resources.iter() {
-                if let Resource::Mapping(m) = r {
-                    unsafe {
-                        libc::munmap(m.start, m.size);
+        for shard in &self.resources {
+            if let Ok(resources) = shard.lock() {
+                for r in resources.iter() {
+                    if let Resource::Mapping(m) = r {
+                        unsafe {
+                            libc::munmap(m.start, m.size);
+                        }
                     }
                 }
             }
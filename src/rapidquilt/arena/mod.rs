@@ -1,13 +1,22 @@
 use std::io;
 use std::fmt;
-use std::path::Path;
+use std::fs::{self, Permissions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use libpatch::modified_file::FileTail;
 
 mod file_arena;
 
 #[cfg(unix)]
 mod mmap_arena;
 
+mod per_thread_arena;
+
 pub use self::file_arena::FileArena;
+pub use self::per_thread_arena::PerThreadArena;
 
 #[cfg(unix)]
 pub use self::mmap_arena::MmapArena;
@@ -18,18 +27,197 @@ pub trait Arena: Sync {
     /// is valid as long as this object is alive. (Same lifetimes.)
     fn load_file(&self, path: &Path) -> Result<&[u8], io::Error>;
 
+    /// Load at least `min_size` bytes from the start of the file, ending with
+    /// a complete line, and return them together with the `FileTail` that
+    /// can load the rest later. The tail is `None` if the whole file was
+    /// loaded.
+    ///
+    /// The default implementation loads the whole file.
+    fn load_file_head(&self, path: &Path, _min_size: usize) -> Result<(&[u8], Option<FileTail<'_>>), io::Error> {
+        Ok((self.load_file(path)?, None))
+    }
+
     /// Load the symlink target and return byte slice of its content. 
     /// The slice is valid as long as this object is alive.
     fn load_symlink_target(&self, path: &Path) -> Result<&[u8], io::Error>;
 
+    /// Keep the `data` that did not come from a file, e.g. a downloaded
+    /// patch, and return byte slice of it. The slice is valid as long as this
+    /// object is alive.
+    fn store_data(&self, data: Box<[u8]>) -> &[u8];
+
+    /// Get the metadata of the file. Symlinks are not followed, same as when
+    /// loading them.
+    fn load_metadata(&self, path: &Path) -> Result<FileMeta, io::Error>;
+
+    /// Release the content of the file at `path` loaded with `load_file`,
+    /// e.g. unmap it, so it does not take memory until the arena is dropped.
+    ///
+    /// The default implementation keeps it.
+    ///
+    /// # Safety
+    ///
+    /// The slices returned for the file are invalid afterwards, the caller
+    /// must be done with them.
+    unsafe fn release_file(&self, _path: &Path) {}
+
     /// Get statistics
     fn stats(&self) -> Stats;
+
+    /// Get the numbers of system calls done so far
+    fn syscall_stats(&self) -> SyscallStats;
+}
+
+/// Refuse to load a file of `size` bytes if it is bigger than the
+/// `max_file_size`, e.g. a core dump that a patch happens to touch.
+pub(crate) fn check_file_size(size: u64, max_file_size: Option<u64>) -> Result<(), io::Error> {
+    match max_file_size {
+        Some(max_file_size) if size > max_file_size => Err(io::Error::other(format!(
+            "The file has {} bytes, more than the limit of {} bytes (see --max-file-size)", size, max_file_size))),
+        _ => Ok(()),
+    }
+}
+
+/// The `errno`s that are retried by default: interrupted calls, resources
+/// that are busy for a moment, and stale handles of network filesystems.
+#[cfg(unix)]
+const DEFAULT_RETRYABLE_ERRNOS: &[i32] = &[libc::EINTR, libc::EAGAIN, libc::ESTALE];
+#[cfg(not(unix))]
+const DEFAULT_RETRYABLE_ERRNOS: &[i32] = &[libc::EINTR, libc::EAGAIN];
+
+/// The wait before the first retry, it doubles with every next one.
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(10);
+
+/// How the arenas retry opening and reading files after transient errors,
+/// e.g. on flaky network filesystems (see `--io-retries`). Other errors fail
+/// immediately.
+#[derive(Clone, Debug)]
+pub struct IoRetry {
+    /// How many times to try again
+    pub retries: usize,
+
+    /// The wait before the first retry, doubled for every next one
+    pub backoff: Duration,
+
+    /// The `errno`s that are retried
+    pub retryable_errnos: Vec<i32>,
+}
+
+impl IoRetry {
+    /// Retry the default `errno`s up to `retries` times.
+    pub fn new(retries: usize) -> Self {
+        Self {
+            retries,
+            backoff: DEFAULT_RETRY_BACKOFF,
+            retryable_errnos: DEFAULT_RETRYABLE_ERRNOS.to_vec(),
+        }
+    }
+
+    pub fn is_retryable(&self, error: &io::Error) -> bool {
+        error.raw_os_error().is_some_and(|errno| self.retryable_errnos.contains(&errno))
+    }
+
+    /// Run the `operation`, again after retryable errors, until it succeeds
+    /// or the retries are used up.
+    pub fn run<T, F: FnMut() -> Result<T, io::Error>>(&self, mut operation: F) -> Result<T, io::Error> {
+        let mut backoff = self.backoff;
+        let mut retries_left = self.retries;
+        loop {
+            match operation() {
+                Err(ref error) if retries_left > 0 && self.is_retryable(error) => {
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                    retries_left -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for IoRetry {
+    /// No retries
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+/// Metadata of a file, as returned by `Arena::load_metadata`.
+#[derive(Clone, Debug)]
+pub struct FileMeta {
+    /// The file type and permission bits, like `st_mode`. On systems without
+    /// them, only the read-only state is represented.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    #[allow(dead_code)] // Not needed by the patching itself, yet.
+    pub mtime: SystemTime,
+
+    permissions: Permissions,
+}
+
+/// The file type bits of `FileMeta::mode`
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_TYPE_SYMLINK: u32 = 0o120000;
+
+impl FileMeta {
+    pub fn is_symlink(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_TYPE_SYMLINK
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        self.permissions.clone()
+    }
+
+    /// The uid and gid, if the system has them.
+    pub fn owner(&self) -> Option<(u32, u32)> {
+        if cfg!(unix) {
+            Some((self.uid, self.gid))
+        } else {
+            None
+        }
+    }
+}
+
+impl From<fs::Metadata> for FileMeta {
+    #[cfg(unix)]
+    fn from(metadata: fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        Self {
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.len(),
+            mtime: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            permissions: metadata.permissions(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn from(metadata: fs::Metadata) -> Self {
+        let file_type = if metadata.file_type().is_symlink() { MODE_TYPE_SYMLINK } else { 0o100000 };
+        let permission_bits = if metadata.permissions().readonly() { 0o444 } else { 0o644 };
+
+        Self {
+            mode: file_type | permission_bits,
+            uid: 0,
+            gid: 0,
+            size: metadata.len(),
+            mtime: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            permissions: metadata.permissions(),
+        }
+    }
 }
 
 #[cfg(unix)]
 pub(crate) struct Mapping {
     pub(crate) start: *mut libc::c_void,
     pub(crate) size: usize,
+
+    /// The mapped file, see `Arena::release_file`
+    pub(crate) path: PathBuf,
 }
 
 #[cfg(unix)]
@@ -38,13 +226,120 @@ pub(crate) enum Resource {
     Data(Box<[u8]>),
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Stats {
     loaded_files: usize,
     total_size: usize,
 }
 
+impl Stats {
+    /// Number of files loaded into the arena, except the released ones
+    pub fn loaded_files(&self) -> usize {
+        self.loaded_files
+    }
+
+    /// Total size of the loaded files in bytes
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    /// Add the `other` statistics, e.g. of another arena, to these.
+    pub fn merge(&mut self, other: &Stats) {
+        self.loaded_files += other.loaded_files;
+        self.total_size += other.total_size;
+    }
+}
+
 impl fmt::Display for Stats {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Arena Statistics (loaded files: {}, total size: {} B)", self.loaded_files, self.total_size)
+        write!(f, "Arena Statistics (loaded files: {}, total size: {} B)", self.loaded_files(), self.total_size())
+    }
+}
+
+/// The system calls counted by `SyscallCounters`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Syscall {
+    Open,
+    Mmap,
+    Read,
+    Readlink,
+}
+
+/// Counters of the system calls done by an arena, to find out about its I/O.
+/// Reading a file counts as one `read`, no matter how many chunks the system
+/// needs.
+#[derive(Debug, Default)]
+pub(crate) struct SyscallCounters {
+    open: AtomicUsize,
+    mmap: AtomicUsize,
+    read: AtomicUsize,
+    readlink: AtomicUsize,
+}
+
+impl SyscallCounters {
+    pub(crate) fn count(&self, syscall: Syscall) {
+        let counter = match syscall {
+            Syscall::Open => &self.open,
+            Syscall::Mmap => &self.mmap,
+            Syscall::Read => &self.read,
+            Syscall::Readlink => &self.readlink,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> SyscallStats {
+        SyscallStats {
+            open: self.open.load(Ordering::Relaxed),
+            mmap: self.mmap.load(Ordering::Relaxed),
+            read: self.read.load(Ordering::Relaxed),
+            readlink: self.readlink.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SyscallStats {
+    open: usize,
+    mmap: usize,
+    read: usize,
+    readlink: usize,
+}
+
+impl SyscallStats {
+    /// Number of opened files
+    pub fn open(&self) -> usize {
+        self.open
+    }
+
+    /// Number of mapped files
+    pub fn mmap(&self) -> usize {
+        self.mmap
+    }
+
+    /// Number of reads of file content
+    pub fn read(&self) -> usize {
+        self.read
+    }
+
+    /// Number of read symlink targets
+    pub fn readlink(&self) -> usize {
+        self.readlink
+    }
+
+    /// Add the `other` numbers, e.g. of another arena, to these.
+    pub fn merge(&mut self, other: &SyscallStats) {
+        self.open += other.open;
+        self.mmap += other.mmap;
+        self.read += other.read;
+        self.readlink += other.readlink;
+    }
+}
+
+impl fmt::Display for SyscallStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Arena System Calls (open: {}, mmap: {}, read: {}, readlink: {})",
+               self.open(), self.mmap(), self.read(), self.readlink())
     }
 }
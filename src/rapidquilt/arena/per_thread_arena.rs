@@ -0,0 +1,85 @@
+// Licensed under the MIT license. See LICENSE.md
+
+use std::io;
+use std::path::Path;
+
+use libpatch::modified_file::FileTail;
+
+use super::{Arena, FileMeta, Stats, SyscallStats};
+
+
+/// Arena that keeps a separate arena for every thread of the rayon pool, so
+/// the threads loading files do not wait for each other's lock of one shared
+/// arena. Calls from outside of the pool use an extra arena.
+///
+/// The returned byte slices are valid as long as this object is alive, no
+/// matter which thread loaded them. A file loaded by two threads is kept
+/// twice, once in each arena, same as when one thread loads it twice.
+pub struct PerThreadArena {
+    arenas: Vec<Box<dyn Arena>>,
+}
+
+impl PerThreadArena {
+    /// The first of the `arenas` is for calls from outside of the pool, the
+    /// others for the threads of the pool.
+    pub fn new(arenas: Vec<Box<dyn Arena>>) -> Self {
+        assert!(!arenas.is_empty());
+        Self { arenas }
+    }
+
+    /// The arena of the current thread.
+    fn arena(&self) -> &dyn Arena {
+        let index = rayon::current_thread_index().map_or(0, |index| index + 1);
+
+        // Threads of a bigger pool than expected share the arenas, that is
+        // slower but still correct.
+        &*self.arenas[index % self.arenas.len()]
+    }
+}
+
+impl Arena for PerThreadArena {
+    fn load_file(&self, path: &Path) -> Result<&[u8], io::Error> {
+        self.arena().load_file(path)
+    }
+
+    fn load_file_head(&self, path: &Path, min_size: usize) -> Result<(&[u8], Option<FileTail<'_>>), io::Error> {
+        self.arena().load_file_head(path, min_size)
+    }
+
+    fn load_symlink_target(&self, path: &Path) -> Result<&[u8], io::Error> {
+        self.arena().load_symlink_target(path)
+    }
+
+    fn store_data(&self, data: Box<[u8]>) -> &[u8] {
+        self.arena().store_data(data)
+    }
+
+    fn load_metadata(&self, path: &Path) -> Result<FileMeta, io::Error> {
+        self.arena().load_metadata(path)
+    }
+
+    /// Release the file in the arenas of all threads, any of them may have
+    /// loaded it.
+    unsafe fn release_file(&self, path: &Path) {
+        for arena in &self.arenas {
+            arena.release_file(path);
+        }
+    }
+
+    /// Get statistics, merged from the arenas of all threads
+    fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        for arena in &self.arenas {
+            stats.merge(&arena.stats());
+        }
+        stats
+    }
+
+    fn syscall_stats(&self) -> SyscallStats {
+        let mut syscall_stats = SyscallStats::default();
+        for arena in &self.arenas {
+            syscall_stats.merge(&arena.syscall_stats());
+        }
+        syscall_stats
+    }
+}
@@ -0,0 +1,148 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Audit log of all changes done to the working tree.
+//!
+//! Every created, modified or deleted file is recorded as one line of JSON
+//! with the hashes (SHA-256) of its content before and after the change.
+//! Every record is written out immediately, so the log is complete up to
+//! the point of failure even if rapidquilt does not finish.
+
+use std::fmt::{self, Write as FmtWrite};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use libpatch::modified_file::ModifiedFile;
+use sha2::{Digest, Sha256};
+
+use crate::json;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditOperation {
+    Create,
+    Modify,
+    Delete,
+}
+
+impl fmt::Display for AuditOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditOperation::Create => write!(f, "create"),
+            AuditOperation::Modify => write!(f, "modify"),
+            AuditOperation::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+/// A single audit log record.
+#[derive(Debug)]
+pub struct AuditRecord<'a> {
+    pub path: &'a Path,
+    pub operation: AuditOperation,
+    pub old_hash: Option<String>,
+    pub new_hash: Option<String>,
+
+    /// The patch responsible for the change, if any
+    pub patch: Option<&'a Path>,
+}
+
+impl AuditRecord<'_> {
+    /// Write the record as a single line of JSON.
+    pub fn write_to<W: Write>(&self, writer: &mut W, timestamp: SystemTime) -> io::Result<()> {
+        write!(writer, "{{\"path\":")?;
+        json::write_path(writer, self.path)?;
+        write!(writer, ",\"operation\":\"{}\",\"old_hash\":", self.operation)?;
+        json::write_opt_str(writer, self.old_hash.as_deref())?;
+        write!(writer, ",\"new_hash\":")?;
+        json::write_opt_str(writer, self.new_hash.as_deref())?;
+        write!(writer, ",\"patch\":")?;
+        match self.patch {
+            Some(patch) => json::write_path(writer, patch)?,
+            None => writer.write_all(b"null")?,
+        }
+        write!(writer, ",\"timestamp\":\"{}\"}}", humantime::format_rfc3339_micros(timestamp))?;
+        writeln!(writer)
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Open the audit log for appending, create it if it does not exist.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+
+    /// Append the record to the log.
+    pub fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        // Format the whole line first, so it goes out in a single write.
+        let mut line = Vec::new();
+        record.write_to(&mut line, SystemTime::now())?;
+
+        // NOTE(unwrap): If the lock is poisoned, another thread panicked.
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)?;
+        file.flush()
+    }
+}
+
+/// Format the result of the `hasher` as a hex string.
+fn finish_hash(hasher: Sha256) -> String {
+    let digest = hasher.finalize();
+    let mut hex = String::with_capacity(digest.len() * 2);
+    for byte in digest {
+        // NOTE(unwrap): Writing into a String can not fail.
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+/// Compute the hash of the content as a hex string.
+pub fn hash_content(content: &[u8]) -> String {
+    finish_hash(Sha256::new_with_prefix(content))
+}
+
+/// Compute the hash of the content of `file`, without making a copy of
+/// it. Parts that were not loaded yet are read as they are hashed.
+pub fn hash_modified_file(file: &ModifiedFile) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    file.write_to(&mut hasher)?;
+    Ok(finish_hash(hasher))
+}
+
+/// Compute the hash of a file on disk. For symlinks it is the hash of the
+/// target, like rapidquilt sees them. Returns `None` if the file does not
+/// exist.
+pub fn hash_file_on_disk(path: &Path) -> io::Result<Option<String>> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+
+        #[cfg(unix)]
+        let data = {
+            use std::os::unix::ffi::OsStrExt;
+            target.as_os_str().as_bytes().to_vec()
+        };
+
+        #[cfg(not(unix))]
+        let data = target.to_string_lossy().into_owned().into_bytes();
+
+        return Ok(Some(hash_content(&data)));
+    }
+
+    // Files may be big, hash them as they are read.
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(Some(finish_hash(hasher)))
+}
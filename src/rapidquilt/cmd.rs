@@ -1,28 +1,91 @@
 // Licensed under the MIT license. See LICENSE.md
 
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::env;
+use std::fmt;
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, BufWriter, Write, IsTerminal};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use colored::*;
 use anyhow::{bail, Context, Result};
 use getopts::{Matches, Options};
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use thiserror::Error;
 
 use libpatch::analysis::{AnalysisSet, MultiApplyAnalysis};
+use libpatch::patch::BatchThreshold;
+use libpatch::patch::unified::parser::parse_patch;
+use rayon::prelude::*;
 
+use crate::applied::{count_applied_patches, repair_applied_patches};
 use crate::apply::{
     ApplyConfig,
+    ApplyError,
     ApplyConfigBackupCount,
     ApplyConfigDoBackups,
+    DEFAULT_REJECT_SUFFIX,
+    FileBackupStore,
+    ReferenceDir,
+    ReferenceTree,
+    adopt_fixed_patch,
     apply_patches,
     apply_patches_parallel,
+    backup_names,
+    check_roundtrip,
+    reconstruct_backups,
+    diff_snapshot,
+    squash_patches,
+    take_snapshot,
+    tune_threads,
+    touched_files_by_patch,
+    verify_tree,
+    write_already_applied_report,
+    write_buffered_messages,
+    write_filtered_files_report,
+    write_skipped_files_report,
+    write_forced_files_warning,
+    write_provenance,
+    write_unchanged_files_report,
+    write_patch_sizes,
+    write_patch_sizes_json,
+    write_summary,
+    write_timings,
     SeriesPatch,
     Verbosity,
 };
-use crate::arena::{Arena, FileArena};
+use crate::arena::{Arena, FileArena, IoRetry, PerThreadArena};
+use crate::audit::AuditLog;
+use crate::dump::{write_dump, write_dump_json};
+use crate::explain::write_explanation;
+use crate::edit::{add_files, launch_editor};
+use crate::file_filter::FileFilter;
+#[cfg(feature = "git")]
+use crate::git::{GitCommit, diff_commits};
+use crate::grep::{GrepConfig, grep_patch};
+use crate::manifest::Manifest;
+use crate::match_cache::MatchCache;
+use crate::mbox::patch_to_mail;
+use crate::normalize::normalize_patch;
+use crate::numstat::write_diff_numstat;
+use crate::parse_check::check_patches;
+use crate::patch_source::{BundleSource, PatchSource};
+#[cfg(feature = "git")]
+use crate::patch_source::MemorySource;
+#[cfg(feature = "remote")]
+use crate::patch_source::UrlSource;
+use crate::pop::{confirm_pop, pop_patches, read_applied_patches};
+use crate::prefix_map::PrefixMap;
+use crate::rcfile::{find_rc_file, read_rc_options};
+use crate::rename_index::RenameIndex;
+use crate::status::{stack_status, write_status, write_status_json};
+use crate::tar::write_tree_tar;
+use crate::touched::{shared_files, write_shared_files, write_shared_files_json};
+#[cfg(feature = "watch")]
+use crate::watch::{PatchWatch, watch_series};
 
 #[cfg(unix)]
 use crate::arena::MmapArena;
@@ -43,13 +106,115 @@ use crate::arena::MmapArena;
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+// The exit codes, see "Exit codes" in README.md.
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILED: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_INEXACT: i32 = 3;
+pub const EXIT_SERIES: i32 = 4;
+pub const EXIT_IO: i32 = 5;
+
+/// How a command that did not fail with an error ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Success,
+
+    /// A patch did not apply, or a check found a difference.
+    Failed,
+
+    /// A patch applied only with fuzz or at an offset, which is not allowed
+    /// with `--strict`.
+    Inexact,
+}
+
+impl From<bool> for Outcome {
+    fn from(ok: bool) -> Self {
+        if ok { Outcome::Success } else { Outcome::Failed }
+    }
+}
+
+/// Wrong options or arguments on the command line
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct UsageError(String);
+
+/// Return a `UsageError` with the formatted message, like `bail!`.
+macro_rules! bail_usage {
+    ($($arg:tt)*) => {
+        return Err(UsageError(format!($($arg)*)).into())
+    };
+}
+
+#[derive(Debug, Error)]
+pub enum SeriesError {
+    #[error("When reading \"series\" file.")]
+    Read,
+
+    #[error("Patch not in series: {0:?}")]
+    NotInSeries(PathBuf),
+
+    #[error("Patch {} is in the series twice, on lines {first_line} and {line} (annotate it with \"#allow-duplicate\" or use --allow-duplicates to apply it twice)", .patch_filename.display())]
+    Duplicate { patch_filename: PathBuf, first_line: usize, line: usize },
+}
+
+/// The exit code for the `result` of `run_with_outcome`: errors in the
+/// command line, the series or its patches, and I/O errors have their own.
+pub fn exit_code(result: &Result<Outcome>) -> i32 {
+    let error = match result {
+        Ok(Outcome::Success) => return EXIT_SUCCESS,
+        Ok(Outcome::Failed) => return EXIT_FAILED,
+        Ok(Outcome::Inexact) => return EXIT_INEXACT,
+        Err(error) => error,
+    };
+
+    // This looks through the contexts too.
+    if error.is::<UsageError>() || error.is::<getopts::Fail>() {
+        EXIT_USAGE
+    } else if error.is::<SeriesError>() || matches!(error.downcast_ref(), Some(ApplyError::PatchLoad { .. })) {
+        EXIT_SERIES
+    } else if error.is::<io::Error>() {
+        EXIT_IO
+    } else {
+        EXIT_FAILED
+    }
+}
+
 
 const DEFAULT_PATCH_STRIP: usize = 1;
 
+/// How many of the slowest patches `--timings` prints without a number
+const DEFAULT_TIMINGS_TOP: usize = 10;
+
+
+/// Options for debugging, left out of the usage
+const HIDDEN_OPTIONS: &[&str] = &["--dump-parsed"];
 
-fn usage(opts: &Options) -> ! {
-    println!("{}", opts.usage("Usage: rapidquilt push [<options>] [num|patch]"));
-    process::exit(1);
+/// Print the usage and exit with the `exit_code`.
+fn usage(opts: &Options, exit_code: i32) -> ! {
+    let brief = concat!("Usage: rapidquilt push [<options>] [num|patch]\n",
+                        "       rapidquilt pop [<options>] [num|patch]\n",
+                        "       rapidquilt edit [<options>] <file...>\n",
+                        "       rapidquilt status [<options>]\n",
+                        "       rapidquilt snapshot [<options>]\n",
+                        "       rapidquilt diff --snapshot [<options>]\n",
+                        "       rapidquilt grep [<options>] <pattern>\n",
+                        "       rapidquilt normalize [<options>] [patch...]\n",
+                        "       rapidquilt squash [<options>] <output.patch>\n",
+                        "       rapidquilt verify [<options>] <reference-dir>\n",
+                        "       rapidquilt verify [<options>] --base-ref <commit>\n",
+                        "       rapidquilt export-mbox [<options>] <dir>\n",
+                        "       rapidquilt explain [<options>] <patch>\n",
+                        "       rapidquilt parse-check [<options>]\n",
+                        "       rapidquilt reconstruct-pc [<options>] --reference <dir>\n",
+                        "       rapidquilt apply [<options>] --from-diff <commit> <commit>\n",
+                        "       rapidquilt watch [<options>]");
+    println!("{}", opts.usage_with_format(|items| {
+        let items: Vec<_> = items
+            .filter(|item| !HIDDEN_OPTIONS.iter().any(|option| item.trim_start().starts_with(option)))
+            .collect();
+        format!("{}\n\nOptions:\n{}\n", brief, items.join("\n"))
+    }));
+    process::exit(exit_code);
 }
 
 fn version() -> ! {
@@ -57,45 +222,318 @@ fn version() -> ! {
     process::exit(0);
 }
 
-fn read_series_file<P: AsRef<Path>>(series_path: P) -> Result<Vec<SeriesPatch>> {
+/// The annotation of a series line that applies a patch once more
+const ALLOW_DUPLICATE_ANNOTATION: &str = "allow-duplicate";
+
+/// A patch in the series file
+#[derive(Debug)]
+struct SeriesLine {
+    /// The number of the line, from 1
+    number: usize,
+    series_patch: SeriesPatch,
+    /// The line is annotated with "#allow-duplicate", so the patch may be
+    /// in the series before.
+    allow_duplicate: bool,
+}
+
+/// Read the patches from the series file at `series_path`, with the numbers
+/// of their lines. Lines with guards are kept only if the `guards` select them,
+/// see `guards_select`.
+fn read_numbered_series_file<P: AsRef<Path>>(series_path: P, guards: &[String]) -> Result<Vec<SeriesLine>> {
+    let file = File::open(series_path)?;
+    parse_numbered_series(BufReader::new(file), guards)
+}
+
+/// A word of a series line, and whether it was quoted.
+type SeriesWord<'a> = (&'a str, bool);
+
+/// Split the series `line` into its words and the comment after them.
+/// Quilt has no way to handle whitespace in filenames of patches, so a word
+/// ends at any whitespace, unless it is quoted with `"` or `'`. A comment
+/// starts with '#' at the start of a word.
+fn split_series_line(line: &str) -> Result<(Vec<SeriesWord<'_>>, &str)> {
+    let mut words = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix('#') {
+            return Ok((words, comment));
+        }
+        let (word, word_end) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let Some(length) = rest[1..].find(quote) else {
+                    bail!("Missing closing {} in \"{}\"", quote, line);
+                };
+                ((&rest[1..length + 1], true), length + 2)
+            }
+            _ => {
+                let length = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                ((&rest[..length], false), length)
+            }
+        };
+        words.push(word);
+        rest = rest[word_end..].trim_start();
+    }
+    Ok((words, ""))
+}
+
+/// Whether a series line with the `guards` ("+symbol" or "-symbol" before
+/// the filename) is selected by the `selected` symbols, like by the `guards`
+/// script of quilt: the first guard with a selected symbol decides, "+"
+/// selects the line and "-" leaves it out. If there is none, the line is
+/// selected unless its last guard is a "+" one.
+fn guards_select(guards: &[&str], selected: &[String]) -> bool {
+    for guard in guards {
+        let (positive, symbol) = guard.split_at(1);
+        if selected.iter().any(|selected| selected == symbol) {
+            return positive == "+";
+        }
+    }
+    guards.last().is_none_or(|guard| guard.starts_with('-'))
+}
+
+/// Parse the series from the `reader`, see `read_numbered_series_file`.
+fn parse_numbered_series<R: BufRead>(reader: R, guards: &[String]) -> Result<Vec<SeriesLine>> {
     let mut patch_opts = Options::new();
     patch_opts.optopt("p", "strip", "Strip this many directories in paths of patched files.", "<n>");
     patch_opts.optflag("R", "reverse", "Reverse the patch direction.");
 
-    let file = File::open(series_path)?;
-    let file = BufReader::new(file);
-
-    file.lines()
-        .filter_map(|line| {
-            match line {
-                // Comments in series file must start with '#' without whitespace before.
-                Ok(line) if line.is_empty() || line.starts_with('#') => None,
-                Ok(line) => {
-                    // Quilt has no way to handle whitespace in filenames of patches. Leading whitespace
-                    // is ignored and then everything up to any other whitespace is considered as filename.
-                    // Anything left out is used as parameters for patch command.
-                    let mut parts = line.split_whitespace().peekable();
-
-                    parts.next().map(|filename| {
-                        let filename = std::path::PathBuf::from(filename);
-                        match parts.peek() {
-                            // Fast path when there are no options
-                            None => Ok(SeriesPatch { filename, strip: DEFAULT_PATCH_STRIP, reverse: false }),
-                            //There are some options, so parse them
-                            Some(_) => patch_opts.parse(parts)
-                                .with_context(|| format!("Parsing patch options for \"{}\"", filename.display()))
-                                .map(|matches| {
-                                    let strip = matches.opt_str("strip")
-                                        .and_then(|n| n.parse::<usize>().ok()).unwrap_or(DEFAULT_PATCH_STRIP);
-                                    let reverse = matches.opt_present("R");
-                                    SeriesPatch { filename, strip, reverse }
-                                }),
-                        }
-                    })
+    let mut series_lines = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let number = index + 1;
+
+        // Comments in series file must start with '#' without whitespace before.
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Leading whitespace is ignored, then come the guards, the filename
+        // and the parameters for the patch command, up to a comment.
+        let (words, comment) = split_series_line(&line)
+            .with_context(|| format!("Parsing line {} of the series", number))?;
+        let allow_duplicate = comment.split_whitespace().next() == Some(ALLOW_DUPLICATE_ANNOTATION);
+        let guard_count = words.iter()
+            .take_while(|&&(word, quoted)| !quoted && word.len() > 1 && (word.starts_with('+') || word.starts_with('-')))
+            .count();
+        let Some(&(filename, _)) = words.get(guard_count) else {
+            if guard_count > 0 {
+                bail!("Line {} of the series has guards, but no patch", number);
+            }
+            continue;
+        };
+        let line_guards: Vec<_> = words[..guard_count].iter().map(|&(guard, _)| guard).collect();
+        if !guards_select(&line_guards, guards) {
+            continue;
+        }
+
+        let filename = PathBuf::from(filename);
+        let options = &words[guard_count + 1..];
+        let series_patch = if options.is_empty() {
+            // Fast path when there are no options
+            SeriesPatch { filename, strip: DEFAULT_PATCH_STRIP, reverse: false }
+        } else {
+            let matches = patch_opts.parse(options.iter().map(|&(option, _)| option))
+                .with_context(|| format!("Parsing patch options for \"{}\"", filename.display()))?;
+            if let Some(argument) = matches.free.first() {
+                bail!("Unexpected \"{}\" in the patch options for \"{}\"", argument, filename.display());
+            }
+            let strip = match matches.opt_str("strip") {
+                Some(n) => n.parse::<usize>()
+                    .with_context(|| format!("Bad strip \"{}\" in the patch options for \"{}\"", n, filename.display()))?,
+                None => DEFAULT_PATCH_STRIP,
+            };
+            let reverse = matches.opt_present("R");
+            SeriesPatch { filename, strip, reverse }
+        };
+        series_lines.push(SeriesLine { number, series_patch, allow_duplicate });
+    }
+    Ok(series_lines)
+}
+
+/// Check that no patch is in the series twice, it would be applied twice.
+/// Lines annotated with "#allow-duplicate" may repeat a patch. If
+/// `allow_duplicates`, the others are only warned about.
+fn check_duplicate_patches(series_lines: &[SeriesLine], allow_duplicates: bool) -> Result<()> {
+    let mut first_lines = HashMap::new();
+    for series_line in series_lines {
+        let filename = &series_line.series_patch.filename;
+        match first_lines.entry(filename) {
+            Entry::Occupied(entry) => {
+                if series_line.allow_duplicate {
+                    continue;
+                }
+                if !allow_duplicates {
+                    return Err(SeriesError::Duplicate {
+                        patch_filename: filename.clone(),
+                        first_line: *entry.get(),
+                        line: series_line.number,
+                    }.into());
                 }
-                Err(err) => Some(Err(err.into())),
+                eprintln!("{}: Patch {} is in the series twice, on lines {} and {}.",
+                          "WARNING".bright_yellow(), filename.display(), entry.get(), series_line.number);
             }
-        }).collect()
+            Entry::Vacant(entry) => {
+                entry.insert(series_line.number);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Order of the patches when there is no "series" file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PatchSort {
+    Name,
+    Mtime,
+}
+
+impl fmt::Display for PatchSort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchSort::Name => write!(f, "name"),
+            PatchSort::Mtime => write!(f, "modification time"),
+        }
+    }
+}
+
+/// List all "*.patch" and "*.diff" files in the patch directory, sorted by
+/// `sort`. Other files and subdirectories are ignored.
+fn read_patch_directory(patches_path: &Path, sort: PatchSort) -> Result<Vec<SeriesPatch>> {
+    let mut patches = Vec::new();
+    for entry in fs::read_dir(patches_path)? {
+        let entry = entry?;
+        let filename = PathBuf::from(entry.file_name());
+        if !matches!(filename.extension().and_then(OsStr::to_str), Some("patch") | Some("diff")) {
+            continue;
+        }
+
+        // Follow symlinks, patch directories are often assembled from links.
+        let metadata = fs::metadata(entry.path())?;
+        if !metadata.is_file() {
+            continue;
+        }
+        patches.push((metadata.modified()?, filename));
+    }
+
+    match sort {
+        PatchSort::Name => patches.sort_by(|(_, a), (_, b)| a.cmp(b)),
+        // Patches with the same time are sorted by name, so the order is stable.
+        PatchSort::Mtime => patches.sort(),
+    }
+
+    Ok(patches.into_iter()
+        .map(|(_, filename)| SeriesPatch { filename, strip: DEFAULT_PATCH_STRIP, reverse: false })
+        .collect())
+}
+
+/// Read the "series" file. If it does not exist and "--no-series" was given,
+/// use all patches from the patch directory instead. In that case returns
+/// also the order in which they were sorted.
+fn read_series(matches: &Matches, base_dir: &Path, patches_path: &Path) -> Result<(Vec<SeriesPatch>, Option<PatchSort>)> {
+    let series_path = base_dir.join("series");
+
+    if matches.opt_present("no-series") && !series_path.exists() {
+        let sort = match matches.opt_str("sort") {
+            Some(ref s) if s == "name"  => PatchSort::Name,
+            Some(ref s) if s == "mtime" => PatchSort::Mtime,
+            None                        => PatchSort::Name,
+            _ => bail_usage!("Bad value given to \"sort\" parameter!"),
+        };
+        let series_patches = read_patch_directory(patches_path, sort)
+            .with_context(|| format!("When reading patch directory \"{}\".", patches_path.display()))?;
+        return Ok((series_patches, Some(sort)));
+    }
+
+    let series_lines = read_numbered_series_file(series_path, &matches.opt_strs("guard"))
+        .context(SeriesError::Read)?;
+    check_duplicate_patches(&series_lines, matches.opt_present("allow-duplicates"))?;
+    Ok((series_lines.into_iter().map(|series_line| series_line.series_patch).collect(), None))
+}
+
+/// Read the "series" file from the "--patches-url" and return it together
+/// with the source of its patches.
+#[cfg(feature = "remote")]
+fn read_remote_series(matches: &Matches, url: &str) -> Result<(Box<dyn PatchSource>, Vec<SeriesPatch>)> {
+    if matches.opt_present("no-series") {
+        bail_usage!("Can not use \"no-series\" together with \"patches-url\".");
+    }
+
+    let patch_source = UrlSource::new(url)?;
+    let series = patch_source.fetch(Path::new("series"))
+        .context(SeriesError::Read)?;
+    let series_lines = parse_numbered_series(&series[..], &matches.opt_strs("guard"))
+        .context(SeriesError::Read)?;
+    check_duplicate_patches(&series_lines, matches.opt_present("allow-duplicates"))?;
+    Ok((Box::new(patch_source), series_lines.into_iter().map(|series_line| series_line.series_patch).collect()))
+}
+
+/// Read the bundle with the "series" file and the patches from the `reader`
+/// and return its series together with the bundle as the source of the
+/// patches.
+fn read_bundle_series<R: BufRead>(matches: &Matches, reader: R) -> Result<(Box<dyn PatchSource>, Vec<SeriesPatch>)> {
+    if matches.opt_present("no-series") {
+        bail_usage!("Can not use \"no-series\" together with \"bundle\".");
+    }
+    if matches.opt_present("patches-url") {
+        bail_usage!("Can not use \"patches-url\" together with \"bundle\".");
+    }
+
+    let patch_source = BundleSource::read(reader)
+        .context("Reading the bundle from the standard input")?;
+    let series_lines = parse_numbered_series(patch_source.series(), &matches.opt_strs("guard"))
+        .context(SeriesError::Read)?;
+    check_duplicate_patches(&series_lines, matches.opt_present("allow-duplicates"))?;
+    Ok((Box::new(patch_source), series_lines.into_iter().map(|series_line| series_line.series_patch).collect()))
+}
+
+#[cfg(not(feature = "remote"))]
+fn read_remote_series(_matches: &Matches, _url: &str) -> Result<(Box<dyn PatchSource>, Vec<SeriesPatch>)> {
+    bail_usage!("This rapidquilt was built without the \"remote\" feature.");
+}
+
+/// The `path` as it is shown in messages: relative to the `base_dir` if
+/// "relative" was given and it is in it.
+fn shown_path<'a>(relative: bool, base_dir: &Path, path: &'a Path) -> &'a Path {
+    match path.strip_prefix(base_dir) {
+        Ok(relative_path) if relative && !relative_path.as_os_str().is_empty() => relative_path,
+        _ => path,
+    }
+}
+
+/// Write the order in which `push` would apply the `series_patches` as a
+/// series file: the patches from `first_patch` up to `last_patch` with their
+/// options, the others commented out with the reason they are skipped.
+pub fn write_series_order<W: Write>(writer: &mut W, series_patches: &[SeriesPatch], first_patch: usize, last_patch: usize)
+    -> Result<(), io::Error>
+{
+    for (index, series_patch) in series_patches.iter().enumerate() {
+        let skip_reason = if index < first_patch {
+            Some("applied")
+        } else if index >= last_patch {
+            Some("after the goal")
+        } else {
+            None
+        };
+        if skip_reason.is_some() {
+            write!(writer, "# ")?;
+        }
+        // Filenames that would be split into words are quoted.
+        let filename = series_patch.filename.display().to_string();
+        if filename.contains(|c: char| c.is_whitespace() || matches!(c, '#' | '"' | '\'')) {
+            let quote = if filename.contains('"') { '\'' } else { '"' };
+            write!(writer, "{}{}{} -p{}", quote, filename, quote, series_patch.strip)?;
+        } else {
+            write!(writer, "{} -p{}", filename, series_patch.strip)?;
+        }
+        if series_patch.reverse {
+            write!(writer, " -R")?;
+        }
+        match skip_reason {
+            Some(skip_reason) => writeln!(writer, " ({})", skip_reason)?,
+            None => writeln!(writer)?,
+        }
+    }
+    Ok(())
 }
 
 fn save_applied_patches(config: &ApplyConfig, applied_patches: &[SeriesPatch]) -> Result<()> {
@@ -108,37 +546,679 @@ fn save_applied_patches(config: &ApplyConfig, applied_patches: &[SeriesPatch]) -
     Ok(())
 }
 
-enum PushGoal {
+/// The working directory given by "directory" and the patches directory
+/// given by "patches" in it.
+fn tree_paths(matches: &Matches) -> (PathBuf, PathBuf) {
+    let base_dir = PathBuf::from(matches.opt_str("directory").unwrap_or_default());
+    let patches_path = base_dir.join(matches.opt_str("p").as_deref().unwrap_or("patches"));
+    (base_dir, patches_path)
+}
+
+/// The configuration to apply the `series_patches` in the tree, with the
+/// options that are the same for every command.
+fn tree_config<'a>(matches: &Matches, base_dir: &'a Path, patches_path: &'a Path, series_patches: &'a [SeriesPatch],
+                   verbosity: Verbosity) -> ApplyConfig<'a>
+{
+    let mut config = ApplyConfig::new(base_dir, patches_path, series_patches, verbosity);
+    config.unsafe_paths = matches.opt_present("unsafe-paths");
+    config.relative = matches.opt_present("relative");
+    config.follow_symlinks = matches.opt_present("follow-symlinks");
+    config
+}
+
+/// Read the series and find out how many patches are applied.
+fn read_applied_series(matches: &Matches, base_dir: &Path, patches_path: &Path)
+    -> Result<(Vec<SeriesPatch>, usize, Option<PatchSort>)>
+{
+    let (series_patches, derived_order) = read_series(matches, base_dir, patches_path)?;
+    let applied_count = count_applied_patches(base_dir, &series_patches)?;
+    Ok((series_patches, applied_count, derived_order))
+}
+
+/// Record the current content of files touched by applied patches.
+fn cmd_snapshot(matches: &Matches, verbosity: Verbosity) -> Result<bool> {
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, applied_count, _) = read_applied_series(matches, &base_dir, &patches_path)?;
+    let config = tree_config(matches, &base_dir, &patches_path, &series_patches[..applied_count], verbosity);
+
+    let arena = build_arena(matches)?;
+    take_snapshot(&config, &*arena)?;
+
+    Ok(true)
+}
+
+/// Print the changes since the last snapshot.
+fn cmd_diff(matches: &Matches, verbosity: Verbosity) -> Result<bool> {
+    if !matches.opt_present("snapshot") {
+        bail_usage!("Only \"diff --snapshot\" is supported.");
+    }
+
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, applied_count, _) = read_applied_series(matches, &base_dir, &patches_path)?;
+    let config = tree_config(matches, &base_dir, &patches_path, &series_patches[..applied_count], verbosity);
+
+    let arena = build_arena(matches)?;
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    if matches.opt_present("numstat") {
+        let mut diff = Vec::new();
+        diff_snapshot(&config, &*arena, &mut diff)?;
+        write_diff_numstat(&mut writer, &diff)?;
+    } else {
+        diff_snapshot(&config, &*arena, &mut writer)?;
+    }
+    writer.flush()?;
+
+    Ok(true)
+}
+
+/// Write all applied patches as a single patch.
+fn cmd_squash<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args: F, verbosity: Verbosity) -> Result<bool> {
+    let Some(output_path) = free_args.next() else {
+        bail_usage!("Missing the file to write the squashed patch into.");
+    };
+
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, applied_count, _) = read_applied_series(matches, &base_dir, &patches_path)?;
+    let config = tree_config(matches, &base_dir, &patches_path, &series_patches[..applied_count], verbosity);
+
+    let arena = build_arena(matches)?;
+    let mut data = Vec::new();
+    let changed_files = squash_patches(&config, &*arena, &mut data)?;
+    fs::write(output_path, &data)
+        .with_context(|| format!("Writing squashed patch \"{}\"", output_path))?;
+
+    // Only the numstat goes to stdout then.
+    if matches.opt_present("numstat") {
+        write_diff_numstat(&mut io::stdout().lock(), &data)?;
+    } else if verbosity >= Verbosity::Normal {
+        println!("Squashed {} patches changing {} files into {}.", applied_count, changed_files, output_path);
+    }
+
+    Ok(true)
+}
+
+/// Apply the unapplied patches in memory and compare the files they touch
+/// with a reference tree.
+fn cmd_verify<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args: F, verbosity: Verbosity) -> Result<bool> {
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let mut reference: Box<dyn ReferenceTree> = match (matches.opt_str("base-ref"), free_args.next()) {
+        (Some(base_ref), None) => open_git_reference(&base_dir, &base_ref)?,
+        (None, Some(reference_dir)) => Box::new(ReferenceDir(Path::new(reference_dir))),
+        (Some(_), Some(_)) => bail_usage!("Can not use \"base-ref\" together with a reference directory."),
+        (None, None) => bail_usage!("Missing the reference directory to compare with."),
+    };
+
+    let (series_patches, applied_count, _) = read_applied_series(matches, &base_dir, &patches_path)?;
+    let mut config = tree_config(matches, &base_dir, &patches_path, &series_patches[applied_count..], verbosity);
+    config.fuzz = fuzz(matches)?;
+
+    let arena = build_arena(matches)?;
+    let ignore_whitespace = matches.opt_present("ignore-whitespace");
+    let clean = verify_tree(&config, &*arena, &mut *reference, ignore_whitespace, &mut io::stderr())?;
+
+    if clean && verbosity >= Verbosity::Normal {
+        println!("All files touched by the {} patches match the reference.", config.series_patches.len());
+    }
+
+    Ok(clean)
+}
+
+/// The files of the git commit named `base_ref` in the repository with the
+/// `base_dir`.
+#[cfg(feature = "git")]
+fn open_git_reference(base_dir: &Path, base_ref: &str) -> Result<Box<dyn ReferenceTree>> {
+    Ok(Box::new(GitCommit::open(base_dir, base_ref)?))
+}
+
+#[cfg(not(feature = "git"))]
+fn open_git_reference(_base_dir: &Path, _base_ref: &str) -> Result<Box<dyn ReferenceTree>> {
+    bail_usage!("This rapidquilt was built without the \"git\" feature.");
+}
+
+/// Write the patches of the series as numbered mails into a directory, to be
+/// imported with `git am`.
+fn cmd_export_mbox<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args: F, verbosity: Verbosity) -> Result<bool> {
+    let Some(output_dir) = free_args.next() else {
+        bail_usage!("Missing the directory to write the mails into.");
+    };
+    let output_dir = Path::new(output_dir);
+
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, _) = read_series(matches, &base_dir, &patches_path)?;
+
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Creating directory {:?}", output_dir))?;
+
+    for (index, series_patch) in series_patches.iter().enumerate() {
+        // `git am` applies the patches with "-p1".
+        if series_patch.strip != DEFAULT_PATCH_STRIP {
+            eprintln!("{}: {} is applied with -p{}, `git am` needs -p{}.",
+                      "WARNING".bright_yellow(), series_patch.filename.display(), series_patch.strip, DEFAULT_PATCH_STRIP);
+        }
+
+        let data = fs::read(patches_path.join(&series_patch.filename))
+            .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+        let mail = patch_to_mail(&series_patch.filename, &data, series_patch.strip, index + 1, series_patches.len())
+            .with_context(|| format!("Converting patch {:?}", series_patch.filename))?;
+        if !mail.has_author {
+            eprintln!("{}: {} does not say who wrote it, `git am` needs a \"From\" line.",
+                      "WARNING".bright_yellow(), series_patch.filename.display());
+        }
+
+        let path = output_dir.join(&mail.filename);
+        fs::write(&path, &mail.data)
+            .with_context(|| format!("Writing mail {:?}", path))?;
+        if verbosity >= Verbosity::Normal {
+            println!("{}", path.display());
+        }
+    }
+
+    Ok(true)
+}
+
+/// Returns true if "--format json" was given, false for "text".
+fn json_format(matches: &Matches) -> Result<bool> {
+    match matches.opt_str("format") {
+        Some(ref s) if s == "json" => Ok(true),
+        Some(ref s) if s == "text" => Ok(false),
+        None => Ok(false),
+        _ => bail_usage!("Bad value given to \"format\" parameter!"),
+    }
+}
+
+/// Describe what the patch of the series named by the first of the
+/// `free_args` does, file by file.
+fn cmd_explain<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args: F) -> Result<bool> {
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let patch_filename = match free_args.next() {
+        Some(patch_filename) => Path::new(patch_filename),
+        None => bail_usage!("Missing patch for \"explain\"."),
+    };
+
+    let (series_patches, _) = read_series(matches, &base_dir, &patches_path)?;
+    let Some(series_patch) = series_patches.iter().find(|series_patch| series_patch.filename == patch_filename) else {
+        return Err(SeriesError::NotInSeries(patch_filename.to_path_buf()).into());
+    };
+
+    let data = fs::read(patches_path.join(&series_patch.filename))
+        .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+    let patch = parse_patch(&data, series_patch.strip)
+        .with_context(|| format!("Parsing patch {:?}", series_patch.filename))?;
+
+    write_explanation(&mut io::stdout().lock(), series_patch, &patch)?;
+    Ok(true)
+}
+
+/// Report the patches of the series that do not parse, without applying
+/// anything.
+fn cmd_parse_check(matches: &Matches, verbosity: Verbosity) -> Result<bool> {
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, _) = read_series(matches, &base_dir, &patches_path)?;
+    let clean = check_patches(&mut io::stderr(), &patches_path, &series_patches, verbosity)?;
+
+    if clean && verbosity >= Verbosity::Normal {
+        println!("All {} patches of the series parse.", series_patches.len());
+    }
+
+    Ok(clean)
+}
+
+/// Rebuild the backups of the applied patches in ".pc" from a pristine tree.
+fn cmd_reconstruct_pc(matches: &Matches, verbosity: Verbosity) -> Result<bool> {
+    let Some(reference_dir) = matches.opt_str("reference") else {
+        bail_usage!("Missing the pristine tree to rebuild the backups from, give it with \"--reference\".");
+    };
+    let reference_dir = Path::new(&reference_dir);
+
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, applied_count, _) = read_applied_series(matches, &base_dir, &patches_path)?;
+    if applied_count == 0 {
+        bail!("No patches are applied according to \".pc/applied-patches\", there are no backups to rebuild.");
+    }
+    let applied_patches = &series_patches[..applied_count];
+    let applied_names = backup_names(applied_patches.iter().map(|series_patch| series_patch.filename.as_path()));
+    let mut config = tree_config(matches, &base_dir, &patches_path, applied_patches, verbosity);
+    config.fuzz = fuzz(matches)?;
+    config.backup_names = Some(&applied_names);
+
+    let arena = build_arena(matches)?;
+    let consistent = reconstruct_backups(&config, &*arena, reference_dir, &mut io::stderr())?;
+
+    if verbosity >= Verbosity::Normal {
+        println!("Rebuilt the backups of the {} applied patches from \"{}\".", applied_count, reference_dir.display());
+    }
+
+    Ok(consistent)
+}
+
+/// Apply the changes between two git commits to the working tree, as if they
+/// were a patch. Nothing is recorded in ".pc".
+#[cfg(feature = "git")]
+fn cmd_apply<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args: F, verbosity: Verbosity) -> Result<bool> {
+    if !matches.opt_present("from-diff") {
+        bail_usage!("\"apply\" needs \"from-diff\" and the two git commits to diff.");
+    }
+    let (Some(from_ref), Some(to_ref), None) = (free_args.next(), free_args.next(), free_args.next()) else {
+        bail_usage!("\"from-diff\" needs two git commits, the changes from the first one to the second one are applied.");
+    };
+
+    let (base_dir, _) = tree_paths(matches);
+
+    let diff = diff_commits(&base_dir, from_ref, to_ref)
+        .with_context(|| format!("Diffing \"{}\" and \"{}\"", from_ref, to_ref))?;
+    let patch_filename = PathBuf::from(format!("{}..{}", from_ref, to_ref));
+    let patch_source = MemorySource::new(&patch_filename, diff);
+    let series_patches = [SeriesPatch { filename: patch_filename, strip: 1, reverse: false }];
+
+    let mut config = tree_config(matches, &base_dir, &base_dir, &series_patches, verbosity);
+    config.patch_source = Some(&patch_source);
+    config.fuzz = fuzz(matches)?;
+    config.dry_run = matches.opt_present("dry-run");
+
+    let arena = build_arena(matches)?;
+    let apply_result = apply_patches(&config, &*arena, &AnalysisSet::default())?;
+    let applied = apply_result.skipped_patches == 0;
+    if applied && verbosity >= Verbosity::Normal {
+        println!("Applied the changes from \"{}\" to \"{}\".", from_ref, to_ref);
+    }
+
+    Ok(applied)
+}
+
+/// Print what the parser made of the patch at `path`, to find out why it
+/// misapplies. The paths are not stripped.
+fn cmd_dump_parsed(matches: &Matches, path: &str) -> Result<bool> {
+    let json = json_format(matches)?;
+
+    let data = fs::read(path).with_context(|| format!("Reading patch \"{}\"", path))?;
+    let patch = parse_patch(&data, 0).with_context(|| format!("Parsing patch \"{}\"", path))?;
+
+    let stdout = io::stdout();
+    let mut writer = stdout.lock();
+    if json {
+        write_dump_json(&mut writer, &patch)?;
+    } else {
+        write_dump(&mut writer, &patch)?;
+    }
+
+    Ok(true)
+}
+
+/// Print the files touched by more than one patch of the series, with the
+/// patches touching them.
+fn cmd_list_touched_across_series(matches: &Matches, verbosity: Verbosity) -> Result<bool> {
+    let json = json_format(matches)?;
+
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, _) = read_series(matches, &base_dir, &patches_path)?;
+    let config = tree_config(matches, &base_dir, &patches_path, &series_patches, verbosity);
+
+    let arena = build_arena(matches)?;
+    let touched_files = touched_files_by_patch(&config, &*arena)?;
+    let shared_files = shared_files(&series_patches, &touched_files);
+
+    let stdout = io::stdout();
+    let mut writer = stdout.lock();
+    if json {
+        write_shared_files_json(&mut writer, &shared_files)?;
+    } else {
+        write_shared_files(&mut writer, &shared_files)?;
+    }
+
+    Ok(true)
+}
+
+/// Rebuild ".pc/applied-patches" from the backups, before the command.
+fn repair_applied(matches: &Matches, verbosity: Verbosity) -> Result<()> {
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, _) = read_series(matches, &base_dir, &patches_path)?;
+    let repair = repair_applied_patches(&base_dir, &series_patches, &FileBackupStore::new(&base_dir))
+        .context("Repairing \".pc/applied-patches\"")?;
+
+    for patch_filename in &repair.missing_backups {
+        eprintln!("{}: Patch {} has no backup in \".pc\", it is assumed to be applied, but it can not be popped.",
+                  "WARNING".bright_yellow(), patch_filename.display());
+    }
+    if verbosity >= Verbosity::Normal {
+        match repair.applied_patches.last() {
+            Some(top_patch) => println!("Rebuilt \".pc/applied-patches\" with {} patches, now at patch {}",
+                                        repair.applied_patches.len(), top_patch.display()),
+            None => println!("Rebuilt \".pc/applied-patches\", there are no backups, so no patches are applied."),
+        }
+    }
+    Ok(())
+}
+
+/// Print the summary of the patch stack.
+fn cmd_status(matches: &Matches) -> Result<bool> {
+    let json = json_format(matches)?;
+
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, _) = read_series(matches, &base_dir, &patches_path)?;
+    let status = stack_status(&base_dir, &patches_path, &series_patches, &FileBackupStore::new(&base_dir))?;
+
+    let stdout = io::stdout();
+    let mut writer = stdout.lock();
+    if json {
+        write_status_json(&mut writer, &status)?;
+    } else {
+        write_status(&mut writer, &status)?;
+    }
+
+    Ok(true)
+}
+
+/// Pop applied patches, restoring their backup files.
+fn cmd_pop<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args: F, verbosity: Verbosity) -> Result<bool> {
+    let goal = parse_goal(matches, free_args.next())?;
+
+    let (base_dir, _) = tree_paths(matches);
+
+    let applied_patches = read_applied_patches(&base_dir)?;
+    if applied_patches.is_empty() {
+        if verbosity >= Verbosity::Normal {
+            println!("No patches applied. Nothing to do.");
+        }
+        return Ok(true);
+    }
+
+    let count = match goal {
+        Goal::All => applied_patches.len(),
+        Goal::Count(n) => {
+            if n > applied_patches.len() && verbosity >= Verbosity::Normal {
+                println!("Only {} patches are applied, popping them.", applied_patches.len());
+            }
+            std::cmp::min(n, applied_patches.len())
+        }
+        Goal::UpTo(patch_filename) => {
+            match applied_patches.iter().position(|filename| *filename == patch_filename) {
+                Some(index) => applied_patches.len() - (index + 1),
+                None => bail!("Patch not applied: {:?}", patch_filename),
+            }
+        }
+    };
+
+    // Asked only when popping the whole stack, scripts are not stopped.
+    if count > 1 && count == applied_patches.len() && !matches.opt_present("yes")
+        && io::stdin().is_terminal() && io::stdout().is_terminal()
+        && !confirm_pop(&mut io::stdin().lock(), &mut io::stdout(), &applied_patches)?
+    {
+        println!("Nothing popped.");
+        return Ok(false);
+    }
+
+    let audit_log = open_audit_log(matches)?;
+    pop_patches(&base_dir, &FileBackupStore::new(&base_dir), &applied_patches, count, audit_log.as_ref(), verbosity)?;
+
+    if verbosity >= Verbosity::Normal {
+        match applied_patches[..(applied_patches.len() - count)].last() {
+            Some(top_patch) => println!("Now at patch {}", top_patch.display()),
+            None => println!("No patches applied."),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Add files to the top patch and open them in the editor.
+fn cmd_edit<'a, F: Iterator<Item = &'a String>>(matches: &Matches, free_args: F, verbosity: Verbosity) -> Result<bool> {
+    let filenames: Vec<PathBuf> = free_args.map(PathBuf::from).collect();
+    if filenames.is_empty() {
+        bail_usage!("Missing the files to edit.");
+    }
+
+    let (base_dir, _) = tree_paths(matches);
+
+    let top_patch = add_files(&base_dir, &FileBackupStore::new(&base_dir), &filenames, verbosity)?;
+
+    // Nobody would see the editor
+    if !io::stdin().is_terminal() {
+        if verbosity >= Verbosity::Normal {
+            println!("Not launching the editor, the input is not a terminal.");
+        }
+        return Ok(true);
+    }
+
+    match env::var("EDITOR") {
+        Ok(ref editor) if !editor.is_empty() => {
+            if !launch_editor(editor, &base_dir, &filenames)? {
+                bail!("Editor \"{}\" failed.", editor);
+            }
+        }
+        _ => {
+            if verbosity >= Verbosity::Normal {
+                println!("$EDITOR is not set. Edit the files yourself, then refresh patch {} with `quilt refresh`.",
+                         top_patch.display());
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Search all patches in the series.
+fn cmd_grep<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args: F) -> Result<bool> {
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let pattern = match free_args.next() {
+        Some(pattern) => pattern,
+        None => bail_usage!("Missing pattern for \"grep\"."),
+    };
+    let config = GrepConfig {
+        regex: regex::bytes::Regex::new(pattern).context("Parsing the pattern")?,
+        // "-a" means "added lines" for "grep"
+        added_only: matches.opt_present("all"),
+        files_with_matches: matches.opt_present("files-with-matches"),
+    };
+
+    let (series_patches, _) = read_series(matches, &base_dir, &patches_path)?;
+
+    let arena = build_arena(matches)?;
+    let arena = &*arena;
+
+    // Search the patches in parallel, but print the results in series order.
+    let outputs = series_patches.par_iter()
+        .map(|series_patch| -> Result<(bool, Vec<u8>)> {
+            let data = arena.load_file(&patches_path.join(&series_patch.filename))
+                .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+            let mut output = Vec::new();
+            let any_match = grep_patch(&mut output, &config, &series_patch.filename, data)?;
+            Ok((any_match, output))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    let mut any_match = false;
+    for (patch_matched, output) in outputs {
+        any_match |= patch_matched;
+        writer.write_all(&output)?;
+    }
+    writer.flush()?;
+
+    Ok(any_match)
+}
+
+/// Rewrite the given patches, or all patches in the series, in the
+/// canonical form.
+fn cmd_normalize<'a, F: Iterator<Item = &'a String>>(matches: &Matches, free_args: F, verbosity: Verbosity) -> Result<bool> {
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    let (series_patches, _) = read_series(matches, &base_dir, &patches_path)?;
+
+    let mut selected_patches = Vec::new();
+    for patch_filename in free_args {
+        match series_patches.iter().find(|series_patch| series_patch.filename == Path::new(patch_filename)) {
+            Some(series_patch) => selected_patches.push(series_patch),
+            None => return Err(SeriesError::NotInSeries(PathBuf::from(patch_filename)).into()),
+        }
+    }
+    if selected_patches.is_empty() {
+        selected_patches.extend(&series_patches);
+    }
+
+    let mut normalized_count = 0;
+    for series_patch in selected_patches {
+        // The canonical form is applied with "-p1", the series would have to
+        // change for other strip levels.
+        if series_patch.strip != DEFAULT_PATCH_STRIP {
+            eprintln!("{}: Skipping {}, it is applied with -p{}.",
+                      "WARNING".bright_yellow(), series_patch.filename.display(), series_patch.strip);
+            continue;
+        }
+
+        let path = patches_path.join(&series_patch.filename);
+        let data = fs::read(&path)
+            .with_context(|| ApplyError::PatchLoad { patch_filename: series_patch.filename.clone() })?;
+        let normalized = normalize_patch(&data)
+            .with_context(|| format!("Normalizing patch {:?}", series_patch.filename))?;
+
+        // Leave patches that are already normalized untouched.
+        if normalized == data {
+            continue;
+        }
+
+        if !matches.opt_present("dry-run") {
+            fs::write(&path, &normalized)
+                .with_context(|| format!("Saving normalized patch {:?}", series_patch.filename))?;
+        }
+        if verbosity >= Verbosity::Normal {
+            println!("Normalized {}", series_patch.filename.display());
+        }
+        normalized_count += 1;
+    }
+
+    if verbosity >= Verbosity::Normal {
+        println!("{} patches normalized.", normalized_count);
+    }
+
+    Ok(true)
+}
+
+/// How many patches to push or pop.
+enum Goal {
     All,
     Count(usize),
+    /// Push up to the patch / pop until it is the top one.
     UpTo(PathBuf),
 }
 
+/// Find out the `Goal` from "--all", "--count" and the free argument after
+/// the command, which is a number of patches or a patch name. One patch if
+/// nothing is given.
+fn parse_goal(matches: &Matches, free_arg: Option<&String>) -> Result<Goal> {
+    let count = match matches.opt_str("count") {
+        Some(s) => match s.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => bail_usage!("Bad value given to \"count\" parameter!"),
+        },
+        None => None,
+    };
+
+    Ok(match (matches.opt_present("a"), count, free_arg) {
+        // The number or name wins over "--all"
+        (_, None, Some(free_arg)) => match free_arg.parse::<usize>() {
+            Ok(n) => Goal::Count(n),
+            Err(_) => Goal::UpTo(PathBuf::from(free_arg)),
+        },
+        (true, None, None) => Goal::All,
+        (false, Some(n), None) => Goal::Count(n),
+        (false, None, None) => Goal::Count(1),
+        _ => bail_usage!("\"count\" can not be used together with \"all\" or the number or name of a patch."),
+    })
+}
+
 /// Returns true if all patches were applied, false if only some, and error if there was error.
-fn cmd_push<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args: F, verbosity: Verbosity) -> Result<bool>
+fn cmd_push<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args: F, verbosity: Verbosity) -> Result<Outcome>
+{
+    let goal = parse_goal(matches, free_args.next())?;
+    push(matches, goal, false, verbosity)
+}
+
+/// Push the patches up to the `goal`. When `watching`, every patch is backed
+/// up, so it can be popped again.
+fn push(matches: &Matches, goal: Goal, watching: bool, verbosity: Verbosity) -> Result<Outcome>
 {
     // Parse "push" specific arguments
-    let base_dir = matches.opt_str("directory").unwrap_or_default();
-    let base_dir = Path::new(&base_dir);
+    let (base_dir, patches_path) = tree_paths(matches);
+
+    if matches.opt_present("out") && matches.opt_present("overlay-upper") {
+        bail_usage!("Can not use \"out\" together with \"overlay-upper\".");
+    }
+    let overlay_whiteouts = matches.opt_present("overlay-upper");
+    let out_option = if overlay_whiteouts { "overlay-upper" } else { "out" };
+    let out_dir = matches.opt_str(out_option).map(PathBuf::from);
+
+    // The tree goes into the archive, nothing is saved on disk.
+    let out_tar = matches.opt_str("out-tar");
+    if out_tar.is_none() && matches.opt_present("out-tar-changed") {
+        bail_usage!("\"out-tar-changed\" can only be used together with \"out-tar\".");
+    }
+    for option in ["out", "overlay-upper", "emit-diff", "numstat", "roundtrip-check", "resume", "interactive", "post-hook"] {
+        if out_tar.is_some() && matches.opt_present(option) {
+            bail_usage!("Can not use \"out-tar\" together with \"{}\".", option);
+        }
+    }
+    // Only the archive may go to stdout.
+    let verbosity = if out_tar.as_deref() == Some("-") { Verbosity::Quiet } else { verbosity };
+
+    // Only the failures and the summary in the end are printed.
+    let summary_only = matches.opt_present("summary-only");
+    for option in ["quiet", "verbose", "roundtrip-check", "dump-series-order", "timings"] {
+        if summary_only && matches.opt_present(option) {
+            bail_usage!("Can not use \"summary-only\" together with \"{}\".", option);
+        }
+    }
+    if summary_only && out_tar.as_deref() == Some("-") {
+        bail_usage!("Can not use \"summary-only\" together with \"out-tar -\".");
+    }
+    let verbosity = if summary_only { Verbosity::Quiet } else { verbosity };
 
     let do_backups = match matches.opt_str("backup") {
         Some(ref s) if s == "always" => ApplyConfigDoBackups::Always,
         Some(ref s) if s == "onfail" => ApplyConfigDoBackups::OnFail,
         Some(ref s) if s == "never"  => ApplyConfigDoBackups::Never,
         None                         => ApplyConfigDoBackups::OnFail,
-        _ => bail!("Bad value given to \"backup\" parameter!"),
+        _ => bail_usage!("Bad value given to \"backup\" parameter!"),
     };
+    // The source tree stays as it is, there is nothing to pop.
+    let do_backups = if out_dir.is_some() { ApplyConfigDoBackups::Never } else { do_backups };
+
+    // Nothing is written to ".pc" at all, the tree is thrown away anyway.
+    let no_backup = matches.opt_present("no-backup");
+    if no_backup && matches.opt_present("backup") {
+        bail_usage!("Can not use \"no-backup\" together with \"backup\".");
+    }
+    let do_backups = if no_backup { ApplyConfigDoBackups::Never } else { do_backups };
 
     let backup_count = match matches.opt_str("backup-count") {
         Some(ref s) if s == "all" => ApplyConfigBackupCount::All,
-        Some(n)                   => ApplyConfigBackupCount::Last(n.parse::<usize>()?),
-        None                      => ApplyConfigBackupCount::Last(100),
+        Some(s) => match s.parse::<usize>() {
+            Ok(n) => ApplyConfigBackupCount::Last(n),
+            Err(_) => bail_usage!("Bad value given to \"backup-count\" parameter!"),
+        },
+        None => ApplyConfigBackupCount::Last(100),
     };
 
-    let patches_path = base_dir.join(
-        if let Some(ref s) = matches.opt_str("p") { s } else { "patches" });
+    let (do_backups, backup_count) = if watching {
+        (ApplyConfigDoBackups::Always, ApplyConfigBackupCount::All)
+    } else {
+        (do_backups, backup_count)
+    };
 
-    let fuzz = matches.opt_str("fuzz").and_then(|n| n.parse::<usize>().ok()).unwrap_or(0);
+    let fuzz = fuzz(matches)?;
 
     if fuzz > 0 {
         println!(concat!("{}: You are using --fuzz {}. The fuzzy patching algorithm in rapidquilt follows the ",
@@ -147,93 +1227,366 @@ fn cmd_push<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args
                 "WARNING".bright_yellow(), fuzz);
     }
 
-    let dry_run = matches.opt_present("dry-run");
-    let stats = matches.opt_present("stats");
+    let max_offset = match matches.opt_str("max-offset") {
+        Some(ref s) if s == "unlimited" => None,
+        Some(s) => match s.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => bail_usage!("Bad value given to \"max-offset\" parameter!"),
+        },
+        None => Some(10000),
+    };
+    let strict_ambiguity = matches.opt_present("strict-ambiguity");
+    let unordered_hunks = matches.opt_present("unordered-hunks");
+    let strict = matches.opt_present("strict");
+    let batch_threshold = BatchThreshold {
+        min_hunks: match matches.opt_str("min-parallel-hunks") {
+            Some(s) => match s.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => bail_usage!("Bad value given to \"min-parallel-hunks\" parameter!"),
+            },
+            None => BatchThreshold::DEFAULT_MIN_HUNKS,
+        },
+        min_file_size: match matches.opt_str("min-file-size") {
+            Some(s) => match s.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => bail_usage!("Bad value given to \"min-file-size\" parameter!"),
+            },
+            None => BatchThreshold::DEFAULT_MIN_FILE_SIZE,
+        },
+    };
+    let posix = matches.opt_present("posix");
+    let verify_index = matches.opt_present("verify-index");
 
-    let arena = build_arena(matches.opt_present("mmap"));
+    let force = matches.opt_present("force");
 
-    let mut goal = if matches.opt_present("a") {
-        PushGoal::All
-    } else {
-        PushGoal::Count(1)
+    if force {
+        println!(concat!("{}: You are using --force. Hunks that do not match the files will be written over ",
+                         "whatever is at their expected position. The result may be broken, review every file ",
+                         "reported as forced."),
+                "WARNING".bright_red().bold());
+    }
+
+    let reverse_if_applied = matches.opt_present("reverse-if-applied");
+
+    // Without a terminal to ask, fail the same way as without the option.
+    let interactive = matches.opt_present("interactive") && io::stdin().is_terminal() && io::stdout().is_terminal();
+
+    let patch_timeout = match matches.opt_str("patch-timeout") {
+        Some(s) => match s.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()) {
+            Some(timeout) => Some(timeout),
+            None => bail_usage!("Bad value given to \"patch-timeout\" parameter!"),
+        },
+        None => None,
     };
-    if let Some(first_free_arg) = free_args.next() {
-        if let Ok(number) = first_free_arg.parse::<usize>() {
-            goal = PushGoal::Count(number);
-        } else {
-            goal = PushGoal::UpTo(PathBuf::from(first_free_arg));
+
+    let lazy_load = match matches.opt_str("lazy-load") {
+        Some(s) => match s.parse::<usize>().ok().filter(|&mib| mib > 0).and_then(|mib| mib.checked_mul(1 << 20)) {
+            Some(size) => Some(size),
+            None => bail_usage!("Bad value given to \"lazy-load\" parameter!"),
+        },
+        None => None,
+    };
+
+    let show_rejects_inline = matches.opt_present("show-rejects-inline");
+    let save_rej_files = !matches.opt_present("no-rej-files");
+    for option in ["reject-dir", "reject-suffix"] {
+        if !save_rej_files && matches.opt_present(option) {
+            bail_usage!("Can not use \"no-rej-files\" together with \"{}\".", option);
         }
     }
+    let reject_dir = matches.opt_str("reject-dir").map(PathBuf::from);
+    let reject_suffix = matches.opt_str("reject-suffix").unwrap_or_else(|| DEFAULT_REJECT_SUFFIX.to_string());
+    if reject_suffix.is_empty() || reject_suffix.contains(std::path::is_separator) {
+        bail_usage!("Bad value given to \"reject-suffix\" parameter!");
+    }
+    let function_context = matches.opt_present("function-context");
+    let preserve_ownership = matches.opt_present("preserve-ownership");
+    // Patching another link of a file that is written through would change
+    // the content still mapped or loaded lazily from it.
+    let preserve_hard_links = matches.opt_present("preserve-hard-links");
+    for option in ["mmap", "lazy-load"] {
+        if preserve_hard_links && matches.opt_present(option) {
+            bail_usage!("Can not use \"preserve-hard-links\" together with \"{}\".", option);
+        }
+    }
+    let touch = touch_time(matches)?;
+    let post_hook = matches.opt_str("post-hook");
+    let deterministic = matches.opt_present("deterministic");
 
-    // Process series file
-    let series_patches = read_series_file(base_dir.join("series"))
-        .with_context(|| "When reading \"series\" file.")?;
-
-    // Determine the last patch
-    let first_patch = if let Ok(applied_patch_filenames) = read_series_file(base_dir.join(".pc/applied-patches")) {
-        for (p1, p2) in series_patches.iter().zip(applied_patch_filenames.iter()) {
-            if p1.filename != p2.filename {
-                bail!("There is mismatch in \"series\" and \".pc/applied-patches\" files! {} vs {}", p1.filename.display(), p2.filename.display());
+    // The rules are used in the order they were given, no matter the option.
+    let mut filter_rules: Vec<_> = matches.opt_strs_pos("include").into_iter().map(|(pos, glob)| (pos, true, glob))
+        .chain(matches.opt_strs_pos("exclude").into_iter().map(|(pos, glob)| (pos, false, glob)))
+        .collect();
+    filter_rules.sort();
+    let file_filter = if filter_rules.is_empty() {
+        None
+    } else {
+        let mut file_filter = FileFilter::new();
+        for (_, include, glob) in filter_rules {
+            let result = if include { file_filter.include(&glob) } else { file_filter.exclude(&glob) };
+            if let Err(err) = result {
+                bail_usage!("Bad pattern \"{}\": {}", glob, err);
             }
         }
-        applied_patch_filenames.len()
+        Some(file_filter)
+    };
+    let allowed_files = read_allowed_files(matches)?;
+    let prefix_map = read_prefix_map(matches)?;
+
+    let relative = matches.opt_present("relative");
+    let auto_strip = matches.opt_present("auto-strip");
+    let dry_run = matches.opt_present("dry-run") || out_tar.is_some();
+    let emit_diff = matches.opt_present("emit-diff");
+    if emit_diff && !dry_run {
+        bail_usage!("\"emit-diff\" can only be used together with \"dry-run\".");
+    }
+    let numstat = matches.opt_present("numstat");
+    if numstat && !dry_run {
+        bail_usage!("\"numstat\" can only be used together with \"dry-run\".");
+    }
+    let stats = matches.opt_present("stats");
+    let timings_top = match matches.opt_str("timings") {
+        Some(s) => match s.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => bail_usage!("Bad value given to \"timings\" parameter!"),
+        },
+        None if matches.opt_present("timings") => Some(DEFAULT_TIMINGS_TOP),
+        None => None,
+    };
+    let report_unchanged = matches.opt_present("report-unchanged");
+    let roundtrip_check = matches.opt_present("roundtrip-check");
+    for option in ["resume", "interactive", "post-hook", "emit-diff", "numstat"] {
+        if roundtrip_check && matches.opt_present(option) {
+            bail_usage!("Can not use \"roundtrip-check\" together with \"{}\".", option);
+        }
+    }
+
+    let audit_log = open_audit_log(matches)?;
+    let manifest_path = matches.opt_str("manifest");
+    let provenance_path = matches.opt_str("provenance");
+    for option in ["interactive", "roundtrip-check"] {
+        if provenance_path.is_some() && matches.opt_present(option) {
+            bail_usage!("Can not use \"provenance\" together with \"{}\".", option);
+        }
+    }
+    let manifest = manifest_path.as_ref().map(|_| Manifest::new());
+    let match_cache_path = matches.opt_str("match-cache");
+    let match_cache = match &match_cache_path {
+        Some(path) => Some(MatchCache::load(Path::new(path))
+                           .with_context(|| format!("Loading match cache \"{}\"", path))?),
+        None => None,
+    };
+    let rename_index = if matches.opt_present("find-renames") {
+        Some(RenameIndex::build(&base_dir)
+             .context("Indexing the files of the working directory")?)
+    } else {
+        None
+    };
+    let json_output = json_format(matches)?;
+
+    // With "auto-tune", the arena is built for the most threads it may get.
+    let threads = matches.opt_str("threads")
+        .or_else(|| env::var("RAPIDQUILT_THREADS").ok());
+    let auto_tune = threads.as_deref() == Some("auto-tune");
+    let mut num_threads = match threads.filter(|_| !auto_tune) {
+        Some(s) => match s.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => bail_usage!("Bad value given to \"threads\" parameter!"),
+        },
+        None => rayon::current_num_threads(),
+    };
+
+    let arena = build_push_arena(matches, num_threads)?;
+
+    let remote_series = if matches.opt_present("bundle") {
+        Some(read_bundle_series(matches, io::stdin().lock())?)
+    } else if let Some(url) = matches.opt_str("patches-url") {
+        Some(read_remote_series(matches, &url)?)
     } else {
-        0
+        None
+    };
+    let (patch_source, series_patches, mut first_patch, derived_order) = match remote_series {
+        Some((patch_source, series_patches)) => {
+            let applied_count = count_applied_patches(&base_dir, &series_patches)?;
+            (Some(patch_source), series_patches, applied_count, None)
+        }
+        None => {
+            let (series_patches, applied_count, derived_order) = read_applied_series(matches, &base_dir, &patches_path)?;
+            (None, series_patches, applied_count, derived_order)
+        }
     };
 
+    let backup_names = backup_names(series_patches.iter().map(|series_patch| series_patch.filename.as_path()));
+
+    let dump_series_order = matches.opt_present("dump-series-order");
+    if dump_series_order && matches.opt_present("resume") {
+        bail_usage!("Can not use \"dump-series-order\" together with \"resume\".");
+    }
+
+    // The first unapplied patch is the one that failed and was fixed by hand.
+    if matches.opt_present("resume") && out_dir.is_some() {
+        bail_usage!("Can not use \"resume\" together with \"{}\".", out_option);
+    }
+    if matches.opt_present("resume") && no_backup {
+        bail_usage!("Can not use \"resume\" together with \"no-backup\".");
+    }
+    if matches.opt_present("resume") && first_patch < series_patches.len() {
+        let fixed_patch = &series_patches[first_patch..=first_patch];
+        let mut config = tree_config(matches, &base_dir, &patches_path, fixed_patch, verbosity);
+        config.patch_source = patch_source.as_deref();
+        config.backup_names = Some(&backup_names[first_patch..=first_patch]);
+        config.fuzz = fuzz;
+        config.dry_run = dry_run;
+        adopt_fixed_patch(&config, &*arena)?;
+
+        if !dry_run {
+            save_applied_patches(&config, fixed_patch)
+                .with_context(|| "When saving applied patches.")?;
+        }
+        first_patch += 1;
+    }
+
     if first_patch == series_patches.len() {
-        if verbosity >= Verbosity::Normal {
+        if dump_series_order {
+            write_series_order(&mut io::stdout().lock(), &series_patches, first_patch, first_patch)?;
+        } else if verbosity >= Verbosity::Normal {
             println!("All patches applied. Nothing to do.");
+        } else if summary_only {
+            println!("Summary: all patches applied, nothing to do");
         }
-        return Ok(true);
+        return Ok(Outcome::Success);
     }
 
     let last_patch = match goal {
-        PushGoal::All => series_patches.len(),
-        PushGoal::Count(n) => std::cmp::min(first_patch + n, series_patches.len()),
-        PushGoal::UpTo(patch_filename) => {
+        Goal::All => series_patches.len(),
+        Goal::Count(n) => {
+            let unapplied = series_patches.len() - first_patch;
+            if n > unapplied && verbosity >= Verbosity::Normal {
+                println!("Only {} patches left in the series, applying them.", unapplied);
+            }
+            first_patch + std::cmp::min(n, unapplied)
+        }
+        Goal::UpTo(patch_filename) => {
             if let Some(index) = series_patches.iter().position(|item| item.filename == patch_filename) {
                 if index < first_patch {
                     bail!("Patch already applied: {:?}", patch_filename);
                 }
                 index + 1
             } else {
-                bail!("Patch not in series: {:?}", patch_filename);
+                return Err(SeriesError::NotInSeries(patch_filename).into());
             }
         }
     };
 
+    if dump_series_order {
+        write_series_order(&mut io::stdout().lock(), &series_patches, first_patch, last_patch)?;
+        return Ok(Outcome::Success);
+    }
+
     let series_patches = &series_patches[first_patch..last_patch];
+    let backup_names = &backup_names[first_patch..last_patch];
+
+    if let Some(sort) = derived_order {
+        if verbosity >= Verbosity::Normal {
+            println!("No \"series\" file, applying patches from \"{}\" sorted by {}:",
+                     shown_path(relative, &base_dir, &patches_path).display(), sort);
+            for series_patch in series_patches {
+                println!("  {}", series_patch.filename.display());
+            }
+        }
+    }
 
     let config = ApplyConfig {
-        base_dir,
-        series_patches,
-        patches_path: patches_path.as_ref(),
+        out_dir: out_dir.as_deref(),
+        overlay_whiteouts,
+        patch_source: patch_source.as_deref(),
+        file_filter: file_filter.as_ref(),
+        allowed_files: allowed_files.as_ref(),
+        prefix_map: prefix_map.as_ref(),
+        auto_strip,
         fuzz,
+        max_offset,
+        strict_ambiguity,
+        unordered_hunks,
+        strict,
+        batch_threshold,
+        posix,
+        verify_index,
+        force,
+        reverse_if_applied,
+        interactive,
+        patch_timeout,
+        lazy_load,
+        show_rejects_inline,
+        save_rej_files,
+        reject_dir: reject_dir.as_deref(),
+        reject_suffix: &reject_suffix,
+        function_context,
+        preserve_ownership,
+        preserve_hard_links,
+        touch,
+        post_hook: post_hook.as_deref(),
+        deterministic,
         do_backups,
+        backup_store: None,
+        backup_names: Some(backup_names),
         backup_count,
         dry_run,
-        stats,
-        verbosity,
+        emit_diff: emit_diff || numstat,
+        emit_files: out_tar.is_some(),
+        stats: stats || summary_only,
+        timings: timings_top.is_some(),
+        report_unchanged,
+        audit_log: audit_log.as_ref(),
+        manifest: manifest.as_ref(),
+        match_cache: match_cache.as_ref(),
+        rename_index: rename_index.as_ref(),
+        ..tree_config(matches, &base_dir, &patches_path, series_patches, verbosity)
     };
 
+    // Nothing is saved, the patches are applied and reverted in memory.
+    if roundtrip_check {
+        let clean = check_roundtrip(&config, &*arena, &mut io::stderr())?;
+        if clean && verbosity >= Verbosity::Normal {
+            println!("All {} patches revert back to the original files.", series_patches.len());
+        }
+        return Ok(clean.into());
+    }
+
+    if let Some(out_dir) = config.out_dir.filter(|_| !config.dry_run) {
+        fs::create_dir_all(out_dir)
+            .with_context(|| format!("Creating output directory \"{}\"", out_dir.display()))?;
+    }
+
     let mut analyses = AnalysisSet::new();
 
     for analysis_name in matches.opt_strs("analyze") {
         if analysis_name.eq_ignore_ascii_case("multiapply") {
             analyses.add_default::<MultiApplyAnalysis>();
         } else {
-            bail!("Unknown analysis \"{}\"", analysis_name);
+            bail_usage!("Unknown analysis \"{}\"", analysis_name);
         }
     }
 
-    let num_threads = matches.opt_str("threads")
-        .or_else(|| env::var("RAPIDQUILT_THREADS").ok())
-        .map(|value_txt| value_txt.parse::<usize>())
-        .transpose().context("Parsing number of threads")?
-        .unwrap_or_else(rayon::current_num_threads);
+    // The patches are applied in memory before they change the files.
+    if let Some(provenance_path) = &provenance_path {
+        let mut writer = BufWriter::new(File::create(provenance_path)
+                                        .with_context(|| format!("Creating provenance file \"{}\"", provenance_path))?);
+        write_provenance(&config, &*arena, &mut writer)?;
+        writer.flush()
+            .with_context(|| format!("Writing provenance file \"{}\"", provenance_path))?;
+    }
+
+    if auto_tune && config.post_hook.is_none() && !config.interactive {
+        num_threads = tune_threads(&config, &|threads| build_push_arena(matches, threads), num_threads)?;
+        if verbosity >= Verbosity::Normal {
+            println!("Auto-tuned to {} threads.", num_threads);
+        }
+    }
 
-    let apply_result = if num_threads <= 1 {
+    // The post-hook needs the patches applied and saved one by one, the
+    // interactive prompt needs them applied one by one.
+    let apply_result = if num_threads <= 1 || config.post_hook.is_some() || config.interactive {
         apply_patches(&config, &*arena, &analyses)?
     } else {
         let pool = rayon::ThreadPoolBuilder::new()
@@ -242,29 +1595,279 @@ fn cmd_push<'a, F: Iterator<Item = &'a String>>(matches: &Matches, mut free_args
         pool.install(|| apply_patches_parallel(&config, &*arena, &analyses))?
     };
 
-    if !config.dry_run {
+    if let (Some(manifest), Some(manifest_path)) = (&manifest, &manifest_path) {
+        let mut writer = BufWriter::new(File::create(manifest_path)
+                                        .with_context(|| format!("Creating manifest \"{}\"", manifest_path))?);
+        if json_output {
+            manifest.write_json_to(&mut writer)?;
+        } else {
+            manifest.write_to(&mut writer)?;
+        }
+        writer.flush()
+            .with_context(|| format!("Writing manifest \"{}\"", manifest_path))?;
+    }
+
+    if let (Some(match_cache), Some(match_cache_path)) = (&match_cache, &match_cache_path) {
+        match_cache.save(Path::new(match_cache_path))
+            .with_context(|| format!("Saving match cache \"{}\"", match_cache_path))?;
+        if verbosity >= Verbosity::Verbose {
+            println!("{} file patches applied at the places from the match cache.", match_cache.hits());
+        }
+    }
+
+    write_buffered_messages(&mut io::stdout(), &mut io::stderr(), &apply_result.messages)?;
+    if verbosity >= Verbosity::Normal {
+        write_already_applied_report(&mut io::stdout(), &apply_result.already_applied_files)?;
+        write_filtered_files_report(&mut io::stdout(), &apply_result.filtered_files)?;
+        write_skipped_files_report(&mut io::stdout(), &apply_result.skipped_files)?;
+    }
+    write_forced_files_warning(&mut io::stderr(), &apply_result.forced_files)?;
+    if config.report_unchanged {
+        write_unchanged_files_report(&mut io::stdout(), &apply_result.unchanged_files)?;
+    }
+    eprint!("{}", apply_result.inline_rejects);
+
+    if stats {
+        println!("{}", arena.stats());
+        println!("{}", arena.syscall_stats());
+        if json_output {
+            write_patch_sizes_json(&mut io::stdout(), &apply_result.patch_sizes)?;
+        } else {
+            write_patch_sizes(&mut io::stdout(), &apply_result.patch_sizes)?;
+        }
+    }
+    if let Some(timings_top) = timings_top {
+        write_timings(&mut io::stdout(), &apply_result.patch_timings, apply_result.critical_path.as_ref(), timings_top)?;
+    }
+
+    if numstat {
+        let diff: Vec<u8> = apply_result.file_diffs.iter().flat_map(|file_diff| file_diff.diff.iter().copied()).collect();
+        write_diff_numstat(&mut io::stdout().lock(), &diff)?;
+    }
+    if emit_diff {
+        let stdout = io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+        for file_diff in &apply_result.file_diffs {
+            writer.write_all(&file_diff.diff)?;
+        }
+        writer.flush()?;
+    }
+
+    if let Some(out_tar) = &out_tar {
+        let mtime = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+        let changed_only = matches.opt_present("out-tar-changed");
+        if out_tar == "-" {
+            let stdout = io::stdout();
+            write_tree_tar(BufWriter::new(stdout.lock()), &*arena, &base_dir, &apply_result.changed_files, changed_only, mtime)
+                .context("Writing the tar archive to stdout")?;
+        } else {
+            let file = File::create(out_tar)
+                .with_context(|| format!("Creating tar archive \"{}\"", out_tar))?;
+            write_tree_tar(BufWriter::new(file), &*arena, &base_dir, &apply_result.changed_files, changed_only, mtime)
+                .with_context(|| format!("Writing tar archive \"{}\"", out_tar))?;
+        }
+    }
+
+    if !config.dry_run && config.out_dir.is_none() && !no_backup {
         save_applied_patches(&config, &config.series_patches[0..apply_result.applied_patches])
             .with_context(|| "When saving applied patches.")?;
     }
 
-    Ok(apply_result.skipped_patches == 0)
+    if summary_only {
+        write_summary(&mut io::stdout(), series_patches.len(), &apply_result)?;
+    }
+
+    Ok(if apply_result.skipped_patches == 0 {
+        Outcome::Success
+    } else if apply_result.rejected_inexact {
+        Outcome::Inexact
+    } else {
+        Outcome::Failed
+    })
+}
+
+/// Push all patches and keep pushing them whenever the series or the
+/// patches change. Runs until it is killed or watching fails.
+#[cfg(feature = "watch")]
+fn cmd_watch(matches: &Matches, verbosity: Verbosity) -> Result<bool> {
+    for option in ["out", "overlay-upper", "out-tar", "dry-run", "resume", "patches-url", "bundle", "no-backup", "roundtrip-check"] {
+        if matches.opt_present(option) {
+            bail_usage!("Can not use \"{}\" together with \"watch\".", option);
+        }
+    }
+
+    // The watched paths must not be empty.
+    let (base_dir, patches_path) = match tree_paths(matches) {
+        (base_dir, patches_path) if base_dir.as_os_str().is_empty() => (PathBuf::from("."), Path::new(".").join(patches_path)),
+        paths => paths,
+    };
+
+    let (series_patches, _) = read_series(matches, &base_dir, &patches_path)?;
+    let mut patch_watch = PatchWatch::new(&base_dir, &patches_path, &series_patches,
+                                          matches.opt_present("assume-unchanged"), verbosity)?;
+
+    let mut update = || {
+        let result = read_series(matches, &base_dir, &patches_path)
+            .and_then(|(series_patches, _)| {
+                patch_watch.update(&series_patches, &mut || Ok(push(matches, Goal::All, true, verbosity)? == Outcome::Success))
+            });
+        // Keep watching, the next change may fix it.
+        if let Err(err) = result {
+            for (i, cause) in err.chain().enumerate() {
+                eprintln!("{}{}", "  ".repeat(i), format!("{}", cause).red());
+            }
+        }
+        if verbosity >= Verbosity::Normal {
+            let relative = matches.opt_present("relative");
+            println!("Watching \"{}\" and \"{}\" for changes...",
+                     shown_path(relative, &base_dir, &base_dir.join("series")).display(),
+                     shown_path(relative, &base_dir, &patches_path).display());
+        }
+    };
+
+    update();
+    watch_series(&base_dir, &patches_path, &mut update)?;
+
+    Ok(true)
+}
+
+/// Open the log given by the "audit-log" option, if any.
+fn open_audit_log(matches: &Matches) -> Result<Option<AuditLog>> {
+    Ok(match matches.opt_str("audit-log") {
+        Some(path) => Some(AuditLog::open(Path::new(&path))
+                           .with_context(|| format!("Opening audit log \"{}\"", path))?),
+        None => None,
+    })
+}
+
+/// The time to set with the "touch" option: `SOURCE_DATE_EPOCH` if it is set,
+/// like in reproducible builds, otherwise the current time.
+fn touch_time(matches: &Matches) -> Result<Option<SystemTime>> {
+    if !matches.opt_present("touch") {
+        return Ok(None);
+    }
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(s) => match s.parse::<u64>() {
+            Ok(seconds) => Ok(Some(UNIX_EPOCH + Duration::from_secs(seconds))),
+            Err(_) => bail_usage!("Bad value given in \"SOURCE_DATE_EPOCH\" for \"touch\"!"),
+        },
+        Err(_) => Ok(Some(SystemTime::now())),
+    }
+}
+
+/// Build the map of path prefixes from the "prefix-map" options, if any were
+/// given.
+fn read_prefix_map(matches: &Matches) -> Result<Option<PrefixMap>> {
+    let mappings = matches.opt_strs("prefix-map");
+    if mappings.is_empty() {
+        return Ok(None);
+    }
+
+    let mut prefix_map = PrefixMap::new();
+    for mapping in mappings {
+        if !prefix_map.add(&mapping) {
+            bail_usage!("Bad value given to \"prefix-map\" parameter!");
+        }
+    }
+    Ok(Some(prefix_map))
+}
+
+/// Build the filter of files that the patches may touch from the "allow-file"
+/// globs and the paths listed in the "file-list" files, if any were given.
+fn read_allowed_files(matches: &Matches) -> Result<Option<FileFilter>> {
+    let globs = matches.opt_strs("allow-file");
+    let file_lists = matches.opt_strs("file-list");
+    if globs.is_empty() && file_lists.is_empty() {
+        return Ok(None);
+    }
+
+    let mut allowed_files = FileFilter::new();
+    for glob in globs {
+        if let Err(err) = allowed_files.include(&glob) {
+            bail_usage!("Bad pattern \"{}\": {}", glob, err);
+        }
+    }
+    for file_list in file_lists {
+        let content = fs::read_to_string(&file_list)
+            .with_context(|| format!("Reading file list \"{}\"", file_list))?;
+        for path in content.lines().filter(|line| !line.is_empty()) {
+            allowed_files.include_path(Path::new(path));
+        }
+    }
+    Ok(Some(allowed_files))
+}
+
+/// Parse the "max-file-size" parameter.
+fn max_file_size(matches: &Matches) -> Result<Option<u64>> {
+    match matches.opt_str("max-file-size") {
+        Some(s) => match s.parse::<u64>() {
+            Ok(size) => Ok(Some(size)),
+            Err(_) => bail_usage!("Bad value given to \"max-file-size\" parameter!"),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parse the "fuzz" parameter.
+fn fuzz(matches: &Matches) -> Result<usize> {
+    match matches.opt_str("fuzz") {
+        Some(s) => match s.parse::<usize>() {
+            Ok(fuzz) => Ok(fuzz),
+            Err(_) => bail_usage!("Bad value given to \"fuzz\" parameter!"),
+        },
+        None => Ok(0),
+    }
+}
+
+/// Parse the "io-retries" parameter.
+fn io_retry(matches: &Matches) -> Result<IoRetry> {
+    match matches.opt_str("io-retries") {
+        Some(s) => match s.parse::<usize>() {
+            Ok(retries) => Ok(IoRetry::new(retries)),
+            Err(_) => bail_usage!("Bad value given to \"io-retries\" parameter!"),
+        },
+        None => Ok(IoRetry::default()),
+    }
 }
 
 #[cfg(unix)]
-fn build_arena(use_mmap: bool) -> Box<dyn Arena> {
-    if use_mmap {
-        Box::new(MmapArena::new())
+fn build_arena(matches: &Matches) -> Result<Box<dyn Arena>> {
+    let max_file_size = max_file_size(matches)?;
+    let io_retry = io_retry(matches)?;
+    let direct_io = matches.opt_present("direct-io");
+    if matches.opt_present("mmap") {
+        if direct_io {
+            bail_usage!("Can not use \"direct-io\" together with \"mmap\".");
+        }
+        Ok(Box::new(MmapArena::new().with_max_file_size(max_file_size).with_io_retry(io_retry)))
     } else {
-        Box::new(FileArena::new())
+        Ok(Box::new(FileArena::new().with_max_file_size(max_file_size).with_io_retry(io_retry).with_direct_io(direct_io)))
     }
 }
 
 #[cfg(not(unix))]
-fn build_arena(use_mmap: bool) -> Box<Arena> {
-    if use_mmap {
+fn build_arena(matches: &Matches) -> Result<Box<dyn Arena>> {
+    let max_file_size = max_file_size(matches)?;
+    let io_retry = io_retry(matches)?;
+    let direct_io = matches.opt_present("direct-io");
+    if matches.opt_present("mmap") {
         panic!();
     } else {
-        Box::new(FileArena::new())
+        Ok(Box::new(FileArena::new().with_max_file_size(max_file_size).with_io_retry(io_retry).with_direct_io(direct_io)))
+    }
+}
+
+/// Build the arena for `push`. With `--arena per-thread`, every of the
+/// `num_threads` gets an arena of its own.
+fn build_push_arena(matches: &Matches, num_threads: usize) -> Result<Box<dyn Arena>> {
+    match matches.opt_str("arena").as_deref() {
+        None | Some("shared") => build_arena(matches),
+        Some("per-thread") => {
+            // One more for loading outside of the thread pool
+            let arenas = (0..=num_threads).map(|_| build_arena(matches)).collect::<Result<_>>()?;
+            Ok(Box::new(PerThreadArena::new(arenas)))
+        }
+        Some(_) => bail_usage!("Bad value given to \"arena\" parameter!"),
     }
 }
 
@@ -277,33 +1880,128 @@ fn build_arena(use_mmap: bool) -> Box<Arena> {
 // not stable yet.
 //
 // TODO: Use the `std::process::Termination` trait once it is stable.
-pub fn run<A: IntoIterator>(args: A) -> Result<bool> where A::Item: AsRef<OsStr>
+pub fn run_with_outcome<A: IntoIterator>(args: A) -> Result<Outcome> where A::Item: AsRef<OsStr>
 {
     let mut opts = Options::new();
-    opts.optflag("a", "all", "apply all patches in series");
+    opts.optflag("a", "all", "push or pop all patches in series (with `grep`: search only added lines)");
+    opts.optopt("", "count", "with `push` and `pop`: push or pop this many patches, like the number argument", "<n>");
+    opts.optflag("y", "yes", "with `pop`: pop all applied patches without asking for confirmation on a terminal");
+    opts.optflag("l", "files-with-matches", "with `grep`: print only names of matching patches");
     opts.optopt("d", "directory", "working directory", "DIR");
     opts.optopt("p", "patch-directory", "directory with patches (default: \"patches\")", "DIR");
+    opts.optopt("", "patches-url", "with `push`: load the \"series\" file and the patches from this ssh://, http:// or https:// URL instead", "URL");
+    opts.optflag("", "bundle", "with `push`: read the \"series\" file and the patches from a bundle on the standard input instead");
+    opts.optopt("", "out", "with `push`: write the patched files into DIR and leave the working directory unchanged", "DIR");
+    opts.optopt("", "overlay-upper", "with `push`: like `--out`, but also mark deleted files with whiteout files \".wh.<name>\", for use as an overlay upper layer", "DIR");
+    opts.optopt("", "out-tar", "with `push`: write the patched tree as a tar archive to this file (\"-\" for stdout) instead of saving it", "FILE");
+    opts.optflag("", "out-tar-changed", "with `push --out-tar`: put only the files touched by the patches into the archive");
     opts.optopt("b", "backup", "create backup files for `quilt pop` (default: onfail)", "always|onfail|never");
     opts.optopt("", "backup-count", "amount of backup files for `quilt pop` to create (default: 100)", "all|<n>");
+    opts.optflag("", "no-backup", "with `push`: write nothing into \".pc\", neither backup files nor the applied patches. The patches can not be popped or refreshed then");
+    opts.optflag("", "resume", "with `push`: mark the next patch, fixed by hand after a failure, as applied and continue after it");
+    opts.optflag("", "dump-series-order", "with `push`: print the patches in the order they would be applied, with the skipped ones commented out, and apply nothing");
+    opts.optflag("", "allow-duplicates", "only warn about patches that are in the series twice, instead of failing");
+    opts.optflag("", "no-series", "if there is no \"series\" file, use all *.patch and *.diff files from the patch directory");
+    opts.optopt("", "sort", "order of the patches with `--no-series` (default: name)", "name|mtime");
+    opts.optmulti("", "guard", "select the series lines with guards on this symbol. You can use this option multiple times", "SYMBOL");
+    opts.optmulti("", "include", "with `push`: patch only files matching the glob. You can use this option multiple times", "GLOB");
+    opts.optmulti("", "exclude", "with `push`: do not patch files matching the glob. You can use this option multiple times", "GLOB");
+    opts.optmulti("", "allow-file", "with `push`: fail if a patch touches a file not matching any of these globs. You can use this option multiple times", "GLOB");
+    opts.optmulti("", "file-list", "with `push`: fail if a patch touches a file not listed in this file, one path per line", "FILE");
     opts.optopt("F", "fuzz", "maximal allowed fuzz (default: 0)", "<n>");
+    opts.optopt("", "max-offset", "search for hunks at most this many lines away from where they are expected (default: 10000)", "unlimited|<n>");
+    opts.optflag("", "strict-ambiguity", "fail hunks that match equally far before and after where they are expected, instead of using the later match");
+    opts.optflag("", "unordered-hunks", "apply hunks that match before the previous hunk of their file, if they do not change the same lines");
+    opts.optflag("", "strict", "fail hunks that apply only with fuzz or offset, so the patches must be refreshed");
+    opts.optopt("", "min-parallel-hunks", "search for the hunks of a file in parallel only if it has at least this many (default: 64)", "<n>");
+    opts.optopt("", "min-file-size", "search for the hunks of a file in parallel only if it has at least this many bytes (default: 65536)", "<bytes>");
+    opts.optflag("", "posix", "like `patch --posix`: leave files deleted by patches as empty files");
+    opts.optflag("", "verify-index", "warn about files that do not match the blob hashes in the \"index\" lines of git patches");
+    opts.optflag("", "force", "write hunks even if their context does not match. Dangerous, the result must be reviewed");
+    opts.optflag("", "reverse-if-applied", "skip files that are already patched (the patch can be reverted) instead of failing");
+    opts.optflag("", "interactive", "with `push`: ask what to do with every file that fails to patch. Only on a terminal");
+    opts.optopt("", "patch-timeout", "fail a patch if applying it takes longer than this", "<secs>");
+    opts.optopt("", "lazy-load", "read only the first <MiB> of bigger files, the rest only when a patch needs it", "<MiB>");
+    opts.optopt("", "max-file-size", "fail to patch files bigger than <bytes> instead of loading them (default: unlimited)", "<bytes>");
+    opts.optopt("", "io-retries", "retry opening and reading files up to <n> times after transient errors (EINTR, EAGAIN, ESTALE), \
+                                   waiting longer every time (default: 0)", "<n>");
+    opts.optflag("", "show-rejects-inline", "print the rejected hunks to stderr");
+    opts.optflag("", "no-rej-files", "do not save the rejected hunks into \".rej\" files");
+    opts.optopt("", "reject-dir", "with `push`: save the \".rej\" files into DIR, at the same relative paths as the patched files", "DIR");
+    opts.optopt("", "reject-suffix", "with `push`: name the reject files by appending this to the name of the patched file (default: .rej)", "SUFFIX");
+    opts.optflag("", "function-context", "for failed hunks, find the function named in the hunk header in the file");
+    opts.optopt("", "post-hook", "run the shell command after every applied patch. The patch fails if the command fails", "CMD");
     opts.optopt("", "color", "use colors in output (default: auto)", "always|auto|never");
+    opts.optflag("", "auto-strip", "with `push`: if the files of a patch are not found, try strip levels 0 to 2");
+    opts.optflag("", "unsafe-paths", "allow patching files outside of the working directory, through \"..\" or symlinks");
+    opts.optflag("", "relative", "make absolute paths into the working directory relative to it, in the patches and all messages");
+    opts.optmulti("", "prefix-map", "with `push`: rewrite the leading directories OLD of the paths in the patches to NEW. You can use this option multiple times", "OLD=NEW");
+    opts.optflag("", "follow-symlinks", "patch the files that symlinks point to, instead of the symlinks themselves");
     opts.optflag("", "dry-run", "do not save any changes");
+    opts.optflag("", "emit-diff", "with `push --dry-run`: print the net change of the applied patches as a single patch");
+    opts.optflag("", "numstat", "with `push --dry-run`, `diff --snapshot` and `squash`: print the added and removed lines of every changed file, like `git diff --numstat`");
+    opts.optflag("", "roundtrip-check", "with `push`: apply the patches and revert them in memory, fail if the files do not come back exactly as they were. Nothing is saved");
     opts.optmulti("A", "analyze", "run additional analysis while patching. You can use this option multiple times to run multiple analyses at once. Available analyses: multiapply", "ANALYSIS"); // TODO: Don't hardcoded the list of available analyses?
-    opts.optopt("", "threads", "number of parallel threads", "NUM");
-    opts.optflag("", "stats", "print statistics in the end");
+    opts.optopt("", "threads", "number of parallel threads, or \"auto-tune\" to measure which number applies the first patches the fastest", "NUM|auto-tune");
+    opts.optopt("", "arena", "with `push`: load the files into one arena shared by the threads, or into an arena per thread \
+                              that needs no lock (default: shared)", "shared|per-thread");
+    opts.optflag("", "deterministic", "print the output from parallel threads in series order, so it is the same every time");
+    opts.optflag("", "stats", "print statistics in the end, with the hunks and changed bytes of every applied patch");
+    opts.optflagopt("", "timings", "with `push`: print the <n> slowest patches in the end (default: 10), and the longest chain of patches that one thread must apply", "<n>");
+    opts.optflag("", "ignore-whitespace", "with `verify`: lines that differ only in whitespace are equal");
+    opts.optopt("", "reference", "with `reconstruct-pc`: the pristine tree, as it was before the patches, to rebuild the backups from", "DIR");
+    opts.optflag("", "from-diff", "with `apply`: apply the changes between the two git commits given after the options");
+    opts.optopt("", "base-ref", "with `verify`: compare with the files of this git commit instead of a reference directory", "COMMIT");
+    opts.optflag("", "report-unchanged", "with `push`: list the files that a patch left byte-identical");
+    opts.optopt("", "audit-log", "append a JSON record of every file change to this file", "FILE");
+    opts.optopt("", "manifest", "with `push`: write the list of created, modified and deleted files to this file", "FILE");
+    opts.optopt("", "provenance", "with `push`: write which patch and hunk last changed every line of the patched files to this file, as JSON", "FILE");
+    opts.optopt("", "match-cache", "with `push`: remember in this file where the hunks matched, to apply them there again when the patches and files did not change", "FILE");
+    opts.optflag("", "find-renames", "with `push`: patch a file with the lines that the patch expects if the file it names is missing, e.g. because it was renamed");
+    opts.optflag("", "repair", "rebuild \".pc/applied-patches\" from the backups in \".pc\" before the command, or alone");
+    opts.optflag("", "list-touched-across-series", "list the files touched by more than one patch of the series, with the patches touching them");
+    opts.optflag("", "assume-unchanged", "with `watch`: do not read the applied patches to find out if they were edited, only push the patches after them");
+    opts.optflag("", "snapshot", "with `diff`: show the changes since the last `snapshot`");
+    opts.optopt("", "format", "with `status`, `--manifest`, `--stats` and `--list-touched-across-series`: output format (default: text)", "text|json");
     opts.optflag("q", "quiet", "only print errors");
+    opts.optflag("", "summary-only", "with `push`: print only the failures and a line with the totals in the end");
     opts.optflagmulti("v", "verbose", "print extra information. Repeat for more verbosity. It may affect performance.");
 
+    #[cfg(unix)]
+    opts.optflag("", "preserve-ownership", "give the patched files the owner and group of the original files");
+    opts.optflag("", "preserve-hard-links", "write patched files with hard links into them, so all links change, instead of replacing them");
+    opts.optflag("", "touch", "set the modification time of the patched files to $SOURCE_DATE_EPOCH, or to now");
+
     #[cfg(unix)]
     opts.optflag("", "mmap", "mmap files instead of reading into buffers. This may reduce memory usage and improve \
                               performance in some cases. Warning: You must ensure that no external program will modify the \
                               files while rapidquilt is running, otherwise you may get incorrect results or even crash.");
+    opts.optflag("", "direct-io", "read files with O_DIRECT, so they do not evict other data from the page cache, \
+                                   where the filesystem supports it (Linux only)");
+
+    // Hidden, see `HIDDEN_OPTIONS`
+    opts.optopt("", "dump-parsed", "print the hunks parsed from the patch, with `--format json` as JSON", "PATCH");
 
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("", "version", "print version");
 
 
-    let matches = opts.parse(args)?;
+    let args: Vec<OsString> = args.into_iter().map(|arg| arg.as_ref().to_os_string()).collect();
+    // The value of "timings" must be attached, "--timings 5" would push 5
+    // patches instead.
+    if args.windows(2).any(|pair| pair[0] == "--timings" && pair[1].to_str().is_some_and(|s| s.parse::<usize>().is_ok())) {
+        bail_usage!("Give the number of patches for \"timings\" as \"--timings=<n>\".");
+    }
+    let mut matches = opts.parse(&args)?;
+
+    // The defaults from ".rapidquiltrc" go first, the command line overrides them.
+    let (base_dir, _) = tree_paths(&matches);
+    if let Some(rc_path) = find_rc_file(&base_dir) {
+        let rc_options = read_rc_options(&opts, &rc_path, &matches)?;
+        if !rc_options.is_empty() {
+            matches = opts.parse(rc_options.into_iter().map(OsString::from).chain(args))?;
+        }
+    }
     let mut free_args = matches.free.iter();
 
     if matches.opt_present("version") {
@@ -311,13 +2009,17 @@ pub fn run<A: IntoIterator>(args: A) -> Result<bool> where A::Item: AsRef<OsStr>
     }
 
     if matches.opt_present("help") {
-        usage(&opts);
+        usage(&opts, EXIT_SUCCESS);
+    }
+
+    if let Some(path) = matches.opt_str("dump-parsed") {
+        return cmd_dump_parsed(&matches, &path).map(Outcome::from);
     }
 
     match matches.opt_str("color") {
         Some(ref s) if s == "always" => colored::control::set_override(true),
         Some(ref s) if s == "never" => colored::control::set_override(false),
-        Some(ref s) if s != "auto" => bail!("Bad value given to \"color\" parameter!"),
+        Some(ref s) if s != "auto" => bail_usage!("Bad value given to \"color\" parameter!"),
         _ /* auto */ => {
             // Force it off if either of the outputs is not terminal. Otherwise leave on default,
             // which uses some env variables.
@@ -338,12 +2040,86 @@ pub fn run<A: IntoIterator>(args: A) -> Result<bool> where A::Item: AsRef<OsStr>
         Verbosity::Normal
     };
 
-    match free_args.next() {
+    if matches.opt_present("repair") {
+        repair_applied(&matches, verbosity)?;
+        if matches.free.is_empty() {
+            return Ok(Outcome::Success);
+        }
+    }
+
+    if matches.opt_present("list-touched-across-series") {
+        return cmd_list_touched_across_series(&matches, verbosity).map(Outcome::from);
+    }
+
+    let ok = match free_args.next() {
         Some(cmd) if cmd == "push" => {
-            cmd_push(&matches, free_args, verbosity)
+            return cmd_push(&matches, free_args, verbosity);
+        }
+        Some(cmd) if cmd == "pop" => {
+            cmd_pop(&matches, free_args, verbosity)
+        }
+        Some(cmd) if cmd == "edit" => {
+            cmd_edit(&matches, free_args, verbosity)
+        }
+        Some(cmd) if cmd == "status" => {
+            cmd_status(&matches)
+        }
+        Some(cmd) if cmd == "snapshot" => {
+            cmd_snapshot(&matches, verbosity)
+        }
+        Some(cmd) if cmd == "diff" => {
+            cmd_diff(&matches, verbosity)
+        }
+        Some(cmd) if cmd == "grep" => {
+            cmd_grep(&matches, free_args)
+        }
+        Some(cmd) if cmd == "normalize" => {
+            cmd_normalize(&matches, free_args, verbosity)
+        }
+        Some(cmd) if cmd == "squash" => {
+            cmd_squash(&matches, free_args, verbosity)
+        }
+        Some(cmd) if cmd == "verify" => {
+            cmd_verify(&matches, free_args, verbosity)
+        }
+        Some(cmd) if cmd == "export-mbox" => {
+            cmd_export_mbox(&matches, free_args, verbosity)
+        }
+        Some(cmd) if cmd == "explain" => {
+            cmd_explain(&matches, free_args)
+        }
+        Some(cmd) if cmd == "parse-check" => {
+            cmd_parse_check(&matches, verbosity)
+        }
+        Some(cmd) if cmd == "reconstruct-pc" => {
+            cmd_reconstruct_pc(&matches, verbosity)
+        }
+        #[cfg(feature = "git")]
+        Some(cmd) if cmd == "apply" => {
+            cmd_apply(&matches, free_args, verbosity)
+        }
+        #[cfg(not(feature = "git"))]
+        Some(cmd) if cmd == "apply" => {
+            bail_usage!("This rapidquilt was built without the \"git\" feature.");
+        }
+        #[cfg(feature = "watch")]
+        Some(cmd) if cmd == "watch" => {
+            cmd_watch(&matches, verbosity)
+        }
+        #[cfg(not(feature = "watch"))]
+        Some(cmd) if cmd == "watch" => {
+            bail_usage!("This rapidquilt was built without the \"watch\" feature.");
         }
         _ => {
-            usage(&opts);
+            usage(&opts, EXIT_USAGE);
         }
-    }
+    }?;
+    Ok(ok.into())
+}
+
+/// Like `run_with_outcome`, but only tells whether the command succeeded.
+#[cfg(test)]
+pub fn run<A: IntoIterator>(args: A) -> Result<bool> where A::Item: AsRef<OsStr>
+{
+    Ok(run_with_outcome(args)? == Outcome::Success)
 }
@@ -0,0 +1,190 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Dump of a parsed patch, for finding out why it misapplies.
+//!
+//! It shows what the parser made of the patch: the files with their kind,
+//! rename flag, modes and hashes, and the hunks with their ranges and lines.
+//! The line numbers are counted from 0, as used when applying. The lines of
+//! a hunk are listed as the context before, the removed lines, the added
+//! lines and the context after, whatever order the patch had them in.
+//! Binary patches are refused by the parser, there is nothing to dump.
+
+use std::borrow::Cow;
+use std::fs::Permissions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use colored::*;
+
+use libpatch::patch::{FilePatchKind, TextHunk, TextPatch};
+
+use crate::json;
+
+/// The kind of a line in a hunk
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LineKind {
+    Context,
+    Remove,
+    Add,
+}
+
+impl LineKind {
+    fn name(self) -> &'static str {
+        match self {
+            LineKind::Context => "context",
+            LineKind::Remove => "remove",
+            LineKind::Add => "add",
+        }
+    }
+
+    fn marker(self) -> char {
+        match self {
+            LineKind::Context => ' ',
+            LineKind::Remove => '-',
+            LineKind::Add => '+',
+        }
+    }
+}
+
+/// The lines of the `hunk` in order: context before, removed, added and
+/// context after.
+fn hunk_lines<'a>(hunk: &TextHunk<'a>) -> Vec<(LineKind, &'a [u8])> {
+    let remove = &hunk.remove.content[..];
+    let add = &hunk.add.content[..];
+    let prefix = hunk.prefix_context;
+    let suffix = hunk.suffix_context;
+
+    let mut lines = Vec::with_capacity(remove.len() + add.len() - prefix - suffix);
+    lines.extend(remove[..prefix].iter().map(|line| (LineKind::Context, *line)));
+    lines.extend(remove[prefix..remove.len() - suffix].iter().map(|line| (LineKind::Remove, *line)));
+    lines.extend(add[prefix..add.len() - suffix].iter().map(|line| (LineKind::Add, *line)));
+    lines.extend(remove[remove.len() - suffix..].iter().map(|line| (LineKind::Context, *line)));
+    lines
+}
+
+fn kind_name(kind: FilePatchKind) -> &'static str {
+    match kind {
+        FilePatchKind::Modify => "modify",
+        FilePatchKind::Create => "create",
+        FilePatchKind::Delete => "delete",
+    }
+}
+
+/// The mode of the `permissions` in octal.
+#[cfg(unix)]
+pub fn mode(permissions: &Permissions) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    format!("{:o}", permissions.mode())
+}
+
+#[cfg(not(unix))]
+pub fn mode(permissions: &Permissions) -> String {
+    (if permissions.readonly() { "readonly" } else { "writable" }).to_string()
+}
+
+/// The `filename` quoted, or "none".
+fn filename_text(filename: Option<&Cow<Path>>) -> String {
+    match filename {
+        Some(filename) => format!("{:?}", filename),
+        None => "none".to_string(),
+    }
+}
+
+/// The object `hash` of an "index" line as text.
+fn hash_text(hash: Option<&[u8]>) -> Option<Cow<'_, str>> {
+    hash.map(String::from_utf8_lossy)
+}
+
+/// Write the parsed `patch` for people.
+pub fn write_dump<W: Write>(writer: &mut W, patch: &TextPatch) -> Result<(), io::Error> {
+    for warning in &patch.warnings {
+        writeln!(writer, "{} {}", "Warning:".yellow(), warning)?;
+    }
+
+    for file_patch in &patch.file_patches {
+        writeln!(writer, "{} {} -> {}", "File:".yellow(),
+                 filename_text(file_patch.old_filename()), filename_text(file_patch.new_filename()))?;
+        writeln!(writer, "  {} {}", "Kind:".yellow(), kind_name(file_patch.kind()))?;
+        writeln!(writer, "  {} {}", "Rename:".yellow(), if file_patch.is_rename() { "yes" } else { "no" })?;
+        writeln!(writer, "  {} {} -> {}", "Mode:".yellow(),
+                 file_patch.old_permissions().map_or("none".to_string(), mode),
+                 file_patch.new_permissions().map_or("none".to_string(), mode))?;
+        if file_patch.old_hash().is_some() || file_patch.new_hash().is_some() {
+            writeln!(writer, "  {} {} -> {}", "Index:".yellow(),
+                     hash_text(file_patch.old_hash()).unwrap_or("none".into()),
+                     hash_text(file_patch.new_hash()).unwrap_or("none".into()))?;
+        }
+
+        for (i, hunk) in file_patch.hunks().iter().enumerate() {
+            write!(writer, "  {} {}: old line {}, {} lines; new line {}, {} lines; context {} before, {} after",
+                   "Hunk".yellow(), i + 1,
+                   hunk.remove.target_line, hunk.remove.content.len(),
+                   hunk.add.target_line, hunk.add.content.len(),
+                   hunk.prefix_context, hunk.suffix_context)?;
+            if !hunk.function.is_empty() {
+                write!(writer, "; function {:?}", String::from_utf8_lossy(hunk.function))?;
+            }
+            writeln!(writer)?;
+
+            // Quoted, so the line endings can be seen
+            for (kind, line) in hunk_lines(hunk) {
+                writeln!(writer, "    {} {:?}", kind.marker(), String::from_utf8_lossy(line))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the parsed `patch` as a JSON object.
+pub fn write_dump_json<W: Write>(writer: &mut W, patch: &TextPatch) -> Result<(), io::Error> {
+    writer.write_all(b"{\"warnings\":[")?;
+    for (i, warning) in patch.warnings.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        json::write_str(writer, warning)?;
+    }
+
+    writer.write_all(b"],\"files\":[")?;
+    for (i, file_patch) in patch.file_patches.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(b"{\"old_filename\":")?;
+        json::write_opt_str(writer, file_patch.old_filename().map(|filename| filename.to_string_lossy()).as_deref())?;
+        writer.write_all(b",\"new_filename\":")?;
+        json::write_opt_str(writer, file_patch.new_filename().map(|filename| filename.to_string_lossy()).as_deref())?;
+        write!(writer, ",\"kind\":\"{}\",\"rename\":{},\"old_mode\":", kind_name(file_patch.kind()), file_patch.is_rename())?;
+        json::write_opt_str(writer, file_patch.old_permissions().map(mode).as_deref())?;
+        writer.write_all(b",\"new_mode\":")?;
+        json::write_opt_str(writer, file_patch.new_permissions().map(mode).as_deref())?;
+        writer.write_all(b",\"old_hash\":")?;
+        json::write_opt_str(writer, hash_text(file_patch.old_hash()).as_deref())?;
+        writer.write_all(b",\"new_hash\":")?;
+        json::write_opt_str(writer, hash_text(file_patch.new_hash()).as_deref())?;
+
+        writer.write_all(b",\"hunks\":[")?;
+        for (j, hunk) in file_patch.hunks().iter().enumerate() {
+            if j > 0 {
+                writer.write_all(b",")?;
+            }
+            write!(writer, "{{\"old_line\":{},\"old_count\":{},\"new_line\":{},\"new_count\":{},\"prefix_context\":{},\"suffix_context\":{},\"function\":",
+                   hunk.remove.target_line, hunk.remove.content.len(),
+                   hunk.add.target_line, hunk.add.content.len(),
+                   hunk.prefix_context, hunk.suffix_context)?;
+            json::write_str(writer, &String::from_utf8_lossy(hunk.function))?;
+            writer.write_all(b",\"lines\":[")?;
+            for (k, (kind, line)) in hunk_lines(hunk).into_iter().enumerate() {
+                if k > 0 {
+                    writer.write_all(b",")?;
+                }
+                write!(writer, "{{\"type\":\"{}\",\"text\":", kind.name())?;
+                json::write_str(writer, &String::from_utf8_lossy(line))?;
+                writer.write_all(b"}")?;
+            }
+            writer.write_all(b"]}")?;
+        }
+        writer.write_all(b"]}")?;
+    }
+    writeln!(writer, "]}}")
+}
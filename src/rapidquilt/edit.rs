@@ -0,0 +1,97 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements adding files to the top patch and opening them in
+//! an editor, like `quilt add` and `quilt edit`.
+//!
+//! Adding a file saves its current content as a quilt backup file in
+//! ".pc/<top patch>" (or another `BackupStore`), so popping the patch
+//! restores it. A file that does not exist yet gets an empty backup file.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+use libpatch::modified_file::ModifiedFile;
+
+use crate::apply::{BackupStore, Verbosity, backup_names};
+use crate::pop::read_applied_patches;
+
+/// Save the backup of one file at `path` for the patch with `patch_filename`
+/// into the `backup_store`.
+fn add_file(backup_store: &dyn BackupStore, patch_filename: &Path, filename: &Path, path: &Path) -> Result<(), io::Error> {
+    let data;
+    let original_file = match fs::symlink_metadata(path) {
+        Ok(metadata) => {
+            data = if metadata.file_type().is_symlink() {
+                fs::read_link(path)?.into_os_string().into_encoded_bytes()
+            } else if metadata.is_file() {
+                fs::read(path)?
+            } else {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+            };
+            ModifiedFile::new(&data, true, Some(metadata.permissions()))
+        }
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => ModifiedFile::new_non_existent(),
+        Err(error) => return Err(error),
+    };
+    backup_store.save(patch_filename, filename, &original_file)
+}
+
+/// Add the `filenames`, relative to the `base_dir`, to the top patch.
+/// Returns the name of the top patch.
+pub fn add_files(base_dir: &Path, backup_store: &dyn BackupStore, filenames: &[PathBuf], verbosity: Verbosity) -> Result<PathBuf> {
+    let mut applied_patches = read_applied_patches(base_dir)?;
+    let backup_name = backup_names(applied_patches.iter().map(PathBuf::as_path)).pop();
+    let (Some(top_patch), Some(backup_name)) = (applied_patches.pop(), backup_name) else {
+        bail!("No patches applied, there is no patch to add the files to.");
+    };
+    let mut backed_up = backup_store.list(&backup_name)
+        .with_context(|| format!("Reading backup files of patch {}", top_patch.display()))?
+        .unwrap_or_default();
+
+    for filename in filenames {
+        if !filename.components().all(|component| matches!(component, Component::Normal(_))) {
+            bail!("Can not add file {}, it must be a relative path without \"..\".", filename.display());
+        }
+        if filename.starts_with(".pc") {
+            bail!("Can not add file {}, it is in the \".pc\" directory.", filename.display());
+        }
+
+        let added = !backed_up.contains(filename);
+        if added {
+            add_file(backup_store, &backup_name, filename, &base_dir.join(filename))
+                .with_context(|| format!("Adding file {} to patch {}", filename.display(), top_patch.display()))?;
+            backed_up.push(filename.clone());
+        }
+
+        if verbosity >= Verbosity::Normal {
+            if added {
+                println!("File {} added to patch {}", filename.display(), top_patch.display());
+            } else {
+                println!("File {} is already in patch {}", filename.display(), top_patch.display());
+            }
+        }
+    }
+
+    Ok(top_patch)
+}
+
+/// Open the `filenames` in the `editor`. Like quilt, the editor command is
+/// split into words by the shell, so it may contain arguments.
+pub fn launch_editor(editor: &str, base_dir: &Path, filenames: &[PathBuf]) -> Result<bool> {
+    let mut command = Command::new("sh");
+    command.arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(editor)
+        .args(filenames);
+    if !base_dir.as_os_str().is_empty() {
+        command.current_dir(base_dir);
+    }
+
+    let status = command.status()
+        .with_context(|| format!("Launching editor \"{}\"", editor))?;
+    Ok(status.success())
+}
@@ -0,0 +1,96 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `explain <patch>`, a summary of what a patch does
+//! for people who do not read diffs every day.
+//!
+//! Every file of the patch gets one line: whether it is created, deleted,
+//! renamed or modified, its mode change and how many hunks and lines it adds
+//! and removes. Git patches of binary files ("Binary files ... differ") have
+//! no hunks, they are listed as binary changes, which are not applied.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::Path;
+
+use libpatch::patch::{FilePatchKind, TextFilePatch, TextPatch};
+
+use crate::apply::SeriesPatch;
+use crate::dump::mode;
+
+/// The numbers of hunks, added and removed lines
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Changes {
+    pub hunks: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl Changes {
+    pub fn of(file_patch: &TextFilePatch) -> Self {
+        let mut changes = Changes::default();
+        for hunk in file_patch.hunks() {
+            let context = hunk.prefix_context + hunk.suffix_context;
+            changes.hunks += 1;
+            changes.added += hunk.add.content.len() - context;
+            changes.removed += hunk.remove.content.len() - context;
+        }
+        changes
+    }
+}
+
+/// "1 line", "2 lines", ...
+fn count_text(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+/// Whether the `file_patch` changes a binary file, git leaves out the
+/// changes of those.
+pub fn is_binary(file_patch: &TextFilePatch) -> bool {
+    file_patch.hunks().is_empty() && file_patch.old_hash().is_some() && file_patch.old_hash() != file_patch.new_hash()
+}
+
+/// What the `file_patch` does with its file, e.g. "Renamed a.c to b.c".
+fn file_action(file_patch: &TextFilePatch) -> String {
+    let filename = |filename: Option<&Cow<Path>>| filename.map_or("?".into(), |filename| filename.display().to_string());
+    let old_filename = filename(file_patch.old_filename());
+    let new_filename = filename(file_patch.new_filename());
+
+    match file_patch.kind() {
+        FilePatchKind::Create => format!("Created {}", new_filename),
+        FilePatchKind::Delete => format!("Deleted {}", old_filename),
+        FilePatchKind::Modify if file_patch.is_rename() => format!("Renamed {} to {}", old_filename, new_filename),
+        FilePatchKind::Modify if is_binary(file_patch) => format!("Binary change of {}, not applied", new_filename),
+        FilePatchKind::Modify => format!("Modified {}", new_filename),
+    }
+}
+
+/// Write the explanation of the `patch` of the `series_patch`. The patch is
+/// explained as it is written, also if the series applies it reversed.
+pub fn write_explanation<W: Write>(writer: &mut W, series_patch: &SeriesPatch, patch: &TextPatch) -> Result<(), io::Error> {
+    let changes: Vec<Changes> = patch.file_patches.iter().map(Changes::of).collect();
+    writeln!(writer, "Patch {}{} changes {} with {}, {} added and {} removed.",
+             series_patch.filename.display(),
+             if series_patch.reverse { " (applied reversed)" } else { "" },
+             count_text(patch.file_patches.len(), "file"),
+             count_text(changes.iter().map(|changes| changes.hunks).sum(), "hunk"),
+             count_text(changes.iter().map(|changes| changes.added).sum(), "line"),
+             changes.iter().map(|changes| changes.removed).sum::<usize>())?;
+    for warning in &patch.warnings {
+        writeln!(writer, "  Warning: {}", warning)?;
+    }
+
+    for (file_patch, changes) in patch.file_patches.iter().zip(&changes) {
+        write!(writer, "  {}", file_action(file_patch))?;
+        match (file_patch.kind(), file_patch.old_permissions(), file_patch.new_permissions()) {
+            (FilePatchKind::Create, _, Some(new)) => write!(writer, ", mode {}", mode(new))?,
+            (FilePatchKind::Modify, Some(old), Some(new)) if old != new => write!(writer, ", mode {} to {}", mode(old), mode(new))?,
+            _ => {}
+        }
+        if changes.hunks > 0 {
+            write!(writer, ": {}, {} added and {} removed",
+                   count_text(changes.hunks, "hunk"), count_text(changes.added, "line"), changes.removed)?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Selection of files to patch by glob patterns, like
+//! `git apply --include/--exclude`.
+//!
+//! The rules are tried in the order they were given and the first one that
+//! matches decides. If none matches, the file is included, unless there are
+//! some include rules.
+
+use std::path::Path;
+
+use regex::Regex;
+
+#[derive(Debug)]
+struct FilterRule {
+    include: bool,
+    regex: Regex,
+}
+
+#[derive(Debug, Default)]
+pub struct FileFilter {
+    rules: Vec<FilterRule>,
+}
+
+/// Translate glob pattern to regex. `*` matches any string, including "/",
+/// `?` any single character and `[...]` any character from the set.
+fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::with_capacity(glob.len() * 2 + 2);
+    pattern.push('^');
+
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            '[' => {
+                pattern.push('[');
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    pattern.push('^');
+                }
+                // "]" right after the opening bracket is part of the set
+                if chars.next_if_eq(&']').is_some() {
+                    pattern.push_str("\\]");
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    // Escape what has special meaning inside of regex classes
+                    if matches!(c, '\\' | '[' | '&' | '~') {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                }
+                pattern.push(']');
+            }
+            c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    pattern.push('$');
+    Regex::new(&pattern)
+}
+
+impl FileFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add rule that includes files matching the `glob`.
+    pub fn include(&mut self, glob: &str) -> Result<(), regex::Error> {
+        self.rules.push(FilterRule { include: true, regex: glob_to_regex(glob)? });
+        Ok(())
+    }
+
+    /// Add rule that includes exactly the file at `path`.
+    pub fn include_path(&mut self, path: &Path) {
+        let pattern = format!("^{}$", regex::escape(&path.to_string_lossy()));
+        // NOTE(unwrap): The escaped path is always a valid regex.
+        self.rules.push(FilterRule { include: true, regex: Regex::new(&pattern).unwrap() });
+    }
+
+    /// Add rule that excludes files matching the `glob`.
+    pub fn exclude(&mut self, glob: &str) -> Result<(), regex::Error> {
+        self.rules.push(FilterRule { include: false, regex: glob_to_regex(glob)? });
+        Ok(())
+    }
+
+    /// Returns true if the file at `path` should be patched.
+    pub fn is_included(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy();
+        match self.rules.iter().find(|rule| rule.regex.is_match(&path)) {
+            Some(rule) => rule.include,
+            None => !self.rules.iter().any(|rule| rule.include),
+        }
+    }
+}
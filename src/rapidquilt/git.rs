@@ -0,0 +1,147 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Files of a git commit, for `verify --base-ref <commit>`, and the diff of
+//! two commits, for `apply --from-diff <commit> <commit>`.
+//!
+//! The files are read with the `git` command: the commit is resolved once
+//! with `git rev-parse` and the files are read from it one by one through a
+//! single `git cat-file --batch`, so comparing many files does not start a
+//! process for every one of them.
+//!
+//! The `git2` crate is not used on purpose: it builds and links libgit2 (and
+//! OpenSSL with it), which the offline and vendored builds of rapidquilt do
+//! not have. The `git` command is there wherever there is a git repository.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use libpatch::modified_file::ModifiedFile;
+
+use crate::apply::ReferenceTree;
+use crate::arena::Arena;
+
+/// The `base_dir` to run git in, "." for the current directory.
+fn git_dir(base_dir: &Path) -> &Path {
+    if base_dir.as_os_str().is_empty() { Path::new(".") } else { base_dir }
+}
+
+/// The full object name of the commit named `reference` (a hash, branch,
+/// tag, ...) of the repository with the `base_dir`.
+fn resolve_commit(base_dir: &Path, reference: &str) -> io::Result<String> {
+    let output = Command::new("git")
+        .arg("-C").arg(base_dir)
+        .args(["rev-parse", "--verify", "--quiet", "--end-of-options"])
+        .arg(format!("{}^{{commit}}", reference))
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::NotFound,
+                                  format!("\"{}\" is not a commit of the git repository in \"{}\"", reference, base_dir.display())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The changes from the commit named `from_ref` to the one named `to_ref` in
+/// the repository with the `base_dir`, as a patch to apply with "-p1".
+///
+/// Like the files, the diff comes from the `git` command and not from `git2`
+/// (see the module documentation). `git diff` writes the git patch format
+/// that the parser reads anyway, renames and modes included.
+pub fn diff_commits(base_dir: &Path, from_ref: &str, to_ref: &str) -> io::Result<Vec<u8>> {
+    let base_dir = git_dir(base_dir);
+    let from_id = resolve_commit(base_dir, from_ref)?;
+    let to_id = resolve_commit(base_dir, to_ref)?;
+
+    // The configuration of the user must not change the format.
+    let output = Command::new("git")
+        .arg("-C").arg(base_dir)
+        .args(["diff", "--no-color", "--no-ext-diff", "--no-textconv", "--full-index", "--find-renames",
+               "--src-prefix=a/", "--dst-prefix=b/"])
+        .arg(&from_id).arg(&to_id)
+        .arg("--")
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("git diff failed: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(output.stdout)
+}
+
+pub struct GitCommit {
+    /// The full object name of the commit
+    commit_id: String,
+
+    process: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl GitCommit {
+    /// Open the commit named `reference` (a hash, branch, tag, ...) of the
+    /// repository with the `base_dir`.
+    pub fn open(base_dir: &Path, reference: &str) -> io::Result<Self> {
+        let base_dir = git_dir(base_dir);
+        let commit_id = resolve_commit(base_dir, reference)?;
+
+        let mut process = Command::new("git")
+            .arg("-C").arg(base_dir)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // NOTE(unwrap): Both are piped above.
+        let stdin = process.stdin.take().unwrap();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+
+        Ok(Self { commit_id, process, stdin, stdout })
+    }
+
+    /// The content of the file with the `filename`, relative to the base
+    /// directory. `None` if the commit does not have such a file.
+    pub fn read_file(&mut self, filename: &Path) -> io::Result<Option<Vec<u8>>> {
+        let filename = filename.to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Non-UTF8 filename"))?;
+        writeln!(self.stdin, "{}:./{}", self.commit_id, filename)?;
+        self.stdin.flush()?;
+
+        // "<object name> <type> <size>" followed by the content, or
+        // "<object> missing"
+        let mut header = String::new();
+        self.stdout.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.ends_with(" missing") {
+            return Ok(None);
+        }
+        let mut fields = header.rsplitn(3, ' ');
+        let (Some(size), Some(object_type)) = (fields.next(), fields.next()) else {
+            return Err(invalid_output(header));
+        };
+        let size = size.parse::<usize>().map_err(|_| invalid_output(header))?;
+
+        // The content ends with an extra newline.
+        let mut content = vec![0; size + 1];
+        self.stdout.read_exact(&mut content)?;
+        content.pop();
+
+        // A directory is not a file either.
+        Ok((object_type == "blob").then_some(content))
+    }
+}
+
+fn invalid_output(header: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected output of git cat-file: {}", header))
+}
+
+impl Drop for GitCommit {
+    fn drop(&mut self) {
+        // It would only end when its stdin is closed after this.
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+impl ReferenceTree for GitCommit {
+    fn load_file<'arena>(&mut self, arena: &'arena dyn Arena, filename: &Path) -> io::Result<Option<ModifiedFile<'arena>>> {
+        Ok(self.read_file(filename)?
+           .map(|content| ModifiedFile::new(arena.store_data(content.into_boxed_slice()), true, None)))
+    }
+}
@@ -0,0 +1,139 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Search through the patches in the series, like grep.
+//!
+//! The patches are scanned as text, not parsed, so even broken patches can be
+//! searched. The scanner only keeps track of which file and hunk it is in, so
+//! it can print them as context for the matches.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use colored::*;
+use regex::bytes::Regex;
+
+#[derive(Debug)]
+pub struct GrepConfig {
+    pub regex: Regex,
+
+    /// Search only the lines added by the patches
+    pub added_only: bool,
+
+    /// Print only names of the patches with matches
+    pub files_with_matches: bool,
+}
+
+/// Parse the line counts from a "@@ -a,b +c,d @@" hunk header. The count may
+/// be omitted, in which case it is 1.
+fn parse_hunk_header_counts(line: &[u8]) -> Option<(usize, usize)> {
+    let line = std::str::from_utf8(line).ok()?;
+    let mut parts = line.strip_prefix("@@ ")?.split(' ');
+
+    let parse_range = |range: &str| -> Option<usize> {
+        match range.split_once(',') {
+            Some((_, count)) => count.parse().ok(),
+            None => Some(1),
+        }
+    };
+
+    let remove = parse_range(parts.next()?.strip_prefix('-')?)?;
+    let add = parse_range(parts.next()?.strip_prefix('+')?)?;
+    Some((remove, add))
+}
+
+fn write_line<W: Write>(writer: &mut W, line: &[u8]) -> io::Result<()> {
+    writer.write_all(line)?;
+    if line.last() != Some(&b'\n') {
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Search single patch and write the matches to `writer`. Returns true if
+/// anything matched.
+pub fn grep_patch<W: Write>(writer: &mut W, config: &GrepConfig, patch_filename: &Path, data: &[u8])
+    -> io::Result<bool>
+{
+    let mut any_match = false;
+
+    // The last file and hunk headers, and whether they were printed already
+    let mut file_header: Option<&[u8]> = None;
+    let mut file_header_printed = false;
+    let mut hunk_header: Option<&[u8]> = None;
+    let mut hunk_header_printed = false;
+
+    // Lines remaining in the current hunk
+    let mut remaining_remove = 0usize;
+    let mut remaining_add = 0usize;
+
+    for line in data.split_inclusive(|&c| c == b'\n') {
+        let in_hunk = remaining_remove > 0 || remaining_add > 0;
+        let mut searchable = !config.added_only;
+
+        if in_hunk {
+            match line.first() {
+                Some(b'+') => {
+                    remaining_add = remaining_add.saturating_sub(1);
+                    searchable = true;
+                }
+                Some(b'-') => remaining_remove = remaining_remove.saturating_sub(1),
+                Some(b'\\') => {}
+                _ => {
+                    // Context line, possibly with the space stripped by some editor
+                    remaining_remove = remaining_remove.saturating_sub(1);
+                    remaining_add = remaining_add.saturating_sub(1);
+                }
+            }
+        } else if line.starts_with(b"@@ ") {
+            if let Some((remove, add)) = parse_hunk_header_counts(line) {
+                remaining_remove = remove;
+                remaining_add = add;
+            }
+            hunk_header = Some(line);
+            hunk_header_printed = false;
+        } else if line.starts_with(b"+++ ") {
+            file_header = Some(line);
+            file_header_printed = false;
+            hunk_header = None;
+        } else if line.starts_with(b"--- ") || line.starts_with(b"diff ") {
+            // Headers of the next file
+            file_header = None;
+            hunk_header = None;
+        }
+
+        if !searchable || !config.regex.is_match(line.strip_suffix(b"\n").unwrap_or(line)) {
+            continue;
+        }
+
+        if !any_match {
+            writeln!(writer, "{}", patch_filename.display().to_string().yellow())?;
+            any_match = true;
+
+            if config.files_with_matches {
+                return Ok(true);
+            }
+        }
+
+        // Print the file and hunk the line belongs to, unless the line is the
+        // header itself.
+        if let Some(header) = file_header {
+            if !file_header_printed && !std::ptr::eq(header, line) {
+                write!(writer, "  ")?;
+                write_line(writer, header)?;
+            }
+            file_header_printed = true;
+        }
+        if let Some(header) = hunk_header {
+            if !hunk_header_printed && !std::ptr::eq(header, line) {
+                let header = header.strip_suffix(b"\n").unwrap_or(header);
+                writeln!(writer, "  {}", String::from_utf8_lossy(header).cyan())?;
+            }
+            hunk_header_printed = true;
+        }
+
+        write!(writer, "  ")?;
+        write_line(writer, line)?;
+    }
+
+    Ok(any_match)
+}
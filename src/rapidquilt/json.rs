@@ -0,0 +1,40 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Minimal helpers for writing JSON output.
+//!
+//! The output we produce is simple enough that it is not worth pulling in a
+//! full serialization framework.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Write `value` as a quoted and escaped JSON string.
+pub fn write_str<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => writer.write_all(b"\\\"")?,
+            '\\' => writer.write_all(b"\\\\")?,
+            '\n' => writer.write_all(b"\\n")?,
+            '\r' => writer.write_all(b"\\r")?,
+            '\t' => writer.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => write!(writer, "{}", c)?,
+        }
+    }
+    writer.write_all(b"\"")
+}
+
+/// Write `path` as a JSON string. Paths that are not valid UTF-8 are
+/// converted lossily.
+pub fn write_path<W: Write>(writer: &mut W, path: &Path) -> io::Result<()> {
+    write_str(writer, &path.to_string_lossy())
+}
+
+/// Write `value` as a JSON string, or `null` if it is `None`.
+pub fn write_opt_str<W: Write>(writer: &mut W, value: Option<&str>) -> io::Result<()> {
+    match value {
+        Some(value) => write_str(writer, value),
+        None => writer.write_all(b"null"),
+    }
+}
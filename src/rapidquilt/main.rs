@@ -4,9 +4,35 @@
 #[cfg(feature = "bencher")]
 extern crate test;
 
+mod applied;
 mod apply;
 mod arena;
+mod audit;
 mod cmd;
+mod dump;
+mod edit;
+mod explain;
+mod file_filter;
+#[cfg(feature = "git")]
+mod git;
+mod grep;
+mod json;
+mod manifest;
+mod match_cache;
+mod mbox;
+mod normalize;
+mod numstat;
+mod parse_check;
+mod patch_source;
+mod pop;
+mod prefix_map;
+mod rcfile;
+mod rename_index;
+mod status;
+mod tar;
+mod touched;
+#[cfg(feature = "watch")]
+mod watch;
 
 #[cfg(test)]
 mod tests;
@@ -17,17 +43,11 @@ use std::process;
 use colored::*;
 
 fn main() {
-    match cmd::run(env::args_os().skip(1)) {
-        Err(err) => {
-            for (i, cause) in err.chain().enumerate() {
-                eprintln!("{}{}", "  ".repeat(i), format!("{}", cause).red());
-            }
-
-            process::exit(1);
-        },
-        Ok(false) => {
-            process::exit(1);
+    let result = cmd::run_with_outcome(env::args_os().skip(1));
+    if let Err(err) = &result {
+        for (i, cause) in err.chain().enumerate() {
+            eprintln!("{}{}", "  ".repeat(i), format!("{}", cause).red());
         }
-        _ => {}
     }
+    process::exit(cmd::exit_code(&result));
 }
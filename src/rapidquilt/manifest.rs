@@ -0,0 +1,103 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Manifest of the files changed by a push.
+//!
+//! Every file that was created, modified or deleted is listed once with its
+//! operation, for tools that only act on the changed files (e.g. incremental
+//! packaging). The operation compares the state before the first save with
+//! the state after the last one, a file that ends up as it was is not listed.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fs::{self, Permissions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use libpatch::modified_file::ModifiedFile;
+
+use crate::audit::{AuditOperation, hash_file_on_disk, hash_modified_file};
+use crate::json;
+
+/// The state of a file: the hash of its content and its permissions
+type FileState = (String, Option<Permissions>);
+
+/// The state of a file before the first save and after the last one, `None`
+/// if it did not exist.
+type FileChange = (Option<FileState>, Option<FileState>);
+
+/// The state of the file at `path`, `None` if it does not exist.
+fn state_on_disk(path: &Path) -> io::Result<Option<FileState>> {
+    match hash_file_on_disk(path)? {
+        Some(hash) => Ok(Some((hash, Some(fs::symlink_metadata(path)?.permissions())))),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    files: Mutex<BTreeMap<PathBuf, FileChange>>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the `file` with `filename` is going to be saved over the
+    /// original at the `original_path`.
+    pub fn record(&self, original_path: &Path, filename: &Path, file: &ModifiedFile) -> io::Result<()> {
+        let new_state = if file.deleted {
+            None
+        } else {
+            Some((hash_modified_file(file)?, file.permissions.clone()))
+        };
+
+        // NOTE(unwrap): If the lock is poisoned, another thread panicked.
+        let mut files = self.files.lock().unwrap();
+        match files.entry(filename.to_path_buf()) {
+            Entry::Occupied(mut entry) => entry.get_mut().1 = new_state,
+            Entry::Vacant(entry) => {
+                let old_state = if file.existed { state_on_disk(original_path)? } else { None };
+                entry.insert((old_state, new_state));
+            }
+        }
+        Ok(())
+    }
+
+    /// The changed files with their operation, sorted by name. Files saved
+    /// with the same content and permissions are not changed.
+    pub fn entries(&self) -> Vec<(PathBuf, AuditOperation)> {
+        // NOTE(unwrap): If the lock is poisoned, another thread panicked.
+        let files = self.files.lock().unwrap();
+        files.iter()
+            .filter_map(|(filename, states)| {
+                let operation = match states {
+                    (None, Some(_)) => AuditOperation::Create,
+                    (Some(_), None) => AuditOperation::Delete,
+                    (Some(old_state), Some(new_state)) if old_state != new_state => AuditOperation::Modify,
+                    _ => return None,
+                };
+                Some((filename.clone(), operation))
+            })
+            .collect()
+    }
+
+    /// Write one line with the operation and name of every changed file.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (filename, operation) in self.entries() {
+            writeln!(writer, "{} {}", operation, filename.display())?;
+        }
+        Ok(())
+    }
+
+    /// Write one JSON object for every changed file, one per line.
+    pub fn write_json_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (filename, operation) in self.entries() {
+            writer.write_all(b"{\"path\":")?;
+            json::write_path(writer, &filename)?;
+            writeln!(writer, ",\"operation\":\"{}\"}}", operation)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Cache of the places where the hunks matched, for `push --match-cache FILE`.
+//!
+//! Pushing the same series onto the same files again, e.g. while fixing one
+//! patch, finds every hunk at the same place as before. The cache remembers
+//! the places for every file patch, keyed by the hash of the file patch (with
+//! the settings that decide where it matches) and the hash of the file it is
+//! applied to. The next push only checks that the hunks still match there,
+//! instead of searching for them. A changed patch or file has another hash,
+//! so its entry is not used, and entries that are not used are dropped when
+//! the cache is saved.
+
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use seahash::SeaHasher;
+
+use libpatch::modified_file::ModifiedFile;
+use libpatch::patch::{HunkMatch, PatchDirection, TextFilePatch};
+
+/// The first line of the cache file, there is no point in reading other versions
+const CACHE_HEADER: &str = "rapidquilt match cache 1";
+
+/// The hash of the file patch with the settings and the hash of the file
+pub type MatchKey = (u64, u64);
+
+/// The key of applying the `file_patch` in the `direction` on the `file`.
+/// The `fuzz`, `max_offset` and `strict_ambiguity` decide where it matches.
+pub fn match_key(file_patch: &TextFilePatch,
+                 direction: PatchDirection,
+                 fuzz: usize,
+                 max_offset: Option<usize>,
+                 strict_ambiguity: bool,
+                 file: &ModifiedFile)
+                 -> MatchKey
+{
+    let mut hasher = SeaHasher::default();
+    hasher.write_u8(direction as u8);
+    hasher.write_usize(fuzz);
+    hasher.write_usize(max_offset.map_or(0, |max_offset| max_offset.saturating_add(1)));
+    hasher.write_u8(strict_ambiguity as u8);
+    for hunk in file_patch.hunks() {
+        hasher.write_usize(hunk.remove.target_line);
+        hasher.write_usize(hunk.add.target_line);
+        hasher.write_usize(hunk.prefix_context);
+        hasher.write_usize(hunk.suffix_context);
+        for part in [&hunk.remove, &hunk.add] {
+            hasher.write_usize(part.content.len());
+            for line in &part.content {
+                hasher.write_usize(line.len());
+                hasher.write(line);
+            }
+        }
+    }
+    let patch_hash = hasher.finish();
+
+    let mut hasher = SeaHasher::default();
+    for line in &file.content {
+        hasher.write(line);
+    }
+    let file_hash = hasher.finish();
+
+    (patch_hash, file_hash)
+}
+
+#[derive(Debug, Default)]
+pub struct MatchCache {
+    /// The matches of the hunks, with whether they were used by this push
+    entries: Mutex<HashMap<MatchKey, (Vec<HunkMatch>, bool)>>,
+
+    /// How many file patches were applied at the cached places
+    hits: AtomicUsize,
+}
+
+/// Parse a line of the cache file.
+fn parse_entry(line: &str) -> Option<(MatchKey, Vec<HunkMatch>)> {
+    let mut words = line.split(' ');
+    let patch_hash = u64::from_str_radix(words.next()?, 16).ok()?;
+    let file_hash = u64::from_str_radix(words.next()?, 16).ok()?;
+    let hunk_matches = words.map(|word| {
+        let (line, fuzz) = word.split_once(':')?;
+        Some(HunkMatch { line: line.parse().ok()?, fuzz: fuzz.parse().ok()? })
+    }).collect::<Option<_>>()?;
+    Some(((patch_hash, file_hash), hunk_matches))
+}
+
+impl MatchCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the cache saved at `path`. A missing file is an empty cache, so
+    /// are files from other versions. Broken lines are skipped.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut lines = data.lines();
+        if lines.next() != Some(CACHE_HEADER) {
+            return Ok(Self::new());
+        }
+        let entries = lines
+            .filter_map(parse_entry)
+            .map(|(key, hunk_matches)| (key, (hunk_matches, false)))
+            .collect();
+        Ok(Self { entries: Mutex::new(entries), hits: AtomicUsize::new(0) })
+    }
+
+    /// The matches cached for the `key`.
+    pub fn get(&self, key: MatchKey) -> Option<Vec<HunkMatch>> {
+        // NOTE(unwrap): If the lock is poisoned, another thread panicked.
+        let mut entries = self.entries.lock().unwrap();
+        entries.get_mut(&key).map(|(hunk_matches, used)| {
+            *used = true;
+            hunk_matches.clone()
+        })
+    }
+
+    /// Remember the `hunk_matches` for the `key`.
+    pub fn insert(&self, key: MatchKey, hunk_matches: Vec<HunkMatch>) {
+        // NOTE(unwrap): If the lock is poisoned, another thread panicked.
+        self.entries.lock().unwrap().insert(key, (hunk_matches, true));
+    }
+
+    /// Count a file patch applied at the cached places.
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many file patches were applied at the cached places.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Save the entries used or added by this push to `path`, sorted so the
+    /// file does not change if the push did not.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // NOTE(unwrap): If the lock is poisoned, another thread panicked.
+        let entries = self.entries.lock().unwrap();
+        let mut used_entries: Vec<_> = entries.iter()
+            .filter(|(_, (_, used))| *used)
+            .map(|(key, (hunk_matches, _))| (key, hunk_matches))
+            .collect();
+        used_entries.sort_unstable_by_key(|(key, _)| **key);
+
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        writeln!(writer, "{}", CACHE_HEADER)?;
+        for ((patch_hash, file_hash), hunk_matches) in used_entries {
+            write!(writer, "{:016x} {:016x}", patch_hash, file_hash)?;
+            for hunk_match in hunk_matches {
+                write!(writer, " {}:{}", hunk_match.line, hunk_match.fuzz)?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+}
@@ -0,0 +1,233 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `export-mbox`, writing the patches of the series
+//! as mails like `git format-patch` does, to be imported with `git am`.
+//!
+//! The author, date and subject of a mail come from the fields at the start
+//! of the patch description: "From", "Subject" and "Date" as written by
+//! `git format-patch`, or "Author" and "Description" of DEP-3 headers. Other
+//! fields (e.g. "Patch-mainline" or "References") are kept at the end of the
+//! message. Patches without a subject get one made from their filename.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use libpatch::patch::unified::parser::parse_patch;
+
+/// The line that starts every mail, `git format-patch` puts the commit there.
+const MBOX_FROM_LINE: &str = "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001";
+
+/// The longest name of a mail file, without the number and the suffix
+const MAX_SLUG_LENGTH: usize = 52;
+
+/// The metadata found in the description of a patch
+#[derive(Debug, Default, PartialEq)]
+pub struct PatchHeader {
+    pub from: Option<String>,
+    pub date: Option<String>,
+    pub subject: Option<String>,
+
+    /// The message, without trailing empty lines
+    pub body: Vec<String>,
+
+    /// The fields that are not one of the above, as "Name: value"
+    pub trailers: Vec<String>,
+
+    /// The lines after the "---" separator, e.g. a diffstat
+    pub after_separator: Vec<String>,
+}
+
+/// Returns the name of the field if the `line` starts one ("Name: value").
+fn field_name(line: &str) -> Option<&str> {
+    let (name, _) = line.split_once(':')?;
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid.then_some(name)
+}
+
+/// Remove the "[PATCH ...]" from the start of the `subject` of a mail.
+fn strip_patch_prefix(subject: &str) -> &str {
+    let mut subject = subject.trim();
+    while let Some(rest) = subject.strip_prefix('[') {
+        match rest.split_once(']') {
+            Some((tag, rest)) if tag.to_ascii_uppercase().contains("PATCH") => subject = rest.trim_start(),
+            _ => break,
+        }
+    }
+    subject
+}
+
+/// Parse the description of a patch (the `TextPatch::header`).
+pub fn parse_header(header: &[u8]) -> PatchHeader {
+    let text = String::from_utf8_lossy(header);
+    let mut lines = text.lines()
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .peekable();
+
+    // The line separating mails in a mbox
+    if lines.peek().is_some_and(|line| line.starts_with("From ") && field_name(line).is_none()) {
+        lines.next();
+    }
+
+    let mut result = PatchHeader::default();
+
+    // The fields, until an empty line or a line that is not a field
+    while let Some(line) = lines.next_if(|line| !line.trim().is_empty() && field_name(line).is_some()) {
+        // NOTE(unwrap): It was just checked by the `next_if`.
+        let name = field_name(line).unwrap();
+        let mut value = line[name.len() + 1..].trim().to_string();
+        let mut continuation = Vec::new();
+        while let Some(line) = lines.next_if(|line| line.starts_with([' ', '\t']) && !line.trim().is_empty()) {
+            continuation.push(line);
+        }
+
+        match name.to_ascii_lowercase().as_str() {
+            "from" | "author" => result.from = Some(value),
+            "date" => result.date = Some(value),
+            "subject" => {
+                // Folded header
+                for line in continuation {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+                result.subject = Some(strip_patch_prefix(&value).to_string());
+            }
+            "description" => {
+                // DEP-3: the first line is the summary, the others are the long
+                // description with " ." for empty lines.
+                result.subject = Some(value);
+                for line in continuation {
+                    let line = &line[1..];
+                    result.body.push(if line.trim() == "." { String::new() } else { line.to_string() });
+                }
+            }
+            _ => {
+                result.trailers.push(format!("{}: {}", name, value));
+                result.trailers.extend(continuation.into_iter().map(str::to_string));
+            }
+        }
+    }
+
+    let mut separator_found = false;
+    for line in lines {
+        if separator_found {
+            result.after_separator.push(line.to_string());
+        } else if line == "---" {
+            separator_found = true;
+        } else {
+            result.body.push(line.to_string());
+        }
+    }
+
+    while result.body.first().is_some_and(|line| line.trim().is_empty()) {
+        result.body.remove(0);
+    }
+    while result.body.last().is_some_and(|line| line.trim().is_empty()) {
+        result.body.pop();
+    }
+
+    result
+}
+
+/// A subject made from the `patch_filename`, for patches without one.
+pub fn subject_from_filename(patch_filename: &Path) -> String {
+    let name = patch_filename.file_name().unwrap_or(patch_filename.as_os_str()).to_string_lossy();
+    let name = name.strip_suffix(".patch").or_else(|| name.strip_suffix(".diff")).unwrap_or(&name);
+
+    // The number of a patch written by `git format-patch`
+    let name = match name.split_once('-') {
+        Some((number, rest)) if !number.is_empty() && number.bytes().all(|c| c.is_ascii_digit()) => rest,
+        _ => name,
+    };
+
+    name.replace(['-', '_'], " ").trim().to_string()
+}
+
+/// The name of the mail file for the `number`th patch with the `subject`, as
+/// `git format-patch` names it.
+pub fn mail_filename(number: usize, subject: &str) -> PathBuf {
+    let mut slug = String::with_capacity(subject.len());
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            slug.push(c);
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_SLUG_LENGTH);
+    let slug = slug.trim_matches(|c| c == '-' || c == '.');
+
+    PathBuf::from(format!("{:04}-{}.patch", number, slug))
+}
+
+/// A patch converted into a mail
+#[derive(Debug)]
+pub struct Mail {
+    pub filename: PathBuf,
+    pub data: Vec<u8>,
+
+    /// Whether the patch says who wrote it, `git am` refuses the mail without it.
+    pub has_author: bool,
+}
+
+/// Convert the patch with `data` into the mail for the `number`th patch of
+/// `count`. The parser finds where the description ends, it uses the `strip`
+/// of the patch in the series.
+pub fn patch_to_mail(patch_filename: &Path, data: &[u8], strip: usize, number: usize, count: usize) -> Result<Mail> {
+    let patch = parse_patch(data, strip)?;
+    let header = parse_header(patch.header);
+    let subject = header.subject.clone().unwrap_or_else(|| subject_from_filename(patch_filename));
+
+    let mut output = Vec::with_capacity(data.len() + 256);
+    let mut push_line = |line: &str| {
+        output.extend_from_slice(line.as_bytes());
+        output.push(b'\n');
+    };
+
+    push_line(MBOX_FROM_LINE);
+    if let Some(from) = &header.from {
+        push_line(&format!("From: {}", from));
+    }
+    if let Some(date) = &header.date {
+        push_line(&format!("Date: {}", date));
+    }
+    if count > 1 {
+        push_line(&format!("Subject: [PATCH {}/{}] {}", number, count, subject));
+    } else {
+        push_line(&format!("Subject: [PATCH] {}", subject));
+    }
+    let non_ascii = !subject.is_ascii()
+        || !header.from.as_deref().unwrap_or_default().is_ascii()
+        || header.body.iter().chain(&header.trailers).any(|line| !line.is_ascii());
+    if non_ascii {
+        push_line("MIME-Version: 1.0");
+        push_line("Content-Type: text/plain; charset=UTF-8");
+        push_line("Content-Transfer-Encoding: 8bit");
+    }
+    push_line("");
+
+    for line in &header.body {
+        push_line(line);
+    }
+    if !header.trailers.is_empty() {
+        if !header.body.is_empty() {
+            push_line("");
+        }
+        for line in &header.trailers {
+            push_line(line);
+        }
+    }
+    push_line("---");
+    for line in &header.after_separator {
+        push_line(line);
+    }
+
+    output.extend_from_slice(&data[patch.header.len()..]);
+
+    Ok(Mail {
+        filename: mail_filename(number, &subject),
+        data: output,
+        has_author: header.from.is_some(),
+    })
+}
@@ -0,0 +1,80 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Rewrite patches in a canonical form, to reduce the noise in diffs of
+//! patches regenerated by different tools.
+//!
+//! The canonical form has "a/" and "b/" prefixed paths (to be applied with
+//! `-p1`), a "diff --git" line for every file, no timestamps and LF line
+//! endings outside of hunks. The files are sorted by name. Lines inside of
+//! hunks are kept as they are, they must match the patched files.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use libpatch::patch::TextFilePatch;
+use libpatch::patch::unified::parser::parse_patch;
+use libpatch::patch::unified::writer::UnifiedPatchWriter;
+
+/// The name of the file the `file_patch` results in.
+fn target_filename<'a>(file_patch: &'a TextFilePatch) -> &'a Path {
+    // NOTE(unwrap): At least one of them must be there.
+    file_patch.new_filename().or_else(|| file_patch.old_filename()).unwrap()
+}
+
+/// Returns true if no file is touched by more than one of the
+/// `file_patches`, so they can be reordered without changing the result.
+fn can_reorder(file_patches: &[TextFilePatch]) -> bool {
+    let mut seen = HashSet::new();
+    file_patches.iter().all(|file_patch| {
+        // A rename touches both files, but a single file may be mentioned twice.
+        let old_filename = file_patch.old_filename();
+        let new_filename = file_patch.new_filename().filter(|new| Some(*new) != old_filename);
+        old_filename.into_iter().chain(new_filename).all(|filename| seen.insert(filename.clone()))
+    })
+}
+
+/// Write the patch in the canonical form. The patch is parsed with `-p1`.
+fn write_canonical(data: &[u8]) -> Result<Vec<u8>> {
+    let mut patch = parse_patch(data, 1)?;
+
+    if can_reorder(&patch.file_patches) {
+        patch.file_patches.sort_by(|a, b| target_filename(a).cmp(target_filename(b)));
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+
+    // The description is free text, only its line endings are normalized.
+    for line in patch.header.split_inclusive(|&c| c == b'\n') {
+        match line.strip_suffix(b"\r\n") {
+            Some(line) => {
+                output.extend_from_slice(line);
+                output.push(b'\n');
+            }
+            None => output.extend_from_slice(line),
+        }
+    }
+
+    for file_patch in &mut patch.file_patches {
+        file_patch.prefix(Path::new("a"), Path::new("b"));
+        file_patch.write_to(&mut output)?;
+    }
+
+    Ok(output)
+}
+
+/// Rewrite the patch in the canonical form. The result applies the same way
+/// as the original patch, with `-p1`. Normalizing it again does not change
+/// it.
+pub fn normalize_patch(data: &[u8]) -> Result<Vec<u8>> {
+    let output = write_canonical(data)?;
+
+    // The canonical form must be stable, otherwise the patch was not
+    // understood well enough to be rewritten.
+    if write_canonical(&output)? != output {
+        bail!("The canonical form of the patch is not stable.");
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,82 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `--numstat`, the lines added and removed in every
+//! file of a diff, in the format of `git diff --numstat`.
+//!
+//! Every file is one line with the added lines, the removed lines and the
+//! path, separated by tabs. Binary files have "-" for both numbers. Renamed
+//! files have the path written like git does, e.g. "dir/{old.c => new.c}".
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use libpatch::patch::{FilePatchKind, TextFilePatch, TextPatch};
+use libpatch::patch::unified::parser::parse_patch;
+
+use crate::explain::{Changes, is_binary};
+
+/// The path of a renamed file, with the parts that the names have in common
+/// outside of the braces, like in "dir/{old.c => new.c}".
+fn rename_path(old_path: &Path, new_path: &Path) -> String {
+    let old = old_path.to_string_lossy();
+    let new = new_path.to_string_lossy();
+    let (old_bytes, new_bytes) = (old.as_bytes(), new.as_bytes());
+
+    // The common directories at the start and at the end. Like in git, both
+    // may share the slash in between, e.g. "dir/{ => sub}/a.c".
+    let common = old_bytes.iter().zip(new_bytes).take_while(|(a, b)| a == b).count();
+    let prefix = old_bytes[..common].iter().rposition(|&c| c == b'/').map_or(0, |index| index + 1);
+    let max_suffix = old_bytes.len().min(new_bytes.len()) - prefix + usize::from(prefix > 0);
+    let common = old_bytes.iter().rev().zip(new_bytes.iter().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+    let suffix = old_bytes[old_bytes.len() - common..].iter().position(|&c| c == b'/').map_or(0, |index| common - index);
+
+    if prefix == 0 && suffix == 0 {
+        return format!("{} => {}", old, new);
+    }
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    let middle = |bytes: &[u8]| text(&bytes[prefix..(bytes.len() - suffix).max(prefix)]);
+    format!("{}{{{} => {}}}{}",
+            text(&old_bytes[..prefix]),
+            middle(old_bytes),
+            middle(new_bytes),
+            text(&old_bytes[old_bytes.len() - suffix..]))
+}
+
+/// Whether the `file_patch` changes a binary file: a git binary change, or
+/// lines with NUL bytes, which git would show as such.
+fn is_binary_change(file_patch: &TextFilePatch) -> bool {
+    is_binary(file_patch) ||
+        file_patch.hunks().iter().any(|hunk| {
+            hunk.remove.content.iter().chain(&hunk.add.content).any(|line| line.contains(&0))
+        })
+}
+
+/// Write the numstat line of every file in the `patch`.
+pub fn write_numstat<W: Write>(writer: &mut W, patch: &TextPatch) -> Result<()> {
+    for file_patch in &patch.file_patches {
+        let path = match (file_patch.kind(), file_patch.old_filename(), file_patch.new_filename()) {
+            (FilePatchKind::Delete, Some(old_filename), _) => old_filename.display().to_string(),
+            (FilePatchKind::Modify, Some(old_filename), Some(new_filename)) if file_patch.is_rename() =>
+                rename_path(old_filename, new_filename),
+            (_, _, Some(new_filename)) => new_filename.display().to_string(),
+            (_, old_filename, None) => old_filename.map_or(String::new(), |filename| filename.display().to_string()),
+        };
+
+        if is_binary_change(file_patch) {
+            writeln!(writer, "-\t-\t{}", path)?;
+        } else {
+            let changes = Changes::of(file_patch);
+            writeln!(writer, "{}\t{}\t{}", changes.added, changes.removed, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the numstat of the `diff` written by rapidquilt, with "a/" and "b/"
+/// before the filenames.
+pub fn write_diff_numstat<W: Write>(writer: &mut W, diff: &[u8]) -> Result<()> {
+    let patch = parse_patch(diff, 1).context("Parsing the diff")?;
+    write_numstat(writer, &patch)
+}
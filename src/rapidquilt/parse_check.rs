@@ -0,0 +1,53 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `parse-check`, a quick check that every patch of
+//! the series parses, before it is pushed anywhere.
+//!
+//! The patches are only parsed, nothing is matched against the tree or
+//! written, so it takes a fraction of the time of `push --dry-run`. Every patch
+//! that does not parse is reported with the line of the syntax error, e.g. a
+//! malformed hunk header or a hunk that ends before all its lines.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+
+use libpatch::patch::unified::parser::parse_patch_located;
+
+use crate::apply::{SeriesPatch, Verbosity};
+
+/// Parse the `series_patches` from the `patches_path` and write the errors,
+/// one per broken patch, and with `verbosity` at least normal also the parser
+/// warnings, into the `writer`. Returns whether all patches parsed.
+pub fn check_patches<W: Write>(writer: &mut W, patches_path: &Path, series_patches: &[SeriesPatch],
+                               verbosity: Verbosity) -> Result<bool> {
+    let mut clean = true;
+    for series_patch in series_patches {
+        let path = patches_path.join(&series_patch.filename);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(err) => {
+                writeln!(writer, "{}: {}", path.display(), err)?;
+                clean = false;
+                continue;
+            }
+        };
+
+        match parse_patch_located(&data, series_patch.strip) {
+            Ok(patch) => {
+                if verbosity >= Verbosity::Normal {
+                    for warning in &patch.warnings {
+                        writeln!(writer, "{}: warning: {}", path.display(), warning)?;
+                    }
+                }
+            }
+            Err(err) => {
+                writeln!(writer, "{}:{}: {}", path.display(), err.line, err.error)?;
+                clean = false;
+            }
+        }
+    }
+    Ok(clean)
+}
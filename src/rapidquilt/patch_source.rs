@@ -0,0 +1,241 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements the loading of patches from where the series is
+//! kept. That is the patch directory in the working tree, or with the
+//! "remote" feature also an `ssh://`, `http://` or `https://` URL, or a
+//! bundle read from the standard input. The working tree itself is always
+//! local.
+//!
+//! A bundle starts with the line "rapidquilt bundle", followed by sections.
+//! Every section is a header line and then exactly as many bytes as the
+//! header says, without any separator:
+//!
+//! ```text
+//! rapidquilt bundle
+//! series <length>
+//! <the "series" file>
+//! patch <filename> <length>
+//! <the patch>
+//! ```
+//!
+//! There must be one "series" section and a "patch" section for every patch
+//! of the series, in any order. The length is the last word of the header,
+//! so the filenames may contain spaces. The content is not searched for
+//! delimiters, so it may be anything.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+
+use crate::arena::Arena;
+
+
+/// The place the patches are loaded from.
+pub trait PatchSource: fmt::Debug + Sync {
+    /// Load the patch with the `filename` and return its content. The slice
+    /// is kept in the `arena`, so it lives as long as the loaded files.
+    fn load_patch<'arena>(&self, arena: &'arena dyn Arena, filename: &Path) -> Result<&'arena [u8], io::Error>;
+}
+
+/// Patches in a local directory, loaded like any other file.
+#[derive(Debug)]
+pub struct FilesystemSource<'a> {
+    patches_path: &'a Path,
+}
+
+impl<'a> FilesystemSource<'a> {
+    pub fn new(patches_path: &'a Path) -> Self {
+        Self { patches_path }
+    }
+}
+
+impl PatchSource for FilesystemSource<'_> {
+    fn load_patch<'arena>(&self, arena: &'arena dyn Arena, filename: &Path) -> Result<&'arena [u8], io::Error> {
+        arena.load_file(&self.patches_path.join(filename))
+    }
+}
+
+/// The first line of a bundle.
+const BUNDLE_MAGIC: &[u8] = b"rapidquilt bundle\n";
+
+/// The "series" file and the patches read from a bundle, kept in memory.
+#[derive(Debug)]
+pub struct BundleSource {
+    series: Vec<u8>,
+    patches: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl BundleSource {
+    /// Read the whole bundle from the `reader`.
+    pub fn read<R: BufRead>(mut reader: R) -> Result<Self, io::Error> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line)?;
+        if line != BUNDLE_MAGIC {
+            return Err(invalid("The input is not a bundle, it does not start with \"rapidquilt bundle\"".to_string()));
+        }
+
+        let mut series = None;
+        let mut patches = HashMap::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let header = std::str::from_utf8(&line).ok()
+                .and_then(|header| header.strip_suffix('\n'))
+                .ok_or_else(|| invalid(format!("Bad section header \"{}\" in the bundle", String::from_utf8_lossy(&line).trim_end())))?;
+            let bad_header = || invalid(format!("Bad section header \"{}\" in the bundle", header));
+
+            let (name, length) = header.rsplit_once(' ').ok_or_else(bad_header)?;
+            let length: usize = length.parse().map_err(|_| bad_header())?;
+            // The length is not trusted, the data is read as it comes.
+            let mut data = Vec::new();
+            (&mut reader).take(length as u64).read_to_end(&mut data)?;
+            if data.len() != length {
+                return Err(invalid(format!("The bundle ends inside of the section \"{}\"", header)));
+            }
+
+            match name.split_once(' ') {
+                None if name == "series" => {
+                    if series.replace(data).is_some() {
+                        return Err(invalid("The bundle has more than one series".to_string()));
+                    }
+                }
+                Some(("patch", filename)) if !filename.is_empty() => {
+                    if patches.insert(PathBuf::from(filename), data).is_some() {
+                        return Err(invalid(format!("The bundle has more than one patch \"{}\"", filename)));
+                    }
+                }
+                _ => return Err(bad_header()),
+            }
+        }
+
+        let series = series.ok_or_else(|| invalid("The bundle has no series".to_string()))?;
+        Ok(Self { series, patches })
+    }
+
+    /// The content of the "series" file of the bundle.
+    pub fn series(&self) -> &[u8] {
+        &self.series
+    }
+}
+
+impl PatchSource for BundleSource {
+    fn load_patch<'arena>(&self, arena: &'arena dyn Arena, filename: &Path) -> Result<&'arena [u8], io::Error> {
+        let data = self.patches.get(filename)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("There is no patch \"{}\" in the bundle", filename.display())))?;
+        Ok(arena.store_data(data.clone().into_boxed_slice()))
+    }
+}
+
+/// A single patch that is kept in memory, e.g. a diff made by git.
+#[cfg(feature = "git")]
+#[derive(Debug)]
+pub struct MemorySource {
+    filename: PathBuf,
+    data: Vec<u8>,
+}
+
+#[cfg(feature = "git")]
+impl MemorySource {
+    /// Source of the patch with the `filename` and the content `data`.
+    pub fn new(filename: &Path, data: Vec<u8>) -> Self {
+        Self { filename: filename.to_path_buf(), data }
+    }
+}
+
+#[cfg(feature = "git")]
+impl PatchSource for MemorySource {
+    fn load_patch<'arena>(&self, arena: &'arena dyn Arena, filename: &Path) -> Result<&'arena [u8], io::Error> {
+        if filename != self.filename {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("There is no patch \"{}\"", filename.display())));
+        }
+        Ok(arena.store_data(self.data.clone().into_boxed_slice()))
+    }
+}
+
+/// Patches downloaded from a URL: with `curl` from `http://` and `https://`
+/// URLs, with `ssh` from `ssh://[user@]host[:port]/path` URLs.
+#[cfg(feature = "remote")]
+#[derive(Debug)]
+pub struct UrlSource {
+    url: String,
+}
+
+#[cfg(feature = "remote")]
+impl UrlSource {
+    /// Source of the patches in the directory at the `url`.
+    pub fn new(url: &str) -> Result<Self, io::Error> {
+        if !["ssh://", "http://", "https://"].iter().any(|scheme| url.starts_with(scheme)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("Unsupported URL \"{}\", only ssh://, http:// and https:// are supported", url)));
+        }
+        // ssh would take such host for an option.
+        if url.strip_prefix("ssh://").is_some_and(|location| location.starts_with('-')) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("Bad URL \"{}\", the host can not start with \"-\"", url)));
+        }
+        Ok(Self { url: url.trim_end_matches('/').to_string() })
+    }
+
+    /// Download the file with the `filename` from the directory at the URL.
+    pub fn fetch(&self, filename: &Path) -> Result<Vec<u8>, io::Error> {
+        use std::process::Command;
+
+        let filename = filename.to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Non-UTF8 patch filename"))?;
+
+        let mut command = if let Some(location) = self.url.strip_prefix("ssh://") {
+            let (authority, path) = location.split_at(location.find('/').unwrap_or(location.len()));
+            let (destination, port) = match authority.rfind(':') {
+                Some(colon) if colon > authority.find('@').unwrap_or(0) => (&authority[..colon], Some(&authority[colon + 1..])),
+                _ => (authority, None),
+            };
+
+            let mut command = Command::new("ssh");
+            if let Some(port) = port {
+                command.arg("-p").arg(port);
+            }
+            // The remote shell splits the command, so the path is quoted.
+            let path = format!("{}/{}", path, filename).replace('\'', "'\\''");
+            command.arg("--").arg(destination).arg(format!("cat -- '{}'", path));
+            command
+        } else {
+            let mut command = Command::new("curl");
+            command.args(["--fail", "--silent", "--show-error", "--location"])
+                .arg(format!("{}/{}", self.url, url_escape(filename)));
+            command
+        };
+
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("Downloading \"{}\" from \"{}\" failed: {}",
+                                                filename, self.url, String::from_utf8_lossy(&output.stderr).trim())));
+        }
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(feature = "remote")]
+impl PatchSource for UrlSource {
+    fn load_patch<'arena>(&self, arena: &'arena dyn Arena, filename: &Path) -> Result<&'arena [u8], io::Error> {
+        Ok(arena.store_data(self.fetch(filename)?.into_boxed_slice()))
+    }
+}
+
+/// Percent-encode the `path` for use in a URL, keeping the slashes.
+#[cfg(feature = "remote")]
+fn url_escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
@@ -0,0 +1,159 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements popping of applied patches, like `quilt pop`.
+//!
+//! Popping restores the quilt backup files in ".pc/<patch>" (or another
+//! `BackupStore`), the patch itself is not reverted. So only patches pushed
+//! with `--backup always` can be popped.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Error, Result};
+
+use crate::apply::{ApplyError, BackupFile, BackupStore, Verbosity, backup_names};
+use crate::audit::{AuditLog, AuditOperation, AuditRecord, hash_content, hash_file_on_disk};
+
+/// Read the names of the patches in ".pc/applied-patches".
+pub fn read_applied_patches(base_dir: &Path) -> Result<Vec<PathBuf>> {
+    match fs::read_to_string(base_dir.join(".pc/applied-patches")) {
+        Ok(content) => Ok(content.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect()),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(error).context("Reading \".pc/applied-patches\""),
+    }
+}
+
+/// Replace the content of ".pc/applied-patches".
+pub fn write_applied_patches(base_dir: &Path, applied_patches: &[PathBuf]) -> Result<()> {
+    let content: String = applied_patches.iter().map(|filename| format!("{}\n", filename.display())).collect();
+    fs::write(base_dir.join(".pc/applied-patches"), content)
+        .context("Writing \".pc/applied-patches\"")
+}
+
+/// Put the `backup` of the file at `path` back, or remove the file if it did
+/// not exist.
+fn restore_file(path: &Path, backup: Option<BackupFile>) -> Result<(), io::Error> {
+    match fs::remove_file(path) {
+        Ok(()) => {},
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => {},
+        Err(error) => return Err(error),
+    }
+
+    let Some(backup) = backup else {
+        return Ok(());
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    #[cfg(unix)]
+    {
+        if backup.is_symlink() {
+            use std::os::unix::ffi::OsStrExt;
+            return std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(&backup.content), path);
+        }
+    }
+
+    fs::write(path, &backup.content)?;
+    if let Some(permissions) = backup.permissions {
+        fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+/// Restore the file like `restore_file` and record the change in the
+/// `audit_log`. The `filename` is relative to the `base_dir`.
+fn restore_audited_file(base_dir: &Path, filename: &Path, backup: Option<BackupFile>, audit_log: &AuditLog, patch_filename: &Path)
+    -> Result<()>
+{
+    let path = base_dir.join(filename);
+    let old_hash = hash_file_on_disk(&path)?;
+    let new_hash = backup.as_ref().map(|backup| hash_content(&backup.content));
+    let operation = match (&old_hash, &new_hash) {
+        (None, Some(_)) => AuditOperation::Create,
+        (Some(_), Some(_)) => AuditOperation::Modify,
+        (Some(_), None) => AuditOperation::Delete,
+        (None, None) => {
+            // It did not exist and it is not restored, nothing changes.
+            return Ok(());
+        }
+    };
+
+    restore_file(&path, backup)?;
+
+    audit_log.record(&AuditRecord {
+        path: filename,
+        operation,
+        old_hash,
+        new_hash,
+        patch: Some(patch_filename),
+    }).context(ApplyError::WriteAuditLog)
+}
+
+/// Put back the files from the backup of the patch with `patch_filename`,
+/// named `backup_name` in the `backup_store` (see `backup_names`), and remove
+/// the backup. If the patch was `backed_up` for sure, a missing backup means
+/// that it changed no files. Every restored file is recorded in the
+/// `audit_log`, if there is one.
+pub fn restore_backup_files(base_dir: &Path, backup_store: &dyn BackupStore, patch_filename: &Path, backup_name: &Path,
+                            backed_up: bool, audit_log: Option<&AuditLog>) -> Result<()> {
+    let files = backup_store.list(backup_name)
+        .with_context(|| format!("Reading backup files of patch {}", patch_filename.display()))?;
+    let Some(files) = files else {
+        if backed_up {
+            return Ok(());
+        }
+        bail!("Can not pop patch {}, there are no backup files for it. Push it with \"--backup always\".",
+              patch_filename.display());
+    };
+
+    for filename in &files {
+        backup_store.restore(backup_name, filename)
+            .map_err(Error::from)
+            .and_then(|backup| match audit_log {
+                Some(audit_log) => restore_audited_file(base_dir, filename, backup, audit_log, patch_filename),
+                None => Ok(restore_file(&base_dir.join(filename), backup)?),
+            })
+            .with_context(|| format!("Restoring file {} from backup", filename.display()))?;
+    }
+
+    backup_store.remove(backup_name)
+        .with_context(|| format!("Removing backup files of patch {}", patch_filename.display()))
+}
+
+/// Pop the last `count` patches of the `applied_patches`, the top one first.
+/// ".pc/applied-patches" is updated after every patch, so it is right even
+/// if popping fails in the middle. The restored files are recorded in the
+/// `audit_log`, if there is one.
+pub fn pop_patches(base_dir: &Path, backup_store: &dyn BackupStore, applied_patches: &[PathBuf], count: usize,
+                   audit_log: Option<&AuditLog>, verbosity: Verbosity) -> Result<()> {
+    let backup_names = backup_names(applied_patches.iter().map(PathBuf::as_path));
+    for remaining in (applied_patches.len().saturating_sub(count)..applied_patches.len()).rev() {
+        let patch_filename = &applied_patches[remaining];
+        if verbosity >= Verbosity::Normal {
+            println!("Popping patch {}", patch_filename.display());
+        }
+        restore_backup_files(base_dir, backup_store, patch_filename, &backup_names[remaining], false, audit_log)?;
+        write_applied_patches(base_dir, &applied_patches[..remaining])?;
+    }
+    Ok(())
+}
+
+/// Show the `patches` about to be popped, the top one first, and ask whether
+/// to go on. Only "y" or "yes" does, the end of the `input` does not.
+pub fn confirm_pop<R: BufRead, W: Write>(input: &mut R, output: &mut W, patches: &[PathBuf]) -> io::Result<bool> {
+    writeln!(output, "Popping {} patches:", patches.len())?;
+    for patch_filename in patches.iter().rev() {
+        writeln!(output, "  {}", patch_filename.display())?;
+    }
+    write!(output, "Changes that are not refreshed are lost. Continue? [y/N] ")?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        writeln!(output)?;
+        return Ok(false);
+    }
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,49 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Rewriting of the leading directories of the paths in patches, for
+//! `--prefix-map OLD=NEW`, e.g. from "src/foo.c" of an upstream patch to
+//! "source/foo.c" of a tree laid out differently.
+//!
+//! The paths are rewritten after stripping, before the files are looked up.
+//! Only whole components match, "src" maps "src/foo.c", but not
+//! "srcfoo/bar.c". The mappings are tried in the order they were given and the
+//! first one that matches decides.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct PrefixMap {
+    mappings: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PrefixMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the mapping given as "OLD=NEW". NEW may be empty, to strip OLD.
+    /// Returns false if it has no "=" or OLD is empty.
+    pub fn add(&mut self, mapping: &str) -> bool {
+        match mapping.split_once('=') {
+            Some((old, new)) if !old.is_empty() => {
+                self.mappings.push((PathBuf::from(old), PathBuf::from(new)));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The `filename` with its prefix replaced by the first mapping that
+    /// matches, `None` if none does.
+    pub fn map(&self, filename: &Path) -> Option<PathBuf> {
+        self.mappings.iter().find_map(|(old, new)| {
+            let rest = filename.strip_prefix(old).ok()?;
+            match (rest.as_os_str().is_empty(), new.as_os_str().is_empty()) {
+                // Nothing would be left of it
+                (true, true) => None,
+                (true, false) => Some(new.clone()),
+                (false, _) => Some(new.join(rest)),
+            }
+        })
+    }
+}
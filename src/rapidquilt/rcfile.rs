@@ -0,0 +1,93 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Default options from a ".rapidquiltrc" file.
+//!
+//! The file holds command-line options, one per line with its value, e.g.
+//! "--fuzz 1" or "--backup always". Empty lines and lines starting with "#"
+//! are skipped. It is looked for in the working directory (`--directory`)
+//! first and then in the home directory, only the first one found is read.
+//! Options given on the command line win over the ones in the file.
+//!
+//! The working directory is often a tree from somewhere else, so the file can
+//! set only the options in `RC_OPTIONS`, not the ones that run commands or
+//! turn off checks.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use getopts::{Matches, Options};
+
+/// The name of the file, in the working or the home directory
+pub const RC_FILENAME: &str = ".rapidquiltrc";
+
+/// The options that the file can set.
+pub const RC_OPTIONS: &[&str] = &["fuzz", "backup", "threads", "patch-directory"];
+
+/// The home directory to look for the rc file in. The tests do not look
+/// there, so the rc file of the developer does not change their results.
+fn home_dir() -> Option<PathBuf> {
+    if cfg!(test) {
+        return None;
+    }
+    env::var_os("HOME").map(PathBuf::from)
+}
+
+/// The rc file for the `base_dir`: in it or else in the home directory.
+pub fn find_rc_file(base_dir: &Path) -> Option<PathBuf> {
+    let home_dir = home_dir();
+    Some(base_dir.to_path_buf()).into_iter()
+        .chain(home_dir)
+        .map(|dir| dir.join(RC_FILENAME))
+        .find(|path| path.is_file())
+}
+
+/// The name of the option at the start of the `word`, e.g. "fuzz" of
+/// "--fuzz=1".
+fn option_name(word: &str) -> &str {
+    let name = word.strip_prefix("--").or_else(|| word.strip_prefix('-')).unwrap_or(word);
+    name.split('=').next().unwrap_or(name)
+}
+
+/// Read the options in the rc file at `path`, checked against the `opts`.
+/// Options that are in the `matches` of the command line are left out.
+pub fn read_rc_options(opts: &Options, path: &Path, matches: &Matches) -> Result<Vec<String>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Reading \"{}\"", path.display())),
+    };
+
+    let mut options = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // An option and its value, short options are not grouped
+        let words: Vec<_> = line.split_whitespace().collect();
+        let single_option = words.len() <= 2
+            && (words[0].starts_with("--") || (words[0].starts_with('-') && words[0].len() == 2))
+            && words[1..].iter().all(|word| !word.starts_with('-'));
+        if !single_option {
+            bail!("Line {} of \"{}\" is not a single option: {}", index + 1, path.display(), line);
+        }
+        let parsed = opts.parse(&words)
+            .with_context(|| format!("Line {} of \"{}\"", index + 1, path.display()))?;
+        if !parsed.free.is_empty() {
+            bail!("Line {} of \"{}\" is not a single option: {}", index + 1, path.display(), line);
+        }
+        if !RC_OPTIONS.iter().any(|name| parsed.opt_present(name)) {
+            bail!("Line {} of \"{}\" sets option {}, only --{} can be set there",
+                  index + 1, path.display(), words[0], RC_OPTIONS.join(", --"));
+        }
+
+        if !matches.opt_present(option_name(words[0])) {
+            options.extend(words.into_iter().map(str::to_string));
+        }
+    }
+    Ok(options)
+}
@@ -0,0 +1,103 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! Index of the files in the working tree by the hashes of their lines, for
+//! `push --find-renames`.
+//!
+//! An old patch may name a file that was renamed or moved in the tree since.
+//! When the file it names is missing, the index finds the files that contain
+//! every line the hunks expect, i.e. their context and removed lines, and the
+//! patch is applied to the one that is found. The index is built once, from
+//! the tree as it is before the push. Files and directories whose names start
+//! with "." (".pc", ".git", ...) and symlinks are not indexed.
+
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use seahash::SeaHasher;
+
+use libpatch::modified_file::ModifiedFile;
+use libpatch::patch::{PatchDirection, TextFilePatch};
+
+#[derive(Debug)]
+struct IndexedFile {
+    /// Relative to the working directory
+    filename: PathBuf,
+
+    /// The hashes of its lines, sorted and without duplicates
+    line_hashes: Vec<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct RenameIndex {
+    files: Vec<IndexedFile>,
+}
+
+fn line_hash(line: &[u8]) -> u64 {
+    let mut hasher = SeaHasher::default();
+    hasher.write(line);
+    hasher.finish()
+}
+
+impl RenameIndex {
+    /// Index all files in the `base_dir`.
+    pub fn build(base_dir: &Path) -> io::Result<Self> {
+        let mut index = Self::default();
+        index.add_directory(base_dir, Path::new(""))?;
+        index.files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        Ok(index)
+    }
+
+    fn add_directory(&mut self, base_dir: &Path, relative: &Path) -> io::Result<()> {
+        let root = if base_dir.as_os_str().is_empty() { Path::new(".") } else { base_dir };
+        for entry in fs::read_dir(root.join(relative))? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let filename = relative.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.add_directory(base_dir, &filename)?;
+            } else if file_type.is_file() {
+                let data = fs::read(entry.path())?;
+                let mut line_hashes: Vec<_> = ModifiedFile::new(&data, true, None).content.iter()
+                    .map(|line| line_hash(line))
+                    .collect();
+                line_hashes.sort_unstable();
+                line_hashes.dedup();
+                self.files.push(IndexedFile { filename, line_hashes });
+            }
+        }
+        Ok(())
+    }
+
+    /// The files that contain every line that the hunks of the `file_patch`
+    /// expect in the `direction`. The context lines that may be left out with
+    /// the `fuzz` are not required. Nothing is found for patches that expect
+    /// no lines, e.g. ones creating a file.
+    pub fn find(&self, file_patch: &TextFilePatch, direction: PatchDirection, fuzz: usize) -> Vec<&Path> {
+        let mut wanted_hashes = Vec::new();
+        for hunk in file_patch.hunks() {
+            let part = match direction {
+                PatchDirection::Forward => &hunk.remove,
+                PatchDirection::Revert => &hunk.add,
+            };
+            let start = hunk.prefix_context.min(fuzz);
+            let end = part.content.len().saturating_sub(hunk.suffix_context.min(fuzz)).max(start);
+            wanted_hashes.extend(part.content[start..end].iter().map(|line| line_hash(line)));
+        }
+        if wanted_hashes.is_empty() {
+            return Vec::new();
+        }
+        wanted_hashes.sort_unstable();
+        wanted_hashes.dedup();
+
+        self.files.iter()
+            .filter(|file| wanted_hashes.iter().all(|hash| file.line_hashes.binary_search(hash).is_ok()))
+            .map(|file| file.filename.as_path())
+            .collect()
+    }
+}
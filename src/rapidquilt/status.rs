@@ -0,0 +1,188 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements the summary of the patch stack, the `status`
+//! command.
+//!
+//! A file needs refresh if applying its patch to the backup of the file does
+//! not give its content after the patch: the backup of the next applied patch
+//! that changes the file, or the working tree. Only patches with backup files
+//! (pushed with `--backup always`) can be checked.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use colored::*;
+
+use libpatch::analysis::{AnalysisSet, fn_analysis_note_noop};
+use libpatch::modified_file::ModifiedFile;
+use libpatch::patch::{BatchThreshold, PatchDirection};
+use libpatch::patch::unified::parser::parse_patch;
+
+use crate::apply::{BackupStore, SeriesPatch, backup_names};
+use crate::json;
+use crate::pop::read_applied_patches;
+
+/// The summary of the patch stack
+#[derive(Debug, Default)]
+pub struct StackStatus {
+    pub series_patches: usize,
+    pub applied_patches: usize,
+    pub top_patch: Option<PathBuf>,
+
+    /// The files that need refresh, with the applied patch they belong to
+    pub needs_refresh: Vec<(PathBuf, PathBuf)>,
+
+    /// The applied patches are not the start of the series, it was reordered
+    /// or changed since they were pushed.
+    pub series_mismatch: bool,
+}
+
+/// Read the content of the file at `path` from the working tree, or `None`
+/// if it does not exist.
+fn read_tree_file(path: &Path) -> Result<Option<Vec<u8>>, io::Error> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() =>
+            Ok(Some(fs::read_link(path)?.into_os_string().into_encoded_bytes())),
+        Ok(_) => Ok(Some(fs::read(path)?)),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Returns true if applying the `patch_data` to the `before` content of the
+/// file with `filename` gives the `after` content. `None` is a file that does
+/// not exist.
+fn patch_makes(patch_data: &[u8], series_patch: &SeriesPatch, filename: &Path, before: Option<&[u8]>, after: Option<&[u8]>) -> Result<bool> {
+    let patch = parse_patch(patch_data, series_patch.strip)?;
+    let file_patch = patch.file_patches.iter().find(|file_patch| {
+        file_patch.old_filename().into_iter().chain(file_patch.new_filename()).any(|name| name == filename)
+    });
+    let file_patch = match file_patch {
+        // Added to the patch by hand, it is not in it yet
+        None => return Ok(before == after),
+        // The content moves to the other name, which is checked instead
+        Some(file_patch) if file_patch.is_rename() => return Ok(true),
+        Some(file_patch) => file_patch,
+    };
+
+    let mut modified_file = match before {
+        Some(content) => ModifiedFile::new(content, true, None),
+        None => ModifiedFile::new_non_existent(),
+    };
+    let direction = if series_patch.reverse { PatchDirection::Revert } else { PatchDirection::Forward };
+    let report = file_patch.apply(&mut modified_file, direction, file_patch.max_useable_fuzz(), None, false, false, false, None, BatchThreshold::default(),
+                                  &AnalysisSet::default(), &fn_analysis_note_noop);
+    if report.failed() {
+        return Ok(false);
+    }
+
+    let mut content = Vec::new();
+    modified_file.write_to(&mut content)?;
+    Ok(match after {
+        Some(after) => !modified_file.deleted && content == after,
+        None => modified_file.deleted,
+    })
+}
+
+/// Summarize the patch stack in the `base_dir`, with the `series_patches`
+/// from the `patches_path` and the backups in the `backup_store`.
+pub fn stack_status(base_dir: &Path, patches_path: &Path, series_patches: &[SeriesPatch], backup_store: &dyn BackupStore) -> Result<StackStatus> {
+    let applied_patches = read_applied_patches(base_dir)?;
+    let series_mismatch = applied_patches.len() > series_patches.len() ||
+        applied_patches.iter().zip(series_patches).any(|(applied, series_patch)| *applied != series_patch.filename);
+
+    // Backed up files of every applied patch, the top one last
+    let backup_names = backup_names(applied_patches.iter().map(PathBuf::as_path));
+    let mut backed_up = Vec::with_capacity(applied_patches.len());
+    for (patch_filename, backup_name) in applied_patches.iter().zip(&backup_names) {
+        let files = backup_store.list(backup_name)
+            .with_context(|| format!("Reading backup files of patch {}", patch_filename.display()))?;
+        backed_up.push(files.unwrap_or_default());
+    }
+
+    let mut needs_refresh = Vec::new();
+    for (index, patch_filename) in applied_patches.iter().enumerate() {
+        // Without the series entry, the strip level is not known.
+        let series_patch = series_patches.iter().find(|series_patch| series_patch.filename == *patch_filename);
+        let Some(series_patch) = series_patch.filter(|_| !backed_up[index].is_empty()) else {
+            continue;
+        };
+        let patch_data = fs::read(patches_path.join(patch_filename))
+            .with_context(|| format!("Reading patch {}", patch_filename.display()))?;
+
+        for filename in &backed_up[index] {
+            let restore = |index: usize| backup_store.restore(&backup_names[index], filename)
+                .with_context(|| format!("Reading backup of file {} in patch {}", filename.display(), applied_patches[index].display()));
+            let before = restore(index)?.map(|backup| backup.content);
+
+            // The next patch that changed the file has its state after this one
+            let next_patch = (index + 1..applied_patches.len()).find(|&next| backed_up[next].contains(filename));
+            let after = match next_patch {
+                Some(next) => restore(next)?.map(|backup| backup.content),
+                None => read_tree_file(&base_dir.join(filename))
+                    .with_context(|| format!("Reading file {}", filename.display()))?,
+            };
+
+            if !patch_makes(&patch_data, series_patch, filename, before.as_deref(), after.as_deref())? {
+                needs_refresh.push((patch_filename.clone(), filename.clone()));
+            }
+        }
+    }
+
+    Ok(StackStatus {
+        series_patches: series_patches.len(),
+        applied_patches: applied_patches.len(),
+        top_patch: applied_patches.last().cloned(),
+        needs_refresh,
+        series_mismatch,
+    })
+}
+
+/// Write the `status` for people.
+pub fn write_status<W: Write>(writer: &mut W, status: &StackStatus) -> Result<(), io::Error> {
+    writeln!(writer, "{} {} of {}", "Applied patches:".yellow(), status.applied_patches, status.series_patches)?;
+    match status.top_patch {
+        Some(ref top_patch) => writeln!(writer, "{} {}", "Top patch:".yellow(), top_patch.display())?,
+        None => writeln!(writer, "{} none", "Top patch:".yellow())?,
+    }
+
+    if status.needs_refresh.is_empty() {
+        writeln!(writer, "{} none", "Needs refresh:".yellow())?;
+    } else {
+        writeln!(writer, "{}", "Needs refresh:".yellow())?;
+        for (patch_filename, filename) in &status.needs_refresh {
+            writeln!(writer, "  {} {} {}", filename.display(), "in".yellow(), patch_filename.display())?;
+        }
+    }
+
+    if status.series_mismatch {
+        writeln!(writer, "{} {}, the applied patches are not the start of the series",
+                 "Series:".yellow(), "REORDERED".bright_red().bold())?;
+    } else {
+        writeln!(writer, "{} in order", "Series:".yellow())?;
+    }
+    Ok(())
+}
+
+/// Write the `status` as a JSON object.
+pub fn write_status_json<W: Write>(writer: &mut W, status: &StackStatus) -> Result<(), io::Error> {
+    write!(writer, "{{\"series_patches\":{},\"applied_patches\":{},\"top_patch\":", status.series_patches, status.applied_patches)?;
+    match status.top_patch {
+        Some(ref top_patch) => json::write_path(writer, top_patch)?,
+        None => writer.write_all(b"null")?,
+    }
+    writer.write_all(b",\"needs_refresh\":[")?;
+    for (i, (patch_filename, filename)) in status.needs_refresh.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(b"{\"patch\":")?;
+        json::write_path(writer, patch_filename)?;
+        writer.write_all(b",\"file\":")?;
+        json::write_path(writer, filename)?;
+        writer.write_all(b"}")?;
+    }
+    writeln!(writer, "],\"series_mismatch\":{}}}", status.series_mismatch)
+}
@@ -0,0 +1,267 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module writes the patched tree as a tar archive, for
+//! `push --out-tar FILE`.
+//!
+//! The archive is in the POSIX ustar format, with pax headers for names that
+//! do not fit. The files that the patches touched are written as they left
+//! them, all other files as they are loaded from the arena, so nothing is
+//! written to disk. Modes and symlinks are kept, the entries are owned by
+//! root and have the time of the push.
+
+use std::collections::HashMap;
+use std::fs::{self, Permissions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::apply::ChangedFile;
+use crate::arena::Arena;
+
+const BLOCK_SIZE: usize = 512;
+
+/// The largest size that fits into the 11 octal digits of the header
+const MAX_SIZE: u64 = 0o77777777777;
+
+const REGULAR_TYPE: u8 = b'0';
+const SYMLINK_TYPE: u8 = b'2';
+const DIRECTORY_TYPE: u8 = b'5';
+const PAX_TYPE: u8 = b'x';
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+/// The file type and permission bits of the `permissions`.
+#[cfg(unix)]
+fn mode(permissions: &Permissions) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.mode()
+}
+
+#[cfg(not(unix))]
+fn mode(permissions: &Permissions) -> u32 {
+    if permissions.readonly() { 0o444 } else { 0o644 }
+}
+
+fn is_symlink_mode(mode: u32) -> bool {
+    mode & 0o170000 == 0o120000
+}
+
+/// Write the `value` as octal number into the `field`, its last byte stays
+/// zero.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+/// Split the `path` into the prefix and name fields of the ustar header, if
+/// it fits.
+fn split_ustar_path(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    if path.len() <= 100 {
+        return Some((&[], path));
+    }
+    path.iter().enumerate()
+        .filter(|&(index, &byte)| byte == b'/' && index <= 155 && path.len() - index - 1 <= 100)
+        .map(|(index, _)| (&path[..index], &path[index + 1..]))
+        .find(|(_, name)| !name.is_empty())
+}
+
+/// A "key=value" record of a pax header, its length counts itself.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let base_len = key.len() + value.len() + 3;
+    let mut len = base_len + 1;
+    while base_len + len.to_string().len() != len {
+        len = base_len + len.to_string().len();
+    }
+    let mut record = format!("{} {}=", len, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+pub struct TarWriter<W: Write> {
+    writer: W,
+
+    /// The modification time of all entries, in seconds since the epoch
+    mtime: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(writer: W, mtime: u64) -> Self {
+        Self { writer, mtime }
+    }
+
+    fn write_header(&mut self, path: &[u8], type_flag: u8, mode: u32, size: u64, link: &[u8]) -> io::Result<()> {
+        if size > MAX_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("{} is too big for a tar entry", String::from_utf8_lossy(path))));
+        }
+
+        // Names that do not fit go into a pax header before the entry.
+        let ustar_path = split_ustar_path(path);
+        if type_flag != PAX_TYPE && (ustar_path.is_none() || link.len() > 100) {
+            let mut records = Vec::new();
+            if ustar_path.is_none() {
+                records.extend(pax_record("path", path));
+            }
+            if link.len() > 100 {
+                records.extend(pax_record("linkpath", link));
+            }
+            self.write_header(b"././@PaxHeader", PAX_TYPE, 0o644, records.len() as u64, b"")?;
+            self.write_data(&records)?;
+        }
+        let (prefix, name) = ustar_path.unwrap_or((&[], &path[path.len().saturating_sub(100)..]));
+
+        let mut header = [0u8; BLOCK_SIZE];
+        header[..name.len()].copy_from_slice(name);
+        write_octal(&mut header[100..108], (mode & 0o7777) as u64);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], size);
+        write_octal(&mut header[136..148], self.mtime);
+        header[156] = type_flag;
+        let link = &link[..link.len().min(100)];
+        header[157..157 + link.len()].copy_from_slice(link);
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[265..269].copy_from_slice(b"root");
+        header[297..301].copy_from_slice(b"root");
+        header[345..345 + prefix.len()].copy_from_slice(prefix);
+
+        // The checksum is computed with its own field as spaces.
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u64 = header.iter().map(|&byte| byte as u64).sum();
+        write_octal(&mut header[148..155], checksum);
+        header[154] = 0;
+
+        self.writer.write_all(&header)
+    }
+
+    /// Write the `data` of an entry, padded to whole blocks.
+    fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        self.writer.write_all(&[0u8; BLOCK_SIZE][..padding])
+    }
+
+    /// Add a regular file, or a symlink to the target in `data` if the `mode`
+    /// says it is one.
+    pub fn append_file(&mut self, path: &Path, mode: u32, data: &[u8]) -> io::Result<()> {
+        let path = path_bytes(path);
+        if is_symlink_mode(mode) {
+            self.write_header(&path, SYMLINK_TYPE, mode, 0, data)
+        } else {
+            self.write_header(&path, REGULAR_TYPE, mode, data.len() as u64, b"")?;
+            self.write_data(data)
+        }
+    }
+
+    pub fn append_directory(&mut self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut path = path_bytes(path);
+        path.push(b'/');
+        self.write_header(&path, DIRECTORY_TYPE, mode, 0, b"")
+    }
+
+    /// Write the end of the archive and return the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0u8; 2 * BLOCK_SIZE])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Add the `changed_file`, unless the patches deleted it.
+fn append_changed_file<W: Write>(tar: &mut TarWriter<W>, changed_file: &ChangedFile) -> io::Result<()> {
+    let Some(content) = &changed_file.content else {
+        return Ok(());
+    };
+    let mode = changed_file.permissions.as_ref().map_or(0o644, mode);
+    tar.append_file(&changed_file.filename, mode, content)
+}
+
+/// Add the files in the `relative` directory of the `base_dir`, with the
+/// content of the `changed_files` instead of the one on disk.
+fn append_directory_tree<W: Write>(
+    tar: &mut TarWriter<W>,
+    arena: &dyn Arena,
+    base_dir: &Path,
+    relative: &Path,
+    changed_files: &mut HashMap<&Path, &ChangedFile>)
+    -> Result<()>
+{
+    let root = if base_dir.as_os_str().is_empty() { Path::new(".") } else { base_dir };
+    let directory = root.join(relative);
+    let mut names = fs::read_dir(&directory)
+        .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.file_name())).collect::<io::Result<Vec<_>>>())
+        .with_context(|| format!("Reading directory \"{}\"", directory.display()))?;
+    names.sort();
+
+    for name in names {
+        let filename = relative.join(&name);
+        if filename == Path::new(".pc") {
+            continue;
+        }
+        if let Some(changed_file) = changed_files.remove(filename.as_path()) {
+            append_changed_file(tar, changed_file)?;
+            continue;
+        }
+
+        let path = root.join(&filename);
+        let metadata = fs::symlink_metadata(&path)
+            .with_context(|| format!("Querying \"{}\" metadata", path.display()))?;
+        let mode = mode(&metadata.permissions());
+        if metadata.is_dir() {
+            tar.append_directory(&filename, mode)?;
+            append_directory_tree(tar, arena, base_dir, &filename, changed_files)?;
+        } else if metadata.file_type().is_symlink() {
+            let target = arena.load_symlink_target(&path)
+                .with_context(|| format!("Reading symlink \"{}\"", path.display()))?;
+            tar.append_file(&filename, mode, target)?;
+        } else if metadata.is_file() {
+            let data = arena.load_file(&path)
+                .with_context(|| format!("Reading \"{}\"", path.display()))?;
+            tar.append_file(&filename, mode, data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the tree in the `base_dir` as patched into the `writer`: the
+/// `changed_files` and with `changed_only` unset also every other file, except
+/// ".pc". Files deleted by the patches are left out.
+pub fn write_tree_tar<W: Write>(
+    writer: W,
+    arena: &dyn Arena,
+    base_dir: &Path,
+    changed_files: &[ChangedFile],
+    changed_only: bool,
+    mtime: u64)
+    -> Result<W>
+{
+    let mut tar = TarWriter::new(writer, mtime);
+    let mut remaining_files: HashMap<&Path, &ChangedFile> = changed_files.iter()
+        .map(|changed_file| (changed_file.filename.as_path(), changed_file))
+        .collect();
+
+    if !changed_only {
+        append_directory_tree(&mut tar, arena, base_dir, Path::new(""), &mut remaining_files)?;
+    }
+
+    // The files created in new directories are left, in order.
+    let mut remaining_files: Vec<_> = remaining_files.into_values().collect();
+    remaining_files.sort_by(|a, b| a.filename.cmp(&b.filename));
+    for changed_file in remaining_files {
+        append_changed_file(&mut tar, changed_file)?;
+    }
+
+    Ok(tar.finish()?)
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::apply::ApplyError;
+use super::push;
+
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/change.patch"), "\
+--- a/src/a[1].c
++++ b/src/a[1].c
+@@ -1 +1 @@
+-a
++A
+--- a/secret.txt
++++ b/secret.txt
+@@ -1 +1 @@
+-secret
++leaked
+")?;
+    fs::write(work_path.join("series"), "change.patch\n")?;
+    fs::create_dir(work_path.join("src"))?;
+    fs::write(work_path.join("src/a[1].c"), "a\n")?;
+    fs::write(work_path.join("secret.txt"), "secret\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn file_outside_allowlist_refused() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+
+    for threads in ["1", "2"] {
+        let error = push(work_path, &["--threads", threads, "--allow-file", "src/*.c"]).unwrap_err();
+        assert!(error.chain().any(|cause| matches!(cause.downcast_ref(),
+                                                   Some(ApplyError::DisallowedFile { filename, .. }) if filename == Path::new("secret.txt"))),
+                "{:?}", error);
+        assert_eq!(fs::read_to_string(work_path.join("src/a[1].c"))?, "a\n");
+        assert_eq!(fs::read_to_string(work_path.join("secret.txt"))?, "secret\n");
+        assert!(!work_path.join(".pc/applied-patches").exists());
+    }
+
+    // Allowed by the list, where the names are not globs
+    let file_list = work_path.join("files.txt");
+    fs::write(&file_list, "src/a[1].c\nsecret.txt\n")?;
+    assert!(push(work_path, &["--threads", "1", "--file-list", file_list.to_str().unwrap()])?);
+    assert_eq!(fs::read_to_string(work_path.join("src/a[1].c"))?, "A\n");
+    assert_eq!(fs::read_to_string(work_path.join("secret.txt"))?, "leaked\n");
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+const PATCH_1: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+-1
++one
+ 2
+ 3
+";
+
+const PATCH_2: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ one
+-2
++two
+ 3
+";
+
+const PATCH_3: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ one
+ two
+-3
++three
+";
+
+#[cfg(test)]
+fn run_in(work_path: &Path, args: &[&str]) -> Result<bool> {
+    let mut all_args = vec![
+        OsStr::new("--quiet"),
+        OsStr::new("--backup"), OsStr::new("always"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    all_args.extend(args.iter().map(OsStr::new));
+    cmd::run(all_args)
+}
+
+/// The error of pushing with the corrupt `applied_patches`
+#[cfg(test)]
+fn push_error(work_path: &Path, applied_patches: &str) -> Result<String> {
+    fs::write(work_path.join(".pc/applied-patches"), applied_patches)?;
+    match run_in(work_path, &["push", "--all"]) {
+        Ok(_) => panic!("Push with applied patches {:?} did not fail", applied_patches),
+        Err(err) => Ok(format!("{:#}", err)),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn corrupt_applied_patches() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/1.patch"), PATCH_1)?;
+    fs::write(work_path.join("patches/2.patch"), PATCH_2)?;
+    fs::write(work_path.join("patches/3.patch"), PATCH_3)?;
+    fs::write(work_path.join("series"), "1.patch\n2.patch\n3.patch\n")?;
+    fs::write(work_path.join("file.txt"), "1\n2\n3\n")?;
+
+    assert!(run_in(work_path, &["push", "2"])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, "1.patch\n2.patch\n");
+
+    let error = push_error(work_path, "1.patch\n2.pa")?;
+    assert!(error.starts_with("The last line of \".pc/applied-patches\" is cut off (\"2.pa\" instead of \"2.patch\")"), "{}", error);
+    assert!(error.ends_with("Run it again with \"--repair\" to rebuild the list from the backups in \".pc\"."), "{}", error);
+
+    let error = push_error(work_path, "1.patch\nremoved.patch\n")?;
+    assert!(error.starts_with("Line 2 of \".pc/applied-patches\" names patch removed.patch, which is not in the series."), "{}", error);
+
+    let error = push_error(work_path, "2.patch\n1.patch\n")?;
+    assert!(error.starts_with("Line 1 of \".pc/applied-patches\" names patch 2.patch, but patch 1.patch is at that place in the series."), "{}", error);
+
+    let error = push_error(work_path, "1.patch\n2.patch\n3.patch\n4.patch\n")?;
+    assert!(error.starts_with("Line 4 of \".pc/applied-patches\" says that patch 4.patch is applied, but the series has only 3 patches."), "{}", error);
+
+    // The backups of the first two patches are there.
+    assert!(run_in(work_path, &["--repair"])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, "1.patch\n2.patch\n");
+
+    fs::write(work_path.join(".pc/applied-patches"), "")?;
+    assert!(run_in(work_path, &["push", "--all", "--repair"])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, "1.patch\n2.patch\n3.patch\n");
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "one\ntwo\nthree\n");
+
+    Ok(())
+}
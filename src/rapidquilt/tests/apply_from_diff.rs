@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+const ORIGINAL: &str = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+
+/// Run git in the `work_path`, as a user that needs no configuration.
+#[cfg(test)]
+fn git(work_path: &Path, args: &[&str]) -> Result<()> {
+    let status = Command::new("git")
+        .arg("-C").arg(work_path)
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .status()?;
+    anyhow::ensure!(status.success(), "git {:?} failed", args);
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn diff_of_two_commits_applied() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+
+    // The upstream change: a modified, a deleted and a created file
+    git(work_path, &["init", "--quiet"])?;
+    fs::write(work_path.join("file.txt"), ORIGINAL)?;
+    fs::write(work_path.join("old.txt"), "old\n")?;
+    git(work_path, &["add", "file.txt", "old.txt"])?;
+    git(work_path, &["commit", "--quiet", "-m", "Start"])?;
+    git(work_path, &["tag", "start"])?;
+    fs::write(work_path.join("file.txt"), ORIGINAL.replace("8\n", "eight\n"))?;
+    fs::write(work_path.join("new.txt"), "new\n")?;
+    git(work_path, &["rm", "--quiet", "old.txt"])?;
+    git(work_path, &["add", "file.txt", "new.txt"])?;
+    git(work_path, &["commit", "--quiet", "-m", "Upstream change"])?;
+    git(work_path, &["tag", "upstream"])?;
+
+    // The patched tree, where the change applies at an offset
+    git(work_path, &["checkout", "--quiet", "start"])?;
+    let patched = format!("0\n{}", ORIGINAL);
+    fs::write(work_path.join("file.txt"), &patched)?;
+
+    let apply = |args: &[&str]| {
+        let mut all_args = vec!["apply", "--quiet", "--directory", &*work_path.to_string_lossy()].into_iter()
+            .map(String::from).collect::<Vec<_>>();
+        all_args.extend(args.iter().map(|arg| arg.to_string()));
+        cmd::run(all_args)
+    };
+
+    assert!(apply(&["--dry-run", "--from-diff", "start", "upstream"])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, patched);
+    assert!(work_path.join("old.txt").exists());
+
+    assert!(apply(&["--from-diff", "start", "upstream"])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, format!("0\n{}", ORIGINAL.replace("8\n", "eight\n")));
+    assert_eq!(fs::read_to_string(work_path.join("new.txt"))?, "new\n");
+    assert!(!work_path.join("old.txt").exists());
+    assert!(!work_path.join(".pc").exists());
+
+    // Applied already, it fails and nothing changes.
+    assert!(!apply(&["--from-diff", "start", "upstream"])?);
+    assert_eq!(fs::read_to_string(work_path.join("new.txt"))?, "new\n");
+
+    let error = apply(&["--from-diff", "start", "no-such-ref"]).unwrap_err();
+    assert!(format!("{:#}", error).contains("\"no-such-ref\" is not a commit"), "{:#}", error);
+    assert!(apply(&["--from-diff", "start"]).is_err());
+    assert!(apply(&["start", "upstream"]).is_err());
+
+    Ok(())
+}
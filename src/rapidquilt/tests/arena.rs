@@ -0,0 +1,461 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    ApplyConfigDoBackups,
+    apply_patches,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::{Arena, FileArena, IoRetry, PerThreadArena};
+use crate::cmd;
+
+#[cfg(test)]
+fn check_load_metadata(arena: &dyn Arena) -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let path = work_dir.path().join("file.txt");
+    fs::write(&path, "some content\n")?;
+
+    let metadata = arena.load_metadata(&path)?;
+    let fs_metadata = fs::symlink_metadata(&path)?;
+    assert_eq!(metadata.size, 13);
+    assert_eq!(metadata.mtime, fs_metadata.modified()?);
+    assert!(!metadata.is_symlink());
+    assert_eq!(metadata.permissions(), fs_metadata.permissions());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt, symlink};
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751))?;
+        let metadata = arena.load_metadata(&path)?;
+        assert_eq!(metadata.mode, 0o100751);
+        assert_eq!(metadata.owner(), Some((fs_metadata.uid(), fs_metadata.gid())));
+
+        // Symlinks are not followed
+        let link_path = work_dir.path().join("link");
+        symlink("file.txt", &link_path)?;
+        let metadata = arena.load_metadata(&link_path)?;
+        assert!(metadata.is_symlink());
+        assert_eq!(metadata.size, 8);
+    }
+
+    let error = arena.load_metadata(&work_dir.path().join("missing")).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn file_arena_load_metadata() -> Result<()> {
+    check_load_metadata(&FileArena::new())
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn mmap_arena_load_metadata() -> Result<()> {
+    check_load_metadata(&crate::arena::MmapArena::new())
+}
+
+#[cfg(test)]
+#[test]
+fn file_arena_stats() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    fs::write(work_dir.path().join("a.txt"), "12345")?;
+    fs::write(work_dir.path().join("b.txt"), "123")?;
+
+    let arena = FileArena::new();
+    arena.load_file(&work_dir.path().join("a.txt"))?;
+    arena.load_file(&work_dir.path().join("b.txt"))?;
+
+    let stats = arena.stats();
+    assert_eq!(stats.loaded_files(), 2);
+    assert_eq!(stats.total_size(), 8);
+    assert_eq!(stats.to_string(), "Arena Statistics (loaded files: 2, total size: 8 B)");
+
+    #[cfg(feature = "serde")]
+    assert_eq!(serde_json::to_string(&stats)?, r#"{"loaded_files":2,"total_size":8}"#);
+
+    Ok(())
+}
+
+/// The arenas do not cache, every load opens the file again.
+#[cfg(test)]
+#[test]
+fn file_arena_syscall_stats() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let path = work_dir.path().join("a.txt");
+    fs::write(&path, "12345\n")?;
+
+    let arena = FileArena::new();
+    arena.load_file(&path)?;
+    arena.load_file(&path)?;
+    arena.load_file_head(&path, 1)?;
+
+    let stats = arena.syscall_stats();
+    assert_eq!((stats.open(), stats.mmap(), stats.read(), stats.readlink()), (3, 0, 3, 0));
+    assert_eq!(stats.to_string(), "Arena System Calls (open: 3, mmap: 0, read: 3, readlink: 0)");
+
+    Ok(())
+}
+
+/// Files read with `O_DIRECT` come out whole, whether their size is a
+/// multiple of the alignment or of the chunk size or not, and also if the
+/// filesystem does not support it.
+#[cfg(all(test, target_os = "linux"))]
+#[test]
+fn file_arena_direct_io() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let arena = FileArena::new().with_direct_io(true);
+
+    for size in [0, 1, 4095, 4096, 4097, 1 << 20, (1 << 20) + 4096, (3 << 20) + 5] {
+        let path = work_dir.path().join(format!("{}.txt", size));
+        let content: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &content)?;
+
+        assert_eq!(arena.load_file(&path)?, &content[..], "file of {} bytes", size);
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn mmap_arena_syscall_stats() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let path = work_dir.path().join("a.txt");
+    fs::write(&path, "12345\n")?;
+    std::os::unix::fs::symlink("a.txt", work_dir.path().join("link"))?;
+
+    let arena = crate::arena::MmapArena::new();
+    arena.load_file(&path)?;
+    arena.load_symlink_target(&work_dir.path().join("link"))?;
+
+    let stats = arena.syscall_stats();
+    assert_eq!((stats.open(), stats.mmap(), stats.read(), stats.readlink()), (1, 1, 0, 1));
+
+    Ok(())
+}
+
+/// The files loaded by many threads are kept in different shards, the
+/// statistics count all of them.
+#[cfg(all(test, unix))]
+#[test]
+fn mmap_arena_stats_from_threads() -> Result<()> {
+    use rayon::prelude::*;
+
+    let work_dir = tempfile::tempdir()?;
+    let paths: Vec<_> = (1..=40).map(|i| work_dir.path().join(format!("{}.txt", i))).collect();
+    for (i, path) in paths.iter().enumerate() {
+        fs::write(path, "x".repeat(i + 1))?;
+    }
+
+    let arena = crate::arena::MmapArena::new();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(20).build()?;
+    let contents = pool.install(|| {
+        paths.par_iter().map(|path| arena.load_file(path)).collect::<Result<Vec<_>, _>>()
+    })?;
+    arena.store_data(Box::new([1, 2, 3]));
+
+    for (i, content) in contents.iter().enumerate() {
+        assert_eq!(*content, "x".repeat(i + 1).as_bytes());
+    }
+    assert_eq!(arena.stats().loaded_files(), 41);
+    assert_eq!(arena.stats().total_size(), (1..=40).sum::<usize>() + 3);
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn mmap_arena_release_file() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let a_path = work_dir.path().join("a.txt");
+    let b_path = work_dir.path().join("b.txt");
+    fs::write(&a_path, "12345")?;
+    fs::write(&b_path, "123")?;
+
+    let arena = crate::arena::MmapArena::new();
+    arena.load_file(&a_path)?;
+    arena.load_file(&a_path)?;
+    assert_eq!(arena.load_file(&b_path)?, b"123");
+    arena.store_data(Box::new([1, 2]));
+    assert_eq!(arena.stats().loaded_files(), 4);
+
+    // Both mappings of the file go
+    unsafe { arena.release_file(&a_path); }
+    assert_eq!(arena.stats().loaded_files(), 2);
+    assert_eq!(arena.stats().total_size(), 5);
+
+    unsafe { arena.release_file(&a_path); }
+    assert_eq!(arena.stats().loaded_files(), 2);
+
+    // The stored data is not a file
+    unsafe { arena.release_file(&b_path); }
+    assert_eq!(arena.stats().loaded_files(), 1);
+    assert_eq!(arena.stats().total_size(), 2);
+
+    Ok(())
+}
+
+/// The files patched by a sequential push are released at its end, only the
+/// patches stay loaded.
+#[cfg(all(test, unix))]
+#[test]
+fn sequential_push_releases_patched_files() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    let mut series_patches = Vec::new();
+    let mut patches_size = 0;
+    for i in 0..3 {
+        fs::write(work_path.join(format!("{}.txt", i)), "one\ntwo\n")?;
+        let patch = format!("--- a/{0}.txt\n+++ b/{0}.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+{0}\n", i);
+        fs::write(patches_path.join(format!("{}.patch", i)), &patch)?;
+        patches_size += patch.len();
+        series_patches.push(SeriesPatch { filename: format!("{}.patch", i).into(), strip: 1, reverse: false });
+    }
+
+    let config = ApplyConfig {
+        deterministic: true,
+        do_backups: ApplyConfigDoBackups::Always,
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet)
+    };
+
+    let arena = crate::arena::MmapArena::new();
+    let result = apply_patches(&config, &arena, &AnalysisSet::default())?;
+    assert_eq!(result.applied_patches, 3);
+    assert_eq!(arena.stats().loaded_files(), 3);
+    assert_eq!(arena.stats().total_size(), patches_size);
+    assert_eq!(arena.syscall_stats().mmap(), 6);
+
+    for i in 0..3 {
+        assert_eq!(fs::read_to_string(work_path.join(format!("{}.txt", i)))?, format!("one\n{}\n", i));
+        assert_eq!(fs::read_to_string(work_path.join(format!(".pc/{}.patch/{}.txt", i, i)))?, "one\ntwo\n");
+    }
+
+    Ok(())
+}
+
+/// A sparse file of 1 GiB is refused with a limit of 1 MiB without reading it.
+#[cfg(test)]
+fn check_max_file_size(arena: &dyn Arena, work_path: &Path) -> Result<()> {
+    let error = arena.load_file(&work_path.join("huge.core")).unwrap_err();
+    assert!(error.to_string().contains("more than the limit of 1048576 bytes"), "{}", error);
+    assert_eq!(arena.stats().total_size(), 0);
+
+    assert_eq!(arena.load_file(&work_path.join("small.txt"))?, b"small\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn setup_max_file_size() -> Result<tempfile::TempDir> {
+    let work_dir = tempfile::tempdir()?;
+    fs::File::create(work_dir.path().join("huge.core"))?.set_len(1 << 30)?;
+    fs::write(work_dir.path().join("small.txt"), "small\n")?;
+    Ok(work_dir)
+}
+
+#[cfg(test)]
+#[test]
+fn file_arena_max_file_size() -> Result<()> {
+    let work_dir = setup_max_file_size()?;
+    check_max_file_size(&FileArena::new().with_max_file_size(Some(1 << 20)), work_dir.path())
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn mmap_arena_max_file_size() -> Result<()> {
+    let work_dir = setup_max_file_size()?;
+    check_max_file_size(&crate::arena::MmapArena::new().with_max_file_size(Some(1 << 20)), work_dir.path())
+}
+
+#[cfg(test)]
+#[test]
+fn push_fails_on_max_file_size() -> Result<()> {
+    let work_dir = setup_max_file_size()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/core.patch"), "\
+--- a/huge.core
++++ b/huge.core
+@@ -1 +1 @@
+-x
++y
+")?;
+    fs::write(work_path.join("series"), "core.patch\n")?;
+
+    let result = cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--max-file-size"), OsStr::new("1048576"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ]);
+    let error = result.unwrap_err();
+    assert!(error.chain().any(|cause| cause.to_string().contains("see --max-file-size")), "{:?}", error);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn per_thread_arena_merges_stats() -> Result<()> {
+    use rayon::prelude::*;
+
+    let work_dir = tempfile::tempdir()?;
+    let paths: Vec<_> = (0..16).map(|i| work_dir.path().join(format!("{}.txt", i))).collect();
+    for (i, path) in paths.iter().enumerate() {
+        fs::write(path, "x".repeat(i))?;
+    }
+
+    let arena = PerThreadArena::new((0..5).map(|_| Box::new(FileArena::new()) as Box<dyn Arena>).collect());
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build()?;
+    let contents = pool.install(|| {
+        paths.par_iter().map(|path| arena.load_file(path)).collect::<Result<Vec<_>, _>>()
+    })?;
+    arena.load_file(&paths[0])?;
+
+    for (i, content) in contents.iter().enumerate() {
+        assert_eq!(*content, "x".repeat(i).as_bytes());
+    }
+    assert_eq!(arena.stats().loaded_files(), 17);
+    assert_eq!(arena.stats().total_size(), (0..16).sum::<usize>());
+    assert_eq!(arena.syscall_stats().open(), 17);
+    assert_eq!(arena.syscall_stats().read(), 17);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn push_with_per_thread_arena() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    let mut series = String::new();
+    for i in 0..8 {
+        fs::write(work_path.join(format!("{}.txt", i)), "one\ntwo\n")?;
+        fs::write(work_path.join(format!("patches/{}.patch", i)), format!("\
+--- a/{0}.txt
++++ b/{0}.txt
+@@ -1,2 +1,2 @@
+ one
+-two
++{0}
+", i))?;
+        series.push_str(&format!("{}.patch\n", i));
+    }
+    fs::write(work_path.join("series"), series)?;
+
+    let push = |arena: &str| cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--all"),
+        OsStr::new("--threads"), OsStr::new("3"),
+        OsStr::new("--arena"), OsStr::new(arena),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ]);
+    assert!(push("threadlocal").is_err());
+    assert!(push("per-thread")?);
+
+    for i in 0..8 {
+        assert_eq!(fs::read_to_string(work_path.join(format!("{}.txt", i)))?, format!("one\n{}\n", i));
+    }
+
+    Ok(())
+}
+
+/// Runs the retries of `io_retry` on an operation that fails with the
+/// `errors` first. Returns the result and how many times it was called.
+#[cfg(test)]
+fn run_failing(io_retry: &IoRetry, errors: &[i32]) -> (Result<&'static str, io::Error>, usize) {
+    let mut calls = 0;
+    let result = io_retry.run(|| {
+        calls += 1;
+        match errors.get(calls - 1) {
+            Some(&errno) => Err(io::Error::from_raw_os_error(errno)),
+            None => Ok("loaded"),
+        }
+    });
+    (result, calls)
+}
+
+#[cfg(test)]
+#[test]
+fn io_retry_of_transient_errors() {
+    let io_retry = IoRetry { backoff: Duration::from_millis(1), ..IoRetry::new(2) };
+
+    let (result, calls) = run_failing(&io_retry, &[libc::EAGAIN, libc::EINTR]);
+    assert_eq!(result.unwrap(), "loaded");
+    assert_eq!(calls, 3);
+
+    // Used up
+    let (result, calls) = run_failing(&io_retry, &[libc::EAGAIN, libc::EAGAIN, libc::EAGAIN]);
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EAGAIN));
+    assert_eq!(calls, 3);
+
+    // Not retryable
+    let (result, calls) = run_failing(&io_retry, &[libc::ENOENT]);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(calls, 1);
+
+    // No retries by default
+    let (result, calls) = run_failing(&IoRetry::default(), &[libc::EINTR]);
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+}
+
+#[cfg(all(test, unix))]
+#[cfg(feature = "bencher")]
+mod benchmarks {
+    use super::*;
+    use rayon::prelude::*;
+    use test::{Bencher, black_box};
+
+    use crate::arena::MmapArena;
+
+    /// Keep `count` small pieces of data in one arena from 16 threads, so
+    /// they mostly wait for the lock.
+    fn bench_store_data(b: &mut Bencher, count: usize) {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(16).build().unwrap();
+
+        b.iter(|| {
+            let arena = MmapArena::new();
+            pool.install(|| (0..count).into_par_iter().for_each(|i| {
+                black_box(arena.store_data(Box::new([i as u8])));
+            }));
+        });
+    }
+
+    #[bench]
+    fn bench_mmap_arena_store_data_16_threads(b: &mut Bencher) {
+        bench_store_data(b, 100_000);
+    }
+
+    #[bench]
+    fn bench_mmap_arena_load_file_16_threads(b: &mut Bencher) {
+        let work_dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = (0..1000).map(|i| work_dir.path().join(format!("{}.txt", i))).collect();
+        for path in &paths {
+            fs::write(path, "some content\n").unwrap();
+        }
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(16).build().unwrap();
+
+        b.iter(|| {
+            let arena = MmapArena::new();
+            pool.install(|| paths.par_iter().for_each(|path| {
+                black_box(arena.load_file(path).unwrap());
+            }));
+        });
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::push;
+
+#[cfg(test)]
+fn write_work_tree(work_path: &Path) -> Result<()> {
+    fs::create_dir_all(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/change.patch"), "\
+--- a/dir/file.txt
++++ b/dir/file.txt
+@@ -1 +1 @@
+-1
++one
+--- /dev/null
++++ b/dir/new.txt
+@@ -0,0 +1 @@
++new
+")?;
+    fs::write(work_path.join("series"), "change.patch\n")?;
+    fs::create_dir(work_path.join("dir"))?;
+    fs::write(work_path.join("dir/file.txt"), "1\n")?;
+    Ok(())
+}
+
+/// The names in the `directory`, sorted.
+#[cfg(test)]
+fn directory_names(directory: &Path) -> Result<Vec<String>> {
+    let mut names = fs::read_dir(directory)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+#[test]
+fn save_replaces_file() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    write_work_tree(work_path)?;
+    fs::hard_link(work_path.join("dir/file.txt"), work_path.join("link.txt"))?;
+
+    assert!(push(work_path, &[])?);
+
+    assert_eq!(fs::read_to_string(work_path.join("dir/file.txt"))?, "one\n");
+    assert_eq!(fs::read_to_string(work_path.join("dir/new.txt"))?, "new\n");
+    // The hard link is replaced, not edited. No temporary file is left.
+    assert_eq!(fs::read_to_string(work_path.join("link.txt"))?, "1\n");
+    assert_eq!(directory_names(&work_path.join("dir"))?, ["file.txt", "new.txt"]);
+
+    // The new file has the default permissions, not those of a temporary file.
+    fs::write(work_path.join("default.txt"), "")?;
+    assert_eq!(fs::metadata(work_path.join("dir/new.txt"))?.permissions(),
+               fs::metadata(work_path.join("default.txt"))?.permissions());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+#[cfg(unix)]
+fn save_on_other_device_than_tmpdir() -> Result<()> {
+    use std::env;
+    use std::os::unix::fs::MetadataExt;
+
+    // The files must not be written into `$TMPDIR` first, they could not be
+    // renamed from there. Skipped if there is no other filesystem to use.
+    let tmp_device = fs::metadata(env::temp_dir())?.dev();
+    let Some(other_dir) = ["/dev/shm", "/run/user"].iter()
+        .map(Path::new)
+        .find(|dir| fs::metadata(dir).is_ok_and(|metadata| metadata.dev() != tmp_device)) else {
+        return Ok(());
+    };
+    let Ok(work_dir) = tempfile::tempdir_in(other_dir) else {
+        return Ok(());
+    };
+    let work_path = work_dir.path();
+    write_work_tree(work_path)?;
+
+    assert!(push(work_path, &[])?);
+
+    assert_eq!(fs::read_to_string(work_path.join("dir/file.txt"))?, "one\n");
+    assert_eq!(fs::read_to_string(work_path.join("dir/new.txt"))?, "new\n");
+    assert_eq!(directory_names(&work_path.join("dir"))?, ["file.txt", "new.txt"]);
+
+    Ok(())
+}
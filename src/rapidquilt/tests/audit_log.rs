@@ -0,0 +1,123 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::audit::hash_content;
+use crate::cmd;
+use super::push;
+use super::quilt_metadata::copy_tree;
+
+/// Push all patches from the test directory with audit log enabled and
+/// return the records from the log, sorted.
+#[cfg(test)]
+fn push_with_audit_log(path: &Path, expect: bool) -> Result<Vec<String>> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path().join("work");
+    fs::create_dir(&work_path)?;
+    copy_tree(&path.join("input"), &work_path)?;
+
+    let audit_log = work_dir.path().join("audit.jsonl");
+
+    let args = [
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--threads"), OsStr::new("1"),
+        OsStr::new("--all"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+        OsStr::new("--audit-log"), audit_log.as_os_str(),
+    ];
+    assert_eq!(cmd::run(&args)?, expect);
+
+    read_records(&audit_log)
+}
+
+/// Read the records from the `audit_log`, sorted and without the timestamps.
+#[cfg(test)]
+fn read_records(audit_log: &Path) -> Result<Vec<String>> {
+    let mut records: Vec<_> = fs::read_to_string(audit_log)?
+        .lines()
+        .map(|line| {
+            // The timestamp is different every time, cut it out.
+            let end = line.find(",\"timestamp\":").expect("Record has timestamp");
+            assert!(line.ends_with("\"}"));
+            line[..end].to_string()
+        })
+        .collect();
+    records.sort();
+    Ok(records)
+}
+
+#[cfg(test)]
+fn hash_file(path: &str) -> String {
+    hash_content(&fs::read(path).unwrap())
+}
+
+#[cfg(test)]
+#[test]
+fn audit_log_success() -> Result<()> {
+    let records = push_with_audit_log(Path::new("testdata/quilt/ok/basic"), true)?;
+
+    assert_eq!(records, [
+        format!(r#"{{"path":"file.in","operation":"delete","old_hash":"{}","new_hash":null,"patch":"delete-create.patch""#,
+                hash_file("testdata/quilt/ok/basic/input/file.in")),
+        format!(r#"{{"path":"file.out","operation":"create","old_hash":null,"new_hash":"{}","patch":"git-rename.patch""#,
+                hash_file("testdata/quilt/ok/basic/expect/file.out")),
+    ]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn audit_log_failure() -> Result<()> {
+    let records = push_with_audit_log(Path::new("testdata/quilt/fail/mismatch"), false)?;
+
+    assert_eq!(records, [
+        format!(r#"{{"path":"file.in","operation":"modify","old_hash":"{}","new_hash":"{}","patch":"modify-ddd.patch""#,
+                hash_file("testdata/quilt/fail/mismatch/input/file.in"),
+                hash_file("testdata/quilt/fail/mismatch/expect/file.in")),
+        format!(r#"{{"path":"file.in.rej","operation":"create","old_hash":null,"new_hash":"{}","patch":"wrong-ddd.patch""#,
+                hash_file("testdata/quilt/fail/mismatch/expect/file.in.rej")),
+    ]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn audit_log_pop() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path().join("work");
+    fs::create_dir(&work_path)?;
+    copy_tree(Path::new("testdata/quilt/ok/basic/input"), &work_path)?;
+    assert!(push(&work_path, &["--all", "--backup", "always"])?);
+
+    let audit_log = work_dir.path().join("audit.jsonl");
+    assert!(cmd::run([
+        OsStr::new("pop"),
+        OsStr::new("--quiet"),
+        OsStr::new("--all"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+        OsStr::new("--audit-log"), audit_log.as_os_str(),
+    ])?);
+
+    // The files are put back the way they were before each patch.
+    let expect = "testdata/quilt/ok/basic/expect";
+    assert_eq!(read_records(&audit_log)?, [
+        format!(r#"{{"path":"file.in","operation":"create","old_hash":null,"new_hash":"{}","patch":"delete-create.patch""#,
+                hash_file(&format!("{}/.pc/delete-create.patch/file.in", expect))),
+        format!(r#"{{"path":"file.in","operation":"modify","old_hash":"{}","new_hash":"{}","patch":"modify-ddd.patch""#,
+                hash_file(&format!("{}/.pc/delete-create.patch/file.in", expect)),
+                hash_file("testdata/quilt/ok/basic/input/file.in")),
+        format!(r#"{{"path":"file.out","operation":"delete","old_hash":"{}","new_hash":null,"patch":"git-rename.patch""#,
+                hash_file(&format!("{}/file.out", expect))),
+        format!(r#"{{"path":"file.tmp","operation":"create","old_hash":null,"new_hash":"{}","patch":"git-rename.patch""#,
+                hash_file(&format!("{}/.pc/git-rename.patch/file.tmp", expect))),
+        format!(r#"{{"path":"file.tmp","operation":"delete","old_hash":"{}","new_hash":null,"patch":"delete-create.patch""#,
+                hash_file(&format!("{}/.pc/git-rename.patch/file.tmp", expect))),
+    ]);
+
+    Ok(())
+}
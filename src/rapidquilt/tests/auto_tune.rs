@@ -0,0 +1,72 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::apply::{
+    ApplyConfig,
+    ApplyConfigDoBackups,
+    tune_threads,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::{Arena, FileArena};
+use crate::cmd;
+use super::quilt_metadata::copy_tree;
+
+#[cfg(test)]
+#[test]
+fn tuned_threads_in_range() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    let mut series_patches = Vec::new();
+    for i in 0..20 {
+        fs::write(work_path.join(format!("{}.txt", i)), "1\n2\n3\n")?;
+        fs::write(patches_path.join(format!("{}.patch", i)),
+                  format!("--- a/{0}.txt\n+++ b/{0}.txt\n@@ -1,3 +1,3 @@\n 1\n-2\n+two\n 3\n", i))?;
+        series_patches.push(SeriesPatch { filename: PathBuf::from(format!("{}.patch", i)), strip: 1, reverse: false });
+    }
+
+    // The calibration does not save anything, even if the push would.
+    let config = ApplyConfig {
+        do_backups: ApplyConfigDoBackups::Always,
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Normal)
+    };
+
+    let build_arena = |_threads: usize| -> Result<Box<dyn Arena>> { Ok(Box::new(FileArena::new())) };
+    for max_threads in [1, 2, 3, 4, 8] {
+        let threads = tune_threads(&config, &build_arena, max_threads)?;
+        assert!((1..=max_threads).contains(&threads), "{} of at most {}", threads, max_threads);
+        assert!(threads.is_power_of_two() || threads == max_threads, "{} of at most {}", threads, max_threads);
+    }
+
+    assert_eq!(fs::read_to_string(work_path.join("0.txt"))?, "1\n2\n3\n");
+    assert!(!work_path.join(".pc").exists());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn auto_tuned_push() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let test_path = Path::new("testdata/quilt/ok/basic");
+    copy_tree(&test_path.join("input"), work_path)?;
+
+    assert!(cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--all"),
+        OsStr::new("--threads"), OsStr::new("auto-tune"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+    assert_eq!(fs::read(work_path.join("file.out"))?, fs::read(test_path.join("expect/file.out"))?);
+    assert_eq!(fs::read(work_path.join(".pc/applied-patches"))?, fs::read(test_path.join("expect/.pc/applied-patches"))?);
+    assert!(!work_path.join("file.in").exists());
+
+    Ok(())
+}
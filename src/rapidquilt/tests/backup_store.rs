@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+use libpatch::modified_file::ModifiedFile;
+
+use crate::apply::{
+    ApplyConfig,
+    ApplyConfigDoBackups,
+    BackupFile,
+    BackupStore,
+    apply_patches,
+    apply_patches_parallel,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+use crate::pop::restore_backup_files;
+
+/// Keeps the backups by patch and file name, without permissions.
+#[derive(Debug, Default)]
+struct MemoryBackupStore {
+    files: Mutex<BTreeMap<(PathBuf, PathBuf), Vec<u8>>>,
+}
+
+impl BackupStore for MemoryBackupStore {
+    fn save(&self, patch_filename: &Path, filename: &Path, original_file: &ModifiedFile) -> Result<(), io::Error> {
+        let mut content = Vec::new();
+        original_file.write_to(&mut content)?;
+        self.files.lock().unwrap().insert((patch_filename.to_path_buf(), filename.to_path_buf()), content);
+        Ok(())
+    }
+
+    fn list(&self, patch_filename: &Path) -> Result<Option<Vec<PathBuf>>, io::Error> {
+        let files: Vec<_> = self.files.lock().unwrap().keys()
+            .filter(|(patch, _)| patch == patch_filename)
+            .map(|(_, filename)| filename.clone())
+            .collect();
+        Ok(if files.is_empty() { None } else { Some(files) })
+    }
+
+    fn restore(&self, patch_filename: &Path, filename: &Path) -> Result<Option<BackupFile>, io::Error> {
+        let files = self.files.lock().unwrap();
+        match files.get(&(patch_filename.to_path_buf(), filename.to_path_buf())) {
+            Some(content) if content.is_empty() => Ok(None),
+            Some(content) => Ok(Some(BackupFile { content: content.clone(), permissions: None })),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn remove(&self, patch_filename: &Path) -> Result<(), io::Error> {
+        self.files.lock().unwrap().retain(|(patch, _), _| patch != patch_filename);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn apply_with_store(work_path: &Path, backup_store: &dyn BackupStore, parallel: bool) -> Result<usize> {
+    let patches_path = work_path.join("patches");
+    let series_patches = [
+        SeriesPatch { filename: "modify.patch".into(), strip: 1, reverse: false },
+        SeriesPatch { filename: "create.patch".into(), strip: 1, reverse: false },
+    ];
+    let config = ApplyConfig {
+        do_backups: ApplyConfigDoBackups::Always,
+        backup_store: Some(backup_store),
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet)
+    };
+
+    let arena = FileArena::new();
+    let result = if parallel {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+        pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?
+    } else {
+        apply_patches(&config, &arena, &AnalysisSet::default())?
+    };
+    Ok(result.applied_patches)
+}
+
+#[cfg(test)]
+#[test]
+fn backups_in_memory() -> Result<()> {
+    for parallel in [false, true] {
+        let work_dir = tempfile::tempdir()?;
+        let work_path = work_dir.path();
+        fs::create_dir(work_path.join("patches"))?;
+        fs::write(work_path.join("patches/modify.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-original
++patched
+")?;
+        fs::write(work_path.join("patches/create.patch"), "\
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+")?;
+        fs::write(work_path.join("file.txt"), "original\n")?;
+
+        let backup_store = MemoryBackupStore::default();
+        assert_eq!(apply_with_store(work_path, &backup_store, parallel)?, 2);
+        assert!(!work_path.join(".pc").exists());
+        assert_eq!(*backup_store.files.lock().unwrap(), BTreeMap::from([
+            ((PathBuf::from("create.patch"), PathBuf::from("new.txt")), Vec::new()),
+            ((PathBuf::from("modify.patch"), PathBuf::from("file.txt")), b"original\n".to_vec()),
+        ]));
+
+        // Popping works from the same store
+        restore_backup_files(work_path, &backup_store, Path::new("create.patch"), Path::new("create.patch"), false, None)?;
+        assert!(!work_path.join("new.txt").exists());
+        restore_backup_files(work_path, &backup_store, Path::new("modify.patch"), Path::new("modify.patch"), false, None)?;
+        assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "original\n");
+        assert!(backup_store.files.lock().unwrap().is_empty());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::arena::FileArena;
+use crate::cmd;
+use crate::patch_source::{BundleSource, PatchSource};
+
+const PATCH_A: &str = "\
+--- a/a.txt
++++ b/a.txt
+@@ -1 +1 @@
+-a
++A
+";
+
+/// Has a line that looks like a section header.
+const PATCH_B: &str = "\
+--- a/dir/b.txt
++++ b/dir/b.txt
+@@ -1,2 +1,2 @@
+-patch fake.patch 3
++b
+ end
+";
+
+/// The bundle with the `series` and the `patches`, as the README shows to
+/// make it.
+#[cfg(test)]
+fn make_bundle(series: &str, patches: &[(&str, &str)]) -> Vec<u8> {
+    let mut bundle = format!("rapidquilt bundle\nseries {}\n{}", series.len(), series);
+    for (filename, patch) in patches {
+        bundle.push_str(&format!("patch {} {}\n{}", filename, patch.len(), patch));
+    }
+    bundle.into_bytes()
+}
+
+#[cfg(test)]
+fn bundle_error(bundle: &[u8]) -> String {
+    BundleSource::read(bundle).unwrap_err().to_string()
+}
+
+#[cfg(test)]
+#[test]
+fn bundle_parsed() -> Result<()> {
+    let series = "a.patch\n\"with space.patch\" -p1\n";
+    let bundle = make_bundle(series, &[("a.patch", PATCH_A), ("with space.patch", PATCH_B)]);
+    let bundle_source = BundleSource::read(&bundle[..])?;
+    assert_eq!(bundle_source.series(), series.as_bytes());
+
+    let arena = FileArena::new();
+    assert_eq!(bundle_source.load_patch(&arena, Path::new("a.patch"))?, PATCH_A.as_bytes());
+    assert_eq!(bundle_source.load_patch(&arena, Path::new("with space.patch"))?, PATCH_B.as_bytes());
+    assert_eq!(bundle_source.load_patch(&arena, Path::new("fake.patch")).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    assert!(bundle_error(b"series 0\n").contains("not a bundle"));
+    assert!(bundle_error(&make_bundle("", &[])[..18]).contains("no series"));
+    assert!(bundle_error(&bundle[..bundle.len() - 1]).contains("ends inside of the section \"patch with space.patch"));
+    assert!(bundle_error(b"rapidquilt bundle\nseries 18446744073709551615\n").contains("ends inside of the section \"series"));
+    assert!(bundle_error(b"rapidquilt bundle\nseries x\n").contains("Bad section header \"series x\""));
+    assert!(bundle_error(b"rapidquilt bundle\nfile a 0\n").contains("Bad section header \"file a 0\""));
+    assert!(bundle_error(&make_bundle("", &[("a.patch", ""), ("a.patch", "")])).contains("more than one patch \"a.patch\""));
+
+    Ok(())
+}
+
+/// Run `rapidquilt` with the file at the `input_path` as its standard input.
+#[cfg(all(test, unix))]
+fn run_with_stdin(input_path: &Path, args: &[&OsStr]) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let input = File::open(input_path)?;
+    // NOTE: The standard input is of the whole process, no other test reads it.
+    let saved_stdin = unsafe { libc::dup(0) };
+    assert!(saved_stdin >= 0);
+    assert_eq!(unsafe { libc::dup2(input.as_raw_fd(), 0) }, 0);
+    let result = cmd::run(args.to_vec());
+    assert_eq!(unsafe { libc::dup2(saved_stdin, 0) }, 0);
+    unsafe { libc::close(saved_stdin) };
+    result
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn push_bundle_from_stdin() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path().join("work");
+    fs::create_dir_all(work_path.join("dir"))?;
+    fs::write(work_path.join("a.txt"), "a\n")?;
+    fs::write(work_path.join("dir/b.txt"), "patch fake.patch 3\nend\n")?;
+
+    let bundle_path = work_dir.path().join("bundle");
+    fs::write(&bundle_path, make_bundle("a.patch\n\"with space.patch\"\n",
+                                        &[("a.patch", PATCH_A), ("with space.patch", PATCH_B)]))?;
+
+    let args = [
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("-a"),
+        OsStr::new("--bundle"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    assert!(run_with_stdin(&bundle_path, &args)?);
+    assert_eq!(fs::read_to_string(work_path.join("a.txt"))?, "A\n");
+    assert_eq!(fs::read_to_string(work_path.join("dir/b.txt"))?, "b\nend\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, "a.patch\nwith space.patch\n");
+    assert!(!work_path.join("patches").exists());
+    assert!(!work_path.join("series").exists());
+
+    // The patches are already applied
+    assert!(run_with_stdin(&bundle_path, &args)?);
+    assert_eq!(fs::read_to_string(work_path.join("a.txt"))?, "A\n");
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use libpatch::patch::unified::parser::parse_patch;
+
+use crate::apply::{ApplyError, CaseGuard, is_case_insensitive};
+use crate::cmd;
+
+const PATCH_UPPER: &str = "\
+--- a/Foo.c
++++ b/Foo.c
+@@ -1 +1 @@
+-old
++upper
+";
+
+const PATCH_LOWER: &str = "\
+--- a/foo.c
++++ b/foo.c
+@@ -1 +1 @@
+-old
++lower
+";
+
+#[cfg(test)]
+#[test]
+fn case_guard_flags_collisions() -> Result<()> {
+    let upper = parse_patch(PATCH_UPPER.as_bytes(), 1)?;
+    let lower = parse_patch(PATCH_LOWER.as_bytes(), 1)?;
+
+    // The same file again is fine
+    let mut case_guard = CaseGuard::default();
+    case_guard.check_patch(Path::new("upper.patch"), &upper)?;
+    case_guard.check_patch(Path::new("upper-again.patch"), &upper)?;
+
+    let error = case_guard.check_patch(Path::new("lower.patch"), &lower).unwrap_err();
+    match error.downcast_ref::<ApplyError>() {
+        Some(ApplyError::CaseCollision { patch_filename, filename, other_patch_filename, other_filename }) => {
+            assert_eq!(patch_filename, Path::new("lower.patch"));
+            assert_eq!(filename, Path::new("foo.c"));
+            assert_eq!(other_patch_filename, Path::new("upper.patch"));
+            assert_eq!(other_filename, Path::new("Foo.c"));
+        }
+        _ => panic!("Unexpected error: {}", error),
+    }
+
+    // Two files of one patch collide too
+    let both_data = format!("{}{}", PATCH_UPPER, PATCH_LOWER);
+    let both = parse_patch(both_data.as_bytes(), 1)?;
+    assert!(CaseGuard::default().check_patch(Path::new("both.patch"), &both).is_err());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn case_sensitivity_detected() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+
+    // Without names with letters, it is taken as case-sensitive.
+    fs::write(work_path.join("1234"), "")?;
+    assert!(!is_case_insensitive(work_path)?);
+
+    fs::write(work_path.join("probe.txt"), "")?;
+    let case_insensitive = work_path.join("PROBE.TXT").exists();
+    assert_eq!(is_case_insensitive(work_path)?, case_insensitive);
+
+    // A missing directory is on the filesystem of its parent
+    assert_eq!(is_case_insensitive(&work_path.join("missing/dir"))?, case_insensitive);
+
+    Ok(())
+}
+
+/// Only runs on a case-insensitive filesystem, e.g. on macOS or Windows.
+#[cfg(test)]
+#[test]
+fn push_fails_on_case_collision() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/upper.patch"), PATCH_UPPER)?;
+    fs::write(work_path.join("patches/lower.patch"), PATCH_LOWER)?;
+    fs::write(work_path.join("series"), "upper.patch\nlower.patch\n")?;
+    fs::write(work_path.join("Foo.c"), "old\n")?;
+
+    if !is_case_insensitive(work_path)? {
+        eprintln!("Skipped, the temporary directory is case-sensitive.");
+        return Ok(());
+    }
+
+    for threads in ["1", "2"] {
+        let error = cmd::run([
+            OsStr::new("push"),
+            OsStr::new("--quiet"),
+            OsStr::new("--all"),
+            OsStr::new("--threads"), OsStr::new(threads),
+            OsStr::new("--directory"), work_path.as_os_str(),
+        ]).unwrap_err();
+        assert!(matches!(error.downcast_ref::<ApplyError>(), Some(ApplyError::CaseCollision { .. })), "{:?}", error);
+        assert_eq!(fs::read_to_string(work_path.join("Foo.c"))?, "old\n");
+    }
+
+    Ok(())
+}
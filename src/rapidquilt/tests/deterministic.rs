@@ -0,0 +1,77 @@
+use std::fs;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    apply_patches_parallel,
+    write_buffered_messages,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+
+/// Number of files patched by the test series, so they are spread over all
+/// threads.
+#[cfg(test)]
+const FILE_COUNT: usize = 16;
+
+/// Apply a series whose second patch fails in every file, using 4 threads.
+/// Returns everything the application would print.
+#[cfg(test)]
+fn apply_failing_series() -> Result<String> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+
+    let mut good_patch = String::new();
+    let mut bad_patch = String::new();
+    for i in 0..FILE_COUNT {
+        fs::write(work_path.join(format!("file{}.txt", i)), "original\n")?;
+        good_patch.push_str(&format!("--- a/file{0}.txt\n+++ b/file{0}.txt\n@@ -1 +1 @@\n-original\n+patched\n", i));
+        bad_patch.push_str(&format!("--- a/file{0}.txt\n+++ b/file{0}.txt\n@@ -1 +1 @@\n-mismatch\n+broken\n", i));
+    }
+    fs::write(patches_path.join("good.patch"), good_patch)?;
+    fs::write(patches_path.join("bad.patch"), bad_patch)?;
+
+    let series_patches = [
+        SeriesPatch { filename: "good.patch".into(), strip: 1, reverse: false },
+        SeriesPatch { filename: "bad.patch".into(), strip: 1, reverse: false },
+    ];
+    let config = ApplyConfig {
+        show_rejects_inline: true,
+        deterministic: true,
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Normal)
+    };
+
+    let arena = FileArena::new();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build()?;
+    let result = pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?;
+    assert_eq!(result.applied_patches, 1);
+
+    let mut output = Vec::new();
+    let mut error_output = Vec::new();
+    write_buffered_messages(&mut output, &mut error_output, &result.messages)?;
+    output.extend(error_output);
+    output.extend(result.inline_rejects.as_bytes());
+
+    Ok(String::from_utf8(output)?)
+}
+
+#[cfg(test)]
+#[test]
+fn deterministic_output() -> Result<()> {
+    colored::control::set_override(false);
+
+    let expected = apply_failing_series()?;
+    assert_eq!(expected.matches("Saving rejects to").count(), FILE_COUNT);
+    assert_eq!(expected.matches("Patch bad.patch FAILED").count(), 1);
+
+    for _ in 0..10 {
+        assert_eq!(apply_failing_series()?, expected);
+    }
+
+    Ok(())
+}
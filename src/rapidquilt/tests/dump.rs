@@ -0,0 +1,93 @@
+use anyhow::Result;
+
+use libpatch::patch::unified::parser::parse_patch;
+
+use crate::dump::{write_dump, write_dump_json};
+
+const PATCH: &[u8] = b"\
+Rename and change the mode
+
+diff --git a/old.c b/new.c
+old mode 100644
+new mode 100755
+similarity index 90%
+rename from old.c
+rename to new.c
+index 1234567..89abcde
+--- a/old.c
++++ b/new.c
+@@ -10,4 +10,4 @@ int main()
+ one
+-two\r
++TWO
+ three
+ four
+";
+
+#[cfg(test)]
+#[test]
+fn dump_parsed_patch() -> Result<()> {
+    colored::control::set_override(false);
+
+    let patch = parse_patch(PATCH, 0)?;
+    let mut output = Vec::new();
+    write_dump(&mut output, &patch)?;
+
+    let mode = if cfg!(unix) { "100644 -> 100755" } else { "none -> none" };
+    assert_eq!(String::from_utf8(output)?, format!("\
+File: \"a/old.c\" -> \"b/new.c\"
+  Kind: modify
+  Rename: yes
+  Mode: {}
+  Index: 1234567 -> 89abcde
+  Hunk 1: old line 9, 4 lines; new line 9, 4 lines; context 1 before, 2 after; function \"int main()\"
+      \"one\\n\"
+    - \"two\\r\\n\"
+    + \"TWO\\n\"
+      \"three\\n\"
+      \"four\\n\"
+", mode));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn dump_parsed_patch_as_json() -> Result<()> {
+    let patch = parse_patch(PATCH, 1)?;
+    let mut output = Vec::new();
+    write_dump_json(&mut output, &patch)?;
+
+    let (old_mode, new_mode) = if cfg!(unix) { (Some("100644"), Some("100755")) } else { (None, None) };
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&output)?, serde_json::json!({
+        "warnings": [],
+        "files": [{
+            "old_filename": "old.c",
+            "new_filename": "new.c",
+            "kind": "modify",
+            "rename": true,
+            "old_mode": old_mode,
+            "new_mode": new_mode,
+            "old_hash": "1234567",
+            "new_hash": "89abcde",
+            "hunks": [{
+                "old_line": 9,
+                "old_count": 4,
+                "new_line": 9,
+                "new_count": 4,
+                "prefix_context": 1,
+                "suffix_context": 2,
+                "function": "int main()",
+                "lines": [
+                    {"type": "context", "text": "one\n"},
+                    {"type": "remove", "text": "two\r\n"},
+                    {"type": "add", "text": "TWO\n"},
+                    {"type": "context", "text": "three\n"},
+                    {"type": "context", "text": "four\n"},
+                ],
+            }],
+        }],
+    }));
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::apply::SeriesPatch;
+use crate::cmd;
+use super::push;
+
+#[cfg(test)]
+fn series_patch(filename: &str, strip: usize, reverse: bool) -> SeriesPatch {
+    SeriesPatch { filename: PathBuf::from(filename), strip, reverse }
+}
+
+#[cfg(test)]
+#[test]
+fn dump_series_order_comments_out_skipped_patches() -> Result<()> {
+    let series_patches = [
+        series_patch("first.patch", 1, false),
+        series_patch("second.patch", 0, true),
+        series_patch("third.patch", 1, false),
+        series_patch("fourth.patch", 2, false),
+    ];
+
+    let mut output = Vec::new();
+    cmd::write_series_order(&mut output, &series_patches, 1, 3)?;
+    assert_eq!(String::from_utf8(output)?,
+               "# first.patch -p1 (applied)\n\
+                second.patch -p0 -R\n\
+                third.patch -p1\n\
+                # fourth.patch -p2 (after the goal)\n");
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn dump_series_order_applies_nothing() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/first.patch"),
+              "--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-a\n+first\n")?;
+    fs::write(work_path.join("patches/second.patch"),
+              "--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-first\n+second\n")?;
+    fs::write(work_path.join("series"), "first.patch\nsecond.patch\n")?;
+    fs::write(work_path.join("a.txt"), "a\n")?;
+
+    assert!(push(work_path, &["--dump-series-order", "second.patch"])?);
+    assert!(push(work_path, &["--dump-series-order", "--all"])?);
+    assert_eq!(fs::read_to_string(work_path.join("a.txt"))?, "a\n");
+    assert!(!work_path.join(".pc").exists());
+
+    // Also with every patch applied, where there is nothing to do.
+    assert!(push(work_path, &["--all"])?);
+    assert!(push(work_path, &["--dump-series-order", "--all"])?);
+    assert_eq!(fs::read_to_string(work_path.join("a.txt"))?, "second\n");
+
+    assert!(push(work_path, &["--dump-series-order", "--resume"]).is_err());
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+use super::quilt_metadata::copy_tree;
+use super::push;
+
+#[cfg(test)]
+#[test]
+fn duplicate_series_line_rejected() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    for name in ["a", "b"] {
+        fs::write(work_path.join(format!("patches/{}.patch", name)),
+                  format!("--- /dev/null\n+++ b/{0}.txt\n@@ -0,0 +1 @@\n+{0}\n", name))?;
+    }
+    fs::write(work_path.join("series"), "a.patch\nb.patch\n# a.patch\n\na.patch -p1\n")?;
+
+    let error = push(work_path, &["--all"]).unwrap_err();
+    assert_eq!(error.to_string(), "Patch a.patch is in the series twice, on lines 1 and 5 \
+                                   (annotate it with \"#allow-duplicate\" or use --allow-duplicates to apply it twice)");
+    assert!(!work_path.join("a.txt").exists());
+
+    // Only a warning, the first patches still apply
+    assert!(push(work_path, &["--allow-duplicates", "2"])?);
+    assert_eq!(fs::read_to_string(work_path.join("a.txt"))?, "a\n");
+    assert_eq!(fs::read_to_string(work_path.join("b.txt"))?, "b\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn annotated_duplicate_popped() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/duplicate-patch/input"), work_path)?;
+
+    // Every application has its own backup
+    assert!(push(work_path, &["--all", "--backup", "always"])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?,
+               "feature.patch\nmove-config.patch\nfeature.patch\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/feature.patch/config"))?, "base\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/feature.patch~2/config"))?, "base\n");
+
+    let pop = |count: &str| cmd::run([
+        OsStr::new("pop"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+        OsStr::new(count),
+    ]);
+    assert!(pop("1")?);
+    assert_eq!(fs::read_to_string(work_path.join("config"))?, "base\n");
+    assert!(!work_path.join(".pc/feature.patch~2").exists());
+    assert!(work_path.join(".pc/feature.patch").exists());
+
+    assert!(pop("2")?);
+    assert_eq!(fs::read_to_string(work_path.join("config"))?, "base\n");
+    assert!(!work_path.join("config-old").exists());
+    assert!(!work_path.join(".pc/feature.patch").exists());
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::apply::{FileBackupStore, Verbosity};
+use crate::cmd;
+use crate::edit::{add_files, launch_editor};
+
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/change.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-1
++one
+")?;
+    fs::write(work_path.join("series"), "change.patch\n")?;
+    fs::write(work_path.join("file.txt"), "1\n")?;
+    fs::write(work_path.join("other.txt"), "other\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn run_in(work_path: &Path, command: &str, args: &[&str]) -> Result<bool> {
+    let mut all_args = vec![
+        OsStr::new(command),
+        OsStr::new("--quiet"),
+        OsStr::new("--backup"), OsStr::new("always"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    all_args.extend(args.iter().map(OsStr::new));
+    cmd::run(all_args)
+}
+
+#[cfg(test)]
+#[test]
+fn edit_adds_files_to_top_patch() -> Result<()> {
+    // It would really launch the editor
+    if io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+
+    // No patch to add the files to
+    assert!(run_in(work_path, "edit", &["other.txt"]).is_err());
+
+    assert!(run_in(work_path, "push", &[])?);
+    assert!(run_in(work_path, "edit", &["other.txt", "dir/new.txt"])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/change.patch/other.txt"))?, "other\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/change.patch/dir/new.txt"))?, "");
+
+    // The backup of the file already in the patch is kept
+    assert!(run_in(work_path, "edit", &["file.txt"])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/change.patch/file.txt"))?, "1\n");
+
+    // Popping restores the added files
+    fs::write(work_path.join("other.txt"), "edited\n")?;
+    fs::create_dir(work_path.join("dir"))?;
+    fs::write(work_path.join("dir/new.txt"), "new\n")?;
+    assert!(run_in(work_path, "pop", &[])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "1\n");
+    assert_eq!(fs::read_to_string(work_path.join("other.txt"))?, "other\n");
+    assert!(!work_path.join("dir/new.txt").exists());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn edit_rejects_unsafe_paths() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+    assert!(run_in(work_path, "push", &[])?);
+
+    for filename in ["../outside.txt", "/etc/passwd", ".pc/applied-patches"] {
+        assert!(add_files(work_path, &FileBackupStore::new(work_path), &[PathBuf::from(filename)], Verbosity::Quiet).is_err(), "{}", filename);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[cfg(test)]
+#[test]
+fn launch_editor_with_arguments() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+
+    let filenames = [PathBuf::from("file.txt"), PathBuf::from("other.txt")];
+    assert!(launch_editor("sed -i -e s/$/!/", work_path, &filenames)?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "1!\n");
+    assert_eq!(fs::read_to_string(work_path.join("other.txt"))?, "other!\n");
+
+    assert!(!launch_editor("false", work_path, &filenames)?);
+
+    Ok(())
+}
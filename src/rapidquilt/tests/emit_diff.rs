@@ -0,0 +1,164 @@
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    ApplyResult,
+    apply_patches,
+    apply_patches_parallel,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+
+const PATCH_1: &str = "\
+--- a/modify.txt
++++ b/modify.txt
+@@ -1,3 +1,3 @@
+ 1
+-2
++two
+ 3
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1,2 @@
++new
++file
+--- a/gone.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+";
+
+const PATCH_2: &str = "\
+--- a/modify.txt
++++ b/modify.txt
+@@ -6,3 +6,3 @@
+ 6
+-7
++seven
+ 8
+diff --git a/script.sh b/script.sh
+old mode 100644
+new mode 100755
+";
+
+/// The first file applies, the second does not, so the patch must be taken
+/// back completely.
+const PATCH_3: &str = "\
+--- a/new.txt
++++ b/new.txt
+@@ -1,2 +1,2 @@
+-new
++NEW
+ file
+--- a/modify.txt
++++ b/modify.txt
+@@ -1,3 +1,3 @@
+ 1
+-mismatch
++broken
+ 3
+";
+
+/// The net effect of the first two patches.
+const EXPECTED_DIFF: &str = "\
+diff --git a/gone.txt b/gone.txt
+deleted file mode 100644
+--- a/gone.txt
++++ /dev/null
+@@ -1,1 +0,0 @@
+-gone
+diff --git a/modify.txt b/modify.txt
+--- a/modify.txt
++++ b/modify.txt
+@@ -1,10 +1,10 @@
+ 1
+-2
++two
+ 3
+ 4
+ 5
+ 6
+-7
++seven
+ 8
+ 9
+ 10
+diff --git a/new.txt b/new.txt
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1,2 @@
++new
++file
+diff --git a/script.sh b/script.sh
+old mode 100644
+new mode 100755
+--- a/script.sh
++++ b/script.sh
+";
+
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("1.patch"), PATCH_1)?;
+    fs::write(patches_path.join("2.patch"), PATCH_2)?;
+    fs::write(patches_path.join("3.patch"), PATCH_3)?;
+
+    let numbers: String = (1..=10).map(|i| format!("{}\n", i)).collect();
+    fs::write(work_path.join("modify.txt"), numbers)?;
+    fs::write(work_path.join("gone.txt"), "gone\n")?;
+    fs::write(work_path.join("script.sh"), "#!/bin/sh\n")?;
+    for filename in ["modify.txt", "gone.txt", "script.sh"] {
+        fs::set_permissions(work_path.join(filename), Permissions::from_mode(0o644))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn apply_with_diff(work_path: &Path, threads: usize) -> Result<ApplyResult> {
+    let patches_path = work_path.join("patches");
+    let series_patches: Vec<_> = ["1.patch", "2.patch", "3.patch"].iter()
+        .map(|filename| SeriesPatch { filename: filename.into(), strip: 1, reverse: false })
+        .collect();
+    let config = ApplyConfig {
+        dry_run: true,
+        emit_diff: true,
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet)
+    };
+
+    let arena = FileArena::new();
+    if threads <= 1 {
+        apply_patches(&config, &arena, &AnalysisSet::default())
+    } else {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+        pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn combined_diff_matches_reference() -> Result<()> {
+    for threads in [1, 2] {
+        let work_dir = tempfile::tempdir()?;
+        setup_work_dir(work_dir.path())?;
+
+        let result = apply_with_diff(work_dir.path(), threads)?;
+        assert_eq!(result.applied_patches, 2);
+
+        let diff: Vec<u8> = result.file_diffs.iter().flat_map(|file_diff| file_diff.diff.iter().copied()).collect();
+        assert_eq!(String::from_utf8(diff)?, EXPECTED_DIFF);
+
+        // Nothing was written
+        assert!(!work_dir.path().join("new.txt").exists());
+        assert!(work_dir.path().join("gone.txt").exists());
+        assert_eq!(fs::metadata(work_dir.path().join("script.sh"))?.permissions().mode() & 0o777, 0o644);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd::{self, EXIT_FAILED, EXIT_INEXACT, EXIT_IO, EXIT_SERIES, EXIT_SUCCESS, EXIT_USAGE};
+
+const FILE: &str = "1\n2\n3\n4\n5\n6\n7\n8\n";
+
+/// Applies exactly
+const EXACT_PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+-1
++one
+ 2
+";
+
+/// Applies only with offset 2
+const OFFSET_PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -4,3 +4,3 @@
+ 6
+-7
++seven
+ 8
+";
+
+/// Does not apply at all
+const REJECTED_PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+-one
++uno
+ 2
+";
+
+#[cfg(test)]
+fn push_exit_code(patch: Option<&str>, args: &[&str]) -> Result<i32> {
+    command_exit_code("push", patch, args)
+}
+
+#[cfg(test)]
+fn command_exit_code(command: &str, patch: Option<&str>, args: &[&str]) -> Result<i32> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    if let Some(patch) = patch {
+        fs::write(work_path.join("patches/test.patch"), patch)?;
+    }
+    fs::write(work_path.join("series"), "test.patch\n")?;
+    fs::write(work_path.join("file.txt"), FILE)?;
+
+    let work_path = work_path.to_string_lossy();
+    let mut all_args = vec![command, "--quiet", "--directory", &work_path];
+    all_args.extend(args);
+    let result = cmd::run_with_outcome(all_args);
+    Ok(cmd::exit_code(&result))
+}
+
+#[cfg(test)]
+#[test]
+fn exit_codes_tell_why_push_failed() -> Result<()> {
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &[])?, EXIT_SUCCESS);
+    assert_eq!(push_exit_code(Some(OFFSET_PATCH), &[])?, EXIT_SUCCESS);
+    assert_eq!(push_exit_code(Some(OFFSET_PATCH), &["--strict"])?, EXIT_INEXACT);
+    assert_eq!(push_exit_code(Some(REJECTED_PATCH), &[])?, EXIT_FAILED);
+    assert_eq!(push_exit_code(Some(REJECTED_PATCH), &["--strict"])?, EXIT_FAILED);
+
+    // A missing patch, or one that is not in the series
+    assert_eq!(push_exit_code(None, &[])?, EXIT_SERIES);
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["other.patch"])?, EXIT_SERIES);
+
+    let missing_dir = Path::new("/nonexistent/provenance.json").to_string_lossy();
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["--provenance", &missing_dir])?, EXIT_IO);
+
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["--backup", "sometimes"])?, EXIT_USAGE);
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["--no-such-option"])?, EXIT_USAGE);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn bad_option_values_are_usage_errors() -> Result<()> {
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["--backup-count", "many"])?, EXIT_USAGE);
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["--threads", "many"])?, EXIT_USAGE);
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["--fuzz", "abc"])?, EXIT_USAGE);
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["--timings=many"])?, EXIT_USAGE);
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["--include", "["])?, EXIT_USAGE);
+    assert_eq!(command_exit_code("verify", Some(EXACT_PATCH), &["--fuzz", "abc", "."])?, EXIT_USAGE);
+
+    // The value must be attached, otherwise it would be the number of
+    // patches to push.
+    assert_eq!(push_exit_code(Some(EXACT_PATCH), &["--timings", "5"])?, EXIT_USAGE);
+
+    Ok(())
+}
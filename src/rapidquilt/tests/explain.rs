@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use libpatch::patch::unified::parser::parse_patch;
+
+use crate::apply::SeriesPatch;
+use crate::explain::write_explanation;
+
+const PATCH: &[u8] = b"\
+Move the driver and add a header for it
+
+diff --git a/old.c b/new.c
+old mode 100644
+new mode 100755
+similarity index 90%
+rename from old.c
+rename to new.c
+--- a/old.c
++++ b/new.c
+@@ -1,3 +1,3 @@
+ one
+-two
++TWO
+ three
+diff --git a/main.c b/main.c
+--- a/main.c
++++ b/main.c
+@@ -1,2 +1,3 @@
+ int main()
++{
+ }
+@@ -10,3 +11,2 @@
+ a
+-b
+-c
++d
+diff --git a/new.h b/new.h
+new file mode 100644
+--- /dev/null
++++ b/new.h
+@@ -0,0 +1,2 @@
++#pragma once
++int new(void);
+diff --git a/logo.png b/logo.png
+index 1234567..89abcde 100644
+Binary files a/logo.png and b/logo.png differ
+";
+
+#[cfg(test)]
+#[test]
+fn explain_mixed_patch() -> Result<()> {
+    let patch = parse_patch(PATCH, 1)?;
+    let series_patch = SeriesPatch { filename: PathBuf::from("move-driver.patch"), strip: 1, reverse: false };
+    let mut output = Vec::new();
+    write_explanation(&mut output, &series_patch, &patch)?;
+
+    let (rename_mode, create_mode) = if cfg!(unix) { (", mode 100644 to 100755", ", mode 100644") } else { ("", "") };
+    assert_eq!(String::from_utf8(output)?, format!("\
+Patch move-driver.patch changes 4 files with 4 hunks, 5 lines added and 3 removed.
+  Renamed old.c to new.c{}: 1 hunk, 1 line added and 1 removed
+  Modified main.c: 2 hunks, 2 lines added and 2 removed
+  Created new.h{}: 1 hunk, 2 lines added and 0 removed
+  Binary change of logo.png, not applied
+", rename_mode, create_mode));
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::apply::{PatchedFile, write_filtered_files_report};
+use crate::cmd;
+use crate::file_filter::FileFilter;
+
+#[cfg(test)]
+fn included(glob: &str, path: &str) -> bool {
+    let mut filter = FileFilter::new();
+    filter.include(glob).unwrap();
+    filter.is_included(Path::new(path))
+}
+
+#[cfg(test)]
+#[test]
+fn file_filter_globs() {
+    assert!(included("*.c", "main.c"));
+    assert!(included("*.c", "drivers/net/main.c"));
+    assert!(!included("*.c", "main.h"));
+    assert!(included("src/?.rs", "src/a.rs"));
+    assert!(!included("src/?.rs", "src/ab.rs"));
+    assert!(included("[ab].txt", "b.txt"));
+    assert!(!included("[!ab].txt", "b.txt"));
+    assert!(included("[a&&b].txt", "&.txt"));
+    assert!(included("a+b(c).txt", "a+b(c).txt"));
+    assert!(!included("a.txt", "aatxt"));
+}
+
+#[cfg(test)]
+#[test]
+fn file_filter_rules() {
+    let mut filter = FileFilter::new();
+    assert!(filter.is_included(Path::new("anything")));
+
+    // The first matching rule wins
+    filter.exclude("docs/internal/*").unwrap();
+    filter.include("docs/*").unwrap();
+    assert!(filter.is_included(Path::new("docs/readme.txt")));
+    assert!(!filter.is_included(Path::new("docs/internal/notes.txt")));
+
+    // With include rules, files that do not match anything are excluded
+    assert!(!filter.is_included(Path::new("src/main.c")));
+
+    // With only exclude rules, they are included
+    let mut filter = FileFilter::new();
+    filter.exclude("*.orig").unwrap();
+    assert!(filter.is_included(Path::new("src/main.c")));
+    assert!(!filter.is_included(Path::new("src/main.c.orig")));
+}
+
+/// Push a patch changing two files, with only one of them included.
+#[cfg(test)]
+fn push_one_of_two(threads: &str) -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/two.patch"), concat!(
+        "--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-a\n+patched a\n",
+        "--- a/b.txt\n+++ b/b.txt\n@@ -1 +1 @@\n-b\n+patched b\n",
+    ))?;
+    fs::write(work_path.join("series"), "two.patch\n")?;
+    fs::write(work_path.join("a.txt"), "a\n")?;
+    fs::write(work_path.join("b.txt"), "b\n")?;
+
+    let args = [
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--threads"), OsStr::new(threads),
+        OsStr::new("--backup"), OsStr::new("always"),
+        OsStr::new("--include"), OsStr::new("b.*"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    assert!(cmd::run(args)?);
+
+    assert_eq!(fs::read_to_string(work_path.join("a.txt"))?, "a\n");
+    assert_eq!(fs::read_to_string(work_path.join("b.txt"))?, "patched b\n");
+
+    // Only the patched file is backed up
+    assert!(!work_path.join(".pc/two.patch/a.txt").exists());
+    assert_eq!(fs::read_to_string(work_path.join(".pc/two.patch/b.txt"))?, "b\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn file_filter_sequential() -> Result<()> {
+    push_one_of_two("1")
+}
+
+#[cfg(test)]
+#[test]
+fn file_filter_parallel() -> Result<()> {
+    push_one_of_two("2")
+}
+
+#[cfg(test)]
+#[test]
+fn filtered_files_report() {
+    colored::control::set_override(false);
+
+    let files = [
+        PatchedFile { index: 0, patch_filename: PathBuf::from("two.patch"), filename: PathBuf::from("a.txt") },
+    ];
+
+    let mut output = Vec::<u8>::new();
+    write_filtered_files_report(&mut output, &files).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), concat!(
+        "Patch two.patch PARTIALLY APPLIED\n",
+        "  File a.txt was skipped by --include/--exclude\n",
+    ));
+}
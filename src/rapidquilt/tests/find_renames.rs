@@ -0,0 +1,59 @@
+use std::fs;
+
+use anyhow::Result;
+
+use super::push;
+
+/// Made before "old/util.c" was moved to "lib/util.c"
+const PATCH: &str = "\
+--- a/old/util.c
++++ b/old/util.c
+@@ -1,4 +1,4 @@
+ int add(int a, int b)
+ {
+-\treturn a - b;
++\treturn a + b;
+ }
+";
+
+const ORIGINAL: &str = "int add(int a, int b)\n{\n\treturn a - b;\n}\n";
+
+#[cfg(test)]
+#[test]
+fn patch_of_renamed_file() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/fix.patch"), PATCH)?;
+    fs::write(work_path.join("series"), "fix.patch\n")?;
+    fs::create_dir(work_path.join("lib"))?;
+    fs::write(work_path.join("lib/util.c"), ORIGINAL)?;
+    fs::write(work_path.join("lib/other.c"), "int main(void)\n{\n\treturn 0;\n}\n")?;
+
+    assert!(!push(work_path, &["--all", "--backup", "never"])?);
+    assert_eq!(fs::read_to_string(work_path.join("lib/util.c"))?, ORIGINAL);
+
+    assert!(push(work_path, &["--all", "--backup", "never", "--find-renames"])?);
+    assert_eq!(fs::read_to_string(work_path.join("lib/util.c"))?, ORIGINAL.replace("a - b", "a + b"));
+    assert!(!work_path.join("old").exists());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn ambiguous_renamed_file_is_not_patched() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/fix.patch"), PATCH)?;
+    fs::write(work_path.join("series"), "fix.patch\n")?;
+    fs::write(work_path.join("copy.c"), ORIGINAL)?;
+    fs::write(work_path.join("other_copy.c"), ORIGINAL)?;
+
+    assert!(!push(work_path, &["--all", "--backup", "never", "--find-renames"])?);
+    assert_eq!(fs::read_to_string(work_path.join("copy.c"))?, ORIGINAL);
+    assert_eq!(fs::read_to_string(work_path.join("other_copy.c"))?, ORIGINAL);
+
+    Ok(())
+}
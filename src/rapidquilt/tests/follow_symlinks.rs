@@ -0,0 +1,87 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+use super::push;
+
+const PATCH: &str = "\
+--- a/link.txt
++++ b/link.txt
+@@ -1,2 +1,2 @@
+ 1
+-2
++two
+";
+
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/change.patch"), PATCH)?;
+    fs::write(work_path.join("series"), "change.patch\n")?;
+    fs::create_dir(work_path.join("dir"))?;
+    fs::write(work_path.join("dir/real.txt"), "1\n2\n")?;
+    symlink("dir/real.txt", work_path.join("link.txt"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn symlink_is_patched_itself() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+
+    // The content of the symlink is its target, the patch does not match it.
+    assert!(!push(work_path, &["--backup", "always"])?);
+    assert_eq!(fs::read_link(work_path.join("link.txt"))?, Path::new("dir/real.txt"));
+    assert_eq!(fs::read_to_string(work_path.join("dir/real.txt"))?, "1\n2\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn symlink_is_followed() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+
+    assert!(push(work_path, &["--backup", "always", "--follow-symlinks"])?);
+    assert_eq!(fs::read_link(work_path.join("link.txt"))?, Path::new("dir/real.txt"));
+    assert_eq!(fs::read_to_string(work_path.join("dir/real.txt"))?, "1\ntwo\n");
+
+    // The backup is of the file, popping restores it.
+    assert!(work_path.join(".pc/change.patch/dir/real.txt").exists());
+    assert!(cmd::run([
+        OsStr::new("pop"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+    assert_eq!(fs::read_link(work_path.join("link.txt"))?, Path::new("dir/real.txt"));
+    assert_eq!(fs::read_to_string(work_path.join("dir/real.txt"))?, "1\n2\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn symlink_out_of_tree_is_not_followed() -> Result<()> {
+    let outside_dir = tempfile::tempdir()?;
+    fs::write(outside_dir.path().join("real.txt"), "1\n2\n")?;
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+    fs::remove_file(work_path.join("link.txt"))?;
+    symlink(outside_dir.path().join("real.txt"), work_path.join("link.txt"))?;
+
+    let error = push(work_path, &["--backup", "always", "--follow-symlinks"]).unwrap_err();
+    assert!(format!("{:#}", error).contains("outside of the working directory"), "{:#}", error);
+    assert_eq!(fs::read_to_string(outside_dir.path().join("real.txt"))?, "1\n2\n");
+
+    Ok(())
+}
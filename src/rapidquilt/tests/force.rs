@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+
+use crate::apply::{PatchedFile, write_forced_files_warning};
+
+#[cfg(test)]
+#[test]
+fn forced_files_warning() {
+    let forced_files = [
+        PatchedFile {
+            index: 1,
+            patch_filename: PathBuf::from("wrong-ddd.patch"),
+            filename: PathBuf::from("file.in"),
+        },
+    ];
+
+    let mut output = Vec::<u8>::new();
+    write_forced_files_warning(&mut output, &forced_files).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.contains("1 file(s) were patched with --force"));
+    assert!(output.contains("file.in"));
+    assert!(output.contains("wrong-ddd.patch"));
+}
+
+#[cfg(test)]
+#[test]
+fn no_forced_files_no_warning() {
+    let mut output = Vec::<u8>::new();
+    write_forced_files_warning(&mut output, &[]).unwrap();
+    assert!(output.is_empty());
+}
@@ -0,0 +1,57 @@
+use std::fs;
+
+use anyhow::Result;
+
+use libpatch::analysis::{AnalysisSet, fn_analysis_note_noop};
+use libpatch::modified_file::ModifiedFile;
+use libpatch::patch::{BatchThreshold, PatchDirection};
+use libpatch::patch::unified::parser::parse_patch;
+
+use crate::apply::diagnostics::write_function_context;
+
+const FILE: &[u8] = b"\
+#include <stdio.h>
+
+static int foo(int argc, char **argv)
+{
+	return 2;
+}
+
+int main(void)
+{
+	foo(2, NULL);
+}
+";
+
+#[cfg(test)]
+#[test]
+fn function_found_in_file() -> Result<()> {
+    let patch_data = fs::read("testdata/parsing/function_context.patch")?;
+    let patch = parse_patch(&patch_data, 1)?;
+    let file_patch = &patch.file_patches[0];
+
+    let mut file = ModifiedFile::new(FILE, true, None);
+    let report = file_patch.apply(&mut file, PatchDirection::Forward, 0, None, false, false, false, None, BatchThreshold::default(), &AnalysisSet::default(), &fn_analysis_note_noop);
+    assert!(report.failed());
+
+    let function_context = |index: usize| -> Result<String> {
+        let mut output = String::new();
+        write_function_context(&report, &file_patch.hunks()[index], &file, &mut output, "")?;
+        Ok(output)
+    };
+
+    let output = function_context(1)?;
+    assert!(output.contains("static int foo(int argc, char **argv)"), "{}", output);
+    assert!(output.contains("found at line 3"), "{}", output);
+
+    // The function separated by TAB
+    let output = function_context(2)?;
+    assert!(output.contains("int main(void)"), "{}", output);
+    assert!(output.contains("found at line 8"), "{}", output);
+
+    let output = function_context(3)?;
+    assert!(output.contains("struct with @@ inside {"), "{}", output);
+    assert!(output.contains("not found in the file"), "{}", output);
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::ffi::OsStr;
+use std::path::Path;
+
+use regex::bytes::Regex;
+
+use crate::cmd;
+use crate::grep::{GrepConfig, grep_patch};
+
+#[cfg(test)]
+const PATCH: &[u8] = br#"Subject: Fix foo
+
+--- a/foo.c
++++ b/foo.c
+@@ -1,3 +1,3 @@ int foo(void)
+ {
+-	return 1;
++	return foo_value();
+ }
+--- a/bar.c
++++ b/bar.c
+@@ -10,3 +10,4 @@ int bar(void)
+ 	foo();
++	bar();
+ 	return 0;
+ }
+"#;
+
+#[cfg(test)]
+fn grep(pattern: &str, added_only: bool, files_with_matches: bool) -> String {
+    colored::control::set_override(false);
+
+    let config = GrepConfig {
+        regex: Regex::new(pattern).unwrap(),
+        added_only,
+        files_with_matches,
+    };
+    let mut output = Vec::new();
+    grep_patch(&mut output, &config, Path::new("fix-foo.patch"), PATCH).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[cfg(test)]
+#[test]
+fn grep_all_lines() {
+    assert_eq!(grep("foo", false, false), concat!(
+        "fix-foo.patch\n",
+        "  Subject: Fix foo\n",
+        "  --- a/foo.c\n",
+        "  +++ b/foo.c\n",
+        "  @@ -1,3 +1,3 @@ int foo(void)\n",
+        "  +\treturn foo_value();\n",
+        "  +++ b/bar.c\n",
+        "  @@ -10,3 +10,4 @@ int bar(void)\n",
+        "   \tfoo();\n",
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn grep_added_lines() {
+    assert_eq!(grep("foo|bar", true, false), concat!(
+        "fix-foo.patch\n",
+        "  +++ b/foo.c\n",
+        "  @@ -1,3 +1,3 @@ int foo(void)\n",
+        "  +\treturn foo_value();\n",
+        "  +++ b/bar.c\n",
+        "  @@ -10,3 +10,4 @@ int bar(void)\n",
+        "  +\tbar();\n",
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn grep_files_with_matches() {
+    assert_eq!(grep("bar", false, true), "fix-foo.patch\n");
+    assert_eq!(grep("baz", false, true), "");
+}
+
+#[cfg(test)]
+#[test]
+fn grep_series() {
+    let run = |pattern: &str| cmd::run([
+        OsStr::new("grep"),
+        OsStr::new("--files-with-matches"),
+        OsStr::new("--directory"), OsStr::new("testdata/quilt/ok/basic/input"),
+        OsStr::new(pattern),
+    ]).unwrap();
+
+    assert!(run("ddd modified"));
+    assert!(!run("no such line"));
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+use anyhow::Result;
+
+use super::push;
+
+/// Set up a patch for "file.txt", which is hard linked to "link.txt".
+#[cfg(test)]
+fn setup_hard_linked_file() -> Result<tempfile::TempDir> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/change.patch"),
+              "--- a/file.txt\n+++ b/file.txt\n@@ -1,2 +1,2 @@\n 1\n-2\n+two\n")?;
+    fs::write(work_path.join("series"), "change.patch\n")?;
+    fs::write(work_path.join("file.txt"), "1\n2\n")?;
+    fs::hard_link(work_path.join("file.txt"), work_path.join("link.txt"))?;
+    Ok(work_dir)
+}
+
+#[cfg(test)]
+#[test]
+fn hard_link_broken() -> Result<()> {
+    let work_dir = setup_hard_linked_file()?;
+    let work_path = work_dir.path();
+
+    assert!(push(work_path, &["--all"])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "1\ntwo\n");
+    assert_eq!(fs::read_to_string(work_path.join("link.txt"))?, "1\n2\n");
+    assert_eq!(fs::metadata(work_path.join("file.txt"))?.nlink(), 1);
+    assert_eq!(fs::metadata(work_path.join("link.txt"))?.nlink(), 1);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn hard_link_preserved() -> Result<()> {
+    let work_dir = setup_hard_linked_file()?;
+    let work_path = work_dir.path();
+    let inode = fs::metadata(work_path.join("file.txt"))?.ino();
+
+    assert!(push(work_path, &["--all", "--preserve-hard-links"])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "1\ntwo\n");
+    assert_eq!(fs::read_to_string(work_path.join("link.txt"))?, "1\ntwo\n");
+    let metadata = fs::metadata(work_path.join("file.txt"))?;
+    assert_eq!((metadata.ino(), metadata.nlink()), (inode, 2));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn hard_link_preserved_not_with_mmap() -> Result<()> {
+    let work_dir = setup_hard_linked_file()?;
+    let work_path = work_dir.path();
+
+    let error = push(work_path, &["--all", "--preserve-hard-links", "--mmap"]).unwrap_err();
+    assert_eq!(error.to_string(), "Can not use \"preserve-hard-links\" together with \"mmap\".");
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "1\n2\n");
+
+    Ok(())
+}
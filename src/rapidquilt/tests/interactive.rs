@@ -0,0 +1,64 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Cursor, IsTerminal};
+
+use anyhow::Result;
+
+use crate::apply::interactive::{ask_resolution, Resolution};
+use crate::cmd;
+
+#[cfg(test)]
+#[test]
+fn resolution_answers() -> Result<()> {
+    let mut output = Vec::new();
+    let mut input = Cursor::new("f\n");
+    assert_eq!(ask_resolution(&mut input, &mut output)?, Resolution::Force);
+
+    // Unknown answers are asked again
+    let mut output = Vec::new();
+    let mut input = Cursor::new("x\n\nReverse\n");
+    assert_eq!(ask_resolution(&mut input, &mut output)?, Resolution::Reverse);
+    let output = String::from_utf8(output)?;
+    assert_eq!(output.matches("[q]uit?").count(), 3);
+    assert!(output.contains("Unknown answer \"x\"."));
+
+    // Nobody is there to answer
+    let mut output = Vec::new();
+    let mut input = Cursor::new("");
+    assert_eq!(ask_resolution(&mut input, &mut output)?, Resolution::Quit);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn interactive_without_terminal_fails() -> Result<()> {
+    // It would really ask
+    if io::stdin().is_terminal() && io::stdout().is_terminal() {
+        return Ok(());
+    }
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/bad.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-missing
++changed
+")?;
+    fs::write(work_path.join("series"), "bad.patch\n")?;
+    fs::write(work_path.join("file.txt"), "original\n")?;
+
+    assert!(!cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--interactive"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "original\n");
+    assert!(work_path.join("file.txt.rej").exists());
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::audit::hash_content;
+use crate::cmd;
+
+/// Enough lines to span several chunks of 1 MiB.
+#[cfg(test)]
+const LINE_COUNT: usize = 100_000;
+
+#[cfg(test)]
+fn numbered_lines(count: usize) -> String {
+    (0..count).map(|i| format!("line number {} of a file that is loaded lazily\n", i)).collect()
+}
+
+/// Set up quilt directory with one big file and the `patch`, push it with
+/// `--lazy-load 1` and return the working directory.
+#[cfg(test)]
+fn push_lazily(patch: &str, threads: &str) -> Result<tempfile::TempDir> {
+    push_big_file(&numbered_lines(LINE_COUNT), patch, threads)
+}
+
+/// Like `push_lazily`, but with the `content` of the big file.
+#[cfg(test)]
+fn push_big_file(content: &str, patch: &str, threads: &str) -> Result<tempfile::TempDir> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/test.patch"), patch)?;
+    fs::write(work_path.join("series"), "test.patch\n")?;
+    fs::write(work_path.join("big.txt"), content)?;
+
+    let args = [
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--all"),
+        OsStr::new("--threads"), OsStr::new(threads),
+        OsStr::new("--backup"), OsStr::new("always"),
+        OsStr::new("--lazy-load"), OsStr::new("1"),
+        // Hunks may be anywhere in the file, not only near their line.
+        OsStr::new("--max-offset"), OsStr::new("unlimited"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    assert!(cmd::run(args)?);
+
+    Ok(work_dir)
+}
+
+#[cfg(test)]
+fn check_result(work_path: &Path, from: &str, to: &str) -> Result<()> {
+    let original = numbered_lines(LINE_COUNT);
+    assert_eq!(fs::read_to_string(work_path.join("big.txt"))?, original.replacen(from, to, 1));
+
+    // The backup must contain the whole original file
+    assert_eq!(hash_content(&fs::read(work_path.join(".pc/test.patch/big.txt"))?),
+               hash_content(original.as_bytes()));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn lazy_load_patch_at_start() -> Result<()> {
+    let patch = concat!(
+        "--- a/big.txt\n",
+        "+++ b/big.txt\n",
+        "@@ -3,3 +3,3 @@\n",
+        " line number 2 of a file that is loaded lazily\n",
+        "-line number 3 of a file that is loaded lazily\n",
+        "+line number 3 of a file that was patched\n",
+        " line number 4 of a file that is loaded lazily\n",
+    );
+
+    for threads in ["1", "2"] {
+        let work_dir = push_lazily(patch, threads)?;
+        check_result(work_dir.path(),
+                     "line number 3 of a file that is loaded lazily\n",
+                     "line number 3 of a file that was patched\n")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn lazy_load_hunk_far_from_expected_line() -> Result<()> {
+    // The hunk says line 10, but it is near the end. It can only be found
+    // after loading the whole file.
+    let patch = concat!(
+        "--- a/big.txt\n",
+        "+++ b/big.txt\n",
+        "@@ -10,3 +10,3 @@\n",
+        " line number 99990 of a file that is loaded lazily\n",
+        "-line number 99991 of a file that is loaded lazily\n",
+        "+line number 99991 of a file that was patched\n",
+        " line number 99992 of a file that is loaded lazily\n",
+    );
+
+    let work_dir = push_lazily(patch, "1")?;
+    check_result(work_dir.path(),
+                 "line number 99991 of a file that is loaded lazily\n",
+                 "line number 99991 of a file that was patched\n")
+}
+
+#[cfg(test)]
+#[test]
+fn lazy_load_patch_at_end() -> Result<()> {
+    let patch = concat!(
+        "--- a/big.txt\n",
+        "+++ b/big.txt\n",
+        "@@ -99998,3 +99998,4 @@\n",
+        " line number 99997 of a file that is loaded lazily\n",
+        " line number 99998 of a file that is loaded lazily\n",
+        " line number 99999 of a file that is loaded lazily\n",
+        "+the end\n",
+    );
+
+    let work_dir = push_lazily(patch, "1")?;
+    check_result(work_dir.path(),
+                 "line number 99999 of a file that is loaded lazily\n",
+                 "line number 99999 of a file that is loaded lazily\nthe end\n")
+}
+
+#[cfg(test)]
+#[test]
+fn lazy_load_hunk_moved_by_previous_hunk() -> Result<()> {
+    // The first hunk is found 19990 lines later than it says, still in the
+    // first MiB. The second hunk is then expected 19990 lines later too,
+    // past the first MiB, where it is. A copy of its lines in the first MiB
+    // must not be picked instead.
+    let mut lines: Vec<String> = numbered_lines(LINE_COUNT).lines().map(|line| format!("{}\n", line)).collect();
+    for start in [20500, 25000] {
+        for i in 0..3 {
+            lines[start + i] = format!("duplicate {}\n", i);
+        }
+    }
+    let content = lines.concat();
+
+    let patch = concat!(
+        "--- a/big.txt\n",
+        "+++ b/big.txt\n",
+        "@@ -11,3 +11,3 @@\n",
+        " line number 20000 of a file that is loaded lazily\n",
+        "-line number 20001 of a file that is loaded lazily\n",
+        "+line number 20001 of a file that was patched\n",
+        " line number 20002 of a file that is loaded lazily\n",
+        "@@ -5001,3 +5001,3 @@\n",
+        " duplicate 0\n",
+        "-duplicate 1\n",
+        "+duplicate 1 was patched\n",
+        " duplicate 2\n",
+    );
+
+    lines[20001] = "line number 20001 of a file that was patched\n".to_string();
+    lines[25001] = "duplicate 1 was patched\n".to_string();
+    let work_dir = push_big_file(&content, patch, "1")?;
+    assert_eq!(fs::read_to_string(work_dir.path().join("big.txt"))?, lines.concat());
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::cmd;
+use super::quilt_metadata::{copy_tree, sorted_entries};
+
+/// All files and symlinks under `path`, with their mode and content (or
+/// target). The quilt metadata is skipped.
+#[cfg(test)]
+fn read_tree(path: &Path, relative: &Path, files: &mut BTreeMap<PathBuf, (u32, Vec<u8>)>) -> Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let relative_path = relative.join(entry.file_name());
+        if relative.as_os_str().is_empty() && ["series", "patches", ".pc"].iter().any(|name| entry.file_name() == *name) {
+            continue;
+        }
+
+        let metadata = fs::symlink_metadata(entry.path())?;
+        if metadata.file_type().is_symlink() {
+            files.insert(relative_path, (metadata.permissions().mode(), fs::read_link(entry.path())?.into_os_string().into_encoded_bytes()));
+        } else if metadata.is_dir() {
+            read_tree(&entry.path(), &relative_path, files)?;
+        } else {
+            files.insert(relative_path, (metadata.permissions().mode(), fs::read(entry.path())?));
+        }
+    }
+    Ok(())
+}
+
+/// The changes between the trees `before` and `after`, formatted like the
+/// lines of the manifest.
+#[cfg(test)]
+fn changed_files(before: &Path, after: &Path) -> Result<Vec<String>> {
+    let mut before_files = BTreeMap::new();
+    read_tree(before, Path::new(""), &mut before_files)?;
+    let mut after_files = BTreeMap::new();
+    read_tree(after, Path::new(""), &mut after_files)?;
+
+    let mut filenames: Vec<_> = before_files.keys().chain(after_files.keys()).collect();
+    filenames.sort();
+    filenames.dedup();
+    Ok(filenames.into_iter().filter_map(|filename| {
+        let operation = match (before_files.get(filename), after_files.get(filename)) {
+            (None, Some(_)) => "create",
+            (Some(_), None) => "delete",
+            (Some(old), Some(new)) if old != new => "modify",
+            _ => return None,
+        };
+        Some(format!("{} {}", operation, filename.display()))
+    }).collect())
+}
+
+#[cfg(test)]
+#[test]
+fn manifest_lists_changed_files() -> Result<()> {
+    for entry in sorted_entries(Path::new("testdata/quilt/ok"))? {
+        let path = entry.path();
+        match fs::read_to_string(path.join("args")) {
+            // The other options do not change what is saved
+            Err(err) if err.kind() == ErrorKind::NotFound => {},
+            _ => continue,
+        }
+
+        for threads in ["1", "2"] {
+            let work_dir = tempfile::tempdir()?;
+            let work_path = work_dir.path();
+            copy_tree(&path.join("input"), work_path)?;
+            let manifest_dir = tempfile::tempdir()?;
+            let manifest_path = manifest_dir.path().join("manifest");
+
+            assert!(cmd::run([
+                OsStr::new("push"),
+                OsStr::new("--quiet"),
+                OsStr::new("--all"),
+                OsStr::new("--threads"), OsStr::new(threads),
+                OsStr::new("--manifest"), manifest_path.as_os_str(),
+                OsStr::new("--directory"), work_path.as_os_str(),
+            ])?);
+
+            let manifest = fs::read_to_string(&manifest_path)?;
+            assert_eq!(manifest.lines().collect::<Vec<_>>(), changed_files(&path.join("input"), work_path)?,
+                       "{}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn manifest_as_json() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/change.patch"), "\
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-old
+--- /dev/null
++++ b/dir/new.txt
+@@ -0,0 +1 @@
++new
+")?;
+    fs::write(work_path.join("series"), "change.patch\n")?;
+    fs::write(work_path.join("old.txt"), "old\n")?;
+
+    let manifest_path = work_path.join("manifest.json");
+    assert!(cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--manifest"), manifest_path.as_os_str(),
+        OsStr::new("--format"), OsStr::new("json"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+
+    let records = fs::read_to_string(&manifest_path)?.lines()
+        .map(serde_json::from_str::<serde_json::Value>)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(records, [
+        serde_json::json!({"path": "dir/new.txt", "operation": "create"}),
+        serde_json::json!({"path": "old.txt", "operation": "delete"}),
+    ]);
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    apply_patches,
+    apply_patches_parallel,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+use crate::match_cache::MatchCache;
+
+/// The file the patch was made for, with three lines added on top. The
+/// hunks match at an offset of 3.
+const ORIGINAL: &str = "\
+added 1\nadded 2\nadded 3\n\
+1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n";
+
+const PATCHED: &str = "\
+added 1\nadded 2\nadded 3\n\
+1\n2\n3\nfour\n5\n6\n7\n8\n9\n10\n11\n12\n13\nfourteen\n15\n16\n";
+
+const PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,5 +2,5 @@
+ 2
+ 3
+-4
++four
+ 5
+ 6
+@@ -12,5 +12,5 @@
+ 12
+ 13
+-14
++fourteen
+ 15
+ 16
+";
+
+/// Write the original file and push the patch with the `match_cache`.
+/// Returns how many file patches were applied at cached places and the
+/// patched file.
+#[cfg(test)]
+fn push_with_cache(work_path: &Path, match_cache: &MatchCache, parallel: bool) -> Result<(usize, String)> {
+    fs::write(work_path.join("file.txt"), ORIGINAL)?;
+    let hits_before = match_cache.hits();
+
+    let patches_path = work_path.join("patches");
+    let series_patches = [
+        SeriesPatch { filename: "offset.patch".into(), strip: 1, reverse: false },
+    ];
+    let config = ApplyConfig {
+        save_rej_files: false,
+        deterministic: true,
+        match_cache: Some(match_cache),
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet)
+    };
+
+    let arena = FileArena::new();
+    let result = if parallel {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+        pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?
+    } else {
+        apply_patches(&config, &arena, &AnalysisSet::default())?
+    };
+    assert_eq!(result.applied_patches, 1);
+
+    Ok((match_cache.hits() - hits_before, fs::read_to_string(work_path.join("file.txt"))?))
+}
+
+#[cfg(test)]
+#[test]
+fn second_push_uses_cached_places() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/offset.patch"), PATCH)?;
+    let cache_path = work_path.join("match-cache");
+
+    let match_cache = MatchCache::new();
+    assert_eq!(push_with_cache(work_path, &match_cache, false)?, (0, PATCHED.to_string()));
+    assert_eq!(push_with_cache(work_path, &match_cache, false)?, (1, PATCHED.to_string()));
+    assert_eq!(push_with_cache(work_path, &match_cache, true)?, (1, PATCHED.to_string()));
+    match_cache.save(&cache_path)?;
+    assert!(fs::read_to_string(&cache_path)?.ends_with(" 4:0 14:0\n"));
+
+    // The next run loads it.
+    let match_cache = MatchCache::load(&cache_path)?;
+    assert_eq!(push_with_cache(work_path, &match_cache, false)?, (1, PATCHED.to_string()));
+
+    // A cache with wrong places is checked, the hunks are searched for.
+    let broken_cache = fs::read_to_string(&cache_path)?.replace(" 4:0 14:0", " 5:0 14:0");
+    fs::write(&cache_path, broken_cache)?;
+    let match_cache = MatchCache::load(&cache_path)?;
+    assert_eq!(push_with_cache(work_path, &match_cache, false)?, (0, PATCHED.to_string()));
+    assert_eq!(push_with_cache(work_path, &match_cache, false)?, (1, PATCHED.to_string()));
+
+    // A changed patch is not found in the cache.
+    fs::write(work_path.join("patches/offset.patch"), PATCH.replace("+four\n", "+FOUR\n"))?;
+    assert_eq!(push_with_cache(work_path, &match_cache, false)?, (0, PATCHED.replace("four\n", "FOUR\n")));
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+/// The patch expects the lines near the start of the file, but they moved
+/// more than 10000 lines down.
+const PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,3 +2,3 @@
+ 11001
+-11002
++changed
+ 11003
+";
+
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/moved.patch"), PATCH)?;
+    fs::write(work_path.join("series"), "moved.patch\n")?;
+
+    let numbers: String = (1..=12000).map(|i| format!("{}\n", i)).collect();
+    fs::write(work_path.join("file.txt"), numbers)?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn push_with_max_offset(max_offset: Option<&str>) -> Result<(bool, String)> {
+    let work_dir = tempfile::tempdir()?;
+    setup_work_dir(work_dir.path())?;
+
+    let mut args = vec![
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_dir.path().as_os_str(),
+    ];
+    if let Some(max_offset) = max_offset {
+        args.extend([OsStr::new("--max-offset"), OsStr::new(max_offset)]);
+    }
+
+    let ok = cmd::run(args)?;
+    Ok((ok, fs::read_to_string(work_dir.path().join("file.txt"))?))
+}
+
+#[cfg(test)]
+#[test]
+fn moved_hunk_needs_raised_max_offset() -> Result<()> {
+    // Too far away for the default
+    let (ok, content) = push_with_max_offset(None)?;
+    assert!(!ok);
+    assert!(content.contains("\n11002\n"));
+
+    let (ok, _) = push_with_max_offset(Some("10500"))?;
+    assert!(!ok);
+
+    for max_offset in ["11000", "unlimited"] {
+        let (ok, content) = push_with_max_offset(Some(max_offset))?;
+        assert!(ok);
+        assert!(content.contains("\n11001\nchanged\n11003\n"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+use crate::mbox::{mail_filename, parse_header, subject_from_filename};
+
+/// Patch with git-style headers, a DEP-3 field and a diffstat
+const DESCRIBED_PATCH: &str = "\
+From: Jane Doe <jane@example.com>
+Date: Tue, 1 Jan 2019 10:00:00 +0100
+Subject: [PATCH] Fix the answer
+Patch-mainline: v5.0
+References: bsc#1234
+
+The answer was wrong.
+
+Signed-off-by: Jane Doe <jane@example.com>
+---
+ answer.txt | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/answer.txt b/answer.txt
+--- a/answer.txt
++++ b/answer.txt
+@@ -1 +1 @@
+-41
++42
+";
+
+/// Patch without any description
+const BARE_PATCH: &str = "\
+--- a/question.txt
++++ b/question.txt
+@@ -1 +1 @@
+-What?
++Why?
+";
+
+#[cfg(test)]
+#[test]
+fn header_fields() {
+    let header = parse_header(b"\
+From 1234567890abcdef1234567890abcdef12345678 Mon Sep 17 00:00:00 2001
+Author: John Doe <john@example.com>
+Description: Shorter lines
+ They fit on the screen.
+ .
+ Really.
+Bug: https://example.com/1
+");
+    assert_eq!(header.from.as_deref(), Some("John Doe <john@example.com>"));
+    assert_eq!(header.subject.as_deref(), Some("Shorter lines"));
+    assert_eq!(header.body, ["They fit on the screen.", "", "Really."]);
+    assert_eq!(header.trailers, ["Bug: https://example.com/1"]);
+
+    assert_eq!(parse_header(b"Subject: [PATCH v2 3/5] Folded\n  subject\n").subject.as_deref(), Some("Folded subject"));
+    assert_eq!(parse_header(b"Just text: not a field\n").body, ["Just text: not a field"]);
+}
+
+#[cfg(test)]
+#[test]
+fn filenames() {
+    assert_eq!(subject_from_filename(Path::new("patches.suse/0003-fix_the-thing.patch")), "fix the thing");
+    assert_eq!(subject_from_filename(Path::new("cleanup.diff")), "cleanup");
+    assert_eq!(mail_filename(7, "Fix \"it\", again..."), Path::new("0007-Fix-it-again.patch"));
+    assert_eq!(mail_filename(1, &"x".repeat(80)), Path::new(&format!("0001-{}.patch", "x".repeat(52))));
+}
+
+#[cfg(test)]
+#[test]
+fn export_two_patches() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let work_path = temp_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/answer.patch"), DESCRIBED_PATCH)?;
+    fs::write(work_path.join("patches/ask-why.patch"), BARE_PATCH)?;
+    fs::write(work_path.join("series"), "answer.patch\nask-why.patch\n")?;
+    let mail_path = work_path.join("mails");
+
+    assert!(cmd::run([
+        OsStr::new("export-mbox"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+        mail_path.as_os_str(),
+    ])?);
+
+    let mut filenames: Vec<_> = fs::read_dir(&mail_path)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<_, _>>()?;
+    filenames.sort();
+    assert_eq!(filenames, ["0001-Fix-the-answer.patch", "0002-ask-why.patch"]);
+
+    assert_eq!(fs::read_to_string(mail_path.join("0001-Fix-the-answer.patch"))?, "\
+From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+From: Jane Doe <jane@example.com>
+Date: Tue, 1 Jan 2019 10:00:00 +0100
+Subject: [PATCH 1/2] Fix the answer
+
+The answer was wrong.
+
+Signed-off-by: Jane Doe <jane@example.com>
+
+Patch-mainline: v5.0
+References: bsc#1234
+---
+ answer.txt | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/answer.txt b/answer.txt
+--- a/answer.txt
++++ b/answer.txt
+@@ -1 +1 @@
+-41
++42
+");
+
+    assert_eq!(fs::read_to_string(mail_path.join("0002-ask-why.patch"))?, format!("\
+From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+Subject: [PATCH 2/2] ask why
+
+---
+{}", BARE_PATCH));
+
+    Ok(())
+}
@@ -1,2 +1,97 @@
+mod allowed_files;
+mod applied;
+#[cfg(feature = "git")]
+mod apply_from_diff;
+mod arena;
+mod atomic_save;
+mod audit_log;
+mod auto_tune;
+mod backup_store;
+mod bundle;
+mod case_collisions;
+mod deterministic;
+mod dump;
+mod dump_series_order;
+mod duplicate_patches;
+mod edit;
+#[cfg(unix)]
+mod emit_diff;
+mod exit_code;
+mod explain;
+mod file_filter;
 mod filename_distributor;
+mod find_renames;
+#[cfg(unix)]
+mod follow_symlinks;
+mod force;
+mod function_context;
+mod grep;
+#[cfg(unix)]
+mod hard_links;
+mod interactive;
+mod lazy_load;
+#[cfg(unix)]
+mod manifest;
+mod match_cache;
+mod max_offset;
+mod mbox;
+mod no_backup;
+mod no_series;
+mod normalize;
+mod numstat;
+mod out_dir;
+mod out_tar;
+mod parse_check;
+mod patch_sizes;
+mod patch_source;
+mod patch_timeout;
+mod pop;
+mod posix;
+#[cfg(unix)]
+mod post_hook;
+mod prefix_map;
+#[cfg(unix)]
+mod preserve_ownership;
+mod provenance;
 mod quilt_metadata;
+mod rcfile;
+mod reconstruct_pc;
+mod reject_dir;
+mod relative;
+mod report_unchanged;
+mod resume;
+mod roundtrip;
+mod reverse_if_applied;
+mod series_options;
+mod show_rejects_inline;
+mod snapshot;
+mod squash;
+mod status;
+mod strict;
+mod summary_only;
+mod timings;
+mod touch;
+mod touched;
+mod unsafe_paths;
+mod verify;
+mod verify_index;
+#[cfg(feature = "watch")]
+mod watch;
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+/// Run "push --quiet" in the `work_path` with the `extra_args`.
+pub(super) fn push(work_path: &Path, extra_args: &[&str]) -> Result<bool> {
+    let mut args = vec![
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    args.extend(extra_args.iter().map(OsStr::new));
+    cmd::run(args)
+}
@@ -0,0 +1,78 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+/// Set up working directory with two patches, the second one fails.
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/good.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+ one
+-two
++TWO
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+")?;
+    fs::write(work_path.join("patches/bad.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-missing
++MISSING
+")?;
+    fs::write(work_path.join("file.txt"), "one\ntwo\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn no_backup_writes_no_metadata() -> Result<()> {
+    for (series, success) in [("good.patch\n", true), ("good.patch\nbad.patch\n", false)] {
+        let work_dir = tempfile::tempdir()?;
+        let work_path = work_dir.path();
+        setup_work_dir(work_path)?;
+        fs::write(work_path.join("series"), series)?;
+
+        assert_eq!(cmd::run([
+            OsStr::new("push"),
+            OsStr::new("--quiet"),
+            OsStr::new("--all"),
+            OsStr::new("--no-backup"),
+            OsStr::new("--directory"), work_path.as_os_str(),
+        ])?, success);
+
+        assert!(!work_path.join(".pc").exists());
+        assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "one\nTWO\n");
+        assert_eq!(fs::read_to_string(work_path.join("new.txt"))?, "new\n");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn no_backup_conflicts_with_backup() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+    fs::write(work_path.join("series"), "good.patch\n")?;
+
+    assert!(cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--no-backup"),
+        OsStr::new("--backup"), OsStr::new("never"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ]).is_err());
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "one\ntwo\n");
+
+    Ok(())
+}
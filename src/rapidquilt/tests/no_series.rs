@@ -0,0 +1,67 @@
+use std::fs::{self, File};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use super::push;
+
+/// Set up quilt directory without "series" file. Every patch appends its
+/// name to "order.txt", so the result shows the order they were applied in.
+#[cfg(test)]
+fn setup_seriesless_dir() -> Result<tempfile::TempDir> {
+    let work_dir = tempfile::tempdir()?;
+    let patches_path = work_dir.path().join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::create_dir(patches_path.join("subdir.patch"))?;
+    fs::write(patches_path.join("notes.txt"), "not a patch\n")?;
+    fs::write(work_dir.path().join("order.txt"), "start\n")?;
+
+    // Written in reverse order of names, so the modification times are
+    // sorted the other way.
+    let now = SystemTime::now();
+    let patches = [("c.patch", "start"), ("b.diff", "c.patch"), ("a.patch", "b.diff")];
+    for (i, (name, previous)) in patches.iter().enumerate() {
+        let patch = format!("--- a/order.txt\n+++ b/order.txt\n@@ -1 +1,2 @@\n {}\n+{}\n", previous, name);
+        fs::write(patches_path.join(name), patch)?;
+        File::options().write(true).open(patches_path.join(name))?
+            .set_modified(now - Duration::from_secs(100 - i as u64))?;
+    }
+
+    Ok(work_dir)
+}
+
+#[cfg(test)]
+#[test]
+fn no_series_requires_flag() -> Result<()> {
+    let work_dir = setup_seriesless_dir()?;
+    assert!(push(work_dir.path(), &["--all"]).is_err());
+    assert!(!work_dir.path().join(".pc").exists());
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn no_series_sort_by_mtime() -> Result<()> {
+    let work_dir = setup_seriesless_dir()?;
+    assert!(push(work_dir.path(), &["--all", "--no-series", "--sort", "mtime"])?);
+
+    assert_eq!(fs::read_to_string(work_dir.path().join("order.txt"))?,
+               "start\nc.patch\nb.diff\na.patch\n");
+    assert_eq!(fs::read_to_string(work_dir.path().join(".pc/applied-patches"))?,
+               "c.patch\nb.diff\na.patch\n");
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn no_series_sort_by_name() -> Result<()> {
+    let work_dir = setup_seriesless_dir()?;
+
+    // The patches do not apply in order of names
+    assert!(!push(work_dir.path(), &["--all", "--no-series", "--backup", "never"])?);
+    assert_eq!(fs::read_to_string(work_dir.path().join(".pc/applied-patches"))?, "");
+    assert_eq!(fs::read_to_string(work_dir.path().join("order.txt"))?, "start\n");
+
+    assert!(push(work_dir.path(), &["--all", "--no-series", "--sort", "size"]).is_err());
+    Ok(())
+}
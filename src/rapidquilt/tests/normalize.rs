@@ -0,0 +1,93 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+use crate::normalize::normalize_patch;
+
+/// Patch with CRLF in the description, timestamps, unsorted files and
+/// unusual prefixes.
+const MESSY_PATCH: &[u8] = b"\
+Subject: Messy patch\r
+\r
+Description.\r
+---\r
+Index: linux/zeta.txt
+===================================================================
+--- linux.orig/zeta.txt\t2019-01-01 10:00:00.000000000 +0100
++++ linux/zeta.txt\t2019-01-02 10:00:00.000000000 +0100
+@@ -1,3 +1,3 @@
+ one
+-two
++TWO
+ three
+Index: linux/alpha.txt
+===================================================================
+--- /dev/null\t1970-01-01 00:00:00.000000000 +0000
++++ linux/alpha.txt\t2019-01-02 10:00:00.000000000 +0100
+@@ -0,0 +1,2 @@
++created
++file
+";
+
+#[cfg(test)]
+#[test]
+fn normalize_twice_identical() -> Result<()> {
+    for data in [MESSY_PATCH.to_vec(), fs::read("testdata/parsing/function_context.patch")?] {
+        let normalized = normalize_patch(&data)?;
+        assert_eq!(normalize_patch(&normalized)?, normalized);
+    }
+
+    let normalized = String::from_utf8(normalize_patch(MESSY_PATCH)?)?;
+    assert!(!normalized.contains('\r'));
+    assert!(!normalized.contains("2019"));
+    assert!(normalized.starts_with("Subject: Messy patch\n\nDescription.\n---\n"));
+    let alpha = normalized.find("+++ b/alpha.txt").unwrap();
+    let zeta = normalized.find("+++ b/zeta.txt").unwrap();
+    assert!(alpha < zeta);
+
+    Ok(())
+}
+
+/// Set up working directory with the messy patch.
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    fs::create_dir_all(work_path.join("patches"))?;
+    fs::write(work_path.join("zeta.txt"), "one\ntwo\nthree\n")?;
+    fs::write(work_path.join("patches/messy.patch"), MESSY_PATCH)?;
+    fs::write(work_path.join("series"), "messy.patch\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn normalized_patch_applies_the_same() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let original_path = temp_dir.path().join("original");
+    let normalized_path = temp_dir.path().join("normalized");
+    setup_work_dir(&original_path)?;
+    setup_work_dir(&normalized_path)?;
+
+    assert!(cmd::run([
+        OsStr::new("normalize"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), normalized_path.as_os_str(),
+    ])?);
+    assert_ne!(fs::read(normalized_path.join("patches/messy.patch"))?, MESSY_PATCH);
+
+    for work_path in [&original_path, &normalized_path] {
+        assert!(cmd::run([
+            OsStr::new("push"),
+            OsStr::new("--quiet"),
+            OsStr::new("--directory"), work_path.as_os_str(),
+        ])?);
+    }
+
+    for filename in ["zeta.txt", "alpha.txt"] {
+        assert_eq!(fs::read(original_path.join(filename))?, fs::read(normalized_path.join(filename))?);
+    }
+
+    Ok(())
+}
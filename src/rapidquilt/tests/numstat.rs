@@ -0,0 +1,96 @@
+use std::ffi::OsStr;
+use std::fs;
+
+use anyhow::Result;
+
+use libpatch::patch::unified::parser::parse_patch;
+
+use crate::cmd;
+use crate::numstat::{write_diff_numstat, write_numstat};
+
+const RENAME_PATCH: &[u8] = b"\
+diff --git a/d/file.txt b/d/renamed.txt
+rename from d/file.txt
+rename to d/renamed.txt
+--- a/d/file.txt
++++ b/d/renamed.txt
+@@ -4,2 +4,2 @@
+ 4
+-5
++five
+";
+
+const DELETE_CREATE_PATCH: &[u8] = b"\
+--- a/gone.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-a
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1,2 @@
++n
++m
+--- /dev/null
++++ b/data.bin
+@@ -0,0 +1 @@
++x\0y
+";
+
+/// `git diff --numstat` of the tree after the series, with renames found
+const NUMSTAT: &str = "\
+1\t1\td/{file.txt => renamed.txt}
+-\t-\tdata.bin
+0\t1\tgone.txt
+2\t0\tnew.txt
+";
+
+#[cfg(test)]
+#[test]
+fn squash_numstat_matches_git() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let work_path = temp_dir.path();
+    fs::create_dir_all(work_path.join("patches"))?;
+    fs::create_dir_all(work_path.join("d"))?;
+    fs::write(work_path.join("d/file.txt"), "1\n2\n3\n4\n5\n")?;
+    fs::write(work_path.join("gone.txt"), "a\n")?;
+    fs::write(work_path.join("patches/rename.patch"), RENAME_PATCH)?;
+    fs::write(work_path.join("patches/delete-create.patch"), DELETE_CREATE_PATCH)?;
+    fs::write(work_path.join("series"), "rename.patch\ndelete-create.patch\n")?;
+
+    let run = |args: &[&OsStr]| {
+        let mut all_args = vec![OsStr::new("--quiet"), OsStr::new("--directory"), work_path.as_os_str()];
+        all_args.extend(args);
+        cmd::run(all_args)
+    };
+    assert!(run(&[OsStr::new("push"), OsStr::new("--all")])?);
+    let squashed_path = work_path.join("squashed.patch");
+    assert!(run(&[OsStr::new("squash"), OsStr::new("--numstat"), squashed_path.as_os_str()])?);
+
+    let mut numstat = Vec::new();
+    write_diff_numstat(&mut numstat, &fs::read(&squashed_path)?)?;
+    assert_eq!(String::from_utf8(numstat)?, NUMSTAT);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn numstat_rename_paths() -> Result<()> {
+    let renames = [
+        ("a/b/c.txt", "a/d/c.txt", "a/{b => d}/c.txt"),
+        ("dir/a.c", "other/a.c", "{dir => other}/a.c"),
+        ("dir/a.c", "dir/sub/a.c", "dir/{ => sub}/a.c"),
+        ("old.c", "new.c", "old.c => new.c"),
+    ];
+
+    for (old_filename, new_filename, path) in renames {
+        let patch_txt = format!("diff --git a/{0} b/{1}\nsimilarity index 100%\nrename from {0}\nrename to {1}\n",
+                                old_filename, new_filename);
+        let patch = parse_patch(patch_txt.as_bytes(), 1)?;
+        let mut numstat = Vec::new();
+        write_numstat(&mut numstat, &patch)?;
+        assert_eq!(String::from_utf8(numstat)?, format!("0\t0\t{}\n", path));
+    }
+
+    Ok(())
+}
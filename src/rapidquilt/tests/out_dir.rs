@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::cmd;
+
+const PATCH: &str = "\
+--- a/dir/modified.txt
++++ b/dir/modified.txt
+@@ -1,2 +1,2 @@
+ one
+-two
++TWO
+--- /dev/null
++++ b/new/created.txt
+@@ -0,0 +1 @@
++created
+--- a/deleted.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-deleted
+";
+
+const BAD_PATCH: &str = "\
+--- a/dir/modified.txt
++++ b/dir/modified.txt
+@@ -1,2 +1,2 @@
+ one
+-missing
++MISSING
+";
+
+/// Set up working directory with a patch that modifies, creates and deletes
+/// a file each.
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    fs::create_dir_all(work_path.join("patches"))?;
+    fs::create_dir_all(work_path.join("dir"))?;
+    fs::write(work_path.join("dir/modified.txt"), "one\ntwo\n")?;
+    fs::write(work_path.join("deleted.txt"), "deleted\n")?;
+    fs::write(work_path.join("patches/good.patch"), PATCH)?;
+    fs::write(work_path.join("patches/bad.patch"), BAD_PATCH)?;
+    fs::write(work_path.join("series"), "good.patch\nbad.patch\n")?;
+    Ok(())
+}
+
+/// Read content of all files in the directory, recursively.
+#[cfg(test)]
+fn read_tree(dir: &Path, relative: &Path, files: &mut BTreeMap<PathBuf, Vec<u8>>) -> Result<()> {
+    for entry in fs::read_dir(dir.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            read_tree(dir, &path, files)?;
+        } else {
+            files.insert(path.clone(), fs::read(dir.join(&path))?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn push_out(work_path: &Path, out_option: &str, out_path: &Path, threads: &str, goal: &str) -> Result<bool> {
+    cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--threads"), OsStr::new(threads),
+        OsStr::new("--directory"), work_path.as_os_str(),
+        OsStr::new(out_option), out_path.as_os_str(),
+        OsStr::new(goal),
+    ])
+}
+
+#[cfg(test)]
+#[test]
+fn source_tree_unchanged() -> Result<()> {
+    for threads in ["1", "2"] {
+        let temp_dir = tempfile::tempdir()?;
+        let work_path = temp_dir.path().join("work");
+        let out_path = temp_dir.path().join("out");
+        setup_work_dir(&work_path)?;
+
+        let mut before = BTreeMap::new();
+        read_tree(&work_path, Path::new(""), &mut before)?;
+
+        assert!(push_out(&work_path, "--out", &out_path, threads, "good.patch")?);
+
+        let mut after = BTreeMap::new();
+        read_tree(&work_path, Path::new(""), &mut after)?;
+        assert_eq!(before, after);
+
+        let mut out = BTreeMap::new();
+        read_tree(&out_path, Path::new(""), &mut out)?;
+        assert_eq!(out, BTreeMap::from([
+            (PathBuf::from("dir/modified.txt"), b"one\nTWO\n".to_vec()),
+            (PathBuf::from("new/created.txt"), b"created\n".to_vec()),
+        ]));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn rejects_saved_to_out_dir() -> Result<()> {
+    for threads in ["1", "2"] {
+        let temp_dir = tempfile::tempdir()?;
+        let work_path = temp_dir.path().join("work");
+        let out_path = temp_dir.path().join("out");
+        setup_work_dir(&work_path)?;
+
+        let mut before = BTreeMap::new();
+        read_tree(&work_path, Path::new(""), &mut before)?;
+
+        assert!(!push_out(&work_path, "--out", &out_path, threads, "-a")?);
+
+        let mut after = BTreeMap::new();
+        read_tree(&work_path, Path::new(""), &mut after)?;
+        assert_eq!(before, after);
+
+        // The good patch is applied, the failed one only leaves its rejects.
+        assert_eq!(fs::read(out_path.join("dir/modified.txt"))?, b"one\nTWO\n");
+        assert!(fs::read_to_string(out_path.join("dir/modified.txt.rej"))?.contains("+MISSING"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn overlay_upper_has_whiteouts() -> Result<()> {
+    for threads in ["1", "2"] {
+        let temp_dir = tempfile::tempdir()?;
+        let work_path = temp_dir.path().join("work");
+        let upper_path = temp_dir.path().join("upper");
+        setup_work_dir(&work_path)?;
+
+        let mut before = BTreeMap::new();
+        read_tree(&work_path, Path::new(""), &mut before)?;
+
+        assert!(push_out(&work_path, "--overlay-upper", &upper_path, threads, "good.patch")?);
+
+        let mut after = BTreeMap::new();
+        read_tree(&work_path, Path::new(""), &mut after)?;
+        assert_eq!(before, after);
+
+        // Only the changed files and the whiteout of the deleted one
+        let mut upper = BTreeMap::new();
+        read_tree(&upper_path, Path::new(""), &mut upper)?;
+        assert_eq!(upper, BTreeMap::from([
+            (PathBuf::from(".wh.deleted.txt"), Vec::new()),
+            (PathBuf::from("dir/modified.txt"), b"one\nTWO\n".to_vec()),
+            (PathBuf::from("new/created.txt"), b"created\n".to_vec()),
+        ]));
+    }
+
+    Ok(())
+}
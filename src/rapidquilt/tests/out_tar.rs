@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use super::push;
+
+const PATCH: &str = "\
+--- a/src/main.c
++++ b/src/main.c
+@@ -1,3 +1,3 @@
+ int main(void)
+ {
+-\treturn 1;
++\treturn 0;
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-old
+--- /dev/null
++++ b/include/new/header.h
+@@ -0,0 +1 @@
++#define NEW 1
+";
+
+/// A file in a tree: its mode and content, or the target of a symlink
+type Entry = (u32, Vec<u8>);
+
+/// Unpack the files and symlinks in the tar `data`, by their path.
+#[cfg(test)]
+fn unpack_tar(data: &[u8]) -> Result<BTreeMap<PathBuf, Entry>> {
+    let mut entries = BTreeMap::new();
+    let mut long_path = None;
+    let mut offset = 0;
+    while offset + 512 <= data.len() && data[offset] != 0 {
+        let header = &data[offset..offset + 512];
+        let field = |range: std::ops::Range<usize>| {
+            let field = &header[range];
+            field[..field.iter().position(|&byte| byte == 0).unwrap_or(field.len())].to_vec()
+        };
+        let octal = |range| usize::from_str_radix(&String::from_utf8_lossy(&field(range)), 8);
+
+        let header_sum: usize = header.iter().enumerate()
+            .map(|(index, &byte)| if (148..156).contains(&index) { b' ' as usize } else { byte as usize })
+            .sum();
+        if octal(148..155)? != header_sum {
+            bail!("Bad checksum at offset {}", offset);
+        }
+
+        let size = octal(124..136)?;
+        let content = data[offset + 512..offset + 512 + size].to_vec();
+        let mut path = field(345..500);
+        if !path.is_empty() {
+            path.push(b'/');
+        }
+        path.extend(field(0..100));
+        let path = long_path.take().unwrap_or(path);
+        let mode = octal(100..108)? as u32;
+        match header[156] {
+            b'x' => {
+                let records = String::from_utf8(content)?;
+                for record in records.lines() {
+                    if let Some((_, value)) = record.split_once(" path=") {
+                        long_path = Some(value.as_bytes().to_vec());
+                    }
+                }
+            }
+            b'0' => { entries.insert(PathBuf::from(String::from_utf8(path)?), (mode, content)); }
+            b'2' => { entries.insert(PathBuf::from(String::from_utf8(path)?), (0o120000 | mode & 0o777, field(157..257))); }
+            b'5' => {}
+            type_flag => bail!("Unexpected entry type {:?}", type_flag as char),
+        }
+        offset += 512 + size.div_ceil(512) * 512;
+    }
+    Ok(entries)
+}
+
+/// The files and symlinks in the `dir` except ".pc", by their path.
+#[cfg(test)]
+fn read_tree(dir: &Path, relative: &Path, entries: &mut BTreeMap<PathBuf, Entry>) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    for entry in fs::read_dir(dir.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let metadata = entry.path().symlink_metadata()?;
+        if path == Path::new(".pc") {
+            continue;
+        } else if metadata.is_dir() {
+            read_tree(dir, &path, entries)?;
+        } else if metadata.file_type().is_symlink() {
+            let target = fs::read_link(entry.path())?.as_os_str().as_bytes().to_vec();
+            entries.insert(path, (0o120000 | metadata.permissions().mode() & 0o777, target));
+        } else {
+            entries.insert(path, (metadata.permissions().mode() & 0o7777, fs::read(entry.path())?));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+#[cfg(unix)]
+fn tar_matches_push() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let tar_path = work_path.join("tree.tar");
+    let work_path = &work_path.join("work");
+    fs::create_dir_all(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/main.patch"), PATCH)?;
+    fs::write(work_path.join("series"), "main.patch\n")?;
+    fs::create_dir(work_path.join("src"))?;
+    fs::write(work_path.join("src/main.c"), "int main(void)\n{\n\treturn 1;\n")?;
+    fs::set_permissions(work_path.join("src/main.c"), fs::Permissions::from_mode(0o750))?;
+    fs::write(work_path.join("old.txt"), "old\n")?;
+    std::os::unix::fs::symlink("src/main.c", work_path.join("main.c"))?;
+    let long_dir = work_path.join("a".repeat(90)).join("b".repeat(90));
+    fs::create_dir_all(&long_dir)?;
+    fs::write(long_dir.join("c".repeat(90)), "deep\n")?;
+
+    let mut original = BTreeMap::new();
+    read_tree(work_path, Path::new(""), &mut original)?;
+
+    // The tree is not changed.
+    assert!(push(work_path, &["--all", "--out-tar", tar_path.to_str().unwrap()])?);
+    let mut unchanged = BTreeMap::new();
+    read_tree(work_path, Path::new(""), &mut unchanged)?;
+    assert_eq!(unchanged, original);
+    assert!(!work_path.join(".pc").exists());
+    let from_tar = unpack_tar(&fs::read(&tar_path)?)?;
+
+    assert!(push(work_path, &["--all", "--out-tar", tar_path.to_str().unwrap(), "--out-tar-changed"])?);
+    let changed_from_tar = unpack_tar(&fs::read(&tar_path)?)?;
+
+    assert!(push(work_path, &["--all", "--backup", "never"])?);
+    let mut pushed = BTreeMap::new();
+    read_tree(work_path, Path::new(""), &mut pushed)?;
+    assert_eq!(from_tar, pushed);
+
+    let changed: Vec<_> = changed_from_tar.keys().cloned().collect();
+    assert_eq!(changed, [PathBuf::from("include/new/header.h"), PathBuf::from("src/main.c")]);
+    assert_eq!(changed_from_tar[Path::new("src/main.c")], pushed[Path::new("src/main.c")]);
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::apply::{SeriesPatch, Verbosity};
+use crate::cmd;
+use crate::parse_check::check_patches;
+
+#[cfg(test)]
+const PATCHES_PATH: &str = "testdata/parse-check/patches";
+
+#[cfg(test)]
+fn series_patch(filename: &str, strip: usize) -> SeriesPatch {
+    SeriesPatch { filename: PathBuf::from(filename), strip, reverse: false }
+}
+
+#[cfg(test)]
+#[test]
+fn malformed_patch_flagged() -> Result<()> {
+    let series_patches = [
+        series_patch("fix-typo.patch", 1),
+        series_patch("broken-header.patch", 1),
+        series_patch("add-notes.patch", 0),
+    ];
+
+    let mut output = Vec::new();
+    assert!(!check_patches(&mut output, Path::new(PATCHES_PATH), &series_patches, Verbosity::Normal)?);
+    assert_eq!(String::from_utf8(output)?,
+               format!("{}:15: Malformed hunk header: \"@@ -2,x +2,3 @@\"\n",
+                       Path::new(PATCHES_PATH).join("broken-header.patch").display()));
+
+    // Without the broken patch, nothing is reported.
+    let series_patches = [
+        series_patch("fix-typo.patch", 1),
+        series_patch("add-notes.patch", 0),
+    ];
+    let mut output = Vec::new();
+    assert!(check_patches(&mut output, Path::new(PATCHES_PATH), &series_patches, Verbosity::Normal)?);
+    assert!(output.is_empty());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn parse_check_fails_and_leaves_tree() -> Result<()> {
+    assert!(!cmd::run(["parse-check", "--quiet", "--directory", "testdata/parse-check"])?);
+    assert!(!Path::new("testdata/parse-check/.pc").exists());
+
+    // A missing patch is an error too.
+    let mut output = Vec::new();
+    assert!(!check_patches(&mut output, Path::new(PATCHES_PATH), &[series_patch("missing.patch", 1)], Verbosity::Normal)?);
+    assert!(String::from_utf8(output)?.starts_with(&format!("{}: ", Path::new(PATCHES_PATH).join("missing.patch").display())));
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    PatchSize,
+    apply_patches,
+    apply_patches_parallel,
+    write_patch_sizes,
+    write_patch_sizes_json,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+
+const PATCH_1: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ 1
+-2
++two
+ 3
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1,2 @@
++new
++file
+";
+
+/// Applied in reverse
+const PATCH_2: &str = "\
+--- a/other.txt
++++ b/other.txt
+@@ -1 +1 @@
+-old
++newer
+";
+
+/// Fails, it is not counted.
+const PATCH_3: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-mismatch
++broken
+";
+
+#[cfg(test)]
+#[test]
+fn patch_sizes_of_small_patches() -> Result<()> {
+    colored::control::set_override(false);
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("1.patch"), PATCH_1)?;
+    fs::write(patches_path.join("2.patch"), PATCH_2)?;
+    fs::write(patches_path.join("3.patch"), PATCH_3)?;
+    fs::write(work_path.join("file.txt"), "1\n2\n3\n")?;
+    fs::write(work_path.join("other.txt"), "newer\n")?;
+
+    let series_patches = [
+        SeriesPatch { filename: PathBuf::from("1.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("2.patch"), strip: 1, reverse: true },
+        SeriesPatch { filename: PathBuf::from("3.patch"), strip: 1, reverse: false },
+    ];
+    let config = ApplyConfig {
+        dry_run: true,
+        stats: true,
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet)
+    };
+
+    let expected_sizes = [
+        PatchSize { patch_filename: PathBuf::from("1.patch"), hunks: 2, fuzzy_hunks: 0, added_bytes: 13, removed_bytes: 2 },
+        PatchSize { patch_filename: PathBuf::from("2.patch"), hunks: 1, fuzzy_hunks: 0, added_bytes: 4, removed_bytes: 6 },
+    ];
+
+    let arena = FileArena::new();
+    let result = apply_patches(&config, &arena, &AnalysisSet::default())?;
+    assert_eq!(result.applied_patches, 2);
+    assert_eq!(result.patch_sizes, expected_sizes);
+
+    // Every thread counts its own files.
+    let arena = FileArena::new();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+    let result = pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?;
+    assert_eq!(result.applied_patches, 2);
+    assert_eq!(result.patch_sizes, expected_sizes);
+
+    let mut output = Vec::new();
+    write_patch_sizes(&mut output, &result.patch_sizes)?;
+    assert_eq!(String::from_utf8(output)?, "\
+Patch sizes:
+  Patch 1.patch: 2 hunk(s), +13 -2 bytes
+  Patch 2.patch: 1 hunk(s), +4 -6 bytes
+");
+
+    let mut output = Vec::new();
+    write_patch_sizes_json(&mut output, &result.patch_sizes)?;
+    assert_eq!(String::from_utf8(output)?, "\
+{\"patch\":\"1.patch\",\"hunks\":2,\"fuzzy_hunks\":0,\"added_bytes\":13,\"removed_bytes\":2}
+{\"patch\":\"2.patch\",\"hunks\":1,\"fuzzy_hunks\":0,\"added_bytes\":4,\"removed_bytes\":6}
+");
+
+    Ok(())
+}
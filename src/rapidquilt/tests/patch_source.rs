@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+#[cfg(feature = "remote")]
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+#[cfg(feature = "remote")]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(feature = "remote")]
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+#[cfg(feature = "remote")]
+use std::thread;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    apply_patches,
+    apply_patches_parallel,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::{Arena, FileArena};
+#[cfg(feature = "remote")]
+use crate::cmd;
+use crate::patch_source::PatchSource;
+#[cfg(feature = "remote")]
+use crate::patch_source::UrlSource;
+
+const MODIFY_PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-original
++patched
+";
+
+const CREATE_PATCH: &str = "\
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+";
+
+/// Serves the patches like a web server would, and remembers the requests.
+#[derive(Debug, Default)]
+struct MockHttpSource {
+    files: HashMap<PathBuf, &'static str>,
+    requests: Mutex<Vec<PathBuf>>,
+}
+
+impl PatchSource for MockHttpSource {
+    fn load_patch<'arena>(&self, arena: &'arena dyn Arena, filename: &Path) -> Result<&'arena [u8], io::Error> {
+        self.requests.lock().unwrap().push(filename.to_path_buf());
+        match self.files.get(filename) {
+            Some(content) => Ok(arena.store_data(content.as_bytes().into())),
+            None => Err(io::Error::other("404 Not Found")),
+        }
+    }
+}
+
+#[cfg(test)]
+fn apply_from_source(work_path: &Path, patch_source: &dyn PatchSource, parallel: bool) -> Result<usize> {
+    // Nothing is loaded from here
+    let patches_path = work_path.join("patches");
+    let series_patches = [
+        SeriesPatch { filename: "modify.patch".into(), strip: 1, reverse: false },
+        SeriesPatch { filename: "create.patch".into(), strip: 1, reverse: false },
+    ];
+    let config = ApplyConfig {
+        patch_source: Some(patch_source),
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet)
+    };
+
+    let arena = FileArena::new();
+    let result = if parallel {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+        pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?
+    } else {
+        apply_patches(&config, &arena, &AnalysisSet::default())?
+    };
+    Ok(result.applied_patches)
+}
+
+#[cfg(test)]
+#[test]
+fn patches_from_mock_source() -> Result<()> {
+    for parallel in [false, true] {
+        let work_dir = tempfile::tempdir()?;
+        let work_path = work_dir.path();
+        fs::write(work_path.join("file.txt"), "original\n")?;
+
+        let patch_source = MockHttpSource {
+            files: HashMap::from([
+                (PathBuf::from("modify.patch"), MODIFY_PATCH),
+                (PathBuf::from("create.patch"), CREATE_PATCH),
+            ]),
+            ..MockHttpSource::default()
+        };
+        assert_eq!(apply_from_source(work_path, &patch_source, parallel)?, 2);
+        assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "patched\n");
+        assert_eq!(fs::read_to_string(work_path.join("new.txt"))?, "new\n");
+
+        let mut requests = patch_source.requests.into_inner().unwrap();
+        requests.sort();
+        assert_eq!(requests, [PathBuf::from("create.patch"), PathBuf::from("modify.patch")]);
+    }
+
+    // A patch that can not be downloaded fails like a missing file
+    let work_dir = tempfile::tempdir()?;
+    fs::write(work_dir.path().join("file.txt"), "original\n")?;
+    let patch_source = MockHttpSource {
+        files: HashMap::from([(PathBuf::from("modify.patch"), MODIFY_PATCH)]),
+        ..MockHttpSource::default()
+    };
+    let error = apply_from_source(work_dir.path(), &patch_source, false).unwrap_err();
+    assert!(error.chain().any(|cause| cause.to_string() == "404 Not Found"), "{:?}", error);
+
+    Ok(())
+}
+
+/// Serve the `files` over HTTP on a local port, for as many requests as
+/// there are files. Returns the URL of the directory with them.
+#[cfg(feature = "remote")]
+fn serve_http(files: HashMap<&'static str, &'static str>) -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/patches", listener.local_addr()?);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().take(files.len()) {
+            let mut stream = stream.unwrap();
+            let mut request_line = String::new();
+            let mut reader = BufReader::new(&stream);
+            reader.read_line(&mut request_line).unwrap();
+            // Skip the headers
+            let mut header = String::new();
+            while reader.read_line(&mut header).unwrap() > 2 {
+                header.clear();
+            }
+
+            let path = request_line.split(' ').nth(1).unwrap_or_default();
+            let response = match path.strip_prefix("/patches/").and_then(|name| files.get(name)) {
+                Some(content) => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", content.len(), content),
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    Ok(url)
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn patches_from_http_url() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::write(work_path.join("file.txt"), "original\n")?;
+
+    let url = serve_http(HashMap::from([
+        ("series", "modify.patch\ncreate.patch\n"),
+        ("modify.patch", MODIFY_PATCH),
+        ("create.patch", CREATE_PATCH),
+    ]))?;
+    assert!(cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--all"),
+        OsStr::new("--threads"), OsStr::new("1"),
+        OsStr::new("--patches-url"), OsStr::new(&url),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "patched\n");
+    assert_eq!(fs::read_to_string(work_path.join("new.txt"))?, "new\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, "modify.patch\ncreate.patch\n");
+
+    Ok(())
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn ssh_url_with_option_refused() {
+    let error = UrlSource::new("ssh://-oProxyCommand=touch${IFS}pwned/patches").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,63 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+use super::quilt_metadata::copy_tree;
+
+#[cfg(test)]
+fn push_all_with_timeout(work_path: &Path, timeout: &str) -> Result<bool> {
+    cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--all"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+        OsStr::new("--backup"), OsStr::new("never"),
+        OsStr::new("--patch-timeout"), OsStr::new(timeout),
+    ])
+}
+
+#[cfg(test)]
+#[test]
+fn timed_out_patch_is_rolled_back() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/basic/input"), work_path)?;
+
+    // With no time at all, the first patch fails and nothing is changed.
+    assert!(!push_all_with_timeout(work_path, "0")?);
+
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, "");
+    assert_eq!(fs::read(work_path.join("file.in"))?,
+               fs::read("testdata/quilt/ok/basic/input/file.in")?);
+    assert!(work_path.join("file.in.rej").exists());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn generous_timeout() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/basic/input"), work_path)?;
+
+    assert!(push_all_with_timeout(work_path, "60")?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn bad_timeout() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/basic/input"), work_path)?;
+
+    assert!(push_all_with_timeout(work_path, "-1").is_err());
+    assert!(push_all_with_timeout(work_path, "soon").is_err());
+
+    Ok(())
+}
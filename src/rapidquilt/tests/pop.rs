@@ -0,0 +1,210 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::cmd;
+use crate::pop::confirm_pop;
+use super::quilt_metadata::copy_tree;
+
+/// Every patch changes one line of "file.txt" and creates its own file
+const PATCHES: [(&str, &str); 3] = [
+    ("1.patch", "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+-1
++one
+ 2
+ 3
+--- /dev/null
++++ b/one.txt
+@@ -0,0 +1 @@
++one
+"),
+    ("2.patch", "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ one
+-2
++two
+ 3
+--- /dev/null
++++ b/two.txt
+@@ -0,0 +1 @@
++two
+"),
+    ("3.patch", "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ one
+ two
+-3
++three
+--- /dev/null
++++ b/three.txt
+@@ -0,0 +1 @@
++three
+"),
+];
+
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    fs::create_dir(work_path.join("patches"))?;
+    let mut series = String::new();
+    for (filename, content) in &PATCHES {
+        fs::write(work_path.join("patches").join(filename), content)?;
+        series.push_str(filename);
+        series.push('\n');
+    }
+    fs::write(work_path.join("series"), series)?;
+    fs::write(work_path.join("file.txt"), "1\n2\n3\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn run_in(work_path: &Path, command: &str, args: &[&str]) -> Result<bool> {
+    let mut all_args = vec![
+        OsStr::new(command),
+        OsStr::new("--quiet"),
+        OsStr::new("--backup"), OsStr::new("always"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    all_args.extend(args.iter().map(OsStr::new));
+    cmd::run(all_args)
+}
+
+#[cfg(test)]
+fn check_state(work_path: &Path, file: &str, applied: &str) -> Result<()> {
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, file);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, applied);
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn push_and_pop_by_count() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+
+    assert!(run_in(work_path, "push", &["2"])?);
+    check_state(work_path, "one\ntwo\n3\n", "1.patch\n2.patch\n")?;
+    assert!(work_path.join("two.txt").exists());
+
+    assert!(run_in(work_path, "pop", &["1"])?);
+    check_state(work_path, "one\n2\n3\n", "1.patch\n")?;
+    assert!(!work_path.join("two.txt").exists());
+    assert!(!work_path.join(".pc/2.patch").exists());
+
+    // Stops at the end of the series
+    assert!(run_in(work_path, "push", &["--count", "5"])?);
+    check_state(work_path, "one\ntwo\nthree\n", "1.patch\n2.patch\n3.patch\n")?;
+
+    // To a named patch, which stays applied
+    assert!(run_in(work_path, "pop", &["1.patch"])?);
+    check_state(work_path, "one\n2\n3\n", "1.patch\n")?;
+
+    // Stops when no patch is applied
+    assert!(run_in(work_path, "pop", &["--count", "5"])?);
+    check_state(work_path, "1\n2\n3\n", "")?;
+    assert!(!work_path.join("one.txt").exists());
+    assert!(run_in(work_path, "pop", &[])?);
+
+    assert!(run_in(work_path, "push", &["--all"])?);
+    assert!(run_in(work_path, "pop", &["--all", "--yes"])?);
+    check_state(work_path, "1\n2\n3\n", "")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn pop_without_backup_fails() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+
+    assert!(cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--all"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+
+    let error = run_in(work_path, "pop", &[]).unwrap_err();
+    assert!(error.to_string().contains("no backup files"), "{}", error);
+    check_state(work_path, "one\ntwo\nthree\n", "1.patch\n2.patch\n3.patch\n")?;
+
+    // Both a count and a number
+    assert!(run_in(work_path, "pop", &["--count", "1", "1"]).is_err());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn pop_all_without_terminal() -> Result<()> {
+    // It would ask on a terminal.
+    if io::stdin().is_terminal() && io::stdout().is_terminal() {
+        return Ok(());
+    }
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+
+    // Scripts do not need `--yes`.
+    assert!(run_in(work_path, "push", &["--all"])?);
+    assert!(run_in(work_path, "pop", &["--all"])?);
+    check_state(work_path, "1\n2\n3\n", "")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn confirm_pop_asks() -> Result<()> {
+    let patches = [PathBuf::from("1.patch"), PathBuf::from("2.patch")];
+
+    let mut output = Vec::new();
+    assert!(confirm_pop(&mut &b"y\n"[..], &mut output, &patches)?);
+    assert_eq!(String::from_utf8(output)?, "\
+Popping 2 patches:
+  2.patch
+  1.patch
+Changes that are not refreshed are lost. Continue? [y/N] ");
+
+    assert!(confirm_pop(&mut &b"YES\n"[..], &mut Vec::new(), &patches)?);
+    assert!(!confirm_pop(&mut &b"\n"[..], &mut Vec::new(), &patches)?);
+    assert!(!confirm_pop(&mut &b"no\n"[..], &mut Vec::new(), &patches)?);
+    // No answer at all
+    assert!(!confirm_pop(&mut &b""[..], &mut Vec::new(), &patches)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+#[cfg(unix)]
+fn pop_restores_file_types() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/type_change/input"), work_path)?;
+
+    assert!(run_in(work_path, "push", &["--all"])?);
+    assert_eq!(fs::read_link(work_path.join("file"))?, Path::new("target"));
+    assert!(!fs::symlink_metadata(work_path.join("link"))?.file_type().is_symlink());
+
+    assert!(run_in(work_path, "pop", &["--all"])?);
+    assert!(!fs::symlink_metadata(work_path.join("file"))?.file_type().is_symlink());
+    assert_eq!(fs::read_to_string(work_path.join("file"))?, "This is a\nregular file.\n");
+    assert_eq!(fs::read_link(work_path.join("link"))?, Path::new("target"));
+    assert_eq!(fs::read_to_string(work_path.join("target"))?, "target\n");
+
+    Ok(())
+}
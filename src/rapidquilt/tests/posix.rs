@@ -0,0 +1,88 @@
+use std::fs;
+
+use anyhow::Result;
+
+const DELETE_PATCH: &str = "\
+--- a/file.txt
++++ /dev/null
+@@ -1,2 +0,0 @@
+-1
+-2
+";
+
+/// Empties the file, but keeps it
+const EMPTY_PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +0,0 @@
+-1
+-2
+";
+
+const CREATE_PATCH: &str = "\
+--- /dev/null
++++ b/file.txt
+@@ -0,0 +1 @@
++new
+";
+
+#[cfg(test)]
+fn push_patches(patches: &[(&str, &str)], posix: bool) -> Result<(tempfile::TempDir, bool)> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    let mut series = String::new();
+    for (filename, content) in patches {
+        fs::write(work_path.join("patches").join(filename), content)?;
+        series.push_str(filename);
+        series.push('\n');
+    }
+    fs::write(work_path.join("series"), series)?;
+    fs::write(work_path.join("file.txt"), "1\n2\n")?;
+
+    let ok = super::push(work_path, if posix { &["--all", "--posix"] } else { &["--all"] })?;
+    Ok((work_dir, ok))
+}
+
+#[cfg(test)]
+fn read_file(work_dir: &tempfile::TempDir) -> Option<String> {
+    fs::read_to_string(work_dir.path().join("file.txt")).ok()
+}
+
+#[cfg(test)]
+#[test]
+fn posix_leaves_deleted_file_empty() -> Result<()> {
+    let (work_dir, ok) = push_patches(&[("delete.patch", DELETE_PATCH)], false)?;
+    assert!(ok);
+    assert_eq!(read_file(&work_dir), None);
+
+    let (work_dir, ok) = push_patches(&[("delete.patch", DELETE_PATCH)], true)?;
+    assert!(ok);
+    assert_eq!(read_file(&work_dir).as_deref(), Some(""));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn emptied_file_is_kept_in_both_modes() -> Result<()> {
+    for posix in [false, true] {
+        let (work_dir, ok) = push_patches(&[("empty.patch", EMPTY_PATCH)], posix)?;
+        assert!(ok);
+        assert_eq!(read_file(&work_dir).as_deref(), Some(""));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn posix_creates_file_over_empty_file() -> Result<()> {
+    for posix in [false, true] {
+        let (work_dir, ok) = push_patches(&[("delete.patch", DELETE_PATCH), ("create.patch", CREATE_PATCH)], posix)?;
+        assert!(ok);
+        assert_eq!(read_file(&work_dir).as_deref(), Some("new\n"));
+    }
+
+    Ok(())
+}
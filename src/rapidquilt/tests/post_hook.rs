@@ -0,0 +1,78 @@
+use std::ffi::OsStr;
+use std::fs;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+/// Set up quilt directory with three patches and push them with a post-hook
+/// that leaves a sentinel file per patch, with a copy of the patched file.
+/// The hook fails for the `failing_patch`.
+#[cfg(test)]
+fn push_with_hook(failing_patch: &str) -> Result<(tempfile::TempDir, bool)> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/first.patch"),
+              "--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-a\n+first\n")?;
+    fs::write(work_path.join("patches/second.patch"),
+              "--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-first\n+second\n--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1 @@\n+new\n")?;
+    fs::write(work_path.join("patches/third.patch"),
+              "--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-second\n+third\n")?;
+    fs::write(work_path.join("series"), "first.patch\nsecond.patch\nthird.patch\n")?;
+    fs::write(work_path.join("a.txt"), "a\n")?;
+
+    let hook = format!(concat!("printf '%s\\n' \"$RAPIDQUILT_FILES\" > \"$RAPIDQUILT_PATCH.files\"; ",
+                               "cp a.txt \"$RAPIDQUILT_PATCH.done\"; ",
+                               "test \"$RAPIDQUILT_PATCH\" != {}"), failing_patch);
+
+    let args = [
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--all"),
+        OsStr::new("--backup"), OsStr::new("always"),
+        OsStr::new("--post-hook"), OsStr::new(&hook),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    let result = cmd::run(args)?;
+
+    Ok((work_dir, result))
+}
+
+#[cfg(test)]
+#[test]
+fn post_hook_runs_after_every_patch() -> Result<()> {
+    let (work_dir, result) = push_with_hook("none")?;
+    let work_path = work_dir.path();
+    assert!(result);
+
+    // The hook saw every patch applied
+    assert_eq!(fs::read_to_string(work_path.join("first.patch.done"))?, "first\n");
+    assert_eq!(fs::read_to_string(work_path.join("second.patch.done"))?, "second\n");
+    assert_eq!(fs::read_to_string(work_path.join("third.patch.done"))?, "third\n");
+    assert_eq!(fs::read_to_string(work_path.join("second.patch.files"))?, "a.txt\nnew.txt\n");
+
+    assert_eq!(fs::read_to_string(work_path.join("a.txt"))?, "third\n");
+    assert_eq!(fs::read_to_string(work_path.join("new.txt"))?, "new\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/second.patch/a.txt"))?, "first\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn post_hook_failure_rolls_back() -> Result<()> {
+    let (work_dir, result) = push_with_hook("second.patch")?;
+    let work_path = work_dir.path();
+    assert!(!result);
+
+    assert!(work_path.join("second.patch.done").exists());
+    assert!(!work_path.join("third.patch.done").exists());
+
+    // The second patch was taken back
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, "first.patch\n");
+    assert_eq!(fs::read_to_string(work_path.join("a.txt"))?, "first\n");
+    assert!(!work_path.join("new.txt").exists());
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::prefix_map::PrefixMap;
+use super::push;
+
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path) -> Result<()> {
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/upstream.patch"), "\
+--- a/src/a.c
++++ b/src/a.c
+@@ -1 +1 @@
+-a
++A
+--- a/src/sub/b.c
++++ b/src/sub/b.c
+@@ -1 +1 @@
+-b
++B
+--- a/srcfoo/c.c
++++ b/srcfoo/c.c
+@@ -1 +1 @@
+-c
++C
+")?;
+    fs::write(work_path.join("series"), "upstream.patch\n")?;
+    fs::create_dir_all(work_path.join("source/sub"))?;
+    fs::write(work_path.join("source/a.c"), "a\n")?;
+    fs::write(work_path.join("source/sub/b.c"), "b\n")?;
+    fs::create_dir(work_path.join("srcfoo"))?;
+    fs::write(work_path.join("srcfoo/c.c"), "c\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn prefix_map_matches_whole_components() {
+    let mut prefix_map = PrefixMap::new();
+    assert!(prefix_map.add("src=source"));
+    assert!(prefix_map.add("lib/old="));
+    assert!(prefix_map.add("src/a.c=never"));
+    assert!(!prefix_map.add("=source"));
+    assert!(!prefix_map.add("src"));
+
+    assert_eq!(prefix_map.map(Path::new("src/a.c")), Some(PathBuf::from("source/a.c")));
+    assert_eq!(prefix_map.map(Path::new("src")), Some(PathBuf::from("source")));
+    assert_eq!(prefix_map.map(Path::new("srcfoo/a.c")), None);
+    assert_eq!(prefix_map.map(Path::new("lib/old/x.c")), Some(PathBuf::from("x.c")));
+    assert_eq!(prefix_map.map(Path::new("lib/old")), None);
+    assert_eq!(prefix_map.map(Path::new("lib/new/x.c")), None);
+}
+
+#[cfg(test)]
+#[test]
+fn push_with_prefix_map() -> Result<()> {
+    for threads in ["1", "2"] {
+        let work_dir = tempfile::tempdir()?;
+        let work_path = work_dir.path();
+        setup_work_dir(work_path)?;
+
+        assert!(push(work_path, &["--threads", threads, "--backup", "always", "--prefix-map", "src=source"])?);
+        assert_eq!(fs::read_to_string(work_path.join("source/a.c"))?, "A\n");
+        assert_eq!(fs::read_to_string(work_path.join("source/sub/b.c"))?, "B\n");
+        assert_eq!(fs::read_to_string(work_path.join("srcfoo/c.c"))?, "C\n");
+        assert!(!work_path.join("src").exists());
+
+        // The backups are of the rewritten paths, so pop restores them
+        assert!(work_path.join(".pc/upstream.patch/source/a.c").exists());
+        assert!(work_path.join(".pc/upstream.patch/source/sub/b.c").exists());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn bad_prefix_map_refused() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    setup_work_dir(work_path)?;
+
+    let error = push(work_path, &["--threads", "1", "--prefix-map", "source"]).unwrap_err();
+    assert!(error.to_string().contains("\"prefix-map\""), "{:?}", error);
+    assert_eq!(fs::read_to_string(work_path.join("source/a.c"))?, "a\n");
+
+    Ok(())
+}
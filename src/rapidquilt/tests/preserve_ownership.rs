@@ -0,0 +1,44 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+use super::quilt_metadata::copy_tree;
+
+/// Push the first patch from "mismatch" on a file owned by someone else and
+/// return the owner of the patched file.
+#[cfg(test)]
+fn push_foreign_file(extra_args: &[&str]) -> Result<(u32, u32)> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/fail/mismatch/input"), work_path)?;
+    std::os::unix::fs::chown(work_path.join("file.in"), Some(1234), Some(5678))?;
+
+    let mut args = vec![
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    args.extend(extra_args.iter().map(OsStr::new));
+    assert!(cmd::run(&args)?);
+
+    let metadata = fs::metadata(work_path.join("file.in"))?;
+    Ok((metadata.uid(), metadata.gid()))
+}
+
+#[cfg(test)]
+#[test]
+fn preserve_ownership() -> Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        println!("Skipping, changing owners of files requires root.");
+        return Ok(());
+    }
+
+    assert_eq!(push_foreign_file(&["--preserve-ownership"])?, (1234, 5678));
+    assert_eq!(push_foreign_file(&[])?, (0, 0));
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::ffi::OsStr;
+use std::fs;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+const FIRST_PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,6 +1,7 @@
+ a
+ b
++new
+ c
+ d
+ e
+ f
+@@ -7,3 +8,3 @@
+ g
+ h
+-i
++I
+";
+
+/// Changes the line added by the first patch and adds one after it.
+const SECOND_PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,3 +2,4 @@
+ b
+-new
++newer
++newest
+ c
+";
+
+#[cfg(test)]
+#[test]
+fn lines_come_from_the_last_patch_that_changed_them() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/first.patch"), FIRST_PATCH)?;
+    fs::write(work_path.join("patches/second.patch"), SECOND_PATCH)?;
+    fs::write(work_path.join("series"), "first.patch\nsecond.patch\n")?;
+    fs::write(work_path.join("file.txt"), "a\nb\nc\nd\ne\nf\ng\nh\ni\n")?;
+
+    let provenance_path = work_path.join("provenance.json");
+    assert!(cmd::run(vec![
+        OsStr::new("push"),
+        OsStr::new("-a"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+        OsStr::new("--provenance"), provenance_path.as_os_str(),
+    ])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "a\nb\nnewer\nnewest\nc\nd\ne\nf\ng\nh\nI\n");
+
+    assert_eq!(fs::read_to_string(&provenance_path)?, "\
+{\"path\":\"file.txt\",\"start\":3,\"end\":4,\"patch\":\"second.patch\",\"hunk\":1}
+{\"path\":\"file.txt\",\"start\":11,\"end\":11,\"patch\":\"first.patch\",\"hunk\":2}
+");
+
+    Ok(())
+}
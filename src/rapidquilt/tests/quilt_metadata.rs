@@ -1,16 +1,27 @@
 use crate::cmd;
+use crate::file_filter::FileFilter;
 
 use std::fs;
 
 use std::ffi::OsStr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::io::{Read, ErrorKind};
 use anyhow::{anyhow, Context, Result};
 
+/// The entries of the directory at `path`, sorted by name, so trees are
+/// walked in the same order on every filesystem.
 #[cfg(test)]
-fn copy_tree(from: &Path, to: &Path) -> Result<()> {
-    for entry in fs::read_dir(from).context(format!("Copying {:?}", from))? {
-        let entry = entry?;
+pub fn sorted_entries(path: &Path) -> Result<Vec<fs::DirEntry>> {
+    let mut entries = fs::read_dir(path)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .context(format!("Reading {:?}", path))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    Ok(entries)
+}
+
+#[cfg(test)]
+pub fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    for entry in sorted_entries(from).context(format!("Copying {:?}", from))? {
         let src_path = entry.path();
         let dest_path = to.join(entry.file_name());
         let metadata = fs::symlink_metadata(&src_path)?;
@@ -33,8 +44,7 @@ fn copy_tree(from: &Path, to: &Path) -> Result<()> {
 
 #[cfg(test)]
 fn compare_tree(src: &Path, dst: &Path) -> Result<()> {
-    for entry in fs::read_dir(src).context(format!("Reading {:?}", src))? {
-        let entry = entry?;
+    for entry in sorted_entries(src)? {
         let src_path = entry.path();
         let dest_path = dst.join(entry.file_name());
 
@@ -83,18 +93,23 @@ fn compare_tree(src: &Path, dst: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Report files in `dst` that are not in `src`. Paths (relative to the top
+/// of the tree) matching the `exclude` globs are skipped together with
+/// everything below them.
 #[cfg(test)]
-fn check_extra_files(src: &Path, dst: &Path) -> Result<()> {
+fn check_extra_files(src: &Path, dst: &Path, relative: &Path, exclude: &FileFilter) -> Result<()> {
     let mut errors = Vec::<String>::new();
-    for entry in fs::read_dir(dst).context(format!("Reading {:?}", dst))? {
-        let entry = entry?;
+    for entry in sorted_entries(dst)? {
         let dst_path = entry.path();
         let src_path = src.join(entry.file_name());
+        let relative_path = relative.join(entry.file_name());
 
-        if src_path.symlink_metadata().is_err() {
+        if !exclude.is_included(&relative_path) {
+            continue;
+        } else if src_path.symlink_metadata().is_err() {
             errors.push(format!("Unexpected file {:?}", dst_path));
         } else if dst_path.is_dir() {
-            check_extra_files(&src_path, &dst_path)?;
+            check_extra_files(&src_path, &dst_path, &relative_path, exclude)?;
         }
     }
     match errors.len() {
@@ -103,16 +118,41 @@ fn check_extra_files(src: &Path, dst: &Path) -> Result<()> {
     }
 }
 
+/// Read the globs of paths to skip when looking for extra files, one per
+/// line in the "exclude" file of the test. E.g. ".pc" and "patches" for
+/// a test that checks only the patched tree.
+#[cfg(test)]
+fn read_exclude(path: &Path) -> Result<FileFilter> {
+    let mut exclude = FileFilter::new();
+    match fs::read_to_string(path.join("exclude")) {
+        Ok(content) => {
+            for glob in content.lines().filter(|line| !line.is_empty()) {
+                exclude.exclude(glob)?;
+            }
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {},
+        Err(err) => return Err(err.into()),
+    }
+    Ok(exclude)
+}
+
 #[cfg(test)]
 fn push_all(path: &Path, num_threads: usize, expect: bool) -> Result<()> {
     eprintln!("Push all patches in {}", path.display());
 
     let work_dir = tempfile::tempdir()?;
     let work_path = work_dir.path();
-    copy_tree(&path.join("input"), &work_path)?;
+    copy_tree(&path.join("input"), work_path)?;
+
+    // Extra arguments for the test, if any
+    let extra_args = match fs::read_to_string(path.join("args")) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
 
     let num_threads = num_threads.to_string();
-    let args = [
+    let mut args = vec![
         OsStr::new("push"),
         OsStr::new("--quiet"),
         OsStr::new("--threads"), OsStr::new(&num_threads),
@@ -120,12 +160,14 @@ fn push_all(path: &Path, num_threads: usize, expect: bool) -> Result<()> {
         OsStr::new("--directory"), work_path.as_os_str(),
         OsStr::new("--backup"), OsStr::new("always"),
     ];
+    args.extend(extra_args.split_whitespace().map(OsStr::new));
+    let exclude = read_exclude(path)?;
     let result = cmd::run(&args);
 
     match result {
         Ok(status) if status == expect => {
-            compare_tree(&path.join("expect"), &work_path)?;
-            check_extra_files(&path.join("expect"), &work_path)
+            compare_tree(&path.join("expect"), work_path)?;
+            check_extra_files(&path.join("expect"), work_path, &PathBuf::new(), &exclude)
         },
         Ok(_) => Err(anyhow!(match expect {
             true => "Push failed unexpectedly",
@@ -137,23 +179,18 @@ fn push_all(path: &Path, num_threads: usize, expect: bool) -> Result<()> {
 
 #[cfg(test)]
 fn check_series(path: &str, num_threads: usize, expect: bool) -> Result<()> {
-    let dir = fs::read_dir(path);
-    match dir {
-        Ok(dir) => {
-            for entry in dir {
-                let entry = entry?;
-                if let Err(err) = push_all(&entry.path(), num_threads, expect) {
-                    for fail in err.chain() {
-                        eprintln!("{}", fail);
-                    }
-                    panic!("Push all failed for {:?}", entry.file_name());
-                }
+    if !Path::new(path).exists() {
+        return Ok(());
+    }
+    for entry in sorted_entries(Path::new(path))? {
+        if let Err(err) = push_all(&entry.path(), num_threads, expect) {
+            for fail in err.chain() {
+                eprintln!("{}", fail);
             }
-            Ok(())
-        },
-        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
-        Err(err) => Err(err.into()),
+            panic!("Push all failed for {:?}", entry.file_name());
+        }
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -182,3 +219,51 @@ fn ok_series_parallel() -> Result<()> {
 fn fail_series_parallel() -> Result<()> {
     check_series("testdata/quilt/fail", NUM_THREADS, false)
 }
+
+#[cfg(test)]
+#[test]
+fn tree_walks_are_sorted() -> Result<()> {
+    use crate::apply::{BackupStore, FileBackupStore};
+
+    let work_dir = tempfile::tempdir()?;
+    let src_path = &work_dir.path().join("src");
+    let dst_path = &work_dir.path().join("dst");
+
+    // Created out of order, in nested directories
+    let mut filenames = Vec::new();
+    for i in 0..300 {
+        let number = i * 7919 % 300;
+        let filename = PathBuf::from(format!("dir-{}", number % 3)).join(format!("file-{:03}", number));
+        fs::create_dir_all(src_path.join(filename.parent().unwrap()))?;
+        fs::write(src_path.join(&filename), format!("{}\n", number))?;
+        filenames.push(filename);
+    }
+    filenames.sort();
+
+    let names: Vec<_> = sorted_entries(src_path)?.iter().map(|entry| entry.file_name()).collect();
+    assert_eq!(names, ["dir-0", "dir-1", "dir-2"]);
+
+    fs::create_dir(dst_path)?;
+    copy_tree(src_path, dst_path)?;
+    compare_tree(src_path, dst_path)?;
+
+    // The first difference is reported, the same on every filesystem.
+    fs::write(dst_path.join("dir-2/file-251"), "changed\n")?;
+    fs::write(dst_path.join("dir-1/file-250"), "changed\n")?;
+    let error = compare_tree(src_path, dst_path).unwrap_err().to_string();
+    assert!(error.starts_with(&format!("Content mismatch in {:?}", dst_path.join("dir-1/file-250"))), "{}", error);
+
+    fs::write(dst_path.join("dir-2/extra-b"), "")?;
+    fs::write(dst_path.join("dir-0/extra-a"), "")?;
+    let error = check_extra_files(src_path, dst_path, Path::new(""), &FileFilter::new()).unwrap_err().to_string();
+    assert_eq!(error, format!("Unexpected file {:?}", dst_path.join("dir-0/extra-a")));
+
+    // The backup files of a patch are listed in order too.
+    let backup_dir = work_dir.path().join(".pc/many.patch");
+    fs::create_dir_all(&backup_dir)?;
+    copy_tree(src_path, &backup_dir)?;
+    let backup_store = FileBackupStore::new(work_dir.path());
+    assert_eq!(backup_store.list(Path::new("many.patch"))?, Some(filenames));
+
+    Ok(())
+}
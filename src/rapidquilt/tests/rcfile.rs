@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::rcfile::RC_FILENAME;
+
+/// Its first context line does not match, it applies only with fuzz 1.
+const FUZZY_PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,7 +1,7 @@
+ changed
+ b
+ c
+-d
++D
+ e
+ f
+ g
+";
+
+const ORIGINAL: &str = "a\nb\nc\nd\ne\nf\ng\n";
+
+#[cfg(test)]
+fn reset_and_push(work_path: &Path, args: &[&str]) -> Result<bool> {
+    fs::write(work_path.join("file.txt"), ORIGINAL)?;
+    let _ = fs::remove_dir_all(work_path.join(".pc"));
+    super::push(work_path, args)
+}
+
+#[cfg(test)]
+#[test]
+fn rc_file_sets_defaults() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("fixes"))?;
+    fs::write(work_path.join("fixes/fuzzy.patch"), FUZZY_PATCH)?;
+    fs::write(work_path.join("series"), "fuzzy.patch\n")?;
+
+    fs::write(work_path.join(RC_FILENAME), "\
+# Defaults of the team
+--fuzz 1
+
+-p fixes
+--backup=never
+")?;
+    assert!(reset_and_push(work_path, &[])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, ORIGINAL.replace("d\n", "D\n"));
+    assert!(!work_path.join(".pc/fuzzy.patch").exists());
+
+    // The command line wins.
+    assert!(!reset_and_push(work_path, &["--fuzz", "0"])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, ORIGINAL);
+
+    fs::write(work_path.join(RC_FILENAME), "--fuzz 1 --backup never\n")?;
+    let error = reset_and_push(work_path, &[]).unwrap_err();
+    assert_eq!(error.to_string(), format!("Line 1 of \"{}\" is not a single option: --fuzz 1 --backup never",
+                                          work_path.join(RC_FILENAME).display()));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn rc_file_refuses_other_options() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/fuzzy.patch"), FUZZY_PATCH)?;
+    fs::write(work_path.join("series"), "fuzzy.patch\n")?;
+
+    for line in ["--post-hook touch${IFS}hooked", "--unsafe-paths"] {
+        fs::write(work_path.join(RC_FILENAME), format!("--fuzz 1\n{}\n", line))?;
+        let error = reset_and_push(work_path, &[]).unwrap_err();
+        assert!(error.to_string().starts_with(&format!("Line 2 of \"{}\" sets option {}, only",
+                                                       work_path.join(RC_FILENAME).display(),
+                                                       line.split(' ').next().unwrap())),
+                "{}", error);
+        assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, ORIGINAL);
+        assert!(!work_path.join("hooked").exists());
+    }
+
+    Ok(())
+}
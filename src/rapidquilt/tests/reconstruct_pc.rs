@@ -0,0 +1,123 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+/// Both patches change "file.txt", the second one deletes "old.txt" and
+/// creates "new.txt".
+const PATCHES: [(&str, &str); 2] = [
+    ("1.patch", "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+-1
++one
+ 2
+ 3
+"),
+    ("2.patch", "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ one
+-2
++two
+ 3
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-old
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+"),
+];
+
+#[cfg(test)]
+fn write_pristine_files(path: &Path) -> Result<()> {
+    fs::write(path.join("file.txt"), "1\n2\n3\n")?;
+    fs::write(path.join("old.txt"), "old\n")?;
+    Ok(())
+}
+
+/// A working tree with the patches pushed and the backups in ".pc" lost,
+/// and a pristine copy of it in "pristine"
+#[cfg(test)]
+fn setup(temp_path: &Path) -> Result<()> {
+    for dir in ["work/patches", "pristine"] {
+        fs::create_dir_all(temp_path.join(dir))?;
+    }
+    let work_path = temp_path.join("work");
+    let mut series = String::new();
+    for (filename, content) in &PATCHES {
+        fs::write(work_path.join("patches").join(filename), content)?;
+        series.push_str(filename);
+        series.push('\n');
+    }
+    fs::write(work_path.join("series"), series)?;
+    write_pristine_files(&work_path)?;
+    write_pristine_files(&temp_path.join("pristine"))?;
+
+    assert!(run_in(&work_path, &["push", "--all", "--backup", "always"])?);
+    for (filename, _) in &PATCHES {
+        fs::remove_dir_all(work_path.join(".pc").join(filename))?;
+    }
+    assert!(run_in(&work_path, &["pop"]).is_err());
+    Ok(())
+}
+
+#[cfg(test)]
+fn run_in(work_path: &Path, args: &[&str]) -> Result<bool> {
+    let mut all_args = vec![OsStr::new("--quiet"), OsStr::new("--directory"), work_path.as_os_str()];
+    all_args.extend(args.iter().map(OsStr::new));
+    cmd::run(all_args)
+}
+
+#[cfg(test)]
+#[test]
+fn reconstructed_backups_pop() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let temp_path = temp_dir.path();
+    setup(temp_path)?;
+    let work_path = temp_path.join("work");
+    let pristine_path = temp_path.join("pristine");
+
+    assert!(run_in(&work_path, &["reconstruct-pc", "--reference", pristine_path.to_str().unwrap()])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/1.patch/file.txt"))?, "1\n2\n3\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/2.patch/file.txt"))?, "one\n2\n3\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/2.patch/old.txt"))?, "old\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/2.patch/new.txt"))?, "");
+
+    assert!(run_in(&work_path, &["pop", "--all"])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "1\n2\n3\n");
+    assert_eq!(fs::read_to_string(work_path.join("old.txt"))?, "old\n");
+    assert!(!work_path.join("new.txt").exists());
+    assert!(!work_path.join(".pc/applied-patches").exists() || fs::read_to_string(work_path.join(".pc/applied-patches"))?.is_empty());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn reconstruct_pc_reports_mismatch() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let temp_path = temp_dir.path();
+    setup(temp_path)?;
+    let work_path = temp_path.join("work");
+    let pristine_path = temp_path.join("pristine");
+
+    // Edited after the push, the backups are still rebuilt.
+    fs::write(work_path.join("new.txt"), "edited\n")?;
+    assert!(!run_in(&work_path, &["reconstruct-pc", "--reference", pristine_path.to_str().unwrap()])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/2.patch/file.txt"))?, "one\n2\n3\n");
+
+    // A reference that the patches do not apply on is an error.
+    fs::write(pristine_path.join("file.txt"), "x\ny\nz\n")?;
+    assert!(run_in(&work_path, &["reconstruct-pc", "--reference", pristine_path.to_str().unwrap()]).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::ffi::OsStr;
+use std::fs;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+const PATCH: &str = "\
+--- a/top.txt
++++ b/top.txt
+@@ -1 +1 @@
+-missing
++MISSING
+--- a/dir/sub/file.txt
++++ b/dir/sub/file.txt
+@@ -1,2 +1,2 @@
+ one
+-missing
++MISSING
+";
+
+#[cfg(test)]
+#[test]
+fn rejects_in_reject_dir() -> Result<()> {
+    for threads in ["1", "2"] {
+        let work_dir = tempfile::tempdir()?;
+        let work_path = work_dir.path();
+        fs::create_dir_all(work_path.join("patches"))?;
+        fs::create_dir_all(work_path.join("dir/sub"))?;
+        fs::write(work_path.join("top.txt"), "top\n")?;
+        fs::write(work_path.join("dir/sub/file.txt"), "one\ntwo\n")?;
+        fs::write(work_path.join("patches/bad.patch"), PATCH)?;
+        fs::write(work_path.join("series"), "bad.patch\n")?;
+
+        let reject_dir = tempfile::tempdir()?;
+        let reject_path = reject_dir.path().join("rejects");
+
+        assert!(!cmd::run([
+            OsStr::new("push"),
+            OsStr::new("--quiet"),
+            OsStr::new("--threads"), OsStr::new(threads),
+            OsStr::new("--reject-dir"), reject_path.as_os_str(),
+            OsStr::new("--reject-suffix"), OsStr::new(".reject"),
+            OsStr::new("--directory"), work_path.as_os_str(),
+        ])?);
+
+        // The structure of the tree is kept
+        assert!(fs::read_to_string(reject_path.join("top.txt.reject"))?.contains("+MISSING\n"));
+        assert!(fs::read_to_string(reject_path.join("dir/sub/file.txt.reject"))?.contains("+MISSING\n"));
+        assert!(!work_path.join("top.txt.rej").exists());
+        assert!(!work_path.join("top.txt.reject").exists());
+        assert!(!work_path.join("dir/sub/file.txt.rej").exists());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn reject_suffix_beside_file() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir_all(work_path.join("patches"))?;
+    fs::create_dir_all(work_path.join("dir/sub"))?;
+    fs::write(work_path.join("top.txt"), "top\n")?;
+    fs::write(work_path.join("dir/sub/file.txt"), "one\ntwo\n")?;
+    fs::write(work_path.join("patches/bad.patch"), PATCH)?;
+    fs::write(work_path.join("series"), "bad.patch\n")?;
+
+    let push = |suffix: &str| cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--reject-suffix"), OsStr::new(suffix),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ]);
+    assert!(push("").is_err());
+    assert!(push("/rej").is_err());
+    assert!(!push("~rej")?);
+
+    assert!(work_path.join("top.txt~rej").exists());
+    assert!(work_path.join("dir/sub/file.txt~rej").exists());
+    assert!(!work_path.join("top.txt.rej").exists());
+
+    Ok(())
+}
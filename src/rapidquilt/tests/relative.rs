@@ -0,0 +1,74 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    apply_patches_parallel,
+    write_buffered_messages,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+
+/// Push a patch that names the files by their absolute paths in the
+/// `work_path`. Returns everything the application would print.
+#[cfg(test)]
+fn apply_absolute_patch(work_path: &Path, relative: bool) -> Result<String> {
+    let patches_path = work_path.join("patches");
+    let series_patches = [
+        SeriesPatch { filename: "absolute.patch".into(), strip: 0, reverse: false },
+    ];
+    let config = ApplyConfig {
+        relative,
+        deterministic: true,
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Normal)
+    };
+
+    let arena = FileArena::new();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+    let result = pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?;
+    assert_eq!(result.applied_patches, 0);
+
+    let mut output = Vec::new();
+    let mut error_output = Vec::new();
+    write_buffered_messages(&mut output, &mut error_output, &result.messages)?;
+    output.extend(error_output);
+
+    Ok(String::from_utf8(output)?)
+}
+
+#[cfg(test)]
+#[test]
+fn rejects_named_relative_to_tree() -> Result<()> {
+    colored::control::set_override(false);
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir_all(work_path.join("patches"))?;
+    fs::create_dir(work_path.join("dir"))?;
+    fs::write(work_path.join("dir/file.txt"), "other\n")?;
+    fs::write(work_path.join("patches/absolute.patch"), format!("\
+--- {0}/dir/file.txt
++++ {0}/dir/file.txt
+@@ -1 +1 @@
+-original
++patched
+", work_path.display()))?;
+
+    // Absolute paths are outside of the working directory otherwise
+    assert!(apply_absolute_patch(work_path, false).is_err());
+
+    let output = apply_absolute_patch(work_path, true)?;
+    assert!(output.contains("Saving rejects to \"dir/file.txt.rej\""), "{}", output);
+    assert!(output.contains("File dir/file.txt FAILED"), "{}", output);
+    assert!(!output.contains(&*work_path.to_string_lossy()), "{}", output);
+
+    let rej = fs::read_to_string(work_path.join("dir/file.txt.rej"))?;
+    assert!(rej.contains("\n--- dir/file.txt\n+++ dir/file.txt\n"), "{}", rej);
+    assert!(!rej.contains(&*work_path.to_string_lossy()), "{}", rej);
+
+    Ok(())
+}
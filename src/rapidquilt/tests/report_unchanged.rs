@@ -0,0 +1,95 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    apply_patches,
+    apply_patches_parallel,
+    write_unchanged_files_report,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+
+/// Its hunk in "a.txt" adds back the line it removes.
+const NOOP_PATCH: &str = "\
+--- a/a.txt
++++ b/a.txt
+@@ -1,3 +1,3 @@
+ one
+-two
++two
+ three
+--- a/b.txt
++++ b/b.txt
+@@ -1 +1 @@
+-old
++new
+";
+
+const REAL_PATCH: &str = "\
+--- a/a.txt
++++ b/a.txt
+@@ -1,3 +1,3 @@
+ one
+-two
++TWO
+ three
+";
+
+#[cfg(test)]
+#[test]
+fn noop_patch_is_reported() -> Result<()> {
+    colored::control::set_override(false);
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("noop.patch"), NOOP_PATCH)?;
+    fs::write(patches_path.join("real.patch"), REAL_PATCH)?;
+    fs::write(work_path.join("a.txt"), "one\ntwo\nthree\n")?;
+    fs::write(work_path.join("b.txt"), "old\n")?;
+
+    let series_patches = [
+        SeriesPatch { filename: PathBuf::from("noop.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("real.patch"), strip: 1, reverse: false },
+    ];
+    let config = ApplyConfig {
+        save_rej_files: false,
+        dry_run: true,
+        report_unchanged: true,
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet)
+    };
+
+    let arena = FileArena::new();
+    let result = apply_patches(&config, &arena, &AnalysisSet::default())?;
+    assert_eq!(result.applied_patches, 2);
+    let unchanged: Vec<_> = result.unchanged_files.iter()
+        .map(|file| (file.patch_filename.clone(), file.filename.clone()))
+        .collect();
+    assert_eq!(unchanged, [(PathBuf::from("noop.patch"), PathBuf::from("a.txt"))]);
+
+    let arena = FileArena::new();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+    let result = pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?;
+    assert_eq!(result.applied_patches, 2);
+
+    let mut output = Vec::new();
+    write_unchanged_files_report(&mut output, &result.unchanged_files)?;
+    assert_eq!(String::from_utf8(output)?, "\
+Patch noop.patch CHANGES NOTHING IN SOME FILES
+  File a.txt is byte-identical after the patch, its hunks may be redundant
+");
+
+    // Without the option, nothing is checked.
+    let config = ApplyConfig { report_unchanged: false, ..config };
+    let arena = FileArena::new();
+    let result = apply_patches(&config, &arena, &AnalysisSet::default())?;
+    assert!(result.unchanged_files.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::fs;
+
+use anyhow::Result;
+
+use super::push;
+
+/// Set up quilt directory where the second of three patches fails, and push
+/// all of them.
+#[cfg(test)]
+fn setup_failed_push() -> Result<tempfile::TempDir> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/first.patch"),
+              "--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-a\n+first\n")?;
+    fs::write(work_path.join("patches/second.patch"),
+              "--- a/b.txt\n+++ b/b.txt\n@@ -1,2 +1,2 @@\n b\n-bb\n+second\n")?;
+    fs::write(work_path.join("patches/third.patch"),
+              "--- a/b.txt\n+++ b/b.txt\n@@ -1,2 +1,3 @@\n b\n second\n+third\n")?;
+    fs::write(work_path.join("series"), "first.patch\nsecond.patch\nthird.patch\n")?;
+    fs::write(work_path.join("a.txt"), "a\n")?;
+    fs::write(work_path.join("b.txt"), "b\nnot bb\n")?;
+
+    assert!(!push(work_path, &["--all"])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, "first.patch\n");
+    assert!(work_path.join("b.txt.rej").exists());
+
+    Ok(work_dir)
+}
+
+#[cfg(test)]
+#[test]
+fn resume_after_fix() -> Result<()> {
+    let work_dir = setup_failed_push()?;
+    let work_path = work_dir.path();
+
+    // Fix the rejected hunk by hand
+    fs::write(work_path.join("b.txt"), "b\nsecond\n")?;
+
+    assert!(push(work_path, &["--all", "--resume"])?);
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?,
+               "first.patch\nsecond.patch\nthird.patch\n");
+    assert_eq!(fs::read_to_string(work_path.join("b.txt"))?, "b\nsecond\nthird\n");
+
+    // The backup of the fixed patch is the reverted file
+    assert_eq!(fs::read_to_string(work_path.join(".pc/second.patch/b.txt"))?, "b\nbb\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn resume_without_fix() -> Result<()> {
+    let work_dir = setup_failed_push()?;
+    let work_path = work_dir.path();
+
+    let error = push(work_path, &["--all", "--resume"]).unwrap_err();
+    assert!(error.to_string().contains("b.txt"), "{}", error);
+
+    // Nothing was changed
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?, "first.patch\n");
+    assert_eq!(fs::read_to_string(work_path.join("b.txt"))?, "b\nnot bb\n");
+    assert!(!work_path.join(".pc/second.patch").exists());
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::apply::{PatchedFile, write_already_applied_report};
+
+#[cfg(test)]
+#[test]
+fn already_applied_report() {
+    colored::control::set_override(false);
+
+    let files = [
+        PatchedFile { index: 1, patch_filename: PathBuf::from("second.patch"), filename: PathBuf::from("a.txt") },
+        PatchedFile { index: 1, patch_filename: PathBuf::from("second.patch"), filename: PathBuf::from("b.txt") },
+        PatchedFile { index: 3, patch_filename: PathBuf::from("fourth.patch"), filename: PathBuf::from("a.txt") },
+    ];
+
+    let mut output = Vec::<u8>::new();
+    write_already_applied_report(&mut output, &files).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), concat!(
+        "Patch second.patch ALREADY APPLIED\n",
+        "  File a.txt is already in the patched state, it was left unchanged\n",
+        "  File b.txt is already in the patched state, it was left unchanged\n",
+        "Patch fourth.patch ALREADY APPLIED\n",
+        "  File a.txt is already in the patched state, it was left unchanged\n",
+    ));
+}
+
+/// Changes a.txt, which is already patched, and b.txt, which does not match
+#[cfg(test)]
+const MIXED_PATCH: &str = "\
+--- a/a.txt
++++ b/a.txt
+@@ -1,3 +1,3 @@
+ 1
+-2
++two
+ 3
+--- a/b.txt
++++ b/b.txt
+@@ -1,3 +1,3 @@
+ 1
+-2
++two
+ 3
+";
+
+#[cfg(test)]
+#[test]
+fn already_applied_file_kept_when_patch_fails() -> Result<()> {
+    for threads in ["1", "4"] {
+        let work_dir = tempfile::tempdir()?;
+        let work_path = work_dir.path();
+        fs::create_dir(work_path.join("patches"))?;
+        fs::write(work_path.join("patches/mixed.patch"), MIXED_PATCH)?;
+        fs::write(work_path.join("series"), "mixed.patch\n")?;
+        fs::write(work_path.join("a.txt"), "1\ntwo\n3\n")?;
+        fs::write(work_path.join("b.txt"), "1\nzwei\n3\n")?;
+
+        assert!(!super::push(work_path, &["--all", "--reverse-if-applied", "--threads", threads])?);
+
+        // The patch failed, so both files stay as they were
+        assert_eq!(fs::read_to_string(work_path.join("a.txt"))?, "1\ntwo\n3\n", "threads {}", threads);
+        assert_eq!(fs::read_to_string(work_path.join("b.txt"))?, "1\nzwei\n3\n", "threads {}", threads);
+    }
+
+    Ok(())
+}
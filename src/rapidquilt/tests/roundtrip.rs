@@ -0,0 +1,105 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+#[cfg(test)]
+fn roundtrip_check(work_path: &Path) -> Result<bool> {
+    cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--all"),
+        OsStr::new("--roundtrip-check"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])
+}
+
+#[cfg(test)]
+#[test]
+fn roundtrip_of_clean_series() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/a.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ one
+-two
++TWO
+ three
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+")?;
+    fs::write(work_path.join("patches/b.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+-one
++ONE
+ TWO
+ three
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-old
+diff --git a/new.txt b/renamed.txt
+similarity index 100%
+rename from new.txt
+rename to renamed.txt
+")?;
+    fs::write(work_path.join("series"), "a.patch\nb.patch\n")?;
+    fs::write(work_path.join("file.txt"), "one\ntwo\nthree\n")?;
+    fs::write(work_path.join("old.txt"), "old\n")?;
+
+    assert!(roundtrip_check(work_path)?);
+
+    // Nothing was saved
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "one\ntwo\nthree\n");
+    assert_eq!(fs::read_to_string(work_path.join("old.txt"))?, "old\n");
+    assert!(!work_path.join("new.txt").exists());
+    assert!(!work_path.join("renamed.txt").exists());
+    assert!(!work_path.join(".pc").exists());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn roundtrip_of_patch_at_offset() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    // Applies at line 5, but reverts at line 1, where "new" already was
+    fs::write(work_path.join("patches/offset.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ before
+-old
++new
+ after
+")?;
+    fs::write(work_path.join("series"), "offset.patch\n")?;
+    fs::write(work_path.join("file.txt"), "before\nnew\nafter\nfiller\nbefore\nold\nafter\n")?;
+
+    assert!(!roundtrip_check(work_path)?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "before\nnew\nafter\nfiller\nbefore\nold\nafter\n");
+    assert!(!work_path.join("file.txt.rej").exists());
+    assert!(!work_path.join(".pc").exists());
+
+    // The patch applies fine otherwise
+    assert!(cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "before\nnew\nafter\nfiller\nbefore\nnew\nafter\n");
+
+    Ok(())
+}
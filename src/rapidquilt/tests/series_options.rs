@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::apply::SeriesPatch;
+use crate::cmd;
+use super::quilt_metadata::copy_tree;
+use super::push;
+
+#[cfg(test)]
+#[test]
+fn guard_selects_series_lines() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/series-options/input"), work_path)?;
+
+    assert!(push(work_path, &["--all", "--guard", "debug"])?);
+    assert_eq!(fs::read_to_string(work_path.join("output.c"))?, "void output(void)\n{\n\tdebug();\n}\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/applied-patches"))?,
+               "fix-p0.patch\nrevert-change.patch\ndebug-output.patch\nfix typo.patch\n");
+    assert_eq!(fs::read_to_string(work_path.join("config.txt"))?, "mode = 1\nlevel = 1\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn bad_series_lines_rejected() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/a.patch"), "--- /dev/null\n+++ b/a.txt\n@@ -0,0 +1 @@\n+a\n")?;
+
+    for (series, error) in [
+        ("\"a.patch -p1\n", "Parsing line 1 of the series: Missing closing \" in \"\"a.patch -p1\""),
+        ("a.patch -px\n", "Bad strip \"x\" in the patch options for \"a.patch\""),
+        ("a.patch -p1 extra\n", "Unexpected \"extra\" in the patch options for \"a.patch\""),
+        ("# Only guards\n+debug -release\n", "Line 2 of the series has guards, but no patch"),
+    ] {
+        fs::write(work_path.join("series"), series)?;
+        let err = push(work_path, &["--all"]).unwrap_err();
+        assert!(format!("{:#}", err).contains(error), "{:#}", err);
+    }
+    assert!(!work_path.join("a.txt").exists());
+
+    // A '#' inside of a word does not start a comment.
+    fs::write(work_path.join("series"), "  a.patch#not-a-comment\n")?;
+    assert!(push(work_path, &["--all", "--dry-run"]).is_err());
+
+    // No line is selected, there is nothing to push.
+    fs::write(work_path.join("series"), "+debug a.patch # no debugging\n-release 'a.patch' -p1\n")?;
+    assert!(push(work_path, &["--all", "--guard", "release"])?);
+    assert!(!work_path.join("a.txt").exists());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn series_order_quotes_filenames() -> Result<()> {
+    let series_patches = [
+        SeriesPatch { filename: PathBuf::from("fix typo.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("\"quoted\".patch"), strip: 0, reverse: true },
+    ];
+
+    let mut output = Vec::new();
+    cmd::write_series_order(&mut output, &series_patches, 0, 2)?;
+    assert_eq!(String::from_utf8(output)?,
+               "\"fix typo.patch\" -p1\n\
+                '\"quoted\".patch' -p0 -R\n");
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    apply_patches,
+    apply_patches_parallel,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+use super::quilt_metadata::copy_tree;
+
+#[cfg(test)]
+#[test]
+fn show_rejects_inline() -> Result<()> {
+    colored::control::set_override(false);
+
+    for parallel in [false, true] {
+        let work_dir = tempfile::tempdir()?;
+        let work_path = work_dir.path();
+        copy_tree(Path::new("testdata/quilt/fail/mismatch/input"), work_path)?;
+
+        let series_patches = [
+            SeriesPatch { filename: "modify-ddd.patch".into(), strip: 1, reverse: false },
+            SeriesPatch { filename: "wrong-ddd.patch".into(), strip: 1, reverse: false },
+        ];
+        let patches_path = work_path.join("patches");
+        let config = ApplyConfig {
+            show_rejects_inline: true,
+            save_rej_files: false,
+            ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet)
+        };
+        let arena = FileArena::new();
+        let result = if parallel {
+            apply_patches_parallel(&config, &arena, &AnalysisSet::default())?
+        } else {
+            apply_patches(&config, &arena, &AnalysisSet::default())?
+        };
+
+        assert_eq!(result.skipped_patches, 1);
+
+        // The rejects are the same as they would be in the ".rej" file...
+        let expected_rej = fs::read_to_string("testdata/quilt/fail/mismatch/expect/file.in.rej")?;
+        assert_eq!(result.inline_rejects, format!("Rejected hunks for file.in:\n{}", expected_rej));
+        assert!(result.inline_rejects.contains("\n-ddd not found\n+ddd modified again\n"));
+
+        // ... but the file was not saved.
+        assert!(!work_path.join("file.in.rej").exists());
+    }
+
+    Ok(())
+}
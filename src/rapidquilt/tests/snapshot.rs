@@ -0,0 +1,131 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::apply::{
+    ApplyConfig,
+    diff_snapshot,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+use crate::cmd;
+use super::quilt_metadata::copy_tree;
+
+#[cfg(test)]
+fn run_in(work_path: &Path, args: &[&str]) -> Result<bool> {
+    let mut full_args = vec![
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    full_args.extend(args.iter().map(OsStr::new));
+    cmd::run(&full_args)
+}
+
+#[cfg(test)]
+#[test]
+fn snapshot_and_diff() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/basic/input"), work_path)?;
+
+    assert!(run_in(work_path, &["push", "--all"])?);
+    assert!(run_in(work_path, &["snapshot"])?);
+
+    // The snapshot uses the format of quilt backup files, but leaves out
+    // the files that do not exist
+    assert_eq!(fs::read(work_path.join(".pc/.snapshot/file.out"))?,
+               fs::read("testdata/quilt/ok/basic/expect/file.out")?);
+    assert!(!work_path.join(".pc/.snapshot/file.in").exists());
+
+    // Experiment with the files...
+    let content = fs::read_to_string(work_path.join("file.out"))?;
+    fs::write(work_path.join("file.out"), content.replace("kkk\n", "kkk experiment\n"))?;
+    fs::write(work_path.join("file.in"), "new\n")?;
+
+    // ... and see the changes
+    let series_patches = [
+        SeriesPatch { filename: "delete-create.patch".into(), strip: 1, reverse: false },
+        SeriesPatch { filename: "modify-ddd.patch".into(), strip: 1, reverse: false },
+        SeriesPatch { filename: "git-rename.patch".into(), strip: 1, reverse: false },
+    ];
+    let patches_path = work_path.join("patches");
+    let config = ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet);
+    let arena = FileArena::new();
+    let mut output = Vec::new();
+    diff_snapshot(&config, &arena, &mut output)?;
+
+    assert_eq!(String::from_utf8(output)?, concat!(
+        "diff --git a/file.in b/file.in\n",
+        "--- /dev/null\n",
+        "+++ b/file.in\n",
+        "@@ -0,0 +1,1 @@\n",
+        "+new\n",
+        "diff --git a/file.out b/file.out\n",
+        "--- a/file.out\n",
+        "+++ b/file.out\n",
+        "@@ -8,7 +8,7 @@\n",
+        " hhh modified\n",
+        " iii\n",
+        " jjj\n",
+        "-kkk\n",
+        "+kkk experiment\n",
+        " lll\n",
+        " mmm\n",
+        " nnn\n",
+    ));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn snapshot_of_empty_file() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/basic/input"), work_path)?;
+
+    assert!(run_in(work_path, &["push", "--all"])?);
+    fs::write(work_path.join("file.in"), "")?;
+    assert!(run_in(work_path, &["snapshot"])?);
+
+    let series_patches = [
+        SeriesPatch { filename: "delete-create.patch".into(), strip: 1, reverse: false },
+        SeriesPatch { filename: "modify-ddd.patch".into(), strip: 1, reverse: false },
+        SeriesPatch { filename: "git-rename.patch".into(), strip: 1, reverse: false },
+    ];
+    let patches_path = work_path.join("patches");
+    let config = ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet);
+    let arena = FileArena::new();
+
+    // The unchanged empty file is not reported as created
+    let mut output = Vec::new();
+    diff_snapshot(&config, &arena, &mut output)?;
+    assert_eq!(String::from_utf8(output)?, "");
+
+    // Removing it is reported as a deletion
+    fs::remove_file(work_path.join("file.in"))?;
+    let mut output = Vec::new();
+    diff_snapshot(&config, &arena, &mut output)?;
+    assert_eq!(String::from_utf8(output)?, concat!(
+        "diff --git a/file.in b/file.in\n",
+        "--- a/file.in\n",
+        "+++ /dev/null\n",
+    ));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn diff_without_snapshot() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/basic/input"), work_path)?;
+
+    assert!(run_in(work_path, &["diff", "--snapshot"]).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+const FILE: &str = "1\n2\n3\n4\n5\n";
+
+const PATCH_1: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,5 +1,5 @@
+ 1
+ 2
+-3
++three
+ 4
+ 5
+";
+
+/// Changes the line changed by the first patch again
+const PATCH_2: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,3 +2,4 @@
+ 2
+-three
++THREE
++3.5
+ 4
+";
+
+/// The two edits of the same lines coalesced
+const SQUASHED: &str = "\
+diff --git a/file.txt b/file.txt
+--- a/file.txt
++++ b/file.txt
+@@ -1,5 +1,6 @@
+ 1
+ 2
+-3
++THREE
++3.5
+ 4
+ 5
+";
+
+const RENAME_PATCH: &str = "\
+diff --git a/file.txt b/renamed.txt
+rename from file.txt
+rename to renamed.txt
+--- a/file.txt
++++ b/renamed.txt
+@@ -4,2 +4,2 @@
+ 4
+-5
++five
+";
+
+#[cfg(test)]
+fn setup_work_dir(work_path: &Path, patches: &[(&str, &str)]) -> Result<()> {
+    fs::create_dir(work_path.join("patches"))?;
+    let mut series = String::new();
+    for (filename, content) in patches {
+        fs::write(work_path.join("patches").join(filename), content)?;
+        series.push_str(filename);
+        series.push('\n');
+    }
+    fs::write(work_path.join("series"), series)?;
+    fs::write(work_path.join("file.txt"), FILE)?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn run_in(work_path: &Path, command: &str, args: &[&OsStr]) -> Result<bool> {
+    let mut all_args = vec![
+        OsStr::new(command),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    all_args.extend(args);
+    cmd::run(all_args)
+}
+
+/// Squash the `patches`, check that the squashed patch applies on the
+/// original files with the same result and return it.
+#[cfg(test)]
+fn squash(patches: &[(&str, &str)], result_files: &[&str]) -> Result<String> {
+    let temp_dir = tempfile::tempdir()?;
+    let work_path = temp_dir.path().join("work");
+    let squash_path = temp_dir.path().join("squash");
+    fs::create_dir(&work_path)?;
+    fs::create_dir(&squash_path)?;
+    setup_work_dir(&work_path, patches)?;
+
+    assert!(run_in(&work_path, "push", &[OsStr::new("--all")])?);
+    let squashed_path = temp_dir.path().join("squashed.patch");
+    assert!(run_in(&work_path, "squash", &[squashed_path.as_os_str()])?);
+    let squashed = fs::read_to_string(&squashed_path)?;
+
+    setup_work_dir(&squash_path, &[("squashed.patch", &squashed)])?;
+    assert!(run_in(&squash_path, "push", &[])?);
+    for filename in result_files {
+        assert_eq!(fs::read(work_path.join(filename))?, fs::read(squash_path.join(filename))?);
+    }
+
+    Ok(squashed)
+}
+
+#[cfg(test)]
+#[test]
+fn squash_edits_of_same_file() -> Result<()> {
+    let squashed = squash(&[("1.patch", PATCH_1), ("2.patch", PATCH_2)], &["file.txt"])?;
+    assert_eq!(squashed, SQUASHED);
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn squash_keeps_renames() -> Result<()> {
+    let squashed = squash(&[("1.patch", PATCH_1), ("2.patch", RENAME_PATCH), ("3.patch", PATCH_2.replace("file.txt", "renamed.txt").as_str())],
+                          &["renamed.txt"])?;
+    assert!(squashed.starts_with("diff --git a/file.txt b/renamed.txt\nrename from file.txt\nrename to renamed.txt\n"), "{}", squashed);
+    assert!(!squashed.contains("/dev/null"), "{}", squashed);
+    Ok(())
+}
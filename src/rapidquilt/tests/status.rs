@@ -0,0 +1,93 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::apply::{FileBackupStore, SeriesPatch};
+use crate::cmd;
+use crate::status::{stack_status, write_status, write_status_json};
+
+#[cfg(test)]
+fn series_patches(names: &[&str]) -> Vec<SeriesPatch> {
+    names.iter().map(|name| SeriesPatch { filename: PathBuf::from(name), strip: 1, reverse: false }).collect()
+}
+
+#[cfg(test)]
+fn status_output(work_path: &Path, series: &[&str], json: bool) -> Result<String> {
+    let status = stack_status(work_path, &work_path.join("patches"), &series_patches(series), &FileBackupStore::new(work_path))?;
+    let mut output = Vec::new();
+    if json {
+        write_status_json(&mut output, &status)?;
+    } else {
+        write_status(&mut output, &status)?;
+    }
+    Ok(String::from_utf8(output)?)
+}
+
+#[cfg(test)]
+#[test]
+fn status_of_dirty_patch() -> Result<()> {
+    colored::control::set_override(false);
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/a.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+-1
++one
+ 2
+")?;
+    fs::write(work_path.join("patches/b.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+ one
+-2
++two
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+")?;
+    fs::write(work_path.join("patches/c.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-one
++ONE
+")?;
+    fs::write(work_path.join("series"), "a.patch\nb.patch\nc.patch\n")?;
+    fs::write(work_path.join("file.txt"), "1\n2\n")?;
+
+    assert!(cmd::run([
+        OsStr::new("push"), OsStr::new("--quiet"), OsStr::new("--backup"), OsStr::new("always"),
+        OsStr::new("--directory"), work_path.as_os_str(), OsStr::new("2"),
+    ])?);
+    let series = ["a.patch", "b.patch", "c.patch"];
+    assert_eq!(status_output(work_path, &series, false)?, "\
+Applied patches: 2 of 3
+Top patch: b.patch
+Needs refresh: none
+Series: in order
+");
+
+    // Changed after b.patch, the lower a.patch is still clean
+    fs::write(work_path.join("file.txt"), "one\ntwo\nthree\n")?;
+    assert_eq!(status_output(work_path, &series, false)?, "\
+Applied patches: 2 of 3
+Top patch: b.patch
+Needs refresh:
+  file.txt in b.patch
+Series: in order
+");
+
+    assert_eq!(status_output(work_path, &["b.patch", "a.patch", "c.patch"], true)?,
+               "{\"series_patches\":3,\"applied_patches\":2,\"top_patch\":\"b.patch\",\
+                \"needs_refresh\":[{\"patch\":\"b.patch\",\"file\":\"file.txt\"}],\"series_mismatch\":true}\n");
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::fs;
+
+use anyhow::Result;
+
+const FILE: &str = "1\n2\n3\n4\n5\n6\n7\n8\n";
+
+/// The first hunk applies exactly, the second one with offset 2
+const PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+-1
++one
+ 2
+@@ -4,3 +4,3 @@
+ 6
+-7
++seven
+ 8
+";
+
+#[cfg(test)]
+fn push_offset_patch(strict: bool) -> Result<(bool, String, bool)> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/offset.patch"), PATCH)?;
+    fs::write(work_path.join("series"), "offset.patch\n")?;
+    fs::write(work_path.join("file.txt"), FILE)?;
+
+    let ok = super::push(work_path, if strict { &["--strict"] } else { &[] })?;
+    let rej = fs::read_to_string(work_path.join("file.txt.rej")).unwrap_or_default();
+    Ok((ok, fs::read_to_string(work_path.join("file.txt"))?, rej.contains("+seven")))
+}
+
+#[cfg(test)]
+#[test]
+fn strict_fails_on_offset() -> Result<()> {
+    let (ok, content, _) = push_offset_patch(false)?;
+    assert!(ok);
+    assert_eq!(content, "one\n2\n3\n4\n5\n6\nseven\n8\n");
+
+    // Only the hunk with offset is rejected
+    let (ok, content, rejected) = push_offset_patch(true)?;
+    assert!(!ok);
+    assert_eq!(content, FILE);
+    assert!(rejected);
+
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    apply_patches,
+    apply_patches_parallel,
+    write_summary,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+use crate::cmd;
+
+const PATCH_1: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ 1
+-2
++two
+ 3
+";
+
+const PATCH_2: &str = "\
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1,2 @@
++new
++file
+";
+
+/// Fails, so the next one is not tried.
+const PATCH_3: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-mismatch
++broken
+";
+
+#[cfg(test)]
+fn summary_config<'a>(work_path: &'a Path, patches_path: &'a Path, series_patches: &'a [SeriesPatch]) -> ApplyConfig<'a> {
+    ApplyConfig {
+        save_rej_files: false,
+        deterministic: true,
+        dry_run: true,
+        stats: true,
+        ..ApplyConfig::new(work_path, patches_path, series_patches, Verbosity::Quiet)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn summary_only_prints_totals() -> Result<()> {
+    colored::control::set_override(false);
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("1.patch"), PATCH_1)?;
+    fs::write(patches_path.join("2.patch"), PATCH_2)?;
+    fs::write(patches_path.join("3.patch"), PATCH_3)?;
+    fs::write(work_path.join("file.txt"), "1\n2\n3\n")?;
+
+    let series_patches = [
+        SeriesPatch { filename: PathBuf::from("1.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("2.patch"), strip: 1, reverse: false },
+    ];
+    let config = summary_config(work_path, &patches_path, &series_patches);
+
+    // The messages of the threads are kept, there are none per patch.
+    let arena = FileArena::new();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+    let result = pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?;
+    assert!(result.messages.is_empty(), "{:?}", result.messages);
+
+    let mut output = Vec::new();
+    write_summary(&mut output, series_patches.len(), &result)?;
+    assert_eq!(String::from_utf8(output)?,
+               "Summary: 2 of 2 patches applied, 0 failed, 0 not tried, 2 hunk(s), +13 -2 bytes\n");
+
+    // Only the failure is reported.
+    let series_patches = [
+        SeriesPatch { filename: PathBuf::from("1.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("3.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("2.patch"), strip: 1, reverse: false },
+    ];
+    let config = summary_config(work_path, &patches_path, &series_patches);
+    let arena = FileArena::new();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+    let result = pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?;
+    assert!(result.messages.iter().all(|message| message.index == 1), "{:?}", result.messages);
+    assert!(result.messages[0].text.contains("Patch 3.patch FAILED"));
+
+    let mut output = Vec::new();
+    write_summary(&mut output, series_patches.len(), &result)?;
+    assert_eq!(String::from_utf8(output)?,
+               "Summary: 1 of 3 patches applied, 1 failed, 1 not tried, 1 hunk(s), +4 -2 bytes\n");
+
+    // The same without threads.
+    let arena = FileArena::new();
+    let result = apply_patches(&config, &arena, &AnalysisSet::default())?;
+    let mut output = Vec::new();
+    write_summary(&mut output, series_patches.len(), &result)?;
+    assert_eq!(String::from_utf8(output)?,
+               "Summary: 1 of 3 patches applied, 1 failed, 1 not tried, 1 hunk(s), +4 -2 bytes\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn summary_only_excludes_other_verbosity() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("1.patch"), PATCH_1)?;
+    fs::write(work_path.join("series"), "1.patch\n")?;
+    fs::write(work_path.join("file.txt"), "1\n2\n3\n")?;
+
+    for option in ["--quiet", "--verbose"] {
+        assert!(cmd::run([
+            OsStr::new("push"),
+            OsStr::new("--summary-only"),
+            OsStr::new(option),
+            OsStr::new("--directory"), work_path.as_os_str(),
+        ]).is_err());
+    }
+
+    assert!(cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--summary-only"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "1\ntwo\n3\n");
+
+    Ok(())
+}
+
+/// Its first context line does not match, it needs fuzz 1.
+const FUZZY_PATCH: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,7 +2,7 @@
+ a
+ b
+ c
+-d
++changed
+ e
+ f
+ g
+";
+
+#[cfg(test)]
+#[test]
+fn summary_counts_fuzzy_hunks() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("1.patch"), PATCH_1)?;
+    fs::write(patches_path.join("fuzzy.patch"), FUZZY_PATCH)?;
+    fs::write(work_path.join("file.txt"), "1\n2\n3\nother\nb\nc\nd\ne\nf\ng\nh\n")?;
+
+    let series_patches = [
+        SeriesPatch { filename: PathBuf::from("1.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("fuzzy.patch"), strip: 1, reverse: false },
+    ];
+    let config = ApplyConfig { fuzz: 2, ..summary_config(work_path, &patches_path, &series_patches) };
+
+    let arena = FileArena::new();
+    let result = apply_patches(&config, &arena, &AnalysisSet::default())?;
+    assert_eq!(result.patch_sizes[0].fuzzy_hunks, 0);
+    assert_eq!(result.patch_sizes[1].fuzzy_hunks, 1);
+
+    let mut output = Vec::new();
+    write_summary(&mut output, series_patches.len(), &result)?;
+    assert_eq!(String::from_utf8(output)?,
+               "Summary: 2 of 2 patches applied, 0 failed, 0 not tried, 2 hunk(s) (1 with fuzz), +12 -4 bytes\n");
+
+    Ok(())
+}
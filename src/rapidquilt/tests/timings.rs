@@ -0,0 +1,128 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    ApplyResult,
+    apply_patches,
+    apply_patches_parallel,
+    write_timings,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+
+const PATCH_1: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ 1
+-2
++two
+ 3
+";
+
+const PATCH_2: &str = "\
+--- a/other.txt
++++ b/other.txt
+@@ -1 +1 @@
+-old
++new
+";
+
+const PATCH_3: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ 1
+ two
+-3
++three
+";
+
+/// Fails, it is not timed.
+const PATCH_4: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-mismatch
++broken
+";
+
+/// Check that every applied patch was timed, in the order of the series, and
+/// that the critical path is the slower of the two files, each patch changes
+/// only one of them. A tiny patch may take no measurable time, so if both
+/// files took the same time, either of them is the critical path.
+#[cfg(test)]
+fn check_timings(result: &ApplyResult) {
+    let filenames: Vec<_> = result.patch_timings.iter()
+        .map(|patch_timing| patch_timing.patch_filename.to_str().unwrap())
+        .collect();
+    assert_eq!(filenames, ["1.patch", "2.patch", "3.patch"]);
+
+    let file_txt = (result.patch_timings[0].duration + result.patch_timings[2].duration, "file.txt", 2);
+    let other_txt = (result.patch_timings[1].duration, "other.txt", 1);
+    let critical_path = result.critical_path.as_ref().unwrap();
+    let critical_path = (critical_path.duration, critical_path.filename.to_str().unwrap(), critical_path.patches);
+    match file_txt.0.cmp(&other_txt.0) {
+        Ordering::Greater => assert_eq!(critical_path, file_txt),
+        Ordering::Less => assert_eq!(critical_path, other_txt),
+        Ordering::Equal => assert!(critical_path == file_txt || critical_path == other_txt, "{:?}", critical_path),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn timings_of_every_patch() -> Result<()> {
+    colored::control::set_override(false);
+
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("1.patch"), PATCH_1)?;
+    fs::write(patches_path.join("2.patch"), PATCH_2)?;
+    fs::write(patches_path.join("3.patch"), PATCH_3)?;
+    fs::write(patches_path.join("4.patch"), PATCH_4)?;
+    fs::write(work_path.join("file.txt"), "1\n2\n3\n")?;
+    fs::write(work_path.join("other.txt"), "old\n")?;
+
+    let series_patches = [
+        SeriesPatch { filename: PathBuf::from("1.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("2.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("3.patch"), strip: 1, reverse: false },
+        SeriesPatch { filename: PathBuf::from("4.patch"), strip: 1, reverse: false },
+    ];
+    let config = ApplyConfig {
+        dry_run: true,
+        timings: true,
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Quiet)
+    };
+
+    let arena = FileArena::new();
+    let result = apply_patches(&config, &arena, &AnalysisSet::default())?;
+    assert_eq!(result.applied_patches, 3);
+    check_timings(&result);
+
+    // Every thread times its own files.
+    let arena = FileArena::new();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+    let result = pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?;
+    assert_eq!(result.applied_patches, 3);
+    check_timings(&result);
+
+    let mut output = Vec::new();
+    write_timings(&mut output, &result.patch_timings, result.critical_path.as_ref(), 2)?;
+    let output = String::from_utf8(output)?;
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", output);
+    assert_eq!(lines[0], "Slowest patches:");
+    assert!(lines[1..3].iter().all(|line| line.starts_with("  Patch ") && line.ends_with(" ms")), "{}", output);
+    assert!(lines[3].starts_with("Critical path: "), "{}", output);
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::cmd;
+
+#[cfg(test)]
+#[test]
+fn touch_sets_source_date_epoch() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/change.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-1
++one
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+")?;
+    fs::write(work_path.join("series"), "change.patch\n")?;
+    fs::write(work_path.join("file.txt"), "1\n")?;
+    fs::write(work_path.join("other.txt"), "other\n")?;
+    let other_modified = fs::metadata(work_path.join("other.txt"))?.modified()?;
+
+    // No other test uses "touch", so it does not matter that this is process-wide.
+    env::set_var("SOURCE_DATE_EPOCH", "1234567890");
+    let result = cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--touch"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ]);
+    env::remove_var("SOURCE_DATE_EPOCH");
+    assert!(result?);
+
+    let epoch = UNIX_EPOCH + Duration::from_secs(1234567890);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "one\n");
+    assert_eq!(fs::metadata(work_path.join("file.txt"))?.modified()?, epoch);
+    assert_eq!(fs::metadata(work_path.join("new.txt"))?.modified()?, epoch);
+    assert_eq!(fs::metadata(work_path.join("other.txt"))?.modified()?, other_modified);
+
+    Ok(())
+}
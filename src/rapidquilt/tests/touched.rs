@@ -0,0 +1,106 @@
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::apply::SeriesPatch;
+use crate::cmd;
+use crate::touched::{SharedFile, shared_files, write_shared_files, write_shared_files_json};
+
+#[cfg(test)]
+fn file_set(names: &[&str]) -> BTreeSet<PathBuf> {
+    names.iter().map(PathBuf::from).collect()
+}
+
+#[cfg(test)]
+#[test]
+fn shared_files_of_series() -> Result<()> {
+    colored::control::set_override(false);
+
+    let series_patches: Vec<_> = ["a.patch", "b.patch", "c.patch"].iter()
+        .map(|name| SeriesPatch { filename: PathBuf::from(name), strip: 1, reverse: false })
+        .collect();
+    let touched_files = [
+        file_set(&["common.c", "only-a.c", "pair.c"]),
+        file_set(&["common.c", "pair.c"]),
+        file_set(&["common.c", "only-c.c"]),
+    ];
+
+    let shared = shared_files(&series_patches, &touched_files);
+    assert_eq!(shared, [
+        SharedFile {
+            filename: PathBuf::from("common.c"),
+            patches: vec![PathBuf::from("a.patch"), PathBuf::from("b.patch"), PathBuf::from("c.patch")],
+        },
+        SharedFile {
+            filename: PathBuf::from("pair.c"),
+            patches: vec![PathBuf::from("a.patch"), PathBuf::from("b.patch")],
+        },
+    ]);
+
+    let mut output = Vec::new();
+    write_shared_files(&mut output, &shared)?;
+    assert_eq!(String::from_utf8(output)?, "\
+common.c touched by 3 patches
+  a.patch
+  b.patch
+  c.patch
+pair.c touched by 2 patches
+  a.patch
+  b.patch
+");
+
+    let mut output = Vec::new();
+    write_shared_files_json(&mut output, &shared)?;
+    assert_eq!(String::from_utf8(output)?,
+               "{\"files\":[{\"path\":\"common.c\",\"patches\":[\"a.patch\",\"b.patch\",\"c.patch\"]},\
+                {\"path\":\"pair.c\",\"patches\":[\"a.patch\",\"b.patch\"]}]}\n");
+
+    let mut output = Vec::new();
+    write_shared_files(&mut output, &shared_files(&series_patches[..1], &touched_files[..1]))?;
+    assert_eq!(String::from_utf8(output)?, "No file is touched by more than one patch.\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn list_touched_across_series() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/a.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-1
++one
+")?;
+    fs::write(work_path.join("patches/b.patch"), "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-one
++ONE
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+")?;
+    fs::write(work_path.join("series"), "a.patch\nb.patch\n")?;
+    fs::write(work_path.join("file.txt"), "1\n")?;
+
+    assert!(cmd::run([
+        OsStr::new("--list-touched-across-series"), OsStr::new("--format"), OsStr::new("json"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+
+    // Nothing is applied.
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "1\n");
+    assert!(!work_path.join("new.txt").exists());
+    assert!(!work_path.join(".pc").exists());
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::apply::ApplyError;
+use super::push;
+
+/// Set up working directory "work" inside of a temporary directory, with a
+/// patch that creates `filename`.
+#[cfg(test)]
+fn setup_patch_creating(filename: &str) -> Result<tempfile::TempDir> {
+    let outer_dir = tempfile::tempdir()?;
+    let work_path = outer_dir.path().join("work");
+    fs::create_dir_all(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/evil.patch"),
+              format!("--- /dev/null\n+++ b/{}\n@@ -0,0 +1 @@\n+evil\n", filename))?;
+    fs::write(work_path.join("series"), "evil.patch\n")?;
+    Ok(outer_dir)
+}
+
+#[cfg(test)]
+fn assert_refused(work_path: &Path, threads: &str) {
+    let error = push(work_path, &["--all", "--threads", threads]).unwrap_err();
+    assert!(error.chain().any(|cause| matches!(cause.downcast_ref(), Some(ApplyError::UnsafePath { .. }))),
+            "{:?}", error);
+    assert!(!work_path.join(".pc/applied-patches").exists());
+}
+
+#[cfg(test)]
+#[test]
+fn parent_dir_path_refused() -> Result<()> {
+    let outer_dir = setup_patch_creating("../outside.txt")?;
+    let work_path = outer_dir.path().join("work");
+
+    for threads in ["1", "2"] {
+        assert_refused(&work_path, threads);
+        assert!(!outer_dir.path().join("outside.txt").exists());
+    }
+
+    assert!(push(&work_path, &["--threads", "1", "--unsafe-paths"])?);
+    assert_eq!(fs::read_to_string(outer_dir.path().join("outside.txt"))?, "evil\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn parent_dir_within_tree_allowed() -> Result<()> {
+    let outer_dir = setup_patch_creating("dir/../inside.txt")?;
+    let work_path = outer_dir.path().join("work");
+    fs::create_dir(work_path.join("dir"))?;
+
+    assert!(push(&work_path, &["--threads", "1"])?);
+    assert_eq!(fs::read_to_string(work_path.join("inside.txt"))?, "evil\n");
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn symlinked_dir_refused() -> Result<()> {
+    let outer_dir = setup_patch_creating("link/sub/file.txt")?;
+    let work_path = outer_dir.path().join("work");
+    fs::create_dir(outer_dir.path().join("elsewhere"))?;
+    std::os::unix::fs::symlink("../elsewhere", work_path.join("link"))?;
+
+    for threads in ["1", "2"] {
+        assert_refused(&work_path, threads);
+        assert!(!outer_dir.path().join("elsewhere/sub").exists());
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn symlinked_working_dir_allowed() -> Result<()> {
+    let outer_dir = setup_patch_creating("sub/file.txt")?;
+    let link_path = outer_dir.path().join("link-to-work");
+    std::os::unix::fs::symlink("work", &link_path)?;
+
+    assert!(push(&link_path, &["--threads", "1"])?);
+    assert_eq!(fs::read_to_string(outer_dir.path().join("work/sub/file.txt"))?, "evil\n");
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn symlinked_file_refused() -> Result<()> {
+    let outer_dir = setup_patch_creating("link.txt")?;
+    let work_path = outer_dir.path().join("work");
+    fs::write(outer_dir.path().join("elsewhere.txt"), "original\n")?;
+    std::os::unix::fs::symlink("../elsewhere.txt", work_path.join("link.txt"))?;
+
+    for threads in ["1", "2"] {
+        assert_refused(&work_path, threads);
+        assert_eq!(fs::read_to_string(outer_dir.path().join("elsewhere.txt"))?, "original\n");
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn dir_symlinked_by_series_refused() -> Result<()> {
+    let outer_dir = setup_patch_creating("link/file.txt")?;
+    let work_path = outer_dir.path().join("work");
+    fs::create_dir(outer_dir.path().join("elsewhere"))?;
+
+    // The first patch creates the symlink that the second one writes through.
+    fs::write(work_path.join("patches/symlink.patch"), concat!(
+        "diff --git a/link b/link\n",
+        "new file mode 120000\n",
+        "--- /dev/null\n",
+        "+++ b/link\n",
+        "@@ -0,0 +1 @@\n",
+        "+../elsewhere\n",
+        "\\ No newline at end of file\n",
+    ))?;
+    fs::write(work_path.join("series"), "symlink.patch\nevil.patch\n")?;
+
+    for threads in ["1", "2"] {
+        assert_refused(&work_path, threads);
+        assert!(!outer_dir.path().join("elsewhere/file.txt").exists());
+        assert!(work_path.join("link").symlink_metadata().is_err());
+    }
+
+    // In the reverse order, it is refused as well.
+    fs::write(work_path.join("series"), "evil.patch\nsymlink.patch\n")?;
+    for threads in ["1", "2"] {
+        assert_refused(&work_path, threads);
+    }
+
+    Ok(())
+}
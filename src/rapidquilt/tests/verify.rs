@@ -0,0 +1,106 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+#[cfg(feature = "git")]
+use std::process::Command;
+
+use anyhow::Result;
+
+use crate::cmd;
+
+const PATCH: &str = "\
+--- a/file.c
++++ b/file.c
+@@ -1,4 +1,5 @@
+ int main(void)
+ {
++\tprintf(\"hello\\n\");
+ \treturn 0;
+ }
+";
+
+const ORIGINAL: &str = "int main(void)\n{\n\treturn 0;\n}\n";
+
+#[cfg(test)]
+fn verify(work_path: &Path, args: &[&str]) -> Result<bool> {
+    let mut all_args = vec![
+        OsStr::new("verify"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ];
+    all_args.extend(args.iter().map(OsStr::new));
+    let reference_path = work_path.join("reference");
+    all_args.push(reference_path.as_os_str());
+    cmd::run(all_args)
+}
+
+#[cfg(test)]
+#[test]
+fn whitespace_differences_are_tolerated() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/hello.patch"), PATCH)?;
+    fs::write(work_path.join("series"), "hello.patch\n")?;
+    fs::write(work_path.join("file.c"), ORIGINAL)?;
+    fs::create_dir(work_path.join("reference"))?;
+
+    // Reindented with spaces and a trailing space
+    fs::write(work_path.join("reference/file.c"), "int main(void)\n{\n    printf(\"hello\\n\"); \n    return 0;\n}\n")?;
+    assert!(!verify(work_path, &[])?);
+    assert!(verify(work_path, &["--ignore-whitespace"])?);
+    assert_eq!(fs::read_to_string(work_path.join("file.c"))?, ORIGINAL);
+
+    // A real difference is reported either way.
+    fs::write(work_path.join("reference/file.c"), "int main(void)\n{\n    printf(\"hi\\n\");\n    return 0;\n}\n")?;
+    assert!(!verify(work_path, &["--ignore-whitespace"])?);
+
+    fs::remove_file(work_path.join("reference/file.c"))?;
+    assert!(!verify(work_path, &["--ignore-whitespace"])?);
+
+    Ok(())
+}
+
+/// Run git in the `work_path`, as a user that needs no configuration.
+#[cfg(feature = "git")]
+fn git(work_path: &Path, args: &[&str]) -> Result<()> {
+    let status = Command::new("git")
+        .arg("-C").arg(work_path)
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .status()?;
+    anyhow::ensure!(status.success(), "git {:?} failed", args);
+    Ok(())
+}
+
+#[cfg(feature = "git")]
+#[test]
+fn base_ref_compares_with_a_commit() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    fs::create_dir(work_path.join("patches"))?;
+    fs::write(work_path.join("patches/hello.patch"), PATCH)?;
+    fs::write(work_path.join("series"), "hello.patch\n")?;
+
+    // The upstream state that the series should reproduce, and one before it
+    let patched = "int main(void)\n{\n\tprintf(\"hello\\n\");\n\treturn 0;\n}\n";
+    git(work_path, &["init", "--quiet"])?;
+    fs::write(work_path.join("file.c"), patched)?;
+    git(work_path, &["add", "file.c"])?;
+    git(work_path, &["commit", "--quiet", "-m", "Say hello"])?;
+    git(work_path, &["tag", "upstream"])?;
+    fs::write(work_path.join("file.c"), ORIGINAL)?;
+    git(work_path, &["commit", "--quiet", "-a", "-m", "Back to the start"])?;
+
+    let base_ref = |reference: &str| cmd::run([
+        "verify", "--quiet", "--directory", &work_path.to_string_lossy(), "--base-ref", reference,
+    ]);
+    assert!(base_ref("upstream")?);
+    assert!(!base_ref("HEAD")?);
+    assert_eq!(fs::read_to_string(work_path.join("file.c"))?, ORIGINAL);
+
+    let error = base_ref("no-such-ref").unwrap_err();
+    assert!(error.to_string().contains("\"no-such-ref\" is not a commit"), "{}", error);
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use anyhow::Result;
+use libpatch::analysis::AnalysisSet;
+
+use crate::apply::{
+    ApplyConfig,
+    apply_patches_parallel,
+    write_buffered_messages,
+    SeriesPatch,
+    Verbosity,
+};
+use crate::arena::FileArena;
+use super::quilt_metadata::copy_tree;
+
+/// Push the "verify-index" quilt test series, whose patch was made for
+/// another "stale.txt". Returns everything the application would print.
+#[cfg(test)]
+fn apply_index_patch(verify_index: bool) -> Result<String> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    copy_tree(Path::new("testdata/quilt/ok/verify-index/input"), work_path)?;
+
+    let patches_path = work_path.join("patches");
+    let series_patches = [
+        SeriesPatch { filename: "index.patch".into(), strip: 1, reverse: false },
+    ];
+    let config = ApplyConfig {
+        verify_index,
+        deterministic: true,
+        dry_run: true,
+        ..ApplyConfig::new(work_path, &patches_path, &series_patches, Verbosity::Verbose)
+    };
+
+    let arena = FileArena::new();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+    let result = pool.install(|| apply_patches_parallel(&config, &arena, &AnalysisSet::default()))?;
+    assert_eq!(result.applied_patches, 1);
+
+    let mut output = Vec::new();
+    let mut error_output = Vec::new();
+    write_buffered_messages(&mut output, &mut error_output, &result.messages)?;
+    output.extend(error_output);
+
+    Ok(String::from_utf8(output)?)
+}
+
+#[cfg(test)]
+#[test]
+fn verify_index_warns_about_other_base() -> Result<()> {
+    colored::control::set_override(false);
+
+    let output = apply_index_patch(true)?;
+    assert!(output.contains("Patch index.patch: good.txt matches the index line of the patch."), "{}", output);
+    assert!(output.contains("warning: Patch index.patch: stale.txt does not match the index line of the patch \
+                             (e73b3e7 instead of 1e395f2), the tree may not be at the expected base."), "{}", output);
+
+    let output = apply_index_patch(false)?;
+    assert!(!output.contains("index line"), "{}", output);
+
+    Ok(())
+}
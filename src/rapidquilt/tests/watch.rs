@@ -0,0 +1,196 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::apply::{SeriesPatch, Verbosity};
+use crate::cmd;
+use crate::watch::{DEBOUNCE, PatchWatch, watch_series};
+use super::push;
+
+const FILE: &str = "1\n2\n3\n4\n5\n";
+
+const PATCH_A: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+-1
++one
+ 2
+";
+
+const PATCH_B: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -4,2 +4,2 @@
+ 4
+-5
++five
+";
+
+const PATCH_C: &str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,3 +2,3 @@
+ 2
+-3
++three
+ 4
+";
+
+#[cfg(test)]
+fn series(filenames: &[&str]) -> Vec<SeriesPatch> {
+    filenames.iter()
+        .map(|filename| SeriesPatch { filename: PathBuf::from(filename), strip: 1, reverse: false })
+        .collect()
+}
+
+#[cfg(test)]
+fn update(patch_watch: &mut PatchWatch, work_path: &Path, filenames: &[&str]) -> Result<bool> {
+    let series = series(filenames);
+    fs::write(work_path.join("series"), filenames.iter().map(|filename| format!("{}\n", filename)).collect::<String>())?;
+    patch_watch.update(&series, &mut || push(work_path, &["--all", "--backup", "always", "--backup-count", "all"]))
+}
+
+#[cfg(test)]
+#[test]
+fn watch_pops_to_first_change() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("a.patch"), PATCH_A)?;
+    fs::write(patches_path.join("b.patch"), PATCH_B)?;
+    fs::write(patches_path.join("c.patch"), PATCH_C)?;
+    fs::write(work_path.join("file.txt"), FILE)?;
+
+    let read_file = || fs::read_to_string(work_path.join("file.txt"));
+    let read_applied = || fs::read_to_string(work_path.join(".pc/applied-patches"));
+
+    let mut patch_watch = PatchWatch::new(work_path, &patches_path, &[], false, Verbosity::Quiet)?;
+    assert!(update(&mut patch_watch, work_path, &["a.patch", "b.patch"])?);
+    assert_eq!(read_file()?, "one\n2\n3\n4\nfive\n");
+    assert_eq!(read_applied()?, "a.patch\nb.patch\n");
+
+    // Nothing changed, nothing to do
+    assert!(update(&mut patch_watch, work_path, &["a.patch", "b.patch"])?);
+    assert_eq!(read_file()?, "one\n2\n3\n4\nfive\n");
+    assert_eq!(read_applied()?, "a.patch\nb.patch\n");
+
+    // The first patch is edited, everything is pushed again
+    fs::write(patches_path.join("a.patch"), PATCH_A.replace("+one", "+ONE"))?;
+    assert!(update(&mut patch_watch, work_path, &["a.patch", "b.patch"])?);
+    assert_eq!(read_file()?, "ONE\n2\n3\n4\nfive\n");
+    assert_eq!(read_applied()?, "a.patch\nb.patch\n");
+
+    // A patch is inserted, the first one stays applied
+    assert!(update(&mut patch_watch, work_path, &["a.patch", "c.patch", "b.patch"])?);
+    assert_eq!(read_file()?, "ONE\n2\nthree\n4\nfive\n");
+    assert_eq!(read_applied()?, "a.patch\nc.patch\nb.patch\n");
+    assert_eq!(fs::read_to_string(work_path.join(".pc/a.patch/file.txt"))?, FILE);
+
+    // A patch is removed
+    assert!(update(&mut patch_watch, work_path, &["c.patch", "b.patch"])?);
+    assert_eq!(read_file()?, "1\n2\nthree\n4\nfive\n");
+    assert_eq!(read_applied()?, "c.patch\nb.patch\n");
+    assert!(!work_path.join(".pc/a.patch").exists());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn watch_assume_unchanged_pushes_only_tail() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("a.patch"), PATCH_A)?;
+    fs::write(patches_path.join("b.patch"), PATCH_B)?;
+    fs::write(patches_path.join("c.patch"), PATCH_C)?;
+    fs::write(work_path.join("file.txt"), FILE)?;
+
+    let read_file = || fs::read_to_string(work_path.join("file.txt"));
+    let read_applied = || fs::read_to_string(work_path.join(".pc/applied-patches"));
+
+    let mut patch_watch = PatchWatch::new(work_path, &patches_path, &[], true, Verbosity::Quiet)?;
+    assert!(update(&mut patch_watch, work_path, &["a.patch", "b.patch"])?);
+    assert_eq!(read_file()?, "one\n2\n3\n4\nfive\n");
+
+    // The applied patches are not read, so neither the edit nor the removal
+    // is noticed, only the new patch at the end is pushed.
+    fs::write(patches_path.join("a.patch"), PATCH_A.replace("+one", "+ONE"))?;
+    fs::remove_file(patches_path.join("b.patch"))?;
+    assert!(update(&mut patch_watch, work_path, &["a.patch", "b.patch", "c.patch"])?);
+    assert_eq!(read_file()?, "one\n2\nthree\n4\nfive\n");
+    assert_eq!(read_applied()?, "a.patch\nb.patch\nc.patch\n");
+
+    // Changes of the series are still noticed
+    assert!(update(&mut patch_watch, work_path, &["a.patch", "c.patch"])?);
+    assert_eq!(read_file()?, "one\n2\nthree\n4\n5\n");
+    assert_eq!(read_applied()?, "a.patch\nc.patch\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn watch_does_not_pop_patches_without_backup() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+    fs::write(patches_path.join("a.patch"), PATCH_A)?;
+    fs::write(work_path.join("series"), "a.patch\n")?;
+    fs::write(work_path.join("file.txt"), FILE)?;
+
+    // Pushed before watching, with the default backups
+    assert!(cmd::run([
+        OsStr::new("push"),
+        OsStr::new("--quiet"),
+        OsStr::new("--directory"), work_path.as_os_str(),
+    ])?);
+
+    let mut patch_watch = PatchWatch::new(work_path, &patches_path, &series(&["a.patch"]), false, Verbosity::Quiet)?;
+    fs::write(patches_path.join("a.patch"), PATCH_A.replace("+one", "+ONE"))?;
+    let error = update(&mut patch_watch, work_path, &["a.patch"]).unwrap_err();
+    assert!(error.to_string().contains("no backup files"), "{}", error);
+    assert_eq!(fs::read_to_string(work_path.join("file.txt"))?, "one\n2\n3\n4\n5\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn watch_debounces_changes() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let work_path = work_dir.path().to_path_buf();
+    let patches_path = work_path.join("patches");
+    fs::create_dir(&patches_path)?;
+
+    let (sender, receiver) = mpsc::channel();
+    {
+        let work_path = work_path.clone();
+        let patches_path = patches_path.clone();
+        // The watch never returns, the thread ends with the test process.
+        thread::spawn(move || watch_series(&work_path, &patches_path, &mut || { let _ = sender.send(()); }));
+    }
+    // Let it start watching
+    thread::sleep(Duration::from_millis(200));
+
+    for i in 0..5 {
+        fs::write(patches_path.join("a.patch"), format!("{}", i))?;
+    }
+    fs::write(work_path.join("series"), "a.patch\n")?;
+    // Not a change of the series
+    fs::write(work_path.join("file.txt"), FILE)?;
+
+    receiver.recv_timeout(DEBOUNCE * 10)?;
+    assert!(receiver.recv_timeout(DEBOUNCE * 2).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements `--list-touched-across-series`, the report of the
+//! files touched by more than one patch of the series.
+//!
+//! These are the places where the patches may conflict, so they are worth
+//! reviewing together, and their patches depend on each other when applying
+//! them in parallel.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use colored::*;
+
+use crate::apply::SeriesPatch;
+use crate::json;
+
+/// A file touched by more than one patch
+#[derive(Debug, PartialEq)]
+pub struct SharedFile {
+    pub filename: PathBuf,
+
+    /// The patches that touch it, in series order
+    pub patches: Vec<PathBuf>,
+}
+
+/// Find the files in the `touched_files` of the `series_patches` (see
+/// `touched_files_by_patch`) that are touched by more than one patch. The
+/// files touched by most patches come first, files with the same count are
+/// sorted by name.
+pub fn shared_files(series_patches: &[SeriesPatch], touched_files: &[BTreeSet<PathBuf>]) -> Vec<SharedFile> {
+    let mut patches_by_file = BTreeMap::<&PathBuf, Vec<PathBuf>>::new();
+    for (series_patch, files) in series_patches.iter().zip(touched_files) {
+        for filename in files {
+            patches_by_file.entry(filename).or_default().push(series_patch.filename.clone());
+        }
+    }
+
+    let mut shared_files: Vec<_> = patches_by_file.into_iter()
+        .filter(|(_, patches)| patches.len() > 1)
+        .map(|(filename, patches)| SharedFile { filename: filename.clone(), patches })
+        .collect();
+    // The sort is stable, so the names stay sorted.
+    shared_files.sort_by_key(|shared_file| Reverse(shared_file.patches.len()));
+    shared_files
+}
+
+/// Write the `shared_files` for people.
+pub fn write_shared_files<W: Write>(writer: &mut W, shared_files: &[SharedFile]) -> Result<(), io::Error> {
+    if shared_files.is_empty() {
+        return writeln!(writer, "No file is touched by more than one patch.");
+    }
+
+    for shared_file in shared_files {
+        writeln!(writer, "{} {} {} patches", shared_file.filename.display(), "touched by".yellow(), shared_file.patches.len())?;
+        for patch_filename in &shared_file.patches {
+            writeln!(writer, "  {}", patch_filename.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the `shared_files` as a JSON object.
+pub fn write_shared_files_json<W: Write>(writer: &mut W, shared_files: &[SharedFile]) -> Result<(), io::Error> {
+    writer.write_all(b"{\"files\":[")?;
+    for (i, shared_file) in shared_files.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(b"{\"path\":")?;
+        json::write_path(writer, &shared_file.filename)?;
+        writer.write_all(b",\"patches\":[")?;
+        for (j, patch_filename) in shared_file.patches.iter().enumerate() {
+            if j > 0 {
+                writer.write_all(b",")?;
+            }
+            json::write_path(writer, patch_filename)?;
+        }
+        writer.write_all(b"]}")?;
+    }
+    writeln!(writer, "]}}")
+}
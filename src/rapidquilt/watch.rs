@@ -0,0 +1,174 @@
+// Licensed under the MIT license. See LICENSE.md
+
+//! This module implements the `watch` command, which keeps the applied
+//! patches in sync with the series file and the patches directory.
+//!
+//! When the series or a patch changes, the applied patches are popped down
+//! to the first one that changed and the rest of the series is pushed again.
+//! Popping restores the quilt backup files (see `crate::pop`), so the watch
+//! pushes every patch with backups.
+//!
+//! With `--assume-unchanged`, the applied patches are not read at all: those
+//! that are still at their place in the series are taken as unchanged, and
+//! only the patches after them are pushed. Edits of the applied patches, and
+//! of the files they patched, are not noticed then.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::apply::{FileBackupStore, SeriesPatch, Verbosity, backup_names};
+use crate::pop::{read_applied_patches, restore_backup_files, write_applied_patches};
+
+/// Changes are handled only after there were no other changes for this long,
+/// so saving a patch in an editor or copying a bunch of patches triggers only
+/// one update.
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// The version of an applied patch, to find out if it changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct AppliedVersion {
+    strip: usize,
+    reverse: bool,
+    /// Hash of the content of the patch, `None` if it could not be read or
+    /// was not read.
+    hash: Option<u64>,
+}
+
+impl AppliedVersion {
+    /// The version of the `series_patch`. With `assume_unchanged`, the patch
+    /// is not read, so only its place and options tell if it changed.
+    fn of(patches_path: &Path, series_patch: &SeriesPatch, assume_unchanged: bool) -> AppliedVersion {
+        AppliedVersion {
+            strip: series_patch.strip,
+            reverse: series_patch.reverse,
+            hash: if assume_unchanged {
+                None
+            } else {
+                fs::read(patches_path.join(&series_patch.filename)).ok().map(|data| seahash::hash(&data))
+            },
+        }
+    }
+}
+
+/// Keeps track of the versions of the applied patches.
+pub struct PatchWatch<'a> {
+    base_dir: &'a Path,
+    patches_path: &'a Path,
+    assume_unchanged: bool,
+    verbosity: Verbosity,
+    applied: HashMap<PathBuf, AppliedVersion>,
+    /// The backup names (see `backup_names`) of the patches pushed by this
+    /// watch, they surely have backups.
+    pushed: HashSet<PathBuf>,
+}
+
+impl<'a> PatchWatch<'a> {
+    /// Start watching. The patches that are already applied are taken as
+    /// they are in the `series` now. With `assume_unchanged`, the patches are
+    /// never read to find out if they were edited.
+    pub fn new(base_dir: &'a Path, patches_path: &'a Path, series: &[SeriesPatch], assume_unchanged: bool,
+               verbosity: Verbosity) -> Result<Self> {
+        let mut applied = HashMap::new();
+        for filename in read_applied_patches(base_dir)? {
+            if let Some(series_patch) = series.iter().find(|series_patch| series_patch.filename == filename) {
+                applied.insert(filename, AppliedVersion::of(patches_path, series_patch, assume_unchanged));
+            }
+        }
+
+        Ok(PatchWatch { base_dir, patches_path, assume_unchanged, verbosity, applied, pushed: HashSet::new() })
+    }
+
+    /// Pop the applied patches down to the first one that is different in
+    /// the `series` and push the rest with `push`. Returns what `push`
+    /// returned.
+    pub fn update(&mut self, series: &[SeriesPatch], push: &mut dyn FnMut() -> Result<bool>) -> Result<bool> {
+        let applied_patches = read_applied_patches(self.base_dir)?;
+
+        // Read the patches before they are pushed. If one changes meanwhile,
+        // the next update pushes it again.
+        let versions: Vec<_> = series.iter()
+            .map(|series_patch| AppliedVersion::of(self.patches_path, series_patch, self.assume_unchanged))
+            .collect();
+
+        let unchanged = applied_patches.iter().zip(series.iter().zip(&versions))
+            .take_while(|(filename, (series_patch, version))| {
+                **filename == series_patch.filename && self.applied.get(*filename) == Some(version)
+            })
+            .count();
+
+        let applied_names = backup_names(applied_patches.iter().map(PathBuf::as_path));
+        for (filename, backup_name) in applied_patches.iter().zip(&applied_names).skip(unchanged).rev() {
+            if self.verbosity >= Verbosity::Normal {
+                println!("Popping patch {}", filename.display());
+            }
+            restore_backup_files(self.base_dir, &FileBackupStore::new(self.base_dir), filename, backup_name,
+                                 self.pushed.remove(backup_name), None)?;
+            self.applied.remove(filename);
+        }
+        if unchanged < applied_patches.len() {
+            write_applied_patches(self.base_dir, &applied_patches[..unchanged])?;
+        }
+
+        let ok = push()?;
+
+        let applied_patches = read_applied_patches(self.base_dir)?;
+        let applied_names = backup_names(applied_patches.iter().map(PathBuf::as_path));
+        for (filename, backup_name) in applied_patches.iter().zip(applied_names).skip(unchanged) {
+            if let Some(index) = series.iter().position(|series_patch| series_patch.filename == *filename) {
+                self.applied.insert(filename.clone(), versions[index].clone());
+                self.pushed.insert(backup_name);
+            }
+        }
+
+        Ok(ok)
+    }
+}
+
+/// Call `on_change` whenever the series file in the `base_dir` or anything in
+/// the `patches_path` changes. Runs until watching fails.
+pub fn watch_series(base_dir: &Path, patches_path: &Path, on_change: &mut dyn FnMut()) -> Result<()> {
+    // The events come with the watched path prepended, compare canonical
+    // paths so it does not matter how it was given.
+    let base_dir = base_dir.canonicalize()
+        .with_context(|| format!("Watching directory \"{}\"", base_dir.display()))?;
+    let patches_path = patches_path.canonicalize()
+        .with_context(|| format!("Watching directory \"{}\"", patches_path.display()))?;
+    let series_path = base_dir.join("series");
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    // The series file is often replaced instead of written, so watch the
+    // directory that contains it.
+    watcher.watch(&base_dir, RecursiveMode::NonRecursive)?;
+    watcher.watch(&patches_path, RecursiveMode::Recursive)?;
+
+    let is_relevant = |event: &notify::Result<notify::Event>| match event {
+        Ok(event) => !matches!(event.kind, EventKind::Access(_)) &&
+            event.paths.iter().any(|path| *path == series_path || path.starts_with(&patches_path)),
+        // Let the update find out what is going on.
+        Err(_) => true,
+    };
+
+    loop {
+        let event = receiver.recv()?;
+        if !is_relevant(&event) {
+            continue;
+        }
+
+        loop {
+            match receiver.recv_timeout(DEBOUNCE) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => bail!("Watching the patches stopped."),
+            }
+        }
+
+        on_change();
+    }
+}
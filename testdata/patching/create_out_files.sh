@@ -13,6 +13,13 @@ for PATCH in *.patch; do
     rm -f "$OUT_FILE"
     rm -f "$ERROR_FILE"
 
+    # There is no equivalent of forced application, strict ambiguity or
+    # unordered hunks in patch
+    if grep -q '^force: \|^strict-ambiguity: \|^unordered-hunks: ' "$PATCH"; then
+      echo "$PATCH skipped"
+      continue
+    fi
+
     # Parse the fuzz parameter from patch headers (actually from whole patch, but we are careful about what we put in)
     fuzz=$(grep '^fuzz: ' "$PATCH" | sed 's/^fuzz: //')
     fuzz=${fuzz:-0}
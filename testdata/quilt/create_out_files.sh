@@ -1,6 +1,11 @@
 #! /bin/sh
 
 for t in {ok,fail}/* ; do
+    # Tests with extra rapidquilt arguments or excluded paths need
+    # hand-written expectations
+    if [ -e "$t"/args ] || [ -e "$t"/exclude ] ; then
+        continue
+    fi
     rm -r "$t"/expect
     cp -r "$t"/input "$t"/expect
     pushd "$t"/expect
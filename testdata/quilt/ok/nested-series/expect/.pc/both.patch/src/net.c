@@ -0,0 +1,3 @@
+connect
+timeout_ms
+close
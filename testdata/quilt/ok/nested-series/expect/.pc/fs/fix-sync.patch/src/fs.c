@@ -0,0 +1,3 @@
+read
+write
+sync
@@ -0,0 +1,3 @@
+pread
+write
+fsync
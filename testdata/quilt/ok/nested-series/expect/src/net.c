@@ -0,0 +1,3 @@
+connect_ms
+timeout_ms
+close
@@ -0,0 +1,3 @@
+void output(void)
+{
+}